@@ -0,0 +1,59 @@
+use crate::Range;
+use std::collections::HashMap;
+
+/// Names a position kept by `Anchors`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AnchorId(u64);
+
+/// Positions in a buffer that others hold on to, which move with the text
+/// around them
+#[derive(Debug, Clone, Default)]
+pub struct Anchors {
+    next_id: u64,
+    positions: HashMap<AnchorId, usize>,
+}
+
+impl Anchors {
+    pub fn add(&mut self, pos: usize) -> AnchorId {
+        let id = AnchorId(self.next_id);
+        self.next_id += 1;
+        self.positions.insert(id, pos);
+        id
+    }
+
+    /// Where an anchor is now, or `None` if it was removed
+    pub fn get(&self, id: AnchorId) -> Option<usize> {
+        self.positions.get(&id).copied()
+    }
+
+    pub fn remove(&mut self, id: AnchorId) {
+        self.positions.remove(&id);
+    }
+
+    pub(crate) fn on_insert(&mut self, char_idx: usize, size: usize) {
+        for pos in self.positions.values_mut() {
+            if *pos >= char_idx {
+                *pos += size;
+            }
+        }
+    }
+
+    /// Anchors in the removed text collapse to its start
+    pub(crate) fn on_remove(&mut self, range: Range) {
+        let size = range.end - range.start;
+        for pos in self.positions.values_mut() {
+            if *pos >= range.end {
+                *pos -= size;
+            } else if *pos > range.start {
+                *pos = range.start;
+            }
+        }
+    }
+
+    /// Moves anchors past the end of the text back onto it
+    pub(crate) fn clamp(&mut self, len_chars: usize) {
+        for pos in self.positions.values_mut() {
+            *pos = (*pos).min(len_chars);
+        }
+    }
+}
@@ -19,9 +19,14 @@ use gflux::sync::Obs;
 use log::error;
 use ssh2::Session;
 
+use crate::files::{FileStat, Gitignore, IgnoreRules};
 use crate::Window;
 
 type ReqId = u64;
+type StatCallback = Box<dyn Fn(&mut Window, Option<FileStat>)>;
+type ReadCallback = Box<dyn Fn(&mut Window, Option<String>)>;
+type EntryCallback = Box<dyn Fn(&mut Window, Option<DirEntry>)>;
+type WalkCallback = Box<dyn Fn(&mut Window, FileTree)>;
 
 pub struct Backend {
     next_req_id: ReqId,
@@ -38,14 +43,14 @@ pub struct PeekableReceiver<T> {
 }
 
 impl<T> PeekableReceiver<T> {
-    fn new(receiver: Receiver<T>) -> Self {
+    pub(crate) fn new(receiver: Receiver<T>) -> Self {
         Self {
             receiver,
             cached_resp: Rc::new(RefCell::new(None)),
         }
     }
 
-    fn has_read(&self) -> bool {
+    pub(crate) fn has_read(&self) -> bool {
         if self.cached_resp.borrow_mut().is_some() {
             return true;
         }
@@ -63,7 +68,7 @@ impl<T> PeekableReceiver<T> {
         }
     }
 
-    fn try_recv(&self) -> Result<T, TryRecvError> {
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
         if let Some(r) = self.cached_resp.borrow_mut().take() {
             return r;
         }
@@ -153,6 +158,86 @@ impl Backend {
         );
     }
 
+    pub fn stat(&mut self, path: &Path, cb: StatCallback) {
+        let req_id = self.next_req_id;
+        self.next_req_id += 1;
+        if let Err(e) = self
+            .req_sender
+            .send((req_id, BackendReq::Stat(path.to_owned())))
+        {
+            error!("backend stat: {e}");
+        }
+        self.callbacks.insert(
+            req_id,
+            Box::new(move |win, resp| {
+                if let BackendResp::Stat(stat) = resp {
+                    cb(win, stat);
+                }
+            }),
+        );
+    }
+
+    /// Looks up what's at `path`, or `None` if there's nothing there
+    pub fn entry(&mut self, path: &Path, cb: EntryCallback) {
+        let req_id = self.next_req_id;
+        self.next_req_id += 1;
+        if let Err(e) = self
+            .req_sender
+            .send((req_id, BackendReq::Entry(path.to_owned())))
+        {
+            error!("backend entry: {e}");
+        }
+        self.callbacks.insert(
+            req_id,
+            Box::new(move |win, resp| {
+                if let BackendResp::Entry(entry) = resp {
+                    cb(win, entry);
+                }
+            }),
+        );
+    }
+
+    /// Reads the whole of a text file, or `None` if it can't be read
+    pub fn read(&mut self, path: &Path, cb: ReadCallback) {
+        let req_id = self.next_req_id;
+        self.next_req_id += 1;
+        if let Err(e) = self
+            .req_sender
+            .send((req_id, BackendReq::Read(path.to_owned())))
+        {
+            error!("backend read: {e}");
+        }
+        self.callbacks.insert(
+            req_id,
+            Box::new(move |win, resp| {
+                if let BackendResp::Read(text) = resp {
+                    cb(win, text);
+                }
+            }),
+        );
+    }
+
+    /// Lists every file under `path` that its `.gitignore` files don't
+    /// ignore
+    pub fn walk(&mut self, path: &Path, cb: WalkCallback) {
+        let req_id = self.next_req_id;
+        self.next_req_id += 1;
+        if let Err(e) = self
+            .req_sender
+            .send((req_id, BackendReq::Walk(path.to_owned())))
+        {
+            error!("backend walk: {e}");
+        }
+        self.callbacks.insert(
+            req_id,
+            Box::new(move |win, resp| {
+                if let BackendResp::Walk(tree) = resp {
+                    cb(win, tree);
+                }
+            }),
+        );
+    }
+
     // fn send(&self, e: BackendEvent) -> Result<(), SendError<BackendEvent>> {
     //     let res = self.sender.send(e);
     //     (self.waker)();
@@ -164,17 +249,36 @@ impl Backend {
 pub enum BackendReq {
     Exists(PathBuf),
     List(PathBuf),
+    Stat(PathBuf),
+    Entry(PathBuf),
+    Read(PathBuf),
+    Walk(PathBuf),
 }
 
 #[derive(Debug)]
 pub enum BackendResp {
     Exists(bool),
     List(Vec<DirEntry>),
+    Stat(Option<FileStat>),
+    Entry(Option<DirEntry>),
+    Read(Option<String>),
+    Walk(FileTree),
 }
 
 #[derive(Debug)]
 pub struct ListResp {}
 
+/// The files found walking a directory tree
+#[derive(Debug, Clone, Default)]
+pub struct FileTree {
+    /// Paths relative to the root of the walk
+    pub files: Vec<PathBuf>,
+    /// The directories that weren't ignored, starting with the root itself
+    pub dirs: Vec<PathBuf>,
+    /// The `.gitignore` files that were found, to check new files against
+    pub ignore: IgnoreRules,
+}
+
 #[derive(Debug, Clone)]
 pub struct DirEntry {
     pub name: OsString,
@@ -339,6 +443,36 @@ pub fn handle_backend_req(
 
                     dbg!(&p);
                 }
+                BackendReq::Stat(p) => {
+                    let sftp = sess.sftp()?;
+                    let stat = sftp.stat(&p).ok().map(|stat| FileStat {
+                        mtime: stat.mtime.unwrap_or_default(),
+                        size: stat.size.unwrap_or_default(),
+                    });
+                    resp_sender.send((req_id, BackendResp::Stat(stat)))?;
+                }
+                BackendReq::Entry(p) => {
+                    let sftp = sess.sftp()?;
+                    let entry = sftp.stat(&p).ok().map(|stat| DirEntry {
+                        name: p.file_name().unwrap_or_default().to_os_string(),
+                        mode: stat.perm.unwrap_or_default(),
+                    });
+                    resp_sender.send((req_id, BackendResp::Entry(entry)))?;
+                }
+                BackendReq::Read(p) => {
+                    let sftp = sess.sftp()?;
+                    let text = sftp.open(&p).ok().and_then(|mut file| {
+                        let mut text = String::new();
+                        file.read_to_string(&mut text).ok().map(|_| text)
+                    });
+                    resp_sender.send((req_id, BackendResp::Read(text)))?;
+                }
+                BackendReq::Walk(p) => {
+                    let sftp = sess.sftp()?;
+                    let mut tree = FileTree::default();
+                    sftp_walk(&sftp, &p, Path::new(""), &mut tree);
+                    resp_sender.send((req_id, BackendResp::Walk(tree)))?;
+                }
             }
         }
         Err(e) => error!("backend failed {e}"),
@@ -347,6 +481,49 @@ pub fn handle_backend_req(
     Ok(())
 }
 
+/// Adds the files under `root.join(rel)` to `tree`, reading each
+/// directory's `.gitignore` before its entries are checked against it
+fn sftp_walk(sftp: &ssh2::Sftp, root: &Path, rel: &Path, tree: &mut FileTree) {
+    let dir_path = root.join(rel);
+    if let Ok(mut file) = sftp.open(dir_path.join(".gitignore")) {
+        let mut text = String::new();
+        if file.read_to_string(&mut text).is_ok() {
+            tree.ignore.add(Gitignore::parse(rel, &text));
+        }
+    }
+    let mut dir = match sftp.opendir(&dir_path) {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("unable to walk {}: {e}", dir_path.display());
+            return;
+        }
+    };
+    tree.dirs.push(rel.to_owned());
+    let mut subdirs = Vec::new();
+    while let Ok((file, stat)) = dir.readdir() {
+        let Some(name) = file.file_name() else {
+            continue;
+        };
+        if name == "." || name == ".." {
+            continue;
+        }
+        let path = rel.join(name);
+        let is_dir = stat.perm.unwrap_or_default() & 0o0170000 == 0o0040000;
+        if tree.ignore.is_ignored(&path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            subdirs.push(path);
+        } else {
+            tree.files.push(path);
+        }
+    }
+    drop(dir);
+    for path in subdirs {
+        sftp_walk(sftp, root, &path, tree);
+    }
+}
+
 #[test]
 fn test_auth() {
     // let backend = BackendWorker::ssh("localhost:22".to_string(), Some(22), "brain".to_string());
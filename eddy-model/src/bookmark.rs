@@ -0,0 +1,137 @@
+use crate::{Range, ViewId};
+use serde::{Deserialize, Serialize};
+
+/// A place in a buffer to come back to.  It moves with the text around it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmark {
+    /// A char offset
+    pub pos: usize,
+    /// Anonymous bookmarks are only visited in order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// A bookmark in one of a window's buffers, for listing them all
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookmarkEntry {
+    /// A view showing the buffer
+    pub view_id: ViewId,
+    pub pos: usize,
+    /// Counting from 0
+    pub line: usize,
+    pub name: Option<String>,
+    /// The name of the buffer's file
+    pub file: String,
+    /// The text of the bookmarked line, trimmed
+    pub preview: String,
+}
+
+/// The bookmarks of one buffer, ordered by position
+#[derive(Debug, Clone, Default)]
+pub struct Bookmarks {
+    marks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn iter(&self) -> impl Iterator<Item = &Bookmark> {
+        self.marks.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    /// Adds a bookmark, replacing the one with the same name if it's named
+    pub fn add(&mut self, mark: Bookmark) {
+        if mark.name.is_some() {
+            self.marks.retain(|m| m.name != mark.name);
+        }
+        let at = self.marks.partition_point(|m| m.pos <= mark.pos);
+        self.marks.insert(at, mark);
+    }
+
+    /// Removes the bookmarks from `start` up to and including `end`.  Returns
+    /// false if there weren't any.
+    pub fn remove_in(&mut self, start: usize, end: usize) -> bool {
+        let len = self.marks.len();
+        self.marks.retain(|m| m.pos < start || m.pos > end);
+        self.marks.len() != len
+    }
+
+    /// The first bookmark after `pos`, going around to the first one
+    pub fn next_after(&self, pos: usize) -> Option<&Bookmark> {
+        self.marks
+            .iter()
+            .find(|m| m.pos > pos)
+            .or(self.marks.first())
+    }
+
+    /// The last bookmark before `pos`, going around to the last one
+    pub fn prev_before(&self, pos: usize) -> Option<&Bookmark> {
+        self.marks
+            .iter()
+            .rev()
+            .find(|m| m.pos < pos)
+            .or(self.marks.last())
+    }
+
+    pub(crate) fn on_insert(&mut self, char_idx: usize, size: usize) {
+        for m in &mut self.marks {
+            if m.pos >= char_idx {
+                m.pos += size;
+            }
+        }
+    }
+
+    /// Bookmarks in the removed text collapse to its start
+    pub(crate) fn on_remove(&mut self, range: Range) {
+        let size = range.end - range.start;
+        for m in &mut self.marks {
+            if m.pos >= range.end {
+                m.pos -= size;
+            } else if m.pos > range.start {
+                m.pos = range.start;
+            }
+        }
+    }
+
+    /// Moves bookmarks past the end of the text back onto it
+    pub(crate) fn clamp(&mut self, len_chars: usize) {
+        for m in &mut self.marks {
+            m.pos = m.pos.min(len_chars);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bookmarks_follow_edits() {
+        let mark = |pos, name: Option<&str>| Bookmark {
+            pos,
+            name: name.map(str::to_string),
+        };
+        let mut marks = Bookmarks::default();
+        marks.add(mark(10, None));
+        marks.add(mark(2, Some("a")));
+        marks.add(mark(20, None));
+        // A name can only be used once
+        marks.add(mark(5, Some("a")));
+        let positions = |marks: &Bookmarks| marks.iter().map(|m| m.pos).collect::<Vec<_>>();
+        assert_eq!(positions(&marks), vec![5, 10, 20]);
+
+        marks.on_insert(10, 3);
+        marks.on_remove(Range { start: 4, end: 8 });
+        assert_eq!(positions(&marks), vec![4, 9, 19]);
+
+        assert_eq!(marks.next_after(9).unwrap().pos, 19);
+        assert_eq!(marks.next_after(19).unwrap().pos, 4);
+        assert_eq!(marks.prev_before(4).unwrap().pos, 19);
+        assert!(marks.remove_in(5, 9));
+        assert!(!marks.remove_in(5, 9));
+        marks.clamp(10);
+        assert_eq!(positions(&marks), vec![4, 10]);
+    }
+}
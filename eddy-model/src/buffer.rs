@@ -1,16 +1,22 @@
+use crate::anchor::{AnchorId, Anchors};
+use crate::bookmark::{Bookmark, Bookmarks};
+use crate::clipboard::ClipboardEntry;
+use crate::diff;
+use crate::files::{self, FileStat, RecoveryKey, SaveError, SaveOptions};
 use crate::graphemes::{
     next_grapheme_boundary, prev_grapheme_boundary, RopeGraphemes, RopeGraphemesRev,
 };
 use crate::history::History;
-use crate::language::{self, Layer, NilLayer};
+use crate::language::{self, Highlighter, LanguageConfig, NilLayer, Symbol};
 use crate::line_ending::LineEnding;
+use crate::snippet::{Snippet, SnippetSession};
 use crate::style::{Attr, AttrSpan, Theme};
 use crate::tab_mode::TabMode;
 use crate::{BufferId, Point, Range, Selection, ViewId};
-use anyhow::bail;
 use gflux::Rev;
 use log::*;
 use ropey::{Rope, RopeSlice};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::collections::HashMap;
@@ -26,10 +32,30 @@ pub struct Buffer {
     pub id: BufferId,
     pub path: Option<PathBuf>,
     pub pristine: bool,
+    /// Set when the file changed on disk while this buffer had unsaved edits
+    pub external_change: Option<ExternalChange>,
+    /// Names the file this buffer's unsaved text is journaled to
+    pub recovery_key: RecoveryKey,
     rope: Rope,
+    /// The contents of the file as of the last load or save
+    saved: Rope,
+    disk_stat: Option<FileStat>,
+    /// Bumped on every edit
+    revision: u64,
+    /// Bumped whenever a parse is shown
+    parses: u64,
+    last_edit: Option<Instant>,
     history: History,
     selections: HashMap<ViewId, Selections>,
-    layer: Box<dyn Layer>,
+    /// The snippet being filled in in each view
+    snippets: HashMap<ViewId, SnippetSession>,
+    bookmarks: Bookmarks,
+    /// Positions held on to from outside, like the jump list's
+    anchors: Anchors,
+    /// Parses and highlights the text in the background
+    highlighter: Highlighter,
+    /// Whether the language was picked by hand, rather than detected
+    language_picked: bool,
     line_ending: LineEnding,
     tab_mode: TabMode,
     tab_size: usize,
@@ -42,9 +68,16 @@ impl fmt::Debug for Buffer {
             .field("id", &self.id)
             .field("path", &self.path)
             .field("pristine", &self.pristine)
+            .field("external_change", &self.external_change)
+            .field("recovery_key", &self.recovery_key)
+            .field("revision", &self.revision)
             .field("rope", &self.rope)
             .field("history", &self.history)
             .field("selections", &self.selections)
+            .field("snippets", &self.snippets)
+            .field("bookmarks", &self.bookmarks)
+            .field("anchors", &self.anchors)
+            .field("highlighter", &self.highlighter)
             .field("line_ending", &self.line_ending)
             .field("tab_mode", &self.tab_mode)
             .field("tab_size", &self.tab_size)
@@ -52,6 +85,24 @@ impl fmt::Debug for Buffer {
     }
 }
 
+/// A change to a buffer's file, made by something other than this editor
+#[derive(Debug, Clone)]
+pub enum ExternalChange {
+    /// The file now has these contents
+    Modified(Rope),
+    Deleted,
+}
+
+/// How to reconcile a buffer with an external change to its file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictResolution {
+    KeepMine,
+    TakeTheirs,
+    /// Three-way merge both sets of changes against the last saved text
+    Merge,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum DragType {
     Point,
@@ -97,10 +148,21 @@ impl Buffer {
             id,
             path: None,
             pristine: false,
+            external_change: None,
+            recovery_key: RecoveryKey::new_untitled(),
             history: History::new(&rope),
+            saved: rope.clone(),
+            disk_stat: None,
+            revision: 0,
+            parses: 0,
+            last_edit: None,
             rope,
             selections: HashMap::new(),
-            layer: Box::new(NilLayer::new()),
+            snippets: HashMap::new(),
+            bookmarks: Bookmarks::default(),
+            anchors: Anchors::default(),
+            highlighter: Highlighter::new(Box::new(NilLayer::new())),
+            language_picked: false,
             line_ending: LineEnding::Lf,
             tab_mode: TabMode::Spaces(4),
             tab_size: 8,
@@ -109,16 +171,29 @@ impl Buffer {
     }
     pub fn from_file(id: BufferId, path: &Path) -> Result<Self, io::Error> {
         let rope = Rope::from_reader(BufReader::new(File::open(path)?))?;
+        let line_ending = LineEnding::detect(&rope);
+        let layer = language::layer_for(language::detect_language(Some(path), &rope));
 
         let mut buffer = Buffer {
             id,
             path: Some(path.to_owned()),
             pristine: true,
+            external_change: None,
+            recovery_key: RecoveryKey::Path(path.to_owned()),
             history: History::new(&rope),
+            saved: rope.clone(),
+            disk_stat: FileStat::from_path(path),
+            revision: 0,
+            parses: 0,
+            last_edit: None,
             rope,
             selections: HashMap::new(),
-            layer: language::layer_from_path(path),
-            line_ending: LineEnding::Lf,
+            snippets: HashMap::new(),
+            bookmarks: Bookmarks::default(),
+            anchors: Anchors::default(),
+            highlighter: Highlighter::new(layer),
+            language_picked: false,
+            line_ending,
             tab_mode: TabMode::Spaces(4),
             tab_size: 8,
             text_change_cbs: Vec::new(),
@@ -160,13 +235,109 @@ impl Buffer {
     }
 
     fn on_text_change(&mut self) {
-        let start = Instant::now();
-        self.layer.update_highlights(&self.rope);
-        debug!("update_highlights took {}ms", start.elapsed().as_millis());
+        self.highlighter.update(&self.rope);
+    }
+
+    /// Called from another thread when new highlights are ready
+    pub fn set_wakeup(&mut self, wakeup: Arc<dyn Fn() + Send + Sync>) {
+        self.highlighter.set_wakeup(wakeup);
+    }
+
+    /// Whether highlighting finished since the last `apply_highlights`
+    pub fn has_highlights(&self) -> bool {
+        self.highlighter.has_results()
+    }
+
+    /// Shows the newest highlights.  Returns false if there weren't any.
+    pub fn apply_highlights(&mut self) -> bool {
+        let applied = self.highlighter.apply_results();
+        if applied {
+            self.parses += 1;
+        }
+        applied
     }
 
     fn set_pristine(&mut self, pristine: bool) {
-        self.pristine = pristine
+        self.pristine = pristine;
+        if !pristine {
+            self.revision += 1;
+            self.last_edit = Some(Instant::now());
+        }
+    }
+
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+
+    /// The language the buffer is in, if it's known
+    pub fn language(&self) -> Option<&'static str> {
+        self.highlighter.language()
+    }
+
+    /// Treats the buffer as being in a language, or plain text for `None`,
+    /// and highlights it again.  Saving under a new name keeps it.
+    pub fn set_language(&mut self, config: Option<&'static LanguageConfig>) {
+        self.language_picked = true;
+        self.highlighter.set_layer(language::layer_for(config));
+        self.on_text_change();
+    }
+
+    /// Where the local variable under the last caret is defined and used,
+    /// in chars.  Empty if there isn't one, or the language has no locals
+    /// query.
+    pub fn local_occurrences(&self, view_id: ViewId) -> Vec<Range> {
+        let Some(sel) = self.selections(view_id).last().copied() else {
+            return Vec::new();
+        };
+        let byte = self.rope.char_to_byte(sel.cursor());
+        let len = self.rope.len_bytes();
+        self.highlighter
+            .locals()
+            .occurrences(byte)
+            .into_iter()
+            .map(|r| Range {
+                start: self.rope.byte_to_char(r.start.min(len)),
+                end: self.rope.byte_to_char(r.end.min(len)),
+            })
+            .collect()
+    }
+
+    /// The functions, types and constants in the buffer, nested the way
+    /// they are in the text.  Empty until the buffer has been parsed.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let config = self.language().and_then(language::language_by_name);
+        match (config, self.highlighter.tree()) {
+            (Some(config), Some(tree)) => language::symbols(config, tree, &self.rope),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The symbols the last caret is in, outermost first, for breadcrumbs
+    pub fn symbol_path(&self, view_id: ViewId) -> Vec<Symbol> {
+        let config = self.language().and_then(language::language_by_name);
+        let sel = self.selections(view_id).last().copied();
+        match (config, self.highlighter.tree(), sel) {
+            (Some(config), Some(tree), Some(sel)) => {
+                language::symbols_at(config, tree, &self.rope, sel.cursor())
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// A counter that changes whenever the buffer is edited
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// A counter that changes whenever a new parse of the text is shown,
+    /// for what's found in the syntax tree to be looked up again
+    pub fn parses(&self) -> u64 {
+        self.parses
+    }
+
+    /// When the buffer was last edited, if it has been since it was opened
+    pub fn last_edit(&self) -> Option<Instant> {
+        self.last_edit
     }
 
     /// Removes a range of text from the buffer
@@ -182,7 +353,7 @@ impl Buffer {
         let start = self.char_to_point(char_range.start);
         let old_end = self.char_to_point(char_range.end);
         self.rope.remove(char_range);
-        self.layer.edit_tree_remove(start, old_end);
+        self.highlighter.edit_tree_remove(start, old_end);
 
         // Update all the selections
         let size = char_range.end - char_range.start;
@@ -205,6 +376,12 @@ impl Buffer {
                 }
             }
         }
+        self.snippets.retain(|_, session| {
+            session.on_remove(char_range);
+            !session.is_removed()
+        });
+        self.bookmarks.on_remove(char_range);
+        self.anchors.on_remove(char_range);
 
         self.set_pristine(false);
     }
@@ -213,12 +390,18 @@ impl Buffer {
     /// `remove` and `insert_at` are the two base methods that all edits
     /// eventually call.
     pub fn insert_at(&mut self, char_idx: usize, text: &str) {
-        let rope = &mut self.rope;
         let text = self.line_ending.normalize(text);
-        rope.insert(char_idx, &text);
+        self.insert_at_raw(char_idx, &text);
+    }
+
+    /// Like `insert_at`, but inserts the text exactly as given, without
+    /// normalizing line endings
+    fn insert_at_raw(&mut self, char_idx: usize, text: &str) {
+        let rope = &mut self.rope;
+        rope.insert(char_idx, text);
         let start = self.char_to_point(char_idx);
         let new_end = self.char_to_point(char_idx + text.chars().count());
-        self.layer.edit_tree_insert(start, new_end);
+        self.highlighter.edit_tree_insert(start, new_end);
 
         let size = text.chars().count();
         for sels in &mut self.selections.values_mut() {
@@ -231,6 +414,11 @@ impl Buffer {
                 }
             }
         }
+        for session in self.snippets.values_mut() {
+            session.on_insert(char_idx, size);
+        }
+        self.bookmarks.on_insert(char_idx, size);
+        self.anchors.on_insert(char_idx, size);
 
         self.set_pristine(false);
     }
@@ -795,6 +983,33 @@ impl Buffer {
         }
     }
 
+    /// Puts a single caret at the start of a line, or the last line if it's
+    /// past the end
+    pub fn go_to_line(&mut self, view_id: ViewId, line: usize) {
+        self.go_to_position(view_id, line, 0);
+    }
+
+    /// Puts a single caret at a line and column, counting from 0, or as
+    /// close as the text allows
+    pub fn go_to_position(&mut self, view_id: ViewId, line: usize, column: usize) {
+        let line = min(line, self.rope.len_lines() - 1);
+        let start = self.rope.line_to_char(line);
+        let line_end = if line == self.rope.len_lines() - 1 {
+            self.rope.len_chars()
+        } else {
+            self.rope.line_to_char(line + 1) - 1
+        };
+        let pos = min(start + column, line_end);
+        self.replace_selections(
+            view_id,
+            &[Selection {
+                start: pos,
+                end: pos,
+                horiz: None,
+            }],
+        );
+    }
+
     pub fn move_to_beginning_of_document(&mut self, view_id: ViewId) {
         for sel in &mut self.selections.entry(view_id).or_default().sels {
             sel.start = 0;
@@ -1080,7 +1295,10 @@ impl Buffer {
 
     pub fn undo(&mut self, view_id: ViewId) {
         if let Some((rope, new_sels)) = self.history.undo() {
-            self.rope = rope;
+            let old = std::mem::replace(&mut self.rope, rope);
+            shift_marks(&old, &self.rope, &mut self.bookmarks, &mut self.anchors);
+            // Where the tab stops went isn't known any more
+            self.snippets.clear();
             use std::collections::hash_map::Entry;
             let sels = self.selections.entry(view_id).or_default();
             sels.sels.clear();
@@ -1088,7 +1306,7 @@ impl Buffer {
 
             // Just redo highlighting entirely.  It's probably not worth it to
             // store a copy of the rope and the InputEdit for every micro-edit.
-            self.layer.unset_tree();
+            self.highlighter.unset_tree();
         }
 
         self.fix_selections();
@@ -1098,7 +1316,10 @@ impl Buffer {
 
     pub fn redo(&mut self, view_id: ViewId) {
         if let Some((rope, new_sels)) = self.history.redo() {
-            self.rope = rope;
+            let old = std::mem::replace(&mut self.rope, rope);
+            shift_marks(&old, &self.rope, &mut self.bookmarks, &mut self.anchors);
+            // Where the tab stops went isn't known any more
+            self.snippets.clear();
             use std::collections::hash_map::Entry;
             let sels = self.selections.entry(view_id).or_default();
             sels.sels.clear();
@@ -1106,7 +1327,7 @@ impl Buffer {
 
             // Just redo highlighting entirely.  It's probably not worth it to
             // store a copy of the rope and the InputEdit for every micro-edit.
-            self.layer.unset_tree();
+            self.highlighter.unset_tree();
         }
 
         self.fix_selections();
@@ -1114,42 +1335,555 @@ impl Buffer {
         self.set_pristine(false);
     }
 
-    pub fn cut(&mut self, view_id: ViewId) -> Option<String> {
-        let ret = self.copy(view_id);
-        for i in 0..self.selections.entry(view_id).or_default().sels.len() {
-            let sel = self.selections.get(&view_id).unwrap().sels[i];
-            if !sel.is_caret() {
-                // Just remove the selection
-                self.remove(sel.range());
+    /// Cuts the selected text.  Where every selection is empty, the lines
+    /// the carets are on are cut instead.
+    pub fn cut(&mut self, view_id: ViewId) -> Option<ClipboardEntry> {
+        let entry = self.copy(view_id)?;
+        let sels_before = self.selections(view_id);
+
+        if entry.linewise {
+            for line in self.caret_lines(view_id).into_iter().rev() {
+                let mut start = self.rope.line_to_char(line);
+                let end = self.rope.line_to_char(min(line + 1, self.rope.len_lines()));
+                // The last line has no line break of its own, so the one
+                // before it goes instead of leaving an empty line behind
+                if line + 1 == self.rope.len_lines() && line > 0 {
+                    start -= 1;
+                    if start > 0
+                        && self.rope.char(start) == '\n'
+                        && self.rope.char(start - 1) == '\r'
+                    {
+                        start -= 1;
+                    }
+                }
+                self.remove(Range { start, end });
+            }
+        } else {
+            for i in 0..self.selections.entry(view_id).or_default().sels.len() {
+                let sel = self.selections.get(&view_id).unwrap().sels[i];
+                if !sel.is_caret() {
+                    self.remove(sel.range());
+                }
             }
         }
-        ret
+
+        let sels_after = self.selections(view_id);
+        self.history.new_change(&self.rope, sels_before, sels_after);
+        self.on_text_change();
+        Some(entry)
     }
 
-    pub fn copy(&self, view_id: ViewId) -> Option<String> {
-        let mut ret = String::new();
-        for i in 0..self
-            .selections
+    /// Cuts from each caret to the end of its line, or just the line break
+    /// when the caret is at the end already.  Returns None if there's
+    /// nothing after any caret.
+    pub fn kill_line(&mut self, view_id: ViewId) -> Option<ClipboardEntry> {
+        let sels: Vec<Selection> = self
+            .selections(view_id)
+            .iter()
+            .map(|sel| {
+                let pos = sel.cursor();
+                let line = self.rope.char_to_line(pos);
+                let line_start = self.rope.line_to_char(line);
+                let text = self.rope.line(line);
+                let content = text
+                    .chars()
+                    .take_while(|c| *c != '\n' && *c != '\r')
+                    .count();
+                let end = if pos < line_start + content {
+                    line_start + content
+                } else {
+                    line_start + text.len_chars()
+                };
+                Selection {
+                    start: pos,
+                    end,
+                    horiz: None,
+                }
+            })
+            .collect();
+        if sels.iter().all(|s| s.is_caret()) {
+            return None;
+        }
+        self.replace_selections(view_id, &sels);
+        self.cut(view_id)
+    }
+
+    /// Copies the selected text, one chunk per selection.  Where every
+    /// selection is empty, the lines the carets are on are copied instead.
+    pub fn copy(&self, view_id: ViewId) -> Option<ClipboardEntry> {
+        let sels = self.selections(view_id);
+        if sels.is_empty() {
+            return None;
+        }
+
+        if sels.iter().all(|s| s.is_caret()) {
+            let chunks = self
+                .caret_lines(view_id)
+                .into_iter()
+                .map(|line| {
+                    let mut s = self.rope.line(line).to_string();
+                    if !s.ends_with('\n') {
+                        s.push('\n');
+                    }
+                    s
+                })
+                .collect();
+            return Some(ClipboardEntry {
+                chunks,
+                linewise: true,
+            });
+        }
+
+        let chunks = sels
+            .iter()
+            .filter(|s| !s.is_caret())
+            .map(|s| self.rope.slice(s.range()).to_string())
+            .collect();
+        Some(ClipboardEntry {
+            chunks,
+            linewise: false,
+        })
+    }
+
+    /// The selected text of a view, joined by newlines, or `None` if nothing
+    /// is selected
+    pub fn selected_text(&self, view_id: ViewId) -> Option<String> {
+        let chunks: Vec<String> = self
+            .selections(view_id)
+            .iter()
+            .filter(|s| !s.is_caret())
+            .map(|s| self.rope.slice(s.range()).to_string())
+            .collect();
+        if chunks.is_empty() {
+            None
+        } else {
+            Some(chunks.join("\n"))
+        }
+    }
+
+    /// The distinct lines that a view's carets are on, in order
+    fn caret_lines(&self, view_id: ViewId) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .selections(view_id)
+            .iter()
+            .map(|s| self.rope.char_to_line(s.cursor()))
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// Replaces ranges of text as a single undoable change, then sets the
+    /// view's selections.  The ranges must be in order and not overlap, and
+    /// `sels_after` refers to the text after the change, as given.  Line
+    /// breaks in the text are made the buffer's, and the selections are
+    /// moved to match.
+    pub fn edit(&mut self, view_id: ViewId, edits: &[(Range, String)], sels_after: &[Selection]) {
+        let sels_before = self.selections(view_id);
+        let (edits, map) = self.normalize_edits(edits);
+        let sels_after: Vec<Selection> = sels_after
+            .iter()
+            .map(|s| Selection {
+                start: map(s.start),
+                end: map(s.end),
+                horiz: s.horiz,
+            })
+            .collect();
+        let edits: Vec<_> = edits
+            .iter()
+            .filter(|(range, text)| range.start != range.end || !text.is_empty())
+            .collect();
+        if edits.is_empty() {
+            self.replace_selections(view_id, &sels_after);
+            return;
+        }
+        for (range, text) in edits.into_iter().rev() {
+            self.remove(*range);
+            self.insert_at_raw(range.start, text);
+        }
+        self.replace_selections(view_id, &sels_after);
+        self.history.new_change(&self.rope, sels_before, sels_after);
+        self.on_text_change();
+    }
+
+    /// Gives the text of `edits` the buffer's line breaks.  Also returns a
+    /// function that moves a position in the text after the edits as given
+    /// to the same place after the normalized ones.
+    fn normalize_edits(
+        &self,
+        edits: &[(Range, String)],
+    ) -> (Vec<(Range, String)>, impl Fn(usize) -> usize) {
+        let mut normalized = Vec::with_capacity(edits.len());
+        // Where each edit's text starts, as given and as normalized, and
+        // where each of its chars went
+        let mut spans: Vec<(usize, usize, Vec<usize>)> = Vec::with_capacity(edits.len());
+        let (mut given_delta, mut delta) = (0isize, 0isize);
+        for (range, text) in edits {
+            let (text_n, offsets) = self.line_ending.normalize_with_offsets(text);
+            let removed = (range.end - range.start) as isize;
+            let given_start = (range.start as isize + given_delta) as usize;
+            let start = (range.start as isize + delta) as usize;
+            given_delta += text.chars().count() as isize - removed;
+            delta += text_n.chars().count() as isize - removed;
+            spans.push((given_start, start, offsets));
+            normalized.push((*range, text_n));
+        }
+        let map = move |pos: usize| {
+            let Some((given_start, start, offsets)) = spans.iter().rev().find(|s| s.0 <= pos)
+            else {
+                return pos;
+            };
+            let i = pos - given_start;
+            let given_len = offsets.len() - 1;
+            match offsets.get(i) {
+                Some(offset) => start + offset,
+                None => start + offsets[given_len] + (i - given_len),
+            }
+        };
+        (normalized, map)
+    }
+
+    /// The position in the undo history, to pass to `squash_history` later
+    pub fn history_checkpoint(&self) -> usize {
+        self.history.checkpoint()
+    }
+
+    /// Merges every change made since `checkpoint` into a single undo step
+    pub fn squash_history(&mut self, checkpoint: usize) {
+        self.history.squash(checkpoint);
+    }
+
+    /// The value of a snippet variable like `TM_FILENAME`, at a selection
+    fn snippet_variable(&self, sel: &Selection, name: &str) -> Option<String> {
+        let path = self.path.as_deref();
+        let line = self.rope.char_to_line(sel.cursor());
+        Some(match name {
+            "TM_SELECTED_TEXT" => self
+                .rope
+                .slice(sel.start.min(sel.end)..sel.start.max(sel.end))
+                .to_string(),
+            "TM_CURRENT_LINE" => self
+                .rope
+                .line(line)
+                .to_string()
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            "TM_LINE_INDEX" => line.to_string(),
+            "TM_LINE_NUMBER" => (line + 1).to_string(),
+            "TM_FILENAME" => path?.file_name()?.to_string_lossy().to_string(),
+            "TM_FILENAME_BASE" => path?.file_stem()?.to_string_lossy().to_string(),
+            "TM_DIRECTORY" => path?.parent()?.to_string_lossy().to_string(),
+            "TM_FILEPATH" => path?.to_string_lossy().to_string(),
+            _ => return None,
+        })
+    }
+
+    /// Replaces every selection with a snippet as one undoable change, and
+    /// selects its first tab stop.  The stops stay linked, so typing in one
+    /// changes its mirrors, until `$0` is reached or the carets leave them.
+    /// Variables the buffer doesn't know are looked up with `vars`.
+    pub fn insert_snippet(
+        &mut self,
+        view_id: ViewId,
+        snippet: &Snippet,
+        vars: &dyn Fn(&str) -> Option<String>,
+    ) {
+        let mut sels = self.selections(view_id);
+        sels.sort_by_key(|s| s.range().start);
+        let indent_unit = self.indent_unit();
+
+        let mut edits = Vec::new();
+        // Each stop's index, ranges and choices.  Stops are matched up by
+        // index, as a variable's default can hold stops that another
+        // caret's copy of the snippet doesn't have.
+        let mut stops: Vec<(usize, Vec<Range>, Vec<String>)> = Vec::new();
+        let mut delta = 0isize;
+        for sel in &sels {
+            let range = sel.range();
+            let line = self.rope.char_to_line(range.start);
+            let indent: String = self
+                .rope
+                .line(line)
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect();
+            let lookup = |name: &str| self.snippet_variable(sel, name).or_else(|| vars(name));
+            let expansion = snippet.expand(&lookup, &indent, &indent_unit);
+
+            let start = (range.start as isize + delta) as usize;
+            for stop in expansion.stops {
+                let ranges = stop.ranges.iter().map(|r| Range {
+                    start: start + r.start,
+                    end: start + r.end,
+                });
+                match stops.iter_mut().find(|(i, _, _)| *i == stop.index) {
+                    Some((_, existing, _)) => existing.extend(ranges),
+                    None => stops.push((stop.index, ranges.collect(), stop.choices)),
+                }
+            }
+            delta += expansion.text.chars().count() as isize - (range.end - range.start) as isize;
+            edits.push((range, expansion.text));
+        }
+        stops.sort_by_key(|(i, _, _)| if *i == 0 { usize::MAX } else { *i });
+
+        // The stops are in the text as expanded, before its line breaks
+        // are made the buffer's
+        let (edits, map) = self.normalize_edits(&edits);
+        let session = SnippetSession::new(
+            stops
+                .into_iter()
+                .map(|(_, ranges, choices)| {
+                    let ranges = ranges
+                        .into_iter()
+                        .map(|r| Range {
+                            start: map(r.start),
+                            end: map(r.end),
+                        })
+                        .collect();
+                    (ranges, choices)
+                })
+                .collect(),
+        );
+        let sels_after = Self::stop_selections(session.ranges());
+        self.snippets.remove(&view_id);
+        self.edit(view_id, &edits, &sels_after);
+        if !session.is_finished() {
+            self.snippets.insert(view_id, session);
+        }
+    }
+
+    /// Selections covering a tab stop's ranges, with the carets at their
+    /// ends
+    fn stop_selections(ranges: &[Range]) -> Vec<Selection> {
+        ranges
+            .iter()
+            .map(|r| Selection {
+                start: r.start,
+                end: r.end,
+                horiz: None,
+            })
+            .collect()
+    }
+
+    /// Returns true while a snippet's tab stops are being filled in
+    pub fn in_snippet(&self, view_id: ViewId) -> bool {
+        self.snippets.contains_key(&view_id)
+    }
+
+    /// The options for the current tab stop, if it's a choice
+    pub fn snippet_choices(&self, view_id: ViewId) -> &[String] {
+        self.snippets
             .get(&view_id)
-            .map(|s| s.sels.len())
+            .map(SnippetSession::choices)
             .unwrap_or_default()
-        {
-            let sel = self.selections.get(&view_id).unwrap().sels[i];
-            if !sel.is_caret() {
-                // Just remove the selection
-                let rope = &self.rope;
-                let text: Cow<'_, str> = rope.slice(sel.range()).into();
-                if !ret.is_empty() {
-                    ret.push('\n');
+    }
+
+    /// Selects the next tab stop of the snippet.  Reaching `$0` finishes it.
+    pub fn next_tab_stop(&mut self, view_id: ViewId) {
+        self.move_tab_stop(view_id, true);
+    }
+
+    pub fn prev_tab_stop(&mut self, view_id: ViewId) {
+        self.move_tab_stop(view_id, false);
+    }
+
+    fn move_tab_stop(&mut self, view_id: ViewId, forward: bool) {
+        let Some(session) = self.snippets.get_mut(&view_id) else {
+            return;
+        };
+        if forward {
+            session.next();
+        } else {
+            session.prev();
+        }
+        let sels = Self::stop_selections(session.ranges());
+        if session.is_finished() {
+            self.snippets.remove(&view_id);
+        }
+        self.replace_selections(view_id, &sels);
+    }
+
+    /// Replaces the current choice stop with its next option
+    pub fn next_snippet_choice(&mut self, view_id: ViewId) {
+        let Some(session) = self.snippets.get(&view_id) else {
+            return;
+        };
+        let (ranges, choices) = (session.ranges().to_vec(), session.choices().to_vec());
+        let Some(first) = ranges.first() else {
+            return;
+        };
+        if choices.is_empty() {
+            return;
+        }
+        let current = self.rope.slice(first.start..first.end).to_string();
+        let next = choices
+            .iter()
+            .position(|c| *c == current)
+            .map(|i| (i + 1) % choices.len())
+            .unwrap_or(0);
+        let text = &choices[next];
+
+        let mut edits: Vec<(Range, String)> = ranges.iter().map(|r| (*r, text.clone())).collect();
+        edits.sort_by_key(|(r, _)| r.start);
+        let mut delta = 0isize;
+        let mut sels_after = Vec::new();
+        for (r, text) in &edits {
+            let start = (r.start as isize + delta) as usize;
+            let len = text.chars().count();
+            sels_after.push(Selection {
+                start,
+                end: start + len,
+                horiz: None,
+            });
+            delta += len as isize - (r.end - r.start) as isize;
+        }
+        self.edit(view_id, &edits, &sels_after);
+    }
+
+    /// Finishes the snippet if a caret has left its current tab stop
+    pub fn check_snippet(&mut self, view_id: ViewId) {
+        let Some(session) = self.snippets.get(&view_id) else {
+            return;
+        };
+        let sels = self.selections(view_id);
+        if !sels.iter().all(|s| session.contains(s.cursor())) {
+            self.snippets.remove(&view_id);
+        }
+    }
+
+    pub fn bookmarks(&self) -> &Bookmarks {
+        &self.bookmarks
+    }
+
+    /// Keeps track of a position as the text is edited, until the anchor is
+    /// removed
+    pub fn add_anchor(&mut self, pos: usize) -> AnchorId {
+        self.anchors.add(pos.min(self.rope.len_chars()))
+    }
+
+    pub fn anchor(&self, id: AnchorId) -> Option<usize> {
+        self.anchors.get(id)
+    }
+
+    pub fn remove_anchor(&mut self, id: AnchorId) {
+        self.anchors.remove(id);
+    }
+
+    /// Replaces the bookmarks, like when restoring a session
+    pub fn set_bookmarks(&mut self, marks: Vec<Bookmark>) {
+        self.bookmarks = Bookmarks::default();
+        for mark in marks {
+            self.bookmarks.add(mark);
+        }
+        self.bookmarks.clamp(self.rope.len_chars());
+    }
+
+    /// Removes the bookmarks on each caret's line, or adds an anonymous one
+    /// at the caret if there are none
+    pub fn toggle_bookmark(&mut self, view_id: ViewId) {
+        let mut lines = Vec::new();
+        for sel in self.selections(view_id) {
+            let line = self.rope.char_to_line(sel.cursor());
+            if !lines.contains(&line) {
+                lines.push(line);
+                let start = self.rope.line_to_char(line);
+                let end = if line + 1 < self.rope.len_lines() {
+                    self.rope.line_to_char(line + 1) - 1
+                } else {
+                    self.rope.len_chars()
+                };
+                if !self.bookmarks.remove_in(start, end) {
+                    self.bookmarks.add(Bookmark {
+                        pos: sel.cursor(),
+                        name: None,
+                    });
                 }
-                ret.push_str(&text);
             }
         }
-        if ret.is_empty() {
-            None
+    }
+
+    /// Bookmarks the caret under a name, moving the bookmark if the name is
+    /// already used
+    pub fn set_bookmark(&mut self, view_id: ViewId, name: &str) {
+        let Some(sel) = self.selections(view_id).last().copied() else {
+            return;
+        };
+        self.bookmarks.add(Bookmark {
+            pos: sel.cursor(),
+            name: Some(name.to_string()),
+        });
+    }
+
+    /// Puts a single caret at the next bookmark, going around to the first
+    pub fn next_bookmark(&mut self, view_id: ViewId) {
+        self.move_to_bookmark(view_id, true);
+    }
+
+    pub fn prev_bookmark(&mut self, view_id: ViewId) {
+        self.move_to_bookmark(view_id, false);
+    }
+
+    fn move_to_bookmark(&mut self, view_id: ViewId, forward: bool) {
+        let Some(sel) = self.selections(view_id).last().copied() else {
+            return;
+        };
+        let mark = if forward {
+            self.bookmarks.next_after(sel.cursor())
         } else {
-            Some(ret)
+            self.bookmarks.prev_before(sel.cursor())
+        };
+        if let Some(pos) = mark.map(|m| m.pos) {
+            self.replace_selections(
+                view_id,
+                &[Selection {
+                    start: pos,
+                    end: pos,
+                    horiz: None,
+                }],
+            );
+        }
+    }
+
+    /// The text that one level of indentation is made of
+    pub fn indent_unit(&self) -> String {
+        match self.tab_mode {
+            TabMode::Tabs => "\t".to_string(),
+            TabMode::Spaces(n) => " ".repeat(n as usize),
+        }
+    }
+
+    /// Pastes a clipboard entry at every selection.  When the entry splits
+    /// into as many pieces as there are carets, each caret gets its own
+    /// piece.  Whole lines are pasted above the caret's line.
+    pub fn paste(&mut self, view_id: ViewId, entry: &ClipboardEntry) {
+        let sels_before = self.selections(view_id);
+        let text = entry.text();
+        let pieces = entry.pieces(sels_before.len());
+
+        for i in 0..sels_before.len() {
+            let sel = self.selections.get(&view_id).unwrap().sels[i];
+            let piece = pieces.as_ref().map(|p| p[i]).unwrap_or(&text);
+            if entry.linewise && sel.is_caret() {
+                let line = self.rope.char_to_line(sel.cursor());
+                let line_start = self.rope.line_to_char(line);
+                self.insert_at(line_start, piece);
+            } else {
+                self.remove(sel.range());
+                let sel = self.selections.get(&view_id).unwrap().sels[i];
+                self.insert_at(sel.cursor(), piece);
+            }
+            if let Some(sel) = self
+                .selections
+                .get_mut(&view_id)
+                .and_then(|s| s.sels.get_mut(i))
+            {
+                sel.horiz = None;
+            }
         }
+
+        let sels_after = self.selections(view_id);
+        self.history.new_change(&self.rope, sels_before, sels_after);
+        self.on_text_change();
     }
 
     pub fn drag_update(&mut self, view_id: ViewId, line_idx: usize, line_byte_idx: usize) {
@@ -1215,12 +1949,14 @@ impl Buffer {
         self.selections.entry(view_id).or_default().drag = None;
     }
 
-    // currently the only thing this does is ensure that all selections are not
-    // out of bounds
+    // currently the only thing this does is ensure that all selections (and
+    // bookmarks) are not out of bounds
     // TODO make sure selection regions never intersect, if so, merge them
     pub fn fix_selections(&mut self) {
         let rope = &self.rope;
         let len_chars = rope.len_chars();
+        self.bookmarks.clamp(len_chars);
+        self.anchors.clamp(len_chars);
 
         for sels in self.selections.values_mut() {
             for sel in &mut sels.sels {
@@ -1335,50 +2071,44 @@ impl Buffer {
             rope.line_to_byte(line_idx + 1)
         };
 
+        // Lines edited since the last parse keep their old highlights, which
+        // may not fit them any more
+        let snap = |byte: usize| line.char_to_byte(line.byte_to_char(byte.min(line.len_bytes())));
         let mut spans = Vec::new();
-        if let Some(tree) = self.layer.tree() {
-            let mut cur = tree.walk();
-            loop {
-                let mut relevant = false;
-                let mut moved = false;
-                // TODO should this be an || ?
-                if cur.node().start_byte() < line_end && cur.node().end_byte() > line_start {
-                    let start_byte = max(line_start, cur.node().start_byte()) - line_start;
-                    let end_byte = min(line_end, cur.node().end_byte()) - line_start;
-
-                    if let Some(capture) = self.layer.capture_from_node(cur.node().id()) {
-                        if let Some(attrs) = theme.attributes(capture) {
-                            if let Some(fg) = attrs.fg {
-                                spans.push(AttrSpan {
-                                    start_idx: start_byte,
-                                    end_idx: end_byte,
-                                    attr: Attr::ForegroundColor(fg),
-                                });
-                            }
-                            if let Some(bg) = attrs.bg {
-                                spans.push(AttrSpan {
-                                    start_idx: start_byte,
-                                    end_idx: end_byte,
-                                    attr: Attr::BackgroundColor(bg),
-                                });
-                            }
-                        }
+        for span in self.highlighter.line_highlights(line_idx) {
+            let (start, end) = (snap(span.start), snap(span.end));
+            if start >= end {
+                continue;
+            }
+            if let Some(attrs) = theme.attributes(span.capture) {
+                spans.extend(attrs.spans(start, end));
+            }
+        }
+
+        // Brackets by how deeply they're nested, and the pair around the
+        // caret, going by the last parse
+        if let Some(tree) = self.highlighter.tree() {
+            if !theme.rainbow.is_empty() {
+                let bytes = line_start..line_start + line.len_bytes();
+                for (range, depth) in language::bracket_depths(tree, rope, bytes) {
+                    if let Some(attrs) = theme.rainbow(depth) {
+                        spans.extend(attrs.spans(range.start - line_start, range.end - line_start));
                     }
-                    relevant = true;
-                }
-                if relevant {
-                    moved = cur.goto_first_child()
-                }
-                if !moved {
-                    moved = cur.goto_next_sibling();
-                }
-                if !moved {
-                    moved = cur.goto_parent() && cur.goto_next_sibling();
-                }
-                if !moved {
-                    break;
                 }
             }
+            let caret = self
+                .selections
+                .get(&view_id)
+                .and_then(|s| s.sels.last())
+                .map(|sel| self.char_to_byte(sel.cursor()));
+            let block = caret
+                .filter(|_| !theme.enclosing_block.attrs().is_empty())
+                .and_then(|byte| language::enclosing_pair(tree, rope, byte));
+            if let Some(block) = block.filter(|b| b.start < line_end && b.end > line_start) {
+                let start = max(line_start, block.start) - line_start;
+                let end = min(line_end, block.end) - line_start;
+                spans.extend(theme.enclosing_block.spans(start, end));
+            }
         }
 
         for sel in self
@@ -1397,21 +2127,7 @@ impl Buffer {
                 if sel_min_byte < line_end && sel_max_byte > line_start {
                     let start_byte = max(line_start, sel_min_byte) - line_start;
                     let end_byte = min(line_end, sel_max_byte) - line_start;
-                    let attrs = theme.selection;
-                    if let Some(fg) = attrs.fg {
-                        spans.push(AttrSpan {
-                            start_idx: start_byte,
-                            end_idx: end_byte,
-                            attr: Attr::ForegroundColor(fg),
-                        });
-                    }
-                    if let Some(bg) = attrs.bg {
-                        spans.push(AttrSpan {
-                            start_idx: start_byte,
-                            end_idx: end_byte,
-                            attr: Attr::BackgroundColor(bg),
-                        });
-                    }
+                    spans.extend(theme.selection.spans(start_byte, end_byte));
                 }
             }
         }
@@ -1419,27 +2135,144 @@ impl Buffer {
         Some((line, spans))
     }
 
-    pub fn save(&mut self) -> Result<(), anyhow::Error> {
-        if let Some(ref path) = self.path {
-            let mut file = File::create(path)?;
-            let rope = &self.rope;
-            rope.write_to(&mut file)?;
-        } else {
-            bail!("cannot save, no known file path");
-        }
-        self.set_pristine(true);
+    /// Saves the buffer to its path
+    pub fn save(&mut self, opts: &SaveOptions) -> Result<(), SaveError> {
+        let path = self.path.clone().ok_or(SaveError::NoPath)?;
+        files::save_rope(&self.rope, &path, opts)?;
+        self.mark_saved();
         Ok(())
     }
 
-    pub fn save_as(&mut self, path: &Path) -> Result<(), io::Error> {
-        let mut file = File::create(path)?;
-        let rope = &self.rope;
-        rope.write_to(&mut file)?;
+    /// Saves the buffer to a new path, which becomes the buffer's path
+    pub fn save_as(&mut self, path: &Path, opts: &SaveOptions) -> Result<(), SaveError> {
+        files::save_rope(&self.rope, path, opts)?;
 
         self.path = Some(path.into());
-        self.set_pristine(true);
+        self.recovery_key = RecoveryKey::Path(path.into());
+        self.mark_saved();
+
+        // The new name may say what the language is, unless it was picked
+        if !self.language_picked {
+            let config = language::detect_language(Some(path), &self.rope);
+            if config.map(|c| c.name) != self.language() {
+                self.highlighter.set_layer(language::layer_for(config));
+                self.on_text_change();
+            }
+        }
         Ok(())
     }
+
+    fn mark_saved(&mut self) {
+        self.saved = self.rope.clone();
+        self.disk_stat = self.path.as_deref().and_then(FileStat::from_path);
+        self.external_change = None;
+        self.set_pristine(true);
+    }
+
+    /// The file metadata recorded when the buffer was last loaded or saved
+    pub fn disk_stat(&self) -> Option<FileStat> {
+        self.disk_stat
+    }
+
+    /// Returns true if `text` is what this buffer last loaded or saved
+    pub fn is_saved_text(&self, text: &Rope) -> bool {
+        self.saved == *text
+    }
+
+    /// Replaces the whole text of the buffer as a single undoable change.
+    /// Only the lines that differ are touched, so selections in the
+    /// unchanged parts stay where they were.  Undoing it restores the
+    /// selections of `view_id`.
+    pub fn replace_text(&mut self, view_id: ViewId, text: &Rope) {
+        let old = self.rope.to_string();
+        let new = text.to_string();
+        let old_lines = diff::split_lines(&old);
+        let new_lines = diff::split_lines(&new);
+
+        let mut line_starts = Vec::with_capacity(old_lines.len() + 1);
+        let mut char_idx = 0;
+        line_starts.push(0);
+        for line in &old_lines {
+            char_idx += line.chars().count();
+            line_starts.push(char_idx);
+        }
+
+        let sels_before = self.selections(view_id);
+
+        for hunk in diff::diff(&old_lines, &new_lines).into_iter().rev() {
+            let start = line_starts[hunk.old.start];
+            let end = line_starts[hunk.old.end];
+            self.remove(Range { start, end });
+            self.insert_at_raw(start, &new_lines[hunk.new].concat());
+        }
+
+        let sels_after = self.selections(view_id);
+        self.history.new_change(&self.rope, sels_before, sels_after);
+        self.on_text_change();
+    }
+
+    /// Records the file metadata last seen for the buffer's file
+    pub fn set_disk_stat(&mut self, stat: Option<FileStat>) {
+        self.disk_stat = stat;
+    }
+
+    /// Replaces the buffer's text with what was just read from its file,
+    /// along with the file's metadata at the time
+    pub fn reload(&mut self, view_id: ViewId, text: Rope, stat: Option<FileStat>) {
+        self.replace_text(view_id, &text);
+        self.saved = text;
+        self.disk_stat = stat;
+        self.external_change = None;
+        self.set_pristine(true);
+    }
+
+    /// Notes that the buffer's file was deleted.  The buffer now holds the
+    /// only copy of its text, so it counts as edited and gets journaled.
+    pub fn mark_deleted(&mut self) {
+        self.disk_stat = None;
+        self.external_change = Some(ExternalChange::Deleted);
+        self.set_pristine(false);
+    }
+
+    /// Resolves a pending external change.  Returns the number of merge
+    /// conflicts that were written into the buffer.
+    pub fn resolve_external_change(
+        &mut self,
+        view_id: ViewId,
+        resolution: ConflictResolution,
+    ) -> usize {
+        let Some(change) = self.external_change.take() else {
+            return 0;
+        };
+        match (change, resolution) {
+            (ExternalChange::Modified(theirs), ConflictResolution::TakeTheirs) => {
+                self.reload(view_id, theirs, self.disk_stat);
+                0
+            }
+            (ExternalChange::Modified(theirs), ConflictResolution::KeepMine) => {
+                // Whatever is on disk is now the base for later merges
+                self.saved = theirs;
+                self.set_pristine(false);
+                0
+            }
+            (ExternalChange::Modified(theirs), ConflictResolution::Merge) => {
+                let merge = diff::merge3(
+                    &self.saved.to_string(),
+                    &self.rope.to_string(),
+                    &theirs.to_string(),
+                );
+                self.replace_text(view_id, &Rope::from_str(&merge.text));
+                self.saved = theirs;
+                self.set_pristine(false);
+                merge.conflicts
+            }
+            (ExternalChange::Deleted, _) => {
+                self.disk_stat = None;
+                self.set_pristine(false);
+                0
+            }
+        }
+    }
 }
 
 impl fmt::Display for Buffer {
@@ -1476,6 +2309,35 @@ impl Iterator for GraphemeIterator {
 }
 */
 
+/// Moves bookmarks and anchors along with text that was swapped out all at
+/// once, like by an undo, as if the chars between the parts the old and the
+/// new text have in common were replaced
+fn shift_marks(old: &Rope, new: &Rope, bookmarks: &mut Bookmarks, anchors: &mut Anchors) {
+    let prefix = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let most = old.len_chars().min(new.len_chars()) - prefix;
+    let (mut a, mut b) = (old.chars_at(old.len_chars()), new.chars_at(new.len_chars()));
+    let mut suffix = 0;
+    while suffix < most {
+        match (a.prev(), b.prev()) {
+            (Some(x), Some(y)) if x == y => suffix += 1,
+            _ => break,
+        }
+    }
+    let removed = Range {
+        start: prefix,
+        end: old.len_chars() - suffix,
+    };
+    let inserted = new.len_chars() - suffix - prefix;
+    bookmarks.on_remove(removed);
+    anchors.on_remove(removed);
+    bookmarks.on_insert(prefix, inserted);
+    anchors.on_insert(prefix, inserted);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1689,4 +2551,99 @@ mod tests {
             }]
         );
     }
+    #[test]
+    fn test_replace_text_keeps_selections() {
+        let mut buf = Buffer::new(0);
+        buf.init_view(0);
+        buf.insert(0, "a\nb\nc");
+        buf.move_up(0);
+        buf.replace_text(0, &Rope::from_str("x\na\nb\nc"));
+        buf.insert(0, "_");
+        assert_eq!(buf.to_string(), "x\na\nb_\nc");
+
+        // Undoing it puts back the selections of the view it was made for
+        buf.init_view(1);
+        buf.move_to_end_of_document(1);
+        buf.move_to_beginning_of_document(0);
+        buf.replace_text(1, &Rope::from_str("y\n"));
+        buf.undo(1);
+        assert_eq!(buf.selections(1)[0].cursor(), 8);
+    }
+    #[test]
+    fn test_resolve_external_change_merge() {
+        let mut buf = Buffer::new(0);
+        buf.init_view(0);
+        buf.reload(0, Rope::from_str("a\nb\nc\n"), None);
+        buf.move_to_beginning_of_document(0);
+        buf.insert(0, "mine ");
+        buf.external_change = Some(ExternalChange::Modified(Rope::from_str("a\nb\ntheirs\n")));
+        assert_eq!(buf.resolve_external_change(0, ConflictResolution::Merge), 0);
+        assert_eq!(buf.to_string(), "mine a\nb\ntheirs\n");
+        assert!(buf.is_saved_text(&Rope::from_str("a\nb\ntheirs\n")));
+    }
+    #[test]
+    fn test_marks_follow_undo() {
+        let mut buf = Buffer::new(0);
+        buf.init_view(0);
+        buf.insert(0, "abc");
+        buf.set_bookmark(0, "end");
+        let anchor = buf.add_anchor(3);
+        buf.move_to_beginning_of_document(0);
+        buf.insert(0, "xy");
+        assert_eq!(buf.anchor(anchor), Some(5));
+        buf.undo(0);
+        assert_eq!(buf.to_string(), "abc");
+        assert_eq!(buf.anchor(anchor), Some(3));
+        assert_eq!(buf.bookmarks().iter().next().unwrap().pos, 3);
+        buf.redo(0);
+        assert_eq!(buf.anchor(anchor), Some(5));
+        assert_eq!(buf.bookmarks().iter().next().unwrap().pos, 5);
+    }
+    #[test]
+    fn test_paste_distributes_across_carets() {
+        let mut buf = Buffer::new(0);
+        buf.init_view(0);
+        buf.insert(0, "a\nb\n");
+        buf.replace_selections(
+            0,
+            &[
+                Selection {
+                    start: 1,
+                    end: 1,
+                    horiz: None,
+                },
+                Selection {
+                    start: 3,
+                    end: 3,
+                    horiz: None,
+                },
+            ],
+        );
+        buf.paste(0, &ClipboardEntry::from_text("1\n2"));
+        assert_eq!(buf.to_string(), "a1\nb2\n");
+        buf.paste(0, &ClipboardEntry::from_text("x"));
+        assert_eq!(buf.to_string(), "a1x\nb2x\n");
+    }
+    #[test]
+    fn test_cut_and_paste_whole_line() {
+        let mut buf = Buffer::new(0);
+        buf.init_view(0);
+        buf.insert(0, "one\ntwo\nthree");
+        buf.move_up(0);
+        let entry = buf.cut(0).unwrap();
+        assert!(entry.linewise);
+        assert_eq!(entry.text(), "two\n");
+        assert_eq!(buf.to_string(), "one\nthree");
+        buf.move_to_beginning_of_document(0);
+        buf.paste(0, &entry);
+        assert_eq!(buf.to_string(), "two\none\nthree");
+
+        // Cutting the last line takes the line break before it
+        buf.move_to_end_of_document(0);
+        let entry = buf.cut(0).unwrap();
+        assert_eq!(entry.text(), "three\n");
+        assert_eq!(buf.to_string(), "two\none");
+        buf.undo(0);
+        assert_eq!(buf.to_string(), "two\none\nthree");
+    }
 }
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Text that was cut or copied, along with the shape it was copied in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipboardEntry {
+    /// One piece of text per selection it was copied from
+    pub chunks: Vec<String>,
+    /// Whole lines were copied from empty selections.  Every chunk ends in a
+    /// newline, and pasting puts them above the caret's line.
+    pub linewise: bool,
+}
+
+impl ClipboardEntry {
+    /// An entry for text that came from somewhere else, like another program
+    pub fn from_text(text: &str) -> Self {
+        Self {
+            chunks: vec![text.to_string()],
+            linewise: false,
+        }
+    }
+
+    /// This entry with another one's text added to the end, piece by piece
+    /// if they have as many pieces
+    pub fn append(&self, other: &ClipboardEntry) -> ClipboardEntry {
+        let linewise = self.linewise || other.linewise;
+        let join = |a: &str, b: &str| {
+            let mut text = a.to_string();
+            if linewise && !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str(b);
+            if linewise && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text
+        };
+        let chunks = if self.chunks.len() == other.chunks.len() {
+            self.chunks
+                .iter()
+                .zip(&other.chunks)
+                .map(|(a, b)| join(a, b))
+                .collect()
+        } else {
+            vec![join(&self.text(), &other.text())]
+        };
+        ClipboardEntry { chunks, linewise }
+    }
+
+    /// The text as it's put on the system clipboard
+    pub fn text(&self) -> String {
+        if self.linewise {
+            self.chunks.concat()
+        } else {
+            self.chunks.join("\n")
+        }
+    }
+
+    /// Splits the entry into one piece per caret, if it has exactly that
+    /// many chunks, or that many lines
+    pub fn pieces(&self, carets: usize) -> Option<Vec<&str>> {
+        if carets < 2 {
+            return None;
+        }
+        if self.chunks.len() == carets {
+            return Some(self.chunks.iter().map(String::as_str).collect());
+        }
+        match self.chunks.as_slice() {
+            [text] if !self.linewise => {
+                let lines: Vec<&str> = text.lines().collect();
+                (lines.len() == carets).then_some(lines)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How many entries a clipboard history keeps by default
+pub const DEFAULT_HISTORY_SIZE: usize = 32;
+
+/// The most recent cuts and copies of a window, newest first, like an Emacs
+/// kill ring
+#[derive(Debug, Clone)]
+pub struct ClipboardHistory {
+    entries: VecDeque<ClipboardEntry>,
+    capacity: usize,
+}
+
+impl Default for ClipboardHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+impl ClipboardHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records a new entry.  An entry equal to one already in the history is
+    /// moved to the front instead of being stored twice.
+    pub fn push(&mut self, entry: ClipboardEntry) {
+        self.entries.retain(|e| *e != entry);
+        self.entries.push_front(entry);
+        self.entries.truncate(self.capacity);
+    }
+
+    /// Replaces the most recent entry, as when a kill is added to
+    pub fn replace_latest(&mut self, entry: ClipboardEntry) {
+        self.entries.pop_front();
+        self.push(entry);
+    }
+
+    /// The most recent entry
+    pub fn latest(&self) -> Option<&ClipboardEntry> {
+        self.entries.front()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ClipboardEntry> {
+        self.entries.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries from newest to oldest
+    pub fn iter(&self) -> impl Iterator<Item = &ClipboardEntry> {
+        self.entries.iter()
+    }
+
+    /// Replaces the history, as when restoring a session
+    pub fn set_entries(&mut self, entries: Vec<ClipboardEntry>) {
+        self.entries = entries.into();
+        self.entries.truncate(self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_is_bounded_and_deduplicated() {
+        let mut history = ClipboardHistory::new(2);
+        history.push(ClipboardEntry::from_text("a"));
+        history.push(ClipboardEntry::from_text("b"));
+        history.push(ClipboardEntry::from_text("a"));
+        history.push(ClipboardEntry::from_text("c"));
+        let texts: Vec<String> = history.iter().map(|e| e.text()).collect();
+        assert_eq!(texts, vec!["c", "a"]);
+    }
+
+    #[test]
+    fn test_append() {
+        let a = ClipboardEntry::from_text("foo");
+        let b = ClipboardEntry::from_text("\n");
+        assert_eq!(a.append(&b).text(), "foo\n");
+
+        let lines = ClipboardEntry {
+            chunks: vec!["x\n".into()],
+            linewise: true,
+        };
+        assert_eq!(a.append(&lines).chunks, vec!["foo\nx\n"]);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{ConflictResolution, ViewId};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Everything that can be done to a view.  Commands serialize to short names
+/// like `"move_left"` or `{ insert = "x" }`, so keymaps, macros and scripts
+/// can refer to them, and `Window::execute` carries them out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    // Editing
+    Insert(String),
+    InsertNewline,
+    InsertTab,
+    /// Insert a snippet in the LSP/TextMate syntax in place of every
+    /// selection
+    InsertSnippet(String),
+    /// Expand the snippet whose prefix is before the caret, or else insert
+    /// a tab
+    ExpandSnippet,
+    /// Go to the next tab stop of the snippet being filled in
+    NextTabStop,
+    PreviousTabStop,
+    /// Swap the current choice tab stop for its next option
+    NextSnippetChoice,
+    DeleteForward,
+    DeleteBackward,
+    Cut,
+    Copy,
+    /// Paste text from the system clipboard
+    Paste(String),
+    /// Ask the frontend for the system clipboard, which it pastes with
+    /// `Paste`
+    PasteSystemClipboard,
+    /// Paste an entry from the clipboard history, newest first
+    PasteFromHistory(usize),
+    /// Let the user pick a clipboard history entry to paste
+    ShowClipboardHistory,
+    YankPop,
+    /// Cut to the end of the line.  Kills in a row add to the same
+    /// clipboard entry.
+    KillLine,
+    Undo,
+    Redo,
+
+    // Motion
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveWordLeft,
+    MoveWordRight,
+    MoveToLeftEndOfLine,
+    MoveToRightEndOfLine,
+    MoveToBeginningOfDocument,
+    MoveToEndOfDocument,
+    /// Move up by the number of lines the view shows
+    PageUp,
+    /// Move down by the number of lines the view shows
+    PageDown,
+    /// Put a single caret at the start of a line, counting from 1
+    GoToLine(usize),
+
+    // Selection
+    MoveLeftAndModifySelection,
+    MoveRightAndModifySelection,
+    MoveUpAndModifySelection,
+    MoveDownAndModifySelection,
+    MoveWordLeftAndModifySelection,
+    MoveWordRightAndModifySelection,
+    MoveToLeftEndOfLineAndModifySelection,
+    MoveToRightEndOfLineAndModifySelection,
+    MoveToBeginningOfDocumentAndModifySelection,
+    MoveToEndOfDocumentAndModifySelection,
+    PageUpAndModifySelection,
+    PageDownAndModifySelection,
+    SelectAll,
+    /// Start a selection at the caret that motions extend, like Emacs' mark
+    SetMark,
+    /// Swap the ends of each selection
+    ExchangePointAndMark,
+    /// Cancel a search, a selection or a pending argument
+    KeyboardQuit,
+    /// Place a single caret at a line and byte offset within the line
+    PointSelect {
+        line: usize,
+        byte: usize,
+    },
+    /// Extend the last selection to a line and byte offset
+    RangeSelect {
+        line: usize,
+        byte: usize,
+    },
+    /// Add a caret, or remove the selection there is one there already
+    ToggleSelect {
+        line: usize,
+        byte: usize,
+    },
+    WordSelect {
+        line: usize,
+        byte: usize,
+    },
+    LineSelect {
+        line: usize,
+    },
+    DragUpdate {
+        line: usize,
+        byte: usize,
+    },
+    DragEnd,
+
+    // Search
+    /// Search forward as the query is typed, or go to the next match
+    IsearchForward,
+    /// Search backward as the query is typed, or go to the previous match
+    IsearchBackward,
+
+    // Bookmarks
+    /// Bookmark each caret's line, or remove the bookmarks on it
+    ToggleBookmark,
+    /// Bookmark the caret under a name
+    SetBookmark(String),
+    NextBookmark,
+    PreviousBookmark,
+    /// Let the user pick a bookmark from all the window's buffers, and go
+    /// to it
+    ShowBookmarks,
+    /// Let the user pick a function, type or the like from the buffer's
+    /// outline, and go to it
+    ShowSymbols,
+
+    /// Treat the buffer as being in a language, by its name, or as plain
+    /// text
+    SetLanguage(String),
+
+    /// Go back to where the caret was before the last jump, like going to
+    /// a line or a search match
+    JumpBack,
+    /// Go forward again to where `JumpBack` came from
+    JumpForward,
+
+    /// Repeat the next command four times, or as many times as the digits
+    /// typed after it say
+    UniversalArgument,
+
+    // Files
+    /// Ask the frontend for a file to open in a new view
+    OpenFile,
+    /// Let the user find a file in the projects by name, and open it
+    QuickOpen,
+    Save,
+    SaveAs(PathBuf),
+    ResolveExternalChange(ConflictResolution),
+
+    // Views
+    /// Let the user pick a command to run from a list
+    ShowCommandPalette,
+    /// Open a new view, on a file or an untitled buffer
+    NewView(Option<PathBuf>),
+    CloseView,
+    FocusView,
+}
+
+/// What came of executing a command, beyond its effect on the window
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CommandOutput {
+    #[default]
+    None,
+    /// Text that should go on the system clipboard
+    Clipboard(String),
+    /// A view that was opened
+    View(ViewId),
+    /// The number of merge conflicts left in the buffer
+    Conflicts(usize),
+    /// Something only the frontend can do
+    Ui(UiRequest),
+}
+
+/// Commands that need the frontend's help to finish
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UiRequest {
+    /// Read the system clipboard and execute `Command::Paste` with it
+    ReadClipboard,
+    /// Show the clipboard history and execute `Command::PasteFromHistory`
+    /// with the picked entry
+    ClipboardHistory,
+    /// Let the user pick a file and execute `Command::NewView` with it
+    OpenFile,
+    /// Show the command palette, and execute the command picked from it
+    CommandPalette,
+    /// Show quick open, and open the file picked with
+    /// `Window::quick_open_file`
+    QuickOpen,
+    /// List the bookmarks, and go to the one picked with
+    /// `Window::go_to_bookmark`
+    Bookmarks,
+    /// Show the outline of the buffer, and go to the symbol picked with
+    /// `Window::go_to_symbol`
+    Symbols,
+}
+
+impl From<Option<String>> for CommandOutput {
+    fn from(text: Option<String>) -> Self {
+        text.map(CommandOutput::Clipboard).unwrap_or_default()
+    }
+}
+
+impl Command {
+    /// Returns true if the command changes the text of the buffer
+    pub fn is_edit(&self) -> bool {
+        use Command::*;
+        matches!(
+            self,
+            Insert(_)
+                | InsertNewline
+                | InsertTab
+                | InsertSnippet(_)
+                | ExpandSnippet
+                | NextSnippetChoice
+                | DeleteForward
+                | DeleteBackward
+                | Cut
+                | Paste(_)
+                | PasteFromHistory(_)
+                | YankPop
+                | KillLine
+                | Undo
+                | Redo
+                | ResolveExternalChange(_)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_serde() {
+        let cmds = vec![
+            Command::MoveLeft,
+            Command::Insert("x".into()),
+            Command::PointSelect { line: 1, byte: 2 },
+        ];
+        let json = serde_json::to_string(&cmds).unwrap();
+        assert_eq!(
+            json,
+            r#"["move_left",{"insert":"x"},{"point_select":{"line":1,"byte":2}}]"#
+        );
+        let back: Vec<Command> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, cmds);
+    }
+}
@@ -0,0 +1,279 @@
+use std::ops::Range;
+
+/// A region where two sequences differ.  `old` and `new` are line ranges into
+/// the old and new sequences.  One of them may be empty, for a pure insertion
+/// or deletion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Splits text into lines, keeping the line endings attached
+pub fn split_lines(s: &str) -> Vec<&str> {
+    s.split_inclusive('\n').collect()
+}
+
+/// Finds the pairs of equal elements in a longest common subsequence of `a`
+/// and `b`, using Myers' O(ND) algorithm.  The pairs are in increasing order.
+pub fn matches<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    // Strip the common prefix and suffix, which is almost everything in
+    // typical edits, before running the real algorithm.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut ret: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    ret.extend(
+        myers(a_mid, b_mid)
+            .into_iter()
+            .map(|(i, j)| (i + prefix, j + prefix)),
+    );
+    ret.extend((0..suffix).map(|i| (a.len() - suffix + i, b.len() - suffix + i)));
+    ret
+}
+
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = (n + m) as usize;
+    if max == 0 {
+        return Vec::new();
+    }
+
+    let offset = max as isize;
+    let mut v = vec![0isize; 2 * max + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'outer: for d in 0..=max as isize {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk the trace backwards to recover the matching diagonals
+    let mut ret = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 {
+            0
+        } else {
+            v[(prev_k + offset) as usize]
+        };
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ret.push((x as usize, y as usize));
+        }
+        if d > 0 {
+            x = prev_x;
+            y = prev_y;
+        }
+    }
+    ret.reverse();
+    ret
+}
+
+/// Computes the hunks that turn `a` into `b`
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (mi, mj) in matches(a, b)
+        .into_iter()
+        .chain(std::iter::once((a.len(), b.len())))
+    {
+        if mi > i || mj > j {
+            hunks.push(Hunk {
+                old: i..mi,
+                new: j..mj,
+            });
+        }
+        i = mi + 1;
+        j = mj + 1;
+    }
+    hunks
+}
+
+/// Produces a unified-style line diff of two texts, for showing to the user
+pub fn unified_diff(old: &str, new: &str) -> String {
+    let a = split_lines(old);
+    let b = split_lines(new);
+    let mut out = String::new();
+    for h in diff(&a, &b) {
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            h.old.start + 1,
+            h.old.len(),
+            h.new.start + 1,
+            h.new.len()
+        ));
+        for (prefix, lines) in [('-', &a[h.old]), ('+', &b[h.new])] {
+            for line in lines {
+                out.push(prefix);
+                out.push_str(line);
+                if !line.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The result of a three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    pub text: String,
+    /// The number of regions changed differently on both sides.  They are
+    /// written out between conflict markers.
+    pub conflicts: usize,
+}
+
+/// Merges the changes made in `mine` and in `theirs`, relative to their
+/// common ancestor `base`, line by line in the manner of diff3.
+pub fn merge3(base: &str, mine: &str, theirs: &str) -> Merge {
+    let o = split_lines(base);
+    let a = split_lines(mine);
+    let b = split_lines(theirs);
+
+    let mut ma = vec![None; o.len()];
+    for (i, j) in matches(&o, &a) {
+        ma[i] = Some(j);
+    }
+    let mut mb = vec![None; o.len()];
+    for (i, j) in matches(&o, &b) {
+        mb[i] = Some(j);
+    }
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let (mut lo, mut la, mut lb) = (0, 0, 0);
+    loop {
+        // Copy lines that are the same in all three
+        let mut i = 0;
+        while lo + i < o.len() && ma[lo + i] == Some(la + i) && mb[lo + i] == Some(lb + i) {
+            text.push_str(o[lo + i]);
+            i += 1;
+        }
+        lo += i;
+        la += i;
+        lb += i;
+
+        // Find the next base line that both sides kept
+        let next = (lo..o.len()).find_map(|j| Some((j, ma[j]?, mb[j]?)));
+        let (eo, ea, eb) = next.unwrap_or((o.len(), a.len(), b.len()));
+        let (co, ca, cb) = (&o[lo..eo], &a[la..ea], &b[lb..eb]);
+
+        if ca == co {
+            cb.iter().for_each(|l| text.push_str(l));
+        } else if cb == co || ca == cb {
+            ca.iter().for_each(|l| text.push_str(l));
+        } else {
+            conflicts += 1;
+            push_conflict(&mut text, "<<<<<<< mine\n", ca);
+            push_conflict(&mut text, "=======\n", cb);
+            text.push_str(">>>>>>> theirs\n");
+        }
+
+        if next.is_none() {
+            break;
+        }
+        lo = eo;
+        la = ea;
+        lb = eb;
+    }
+
+    Merge { text, conflicts }
+}
+
+fn push_conflict(text: &mut String, marker: &str, lines: &[&str]) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(marker);
+    for line in lines {
+        text.push_str(line);
+    }
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let a = ["a", "b", "c", "d"];
+        let b = ["a", "x", "c", "d", "e"];
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                Hunk {
+                    old: 1..2,
+                    new: 1..2
+                },
+                Hunk {
+                    old: 4..4,
+                    new: 4..5
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge3_clean() {
+        let base = "a\nb\nc\nd\n";
+        let mine = "a\nB\nc\nd\n";
+        let theirs = "a\nb\nc\nD\n";
+        let m = merge3(base, mine, theirs);
+        assert_eq!(m.text, "a\nB\nc\nD\n");
+        assert_eq!(m.conflicts, 0);
+    }
+
+    #[test]
+    fn test_merge3_conflict() {
+        let base = "a\nb\nc\n";
+        let mine = "a\nmine\nc\n";
+        let theirs = "a\ntheirs\nc\n";
+        let m = merge3(base, mine, theirs);
+        assert_eq!(
+            m.text,
+            "a\n<<<<<<< mine\nmine\n=======\ntheirs\n>>>>>>> theirs\nc\n"
+        );
+        assert_eq!(m.conflicts, 1);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::keymap::KeyChord;
+use crate::{Buffer, Command, Selection, ViewId};
+use ropey::Rope;
+use std::cmp::min;
+
+/// An incremental search in progress
+#[derive(Debug, Clone)]
+struct Isearch {
+    query: String,
+    forward: bool,
+    /// The selections from before the search, to go back to if it's
+    /// cancelled
+    origin: Vec<Selection>,
+    /// Where the current match starts, or where the search started
+    pos: usize,
+    /// Nothing matches the query from `pos` on
+    failing: bool,
+}
+
+/// The state of Emacs emulation in one view
+#[derive(Debug, Clone, Default)]
+pub struct Emacs {
+    /// The count given with C-u, and whether digits were typed after it
+    argument: Option<(usize, bool)>,
+    /// Motions extend the selection from the mark
+    mark_active: bool,
+    isearch: Option<Isearch>,
+    /// What was searched for last, for C-s straight after C-s
+    last_query: String,
+    message: Option<String>,
+}
+
+/// The selecting version of a motion command
+fn extend_selection(cmd: &Command) -> Option<Command> {
+    use Command::*;
+    Some(match cmd {
+        MoveLeft => MoveLeftAndModifySelection,
+        MoveRight => MoveRightAndModifySelection,
+        MoveUp => MoveUpAndModifySelection,
+        MoveDown => MoveDownAndModifySelection,
+        MoveWordLeft => MoveWordLeftAndModifySelection,
+        MoveWordRight => MoveWordRightAndModifySelection,
+        MoveToLeftEndOfLine => MoveToLeftEndOfLineAndModifySelection,
+        MoveToRightEndOfLine => MoveToRightEndOfLineAndModifySelection,
+        MoveToBeginningOfDocument => MoveToBeginningOfDocumentAndModifySelection,
+        MoveToEndOfDocument => MoveToEndOfDocumentAndModifySelection,
+        PageUp => PageUpAndModifySelection,
+        PageDown => PageDownAndModifySelection,
+        _ => return None,
+    })
+}
+
+/// Finds where `query` next occurs at or after `from`, or at or before it
+/// going backward.  Like Emacs, a query without capitals ignores case.
+fn find(rope: &Rope, query: &str, from: usize, forward: bool) -> Option<usize> {
+    let fold = !query.chars().any(char::is_uppercase);
+    let query: Vec<char> = query.chars().collect();
+    let len = rope.len_chars();
+    if query.is_empty() || query.len() > len {
+        return None;
+    }
+    let last = len - query.len();
+    let matches_at = |i: usize| {
+        rope.chars_at(i).zip(&query).all(|(a, &b)| {
+            if fold {
+                a.to_lowercase().eq(b.to_lowercase())
+            } else {
+                a == b
+            }
+        })
+    };
+    if forward {
+        (from..=last).find(|&i| matches_at(i))
+    } else {
+        (0..=min(from, last)).rev().find(|&i| matches_at(i))
+    }
+}
+
+impl Emacs {
+    pub fn is_searching(&self) -> bool {
+        self.isearch.is_some()
+    }
+
+    /// Where the caret was when the search started
+    pub fn search_origin(&self) -> Option<usize> {
+        self.isearch
+            .as_ref()
+            .and_then(|s| s.origin.first())
+            .map(Selection::cursor)
+    }
+
+    /// What a status line should show: the search, the argument being
+    /// typed, or a message
+    pub fn status(&self) -> String {
+        if let Some(search) = &self.isearch {
+            return format!(
+                "{}I-search{}: {}",
+                if search.failing { "Failing " } else { "" },
+                if search.forward { "" } else { " backward" },
+                search.query
+            );
+        }
+        if let Some((n, _)) = self.argument {
+            return format!("C-u {n}-");
+        }
+        self.message.clone().unwrap_or_default()
+    }
+
+    /// Sees a key before the keymap does, to type into a search or an
+    /// argument.  Returns true if the key was used up.
+    pub fn press(&mut self, buf: &mut Buffer, view_id: ViewId, chord: &KeyChord) -> bool {
+        self.message = None;
+        let text = chord.text();
+        if let Some(search) = &mut self.isearch {
+            match (chord.key.as_str(), text) {
+                (_, Some(text)) => search.query.push_str(&text),
+                ("backspace", _) if chord.is_plain() => {
+                    search.query.pop();
+                    search.pos = search.origin.first().map(|s| s.cursor()).unwrap_or(0);
+                }
+                ("return" | "escape", _) if chord.is_plain() => {
+                    self.end_search(buf, view_id);
+                    return true;
+                }
+                _ => return false,
+            }
+            self.search(buf, view_id, false);
+            return true;
+        }
+        if let (Some((value, digits)), Some(digit)) = (
+            &mut self.argument,
+            text.and_then(|t| t.parse::<usize>().ok()),
+        ) {
+            *value = if *digits {
+                value.saturating_mul(10).saturating_add(digit)
+            } else {
+                digit
+            };
+            *digits = true;
+            return true;
+        }
+        false
+    }
+
+    /// Takes the count given with C-u, for the command after it
+    pub fn take_argument(&mut self) -> Option<usize> {
+        self.argument.take().map(|(n, _)| n)
+    }
+
+    /// Lets Emacs react to a command about to run in its view.  While the
+    /// mark is active, motions extend the selection.  Anything else
+    /// deactivates the mark, and ends a search.
+    pub fn before_command(&mut self, buf: &mut Buffer, view_id: ViewId, cmd: Command) -> Command {
+        use Command::*;
+        if !matches!(cmd, IsearchForward | IsearchBackward | KeyboardQuit) {
+            self.end_search(buf, view_id);
+        }
+        if matches!(
+            cmd,
+            UniversalArgument | SetMark | ExchangePointAndMark | KeyboardQuit
+        ) {
+            return cmd;
+        }
+        if self.mark_active {
+            if let Some(extended) = extend_selection(&cmd) {
+                return extended;
+            }
+            self.mark_active = false;
+        }
+        cmd
+    }
+
+    pub fn universal_argument(&mut self) {
+        self.argument = Some(match self.argument {
+            Some((n, false)) => (n.saturating_mul(4), false),
+            _ => (4, false),
+        });
+    }
+
+    /// Drops the selections to their carets, where motions will extend them
+    /// from
+    pub fn set_mark(&mut self, buf: &mut Buffer, view_id: ViewId) {
+        let sels: Vec<Selection> = buf
+            .selections(view_id)
+            .iter()
+            .map(|s| Selection {
+                start: s.cursor(),
+                end: s.cursor(),
+                horiz: None,
+            })
+            .collect();
+        buf.replace_selections(view_id, &sels);
+        self.mark_active = true;
+        self.message = Some("Mark set".to_string());
+    }
+
+    pub fn exchange_point_and_mark(&mut self, buf: &mut Buffer, view_id: ViewId) {
+        let sels: Vec<Selection> = buf
+            .selections(view_id)
+            .iter()
+            .map(|s| Selection {
+                start: s.end,
+                end: s.start,
+                horiz: None,
+            })
+            .collect();
+        buf.replace_selections(view_id, &sels);
+        self.mark_active = true;
+    }
+
+    /// Cancels a search, going back to where it started, or else drops the
+    /// selections and any argument
+    pub fn keyboard_quit(&mut self, buf: &mut Buffer, view_id: ViewId) {
+        self.argument = None;
+        self.mark_active = false;
+        if let Some(search) = self.isearch.take() {
+            buf.replace_selections(view_id, &search.origin);
+            return;
+        }
+        let sels: Vec<Selection> = buf
+            .selections(view_id)
+            .iter()
+            .map(|s| Selection {
+                start: s.cursor(),
+                end: s.cursor(),
+                horiz: None,
+            })
+            .collect();
+        buf.replace_selections(view_id, &sels);
+        self.message = Some("Quit".to_string());
+    }
+
+    /// Starts searching, or moves on to the next match.  C-s straight after
+    /// C-s searches for the last query again.
+    pub fn isearch(&mut self, buf: &mut Buffer, view_id: ViewId, forward: bool) {
+        self.mark_active = false;
+        let Some(search) = &mut self.isearch else {
+            let origin = buf.selections(view_id);
+            self.isearch = Some(Isearch {
+                query: String::new(),
+                forward,
+                pos: origin.first().map(|s| s.cursor()).unwrap_or(0),
+                origin,
+                failing: false,
+            });
+            return;
+        };
+        if search.query.is_empty() {
+            search.query = self.last_query.clone();
+            search.forward = forward;
+            self.search(buf, view_id, false);
+        } else {
+            search.forward = forward;
+            self.search(buf, view_id, true);
+        }
+    }
+
+    /// Selects the match for the query, starting at the current one, or
+    /// after it with `next`.  After a failed search, `next` wraps around.
+    fn search(&mut self, buf: &mut Buffer, view_id: ViewId, next: bool) {
+        let Some(search) = &mut self.isearch else {
+            return;
+        };
+        if search.query.is_empty() {
+            buf.replace_selections(view_id, &search.origin);
+            search.failing = false;
+            return;
+        }
+        let rope = buf.rope();
+        let from = match (next, search.failing, search.forward) {
+            (false, _, _) => Some(search.pos),
+            (true, true, true) => Some(0),
+            (true, true, false) => Some(rope.len_chars()),
+            (true, false, true) => Some(search.pos + 1),
+            (true, false, false) => search.pos.checked_sub(1),
+        };
+        let found = from.and_then(|from| find(rope, &search.query, from, search.forward));
+        search.failing = found.is_none();
+        if let Some(start) = found {
+            search.pos = start;
+            let end = start + search.query.chars().count();
+            let (start, end) = if search.forward {
+                (start, end)
+            } else {
+                (end, start)
+            };
+            buf.replace_selections(
+                view_id,
+                &[Selection {
+                    start,
+                    end,
+                    horiz: None,
+                }],
+            );
+        }
+        self.last_query = search.query.clone();
+    }
+
+    /// Leaves the caret where the search got to
+    fn end_search(&mut self, buf: &mut Buffer, view_id: ViewId) {
+        if self.isearch.take().is_none() {
+            return;
+        }
+        if let Some(sel) = buf.selections(view_id).first() {
+            let pos = sel.cursor();
+            buf.replace_selections(
+                view_id,
+                &[Selection {
+                    start: pos,
+                    end: pos,
+                    horiz: None,
+                }],
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Range;
+
+    fn setup(text: &str) -> (Emacs, Buffer) {
+        let mut buf = Buffer::new(0);
+        buf.init_view(1);
+        let caret = Selection {
+            start: 0,
+            end: 0,
+            horiz: None,
+        };
+        buf.edit(
+            1,
+            &[(Range { start: 0, end: 0 }, text.to_string())],
+            &[caret],
+        );
+        buf.replace_selections(1, &[caret]);
+        (Emacs::default(), buf)
+    }
+
+    fn type_keys(emacs: &mut Emacs, buf: &mut Buffer, s: &str) {
+        for c in s.chars() {
+            assert!(emacs.press(buf, 1, &KeyChord::new(&c.to_string())));
+        }
+    }
+
+    #[test]
+    fn test_isearch() {
+        let (mut emacs, mut buf) = setup("Foo bar foo baz");
+        emacs.isearch(&mut buf, 1, true);
+        type_keys(&mut emacs, &mut buf, "fo");
+        assert_eq!(buf.selections(1)[0].range(), Range { start: 0, end: 2 });
+        emacs.isearch(&mut buf, 1, true);
+        assert_eq!(buf.selections(1)[0].range(), Range { start: 8, end: 10 });
+        emacs.isearch(&mut buf, 1, true);
+        assert!(emacs.status().starts_with("Failing"));
+        // Going on after a failed search wraps around
+        emacs.isearch(&mut buf, 1, true);
+        assert_eq!(buf.selections(1)[0].range(), Range { start: 0, end: 2 });
+
+        let cmd = emacs.before_command(&mut buf, 1, Command::MoveRight);
+        assert_eq!(cmd, Command::MoveRight);
+        assert!(!emacs.is_searching());
+        assert!(buf.selections(1)[0].is_caret());
+    }
+
+    #[test]
+    fn test_mark_and_argument() {
+        let (mut emacs, mut buf) = setup("hello");
+        emacs.set_mark(&mut buf, 1);
+        assert_eq!(
+            emacs.before_command(&mut buf, 1, Command::MoveRight),
+            Command::MoveRightAndModifySelection
+        );
+        assert_eq!(
+            emacs.before_command(&mut buf, 1, Command::DeleteForward),
+            Command::DeleteForward
+        );
+        assert_eq!(
+            emacs.before_command(&mut buf, 1, Command::MoveRight),
+            Command::MoveRight
+        );
+
+        emacs.universal_argument();
+        emacs.universal_argument();
+        assert_eq!(emacs.status(), "C-u 16-");
+        type_keys(&mut emacs, &mut buf, "12");
+        assert_eq!(emacs.take_argument(), Some(12));
+    }
+}
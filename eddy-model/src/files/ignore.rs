@@ -0,0 +1,213 @@
+use log::error;
+use regex::Regex;
+use std::path::{Component, Path, PathBuf};
+
+/// One line of a `.gitignore` file
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    regex: Regex,
+    /// Starts with `!`, so matching paths are included again
+    negated: bool,
+    /// Ends with `/`, so only directories match
+    dir_only: bool,
+}
+
+/// The patterns of one `.gitignore` file
+#[derive(Debug, Clone)]
+pub struct Gitignore {
+    /// The directory the file is in, relative to the root of the walk
+    base: PathBuf,
+    patterns: Vec<IgnorePattern>,
+}
+
+/// Translates a glob to a regex body.  `*` and `?` don't match `/`, and
+/// `**` between slashes matches any number of directories.
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    Some('/') if at_start => {
+                        re.push_str("(?:.*/)?");
+                        i += 3;
+                    }
+                    None if at_start => {
+                        re.push_str(".*");
+                        i += 2;
+                    }
+                    _ => {
+                        re.push_str("[^/]*");
+                        i += 2;
+                    }
+                }
+                continue;
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(len) if len > 0 => {
+                    let mut class: String = chars[i + 1..i + 1 + len].iter().collect();
+                    if let Some(rest) = class.strip_prefix('!') {
+                        class = format!("^{rest}");
+                    }
+                    re.push('[');
+                    re.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                    re.push(']');
+                    i += len + 2;
+                    continue;
+                }
+                _ => re.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                re.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    re
+}
+
+/// A path relative to the root of the walk, with `/` between components
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Gitignore {
+    /// Parses the text of the `.gitignore` file in `base`.  Lines that don't
+    /// make a valid pattern are logged and skipped.
+    pub fn parse(base: &Path, text: &str) -> Self {
+        let mut patterns = Vec::new();
+        for line in text.lines() {
+            let mut line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // A leading `\` escapes a `!` or `#` that should be matched
+            let negated = line.starts_with('!');
+            if negated || line.starts_with("\\!") || line.starts_with("\\#") {
+                line = &line[1..];
+            }
+            let dir_only = line.ends_with('/');
+            let line = line.trim_end_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+            // A slash anywhere but the end ties the pattern to `base`
+            let anchored = line.contains('/');
+            let body = glob_to_regex(line.trim_start_matches('/'));
+            let re = if anchored {
+                format!("^{body}$")
+            } else {
+                format!("^(?:.*/)?{body}$")
+            };
+            match Regex::new(&re) {
+                Ok(regex) => patterns.push(IgnorePattern {
+                    regex,
+                    negated,
+                    dir_only,
+                }),
+                Err(e) => error!("bad ignore pattern {line:?}: {e}"),
+            }
+        }
+        Self {
+            base: base.to_owned(),
+            patterns,
+        }
+    }
+
+    /// Whether the last pattern matching `path` ignores it (`Some(true)`) or
+    /// includes it again (`Some(false)`).  `None` if nothing matches or the
+    /// path isn't under this file's directory.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel = slash_path(path.strip_prefix(&self.base).ok()?);
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| (is_dir || !p.dir_only) && p.regex.is_match(&rel))
+            .map(|p| !p.negated)
+    }
+}
+
+/// The `.gitignore` files found while walking a directory tree
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    /// Shallowest first, so deeper files can override them
+    files: Vec<Gitignore>,
+}
+
+impl IgnoreRules {
+    pub fn add(&mut self, gitignore: Gitignore) {
+        let depth = gitignore.base.components().count();
+        let at = self
+            .files
+            .iter()
+            .position(|g| g.base.components().count() > depth)
+            .unwrap_or(self.files.len());
+        self.files.insert(at, gitignore);
+    }
+
+    /// Whether a path relative to the root of the walk is ignored, either
+    /// itself or because a directory it's in is.  `.git` always is.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut prefix = PathBuf::new();
+        let mut comps = path.components().peekable();
+        while let Some(comp) = comps.next() {
+            if comp.as_os_str() == ".git" {
+                return true;
+            }
+            prefix.push(comp);
+            let comp_is_dir = is_dir || comps.peek().is_some();
+            let verdict = self
+                .files
+                .iter()
+                .rev()
+                .find_map(|g| g.matched(&prefix, comp_is_dir));
+            if verdict == Some(true) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let mut rules = IgnoreRules::default();
+        rules.add(Gitignore::parse(
+            Path::new(""),
+            "# build output\n/target\n*.log\n!keep.log\nbuild/\ndocs/**/*.html\n",
+        ));
+        rules.add(Gitignore::parse(Path::new("sub"), "*.txt\n!/keep.log\n"));
+
+        let ignored = |p: &str, is_dir| rules.is_ignored(Path::new(p), is_dir);
+        assert!(ignored("target", true));
+        assert!(ignored("target/debug/eddy", false));
+        assert!(!ignored("sub/target", true));
+        assert!(ignored("a/b/error.log", false));
+        assert!(!ignored("keep.log", false));
+        assert!(ignored("build/out.o", false));
+        assert!(!ignored("build", false));
+        assert!(ignored("docs/api/x/index.html", false));
+        assert!(ignored("docs/index.html", false));
+        assert!(!ignored("notes.txt", false));
+        assert!(ignored("sub/deep/notes.txt", false));
+        assert!(ignored(".git/HEAD", false));
+        assert!(!ignored("src/main.rs", false));
+    }
+}
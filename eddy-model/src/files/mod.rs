@@ -1 +1,9 @@
-use std::path::PathBuf;
+mod ignore;
+mod recovery;
+mod save;
+mod watch;
+
+pub use ignore::*;
+pub use recovery::*;
+pub use save::*;
+pub use watch::*;
@@ -0,0 +1,265 @@
+use super::{save_rope, SaveOptions};
+use crate::diff;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "eddy-recovery 1\n";
+const EXTENSION: &str = "swp";
+
+/// What a recovery file is for: a file on disk, or a buffer that was never
+/// saved
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RecoveryKey {
+    Path(PathBuf),
+    Untitled(String),
+}
+
+impl RecoveryKey {
+    /// Creates a key for a new untitled buffer that won't collide with one
+    /// left behind by an earlier session
+    pub fn new_untitled() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        RecoveryKey::Untitled(format!("{}-{}-{}", now, std::process::id(), n))
+    }
+
+    /// The name of this key's recovery file
+    fn file_name(&self) -> String {
+        match self {
+            RecoveryKey::Path(p) => format!("{}.{EXTENSION}", flatten_path(p)),
+            RecoveryKey::Untitled(id) => format!("untitled-{id}.{EXTENSION}"),
+        }
+    }
+
+    fn to_header(&self) -> String {
+        match self {
+            RecoveryKey::Path(p) => format!("path {}\n", p.display()),
+            RecoveryKey::Untitled(id) => format!("untitled {id}\n"),
+        }
+    }
+
+    fn from_header(line: &str) -> Option<Self> {
+        let (kind, rest) = line.split_once(' ')?;
+        match kind {
+            "path" => Some(RecoveryKey::Path(PathBuf::from(rest))),
+            "untitled" => Some(RecoveryKey::Untitled(rest.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Unsaved text left behind by a session that didn't exit cleanly
+#[derive(Debug, Clone)]
+pub struct RecoveryFile {
+    pub key: RecoveryKey,
+    pub text: String,
+    /// When the text was journaled
+    pub modified: SystemTime,
+    /// Where the recovery file itself lives
+    pub swap_path: PathBuf,
+    /// The process that wrote it, if it was recorded
+    pub pid: Option<u32>,
+}
+
+impl RecoveryFile {
+    /// A unified diff from the file on disk to the recovered text.  For
+    /// untitled buffers, or files that no longer exist, everything shows as
+    /// added.
+    pub fn diff_against_disk(&self) -> String {
+        let on_disk = match &self.key {
+            RecoveryKey::Path(p) => fs::read_to_string(p).unwrap_or_default(),
+            RecoveryKey::Untitled(_) => String::new(),
+        };
+        diff::unified_diff(&on_disk, &self.text)
+    }
+
+    /// Returns true if the session that wrote the file is still running,
+    /// in which case the file isn't left behind but in use
+    pub fn is_owner_alive(&self) -> bool {
+        self.pid.is_some_and(process_alive)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_alive(pid: u32) -> bool {
+    // kill -0 only checks that the process could be signalled.  Zero and
+    // negative pids would mean process groups.
+    i32::try_from(pid).is_ok_and(|pid| pid > 0)
+        && std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+/// Turns a path into a single file name, the way vim names its swap files,
+/// with `%` standing in for `/`
+pub(crate) fn flatten_path(path: &Path) -> String {
+    path.to_string_lossy().replace('%', "%%").replace('/', "%")
+}
+
+/// Where eddy keeps its own state, `$XDG_DATA_HOME/eddy` or
+/// `~/.local/share/eddy`
+pub fn data_dir() -> PathBuf {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir);
+    data_home.join("eddy")
+}
+
+/// The directory that recovery files are kept in
+pub fn default_recovery_dir() -> PathBuf {
+    data_dir().join("recovery")
+}
+
+/// Reads and writes the recovery files in one directory
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    dir: PathBuf,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self::new(default_recovery_dir())
+    }
+}
+
+impl Recovery {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn swap_path(&self, key: &RecoveryKey) -> PathBuf {
+        self.dir.join(key.file_name())
+    }
+
+    /// Journals `text` for `key`, replacing anything written before
+    pub fn write(&self, key: &RecoveryKey, text: &Rope) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut contents = Rope::from_str(HEADER);
+        contents.append(Rope::from_str(&format!("pid {}\n", std::process::id())));
+        contents.append(Rope::from_str(&key.to_header()));
+        contents.append(text.clone());
+        save_rope(&contents, &self.swap_path(key), &SaveOptions::default())
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    /// Deletes the recovery file for `key`, if there is one
+    pub fn remove(&self, key: &RecoveryKey) -> io::Result<()> {
+        match fs::remove_file(self.swap_path(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every readable recovery file, oldest first
+    pub fn list(&self) -> Vec<RecoveryFile> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut files: Vec<RecoveryFile> = entries
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|e| e == EXTENSION).unwrap_or_default())
+            .filter_map(|p| Self::read(&p))
+            .collect();
+        files.sort_by_key(|f| f.modified);
+        files
+    }
+
+    fn read(swap_path: &Path) -> Option<RecoveryFile> {
+        let contents = fs::read_to_string(swap_path).ok()?;
+        let rest = contents.strip_prefix(HEADER)?;
+        // Files from before the pid was recorded go straight to the key
+        let (pid, rest) = match rest.strip_prefix("pid ") {
+            Some(rest) => {
+                let (pid, rest) = rest.split_once('\n')?;
+                (pid.parse().ok(), rest)
+            }
+            None => (None, rest),
+        };
+        let (key_line, text) = rest.split_once('\n')?;
+        let modified = fs::metadata(swap_path)
+            .and_then(|md| md.modified())
+            .unwrap_or(UNIX_EPOCH);
+        Some(RecoveryFile {
+            key: RecoveryKey::from_header(key_line)?,
+            text: text.to_string(),
+            modified,
+            swap_path: swap_path.to_owned(),
+            pid,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("eddy-recovery-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let rec = Recovery::new(dir.clone());
+
+        let path_key = RecoveryKey::Path(PathBuf::from("/tmp/100%/a.rs"));
+        let untitled_key = RecoveryKey::new_untitled();
+        rec.write(&path_key, &Rope::from_str("fn main() {}\n"))
+            .unwrap();
+        rec.write(&untitled_key, &Rope::from_str("scratch"))
+            .unwrap();
+
+        let mut files = rec.list();
+        files.sort_by_key(|f| f.text.clone());
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].key, path_key);
+        assert_eq!(files[0].text, "fn main() {}\n");
+        assert_eq!(files[1].key, untitled_key);
+        assert_eq!(files[1].text, "scratch");
+        assert_eq!(files[0].pid, Some(std::process::id()));
+        assert!(files[0].is_owner_alive());
+
+        // A session that's gone, and one from before pids were recorded
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        let swap_path = dir.join(path_key.file_name());
+        let text = fs::read_to_string(&swap_path).unwrap();
+        let ours = format!("pid {}\n", std::process::id());
+        fs::write(&swap_path, text.replace(&ours, &format!("pid {dead}\n"))).unwrap();
+        assert!(!Recovery::read(&swap_path).unwrap().is_owner_alive());
+        fs::write(&swap_path, text.replace(&ours, "")).unwrap();
+        let old = Recovery::read(&swap_path).unwrap();
+        assert_eq!((old.pid, old.text.as_str()), (None, "fn main() {}\n"));
+
+        rec.remove(&path_key).unwrap();
+        rec.remove(&path_key).unwrap();
+        assert_eq!(rec.list().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use ropey::Rope;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Options controlling how a buffer is written to disk
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    /// Copy the previous contents of the file to `file~` before replacing it
+    pub backup: bool,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The buffer has never been associated with a file
+    NoPath,
+    PermissionDenied(PathBuf),
+    ReadOnlyFilesystem(PathBuf),
+    DiskFull(PathBuf),
+    Io(PathBuf, io::Error),
+}
+
+impl SaveError {
+    fn from_io(path: &Path, e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => SaveError::PermissionDenied(path.to_owned()),
+            io::ErrorKind::ReadOnlyFilesystem => SaveError::ReadOnlyFilesystem(path.to_owned()),
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => {
+                SaveError::DiskFull(path.to_owned())
+            }
+            _ => SaveError::Io(path.to_owned(), e),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::NoPath => write!(f, "cannot save, no known file path"),
+            SaveError::PermissionDenied(p) => {
+                write!(f, "cannot save {}: permission denied", p.display())
+            }
+            SaveError::ReadOnlyFilesystem(p) => {
+                write!(f, "cannot save {}: read-only file system", p.display())
+            }
+            SaveError::DiskFull(p) => write!(f, "cannot save {}: no space left", p.display()),
+            SaveError::Io(p, e) => write!(f, "cannot save {}: {}", p.display(), e),
+        }
+    }
+}
+
+/// Resolves symlinks so that saving replaces the file that `path` points to,
+/// leaving the link itself in place.
+fn resolve_target(path: &Path) -> PathBuf {
+    match fs::symlink_metadata(path) {
+        Ok(md) if md.file_type().is_symlink() => {
+            fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+        }
+        _ => path.to_owned(),
+    }
+}
+
+/// Returns a path for a temporary file next to `target`.  It has to be in the
+/// same directory so the final rename stays on one file system.
+fn temp_path(target: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let mut name = OsString::from(".");
+    name.push(target.file_name().unwrap_or_default());
+    name.push(format!(".eddy-{}-{}.tmp", std::process::id(), n));
+    target.with_file_name(name)
+}
+
+/// The path a backup of `target` is written to
+pub fn backup_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push("~");
+    target.with_file_name(name)
+}
+
+/// Writes `rope` to `path` without ever leaving a partially written file
+/// behind.  The text goes to a temporary file in the same directory, which is
+/// synced and then renamed over the original.  Permissions, and ownership
+/// where possible, are carried over from the file being replaced.
+pub fn save_rope(rope: &Rope, path: &Path, opts: &SaveOptions) -> Result<(), SaveError> {
+    let target = resolve_target(path);
+    let orig_md = fs::metadata(&target).ok();
+
+    if opts.backup && orig_md.is_some() {
+        fs::copy(&target, backup_path(&target)).map_err(|e| SaveError::from_io(&target, e))?;
+    }
+
+    let tmp = temp_path(&target);
+    let res = write_temp(rope, &tmp, orig_md.as_ref())
+        .and_then(|_| fs::rename(&tmp, &target))
+        .map_err(|e| SaveError::from_io(&target, e));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return res;
+    }
+
+    sync_dir(&target);
+    Ok(())
+}
+
+fn write_temp(rope: &Rope, tmp: &Path, orig_md: Option<&fs::Metadata>) -> io::Result<()> {
+    let file = OpenOptions::new().write(true).create_new(true).open(tmp)?;
+    let mut writer = BufWriter::new(file);
+    rope.write_to(&mut writer)?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+
+    if let Some(md) = orig_md {
+        fs::set_permissions(tmp, md.permissions())?;
+        copy_owner(&file, md);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_owner(file: &File, md: &fs::Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    // Only root can give a file away, so failure here is expected and harmless
+    let _ = fchown(file, Some(md.uid()), Some(md.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _md: &fs::Metadata) {}
+
+/// Syncs the directory containing `path`, so the rename itself is durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) {
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        if let Ok(d) = File::open(dir) {
+            let _ = d.sync_all();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("eddy-save-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_save_replaces_contents() {
+        let dir = test_dir("replace");
+        let path = dir.join("a.txt");
+        fs::write(&path, "old contents that are longer").unwrap();
+        save_rope(&Rope::from_str("new"), &path, &SaveOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_backup() {
+        let dir = test_dir("backup");
+        let path = dir.join("a.txt");
+        fs::write(&path, "old").unwrap();
+        let opts = SaveOptions { backup: true };
+        save_rope(&Rope::from_str("new"), &path, &opts).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("a.txt~")).unwrap(), "old");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_permissions_and_symlink() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = test_dir("symlink");
+        let real = dir.join("real.sh");
+        let link = dir.join("link.sh");
+        fs::write(&real, "old").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o750)).unwrap();
+        symlink(&real, &link).unwrap();
+
+        save_rope(&Rope::from_str("new"), &link, &SaveOptions::default()).unwrap();
+
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "new");
+        let mode = fs::metadata(&real).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use crate::backend::PeekableReceiver;
+use log::error;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// Something that happened to a file on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    /// The contents may have changed, or the file was replaced
+    Modified,
+    Removed,
+    /// The file was moved to a new path
+    Renamed(PathBuf),
+}
+
+/// The parts of a file's metadata used to notice that it changed when we
+/// can only poll for it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// Modification time, in seconds since the epoch
+    pub mtime: u64,
+    pub size: u64,
+}
+
+impl FileStat {
+    pub fn from_metadata(md: &fs::Metadata) -> Self {
+        let mtime = md
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            mtime,
+            size: md.len(),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        fs::metadata(path).ok().map(|md| Self::from_metadata(&md))
+    }
+}
+
+/// Watches the files of open buffers on the local file system.
+///
+/// Directories are watched rather than the files themselves, so that editors
+/// and tools that save by renaming a new file into place are still noticed.
+/// Whole directories, or whole trees, can be watched too, to hear about
+/// every file in them.
+pub struct FileWatcher {
+    watcher: Option<RecommendedWatcher>,
+    /// The watched files, by their canonical paths, to the paths they were
+    /// watched as.  notify reports canonical paths.
+    files: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    /// Likewise for the directories whose entries are all watched
+    listed: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    /// Likewise for the watched trees
+    trees: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    /// How many watched files and directories each directory notify watches
+    /// is there for
+    dirs: HashMap<PathBuf, usize>,
+    /// Directories notify couldn't watch, so their files need polling
+    failed_dirs: HashSet<PathBuf>,
+    /// Trees notify couldn't watch
+    failed_trees: HashSet<PathBuf>,
+    receiver: PeekableReceiver<(PathBuf, FileChange)>,
+}
+
+impl fmt::Debug for FileWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileWatcher")
+            .field("files", &self.files)
+            .field("dirs", &self.dirs)
+            .field("listed", &self.listed)
+            .field("trees", &self.trees)
+            .finish()
+    }
+}
+
+impl FileWatcher {
+    pub fn new(wakeup: Arc<dyn Fn() + Send + Sync>) -> Self {
+        let (sender, receiver) = channel();
+        let files: Arc<Mutex<HashMap<PathBuf, PathBuf>>> = Arc::default();
+        let watched = files.clone();
+        let listed: Arc<Mutex<HashMap<PathBuf, PathBuf>>> = Arc::default();
+        let watched_dirs = listed.clone();
+        let trees: Arc<Mutex<HashMap<PathBuf, PathBuf>>> = Arc::default();
+        let watched_trees = trees.clone();
+
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    error!("file watcher: {e}");
+                    return;
+                }
+            };
+            let watched = watched.lock().expect("watched files");
+            let watched_dirs = watched_dirs.lock().expect("watched dirs");
+            let watched_trees = watched_trees.lock().expect("watched trees");
+            // Changes are reported under the paths that were watched
+            let as_watched = |path: &Path| {
+                let in_dir = || {
+                    let dir = watched_dirs.get(path.parent()?)?;
+                    Some(dir.join(path.file_name()?))
+                };
+                watched.get(path).cloned().or_else(in_dir).or_else(|| {
+                    watched_trees.iter().find_map(|(canonical, tree)| {
+                        path.strip_prefix(canonical).ok().map(|rel| tree.join(rel))
+                    })
+                })
+            };
+            let mut sent = false;
+            for (path, change) in changes_from_event(&event) {
+                let Some(path) = as_watched(&path) else {
+                    continue;
+                };
+                let change = match change {
+                    FileChange::Renamed(to) => FileChange::Renamed(as_watched(&to).unwrap_or(to)),
+                    c => c,
+                };
+                sent |= sender.send((path, change)).is_ok();
+            }
+            if sent {
+                wakeup();
+            }
+        });
+        let watcher = match watcher {
+            Ok(w) => Some(w),
+            Err(e) => {
+                error!("unable to start file watcher: {e}");
+                None
+            }
+        };
+
+        Self {
+            watcher,
+            files,
+            listed,
+            trees,
+            dirs: HashMap::new(),
+            failed_dirs: HashSet::new(),
+            failed_trees: HashSet::new(),
+            receiver: PeekableReceiver::new(receiver),
+        }
+    }
+
+    pub fn watch(&mut self, path: &Path) {
+        let canonical = canonicalize(path);
+        if self
+            .files
+            .lock()
+            .expect("watched files")
+            .insert(canonical.clone(), path.to_owned())
+            .is_some()
+        {
+            return;
+        }
+        if let Some(dir) = canonical.parent() {
+            self.hold_dir(dir.to_owned());
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        let canonical = canonicalize(path);
+        if self
+            .files
+            .lock()
+            .expect("watched files")
+            .remove(&canonical)
+            .is_none()
+        {
+            return;
+        }
+        if let Some(dir) = canonical.parent() {
+            self.release_dir(dir);
+        }
+    }
+
+    /// Watches the files and directories directly in `dir`, but not what's
+    /// in its subdirectories
+    pub fn watch_dir(&mut self, dir: &Path) {
+        let canonical = canonicalize(dir);
+        if self
+            .listed
+            .lock()
+            .expect("watched dirs")
+            .insert(canonical.clone(), dir.to_owned())
+            .is_none()
+        {
+            self.hold_dir(canonical);
+        }
+    }
+
+    pub fn unwatch_dir(&mut self, dir: &Path) {
+        let canonical = canonicalize(dir);
+        if self
+            .listed
+            .lock()
+            .expect("watched dirs")
+            .remove(&canonical)
+            .is_some()
+        {
+            self.release_dir(&canonical);
+        }
+    }
+
+    /// Returns true if `dir` was passed to `watch_dir`
+    pub fn is_dir_watched(&self, dir: &Path) -> bool {
+        self.listed
+            .lock()
+            .expect("watched dirs")
+            .contains_key(&canonicalize(dir))
+    }
+
+    /// Has notify watch `dir`, unless it already is
+    fn hold_dir(&mut self, dir: PathBuf) {
+        let count = self.dirs.entry(dir.clone()).or_default();
+        *count += 1;
+        // A watched tree already covers the directory, and watching it again
+        // would take it out of the tree when the directory is let go
+        if *count == 1 && !self.in_tree(&dir) {
+            if let Some(watcher) = &mut self.watcher {
+                if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                    error!("unable to watch {}: {e}", dir.display());
+                    self.failed_dirs.insert(dir);
+                }
+            }
+        }
+    }
+
+    /// Stops notify watching `dir` once nothing needs it anymore
+    fn release_dir(&mut self, dir: &Path) {
+        let Some(count) = self.dirs.get_mut(dir) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            self.dirs.remove(dir);
+            if self.failed_dirs.remove(dir) || self.in_tree(dir) {
+                return;
+            }
+            if let Some(watcher) = &mut self.watcher {
+                let _ = watcher.unwatch(dir);
+            }
+        }
+    }
+
+    /// Returns true if `path` was passed to `watch`
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.files
+            .lock()
+            .expect("watched files")
+            .contains_key(&canonicalize(path))
+    }
+
+    /// Returns true if notify is watching `path`, so changes to it don't
+    /// need to be polled for
+    pub fn is_notified(&self, path: &Path) -> bool {
+        let canonical = canonicalize(path);
+        let Some(dir) = canonical.parent() else {
+            return false;
+        };
+        self.watcher.is_some()
+            && self.is_watched(path)
+            && (self.in_tree(dir) || !self.failed_dirs.contains(dir))
+    }
+
+    fn in_tree(&self, dir: &Path) -> bool {
+        let trees = self.trees.lock().expect("watched trees");
+        trees.keys().any(|t| dir.starts_with(t))
+    }
+
+    /// Watches everything under `dir`, however deep
+    pub fn watch_tree(&mut self, dir: &Path) {
+        if self.is_tree_watched(dir) {
+            return;
+        }
+        let canonical = canonicalize(dir);
+        let Some(watcher) = &mut self.watcher else {
+            self.failed_trees.insert(canonical);
+            return;
+        };
+        if let Err(e) = watcher.watch(&canonical, RecursiveMode::Recursive) {
+            error!("unable to watch {}: {e}", dir.display());
+            self.failed_trees.insert(canonical);
+            return;
+        }
+        self.trees
+            .lock()
+            .expect("watched trees")
+            .insert(canonical, dir.to_owned());
+    }
+
+    /// Returns true if `dir` was passed to `watch_tree`
+    pub fn is_tree_watched(&self, dir: &Path) -> bool {
+        let canonical = canonicalize(dir);
+        self.failed_trees.contains(&canonical)
+            || self
+                .trees
+                .lock()
+                .expect("watched trees")
+                .contains_key(&canonical)
+    }
+
+    pub fn unwatch_tree(&mut self, dir: &Path) {
+        let canonical = canonicalize(dir);
+        self.failed_trees.remove(&canonical);
+        if self
+            .trees
+            .lock()
+            .expect("watched trees")
+            .remove(&canonical)
+            .is_some()
+        {
+            if let Some(watcher) = &mut self.watcher {
+                let _ = watcher.unwatch(&canonical);
+            }
+        }
+    }
+
+    pub fn has_events(&self) -> bool {
+        self.receiver.has_read()
+    }
+
+    pub fn try_recv(&self) -> Option<(PathBuf, FileChange)> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// The absolute path notify will report for `path`, with its directory's
+/// symlinks resolved.  The file itself needn't exist.
+fn canonicalize(path: &Path) -> PathBuf {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    match fs::canonicalize(dir) {
+        Ok(dir) => dir.join(name),
+        Err(_) => path.to_owned(),
+    }
+}
+
+fn changes_from_event(event: &notify::Event) -> Vec<(PathBuf, FileChange)> {
+    let paths = &event.paths;
+    match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => vec![
+            (paths[0].clone(), FileChange::Renamed(paths[1].clone())),
+            (paths[1].clone(), FileChange::Modified),
+        ],
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => paths
+            .iter()
+            .map(|p| (p.clone(), FileChange::Removed))
+            .collect(),
+        EventKind::Modify(ModifyKind::Metadata(_)) | EventKind::Access(_) => vec![],
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Any | EventKind::Other => paths
+            .iter()
+            .map(|p| (p.clone(), FileChange::Modified))
+            .collect(),
+    }
+}
@@ -0,0 +1,210 @@
+//! Fuzzy matching for pickers like the command palette, where typing a few
+//! letters from a name finds it.
+
+/// Points for each matched character
+const MATCH: i64 = 16;
+/// Extra points for a match at the start of a word
+const WORD_START: i64 = 24;
+/// Extra points for a match right after the previous one
+const CONTIGUOUS: i64 = 20;
+/// Points lost for each character skipped between matches, up to
+/// `MAX_GAP_PENALTY`
+const GAP: i64 = 3;
+const MAX_GAP_PENALTY: i64 = 12;
+
+/// How well a query matched, and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    /// The matched characters, as char indices into the candidate
+    pub indices: Vec<usize>,
+}
+
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+    let (prev, c) = (chars[i - 1], chars[i]);
+    !prev.is_alphanumeric() && c.is_alphanumeric() || prev.is_lowercase() && c.is_uppercase()
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// Matches the characters of `query` in order anywhere in `candidate`,
+/// ignoring case and whitespace in the query.  Of all the ways to match, the
+/// best scoring one is picked, favouring word starts and contiguous runs.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<FuzzyMatch> {
+    FuzzyMatcher::new(query).matches(candidate)
+}
+
+/// Matches one query against many candidates, like `fuzzy_match`, reusing
+/// its buffers from one candidate to the next
+#[derive(Debug, Clone, Default)]
+pub struct FuzzyMatcher {
+    query: Vec<char>,
+    /// The query in lower case, for checking that a candidate could match
+    /// before scoring it
+    lower: Vec<char>,
+    chars: Vec<char>,
+    /// `best[i * m + j]` is the best score for matching `query[..=i]` with
+    /// `query[i]` at `chars[j]`, and `from[i * m + j]` where `query[i - 1]`
+    /// was matched then
+    best: Vec<Option<i64>>,
+    from: Vec<usize>,
+}
+
+impl FuzzyMatcher {
+    pub fn new(query: &str) -> Self {
+        let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).collect();
+        let lower = query.iter().flat_map(|c| c.to_lowercase()).collect();
+        Self {
+            query,
+            lower,
+            ..Self::default()
+        }
+    }
+
+    /// Returns true if the query's characters are all in `candidate`, in
+    /// order.  Every match passes this, and most candidates that don't
+    /// match are turned away by it.
+    fn could_match(&self, candidate: &str) -> bool {
+        let mut lower = self.lower.iter().peekable();
+        for c in candidate.chars().flat_map(char::to_lowercase) {
+            if lower.next_if_eq(&&c).is_some() && lower.peek().is_none() {
+                return true;
+            }
+        }
+        lower.peek().is_none()
+    }
+
+    pub fn matches(&mut self, candidate: &str) -> Option<FuzzyMatch> {
+        let query = &self.query;
+        if query.is_empty() {
+            return Some(FuzzyMatch {
+                score: 0,
+                indices: Vec::new(),
+            });
+        }
+        if !self.could_match(candidate) {
+            return None;
+        }
+        self.chars.clear();
+        self.chars.extend(candidate.chars());
+        let chars = &self.chars;
+        let (n, m) = (query.len(), chars.len());
+        if n > m {
+            return None;
+        }
+        self.best.clear();
+        self.best.resize(n * m, None);
+        self.from.clear();
+        self.from.resize(n * m, 0);
+        let (best, from) = (&mut self.best, &mut self.from);
+
+        // The gap penalty stops growing after `FAR` skipped characters, so
+        // only the nearest previous matches need scoring one by one.  The
+        // best of those further back is kept as the row goes along.
+        const FAR: usize = (MAX_GAP_PENALTY / GAP) as usize;
+        for i in 0..n {
+            let mut far: Option<(i64, usize)> = None;
+            for j in i..m {
+                if i > 0 && j > FAR + 1 {
+                    let k = j - FAR - 2;
+                    if let Some(prev) = best[(i - 1) * m + k] {
+                        if far.is_none_or(|(s, _)| prev > s) {
+                            far = Some((prev, k));
+                        }
+                    }
+                }
+                if !eq_ignore_case(query[i], chars[j]) {
+                    continue;
+                }
+                let bonus = MATCH
+                    + if is_word_start(chars, j) {
+                        WORD_START
+                    } else {
+                        0
+                    };
+                let cell = i * m + j;
+                if i == 0 {
+                    best[cell] = Some(bonus - (GAP * j as i64).min(MAX_GAP_PENALTY));
+                    continue;
+                }
+                // Ties go to the earliest previous match
+                if let Some((prev, k)) = far {
+                    best[cell] = Some(prev + bonus - MAX_GAP_PENALTY);
+                    from[cell] = k;
+                }
+                for k in (i - 1).max(j.saturating_sub(FAR + 1))..j {
+                    let Some(prev) = best[(i - 1) * m + k] else {
+                        continue;
+                    };
+                    let link = if k + 1 == j {
+                        CONTIGUOUS
+                    } else {
+                        -(GAP * (j - k - 1) as i64).min(MAX_GAP_PENALTY)
+                    };
+                    let score = prev + bonus + link;
+                    if best[cell].is_none_or(|s| score > s) {
+                        best[cell] = Some(score);
+                        from[cell] = k;
+                    }
+                }
+            }
+        }
+
+        let (mut j, score) = best[(n - 1) * m..]
+            .iter()
+            .enumerate()
+            .filter_map(|(j, s)| s.map(|s| (j, s)))
+            .max_by_key(|&(j, s)| (s, std::cmp::Reverse(j)))?;
+        let mut indices = vec![j];
+        for i in (1..n).rev() {
+            j = from[i * m + j];
+            indices.push(j);
+        }
+        indices.reverse();
+        Some(FuzzyMatch { score, indices })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(fuzzy_match("xyz", "Save"), None);
+        assert_eq!(fuzzy_match("", "Save").unwrap().score, 0);
+
+        // Word starts are picked over earlier letters inside words
+        let m = fuzzy_match("mwl", "Move Word Left").unwrap();
+        assert_eq!(m.indices, vec![0, 5, 10]);
+
+        let m = fuzzy_match("save", "Save As").unwrap();
+        assert_eq!(m.indices, vec![0, 1, 2, 3]);
+
+        // Contiguous runs and word starts beat scattered matches
+        let run = fuzzy_match("undo", "Undo").unwrap().score;
+        let scattered = fuzzy_match("undo", "Unfold Document").unwrap().score;
+        assert!(run > scattered);
+        let start = fuzzy_match("sel", "Select All").unwrap().score;
+        let inside = fuzzy_match("sel", "Close View And Select").unwrap().score;
+        assert!(start > inside);
+
+        // Long gaps cost the same, so the word start far away still wins
+        let m = fuzzy_match("ab", "a-xxxxxxxxxxxxxxxxb_Ab").unwrap();
+        assert_eq!(m.indices, vec![20, 21]);
+
+        // A matcher is reused from one candidate to the next
+        let mut matcher = FuzzyMatcher::new("mwl");
+        assert_eq!(matcher.matches("Save"), None);
+        assert_eq!(
+            matcher.matches("Move Word Left").unwrap().indices,
+            vec![0, 5, 10]
+        );
+        assert_eq!(matcher.matches("mwl").unwrap().indices, vec![0, 1, 2]);
+    }
+}
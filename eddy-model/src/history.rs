@@ -54,6 +54,29 @@ impl History {
         self.history_ix = self.history.len() - 1;
     }
 
+    /// The position in the history, to pass to `squash` later
+    pub fn checkpoint(&self) -> usize {
+        self.history_ix
+    }
+
+    /// Merges every change made since `checkpoint` into one, so that they're
+    /// undone together
+    pub fn squash(&mut self, checkpoint: usize) {
+        if checkpoint + 1 >= self.history_ix {
+            return;
+        }
+        self.history.truncate(self.history_ix + 1);
+        let mut groups = self.history.drain(checkpoint + 1..);
+        let mut merged = groups.next().expect("at least two changes");
+        for group in groups {
+            merged.edits.extend(group.edits);
+            merged.selections_after = group.selections_after;
+            merged.rope = group.rope;
+        }
+        self.history.push(merged);
+        self.history_ix = checkpoint + 1;
+    }
+
     /// Performs an undo.  If an undo action was successfully performed,
     /// returns where the selection regions should be changed to.
     pub fn undo(&mut self) -> Option<(Rope, &[Selection])> {
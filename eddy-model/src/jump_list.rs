@@ -0,0 +1,102 @@
+//! Going back to where the caret was before a jump, and forward again,
+//! across views.
+
+use crate::{AnchorId, BufferId, Command, ViewId};
+
+/// How many jumps back are remembered
+const MAX_JUMPS: usize = 100;
+/// Clicks and other motions that move the caret at least this many lines
+/// count as jumps
+pub const FAR_LINES: usize = 10;
+
+/// A place the caret jumped away from.  The position is anchored in the
+/// buffer, so it follows edits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Jump {
+    /// The view it was in, if that's still open
+    pub view_id: ViewId,
+    pub buf_id: BufferId,
+    pub anchor: AnchorId,
+}
+
+/// Whether a command's motion is worth coming back from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JumpKind {
+    /// Whenever it leaves the line, like going to a line or a file
+    Always,
+    /// When it moves at least `FAR_LINES` lines, like a click
+    IfFar,
+    /// Never, like arrow keys and edits
+    Never,
+}
+
+impl JumpKind {
+    pub fn of(cmd: &Command) -> Self {
+        use Command::*;
+        match cmd {
+            MoveToBeginningOfDocument
+            | MoveToEndOfDocument
+            | GoToLine(_)
+            | NextBookmark
+            | PreviousBookmark
+            | NewView(_) => JumpKind::Always,
+            PointSelect { .. } | WordSelect { .. } | LineSelect { .. } | ExchangePointAndMark => {
+                JumpKind::IfFar
+            }
+            _ => JumpKind::Never,
+        }
+    }
+}
+
+/// The jumps made in a window, to go back and forth through
+#[derive(Debug, Clone, Default)]
+pub struct JumpList {
+    /// Oldest first
+    back: Vec<Jump>,
+    /// Where going back came from, most recent last
+    forward: Vec<Jump>,
+}
+
+impl JumpList {
+    /// Adds a jump, forgetting the ones that were gone back from.  Returns
+    /// the jumps that were dropped, so their anchors can be removed.
+    pub fn push(&mut self, jump: Jump) -> Vec<Jump> {
+        let mut dropped: Vec<Jump> = self.forward.drain(..).collect();
+        self.back.push(jump);
+        if self.back.len() > MAX_JUMPS {
+            dropped.push(self.back.remove(0));
+        }
+        dropped
+    }
+
+    /// The most recent jump
+    pub fn last(&self) -> Option<&Jump> {
+        self.back.last()
+    }
+
+    /// Takes the most recent jump, to go back to it
+    pub fn take_back(&mut self) -> Option<Jump> {
+        self.back.pop()
+    }
+
+    /// Takes the jump most recently gone back from, to go forward to it
+    pub fn take_forward(&mut self) -> Option<Jump> {
+        self.forward.pop()
+    }
+
+    /// Keeps where going back left from, to come forward to
+    pub fn left_back(&mut self, jump: Jump) {
+        self.forward.push(jump);
+    }
+
+    /// Keeps where going forward left from, to go back to
+    pub fn left_forward(&mut self, jump: Jump) {
+        self.back.push(jump);
+    }
+
+    /// Forgets the jumps into a buffer, when it's closed
+    pub fn remove_buffer(&mut self, buf_id: BufferId) {
+        self.back.retain(|j| j.buf_id != buf_id);
+        self.forward.retain(|j| j.buf_id != buf_id);
+    }
+}
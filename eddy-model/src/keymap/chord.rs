@@ -0,0 +1,145 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A key pressed together with modifiers, like `ctrl+shift+z`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+    /// The key's canonical name: a lowercase character like `a` or `/`, or
+    /// a lowercase key name like `page_up`
+    pub key: String,
+}
+
+impl KeyChord {
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: normalize_key(key),
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if the chord has no modifiers besides shift, so the key
+    /// would normally type a character
+    pub fn is_plain(&self) -> bool {
+        !self.ctrl && !self.alt && !self.meta
+    }
+
+    /// The text the chord types, if it's a plain key for a single character
+    pub fn text(&self) -> Option<String> {
+        let mut chars = self.key.chars();
+        let ch = chars.next()?;
+        if !self.is_plain() || chars.next().is_some() {
+            return None;
+        }
+        if self.shift {
+            Some(ch.to_uppercase().collect())
+        } else {
+            Some(ch.to_string())
+        }
+    }
+}
+
+/// Maps the names that keys can be written as to one canonical name
+pub fn normalize_key(name: &str) -> String {
+    let lower = name.to_lowercase();
+    let canonical = match lower.as_str() {
+        "enter" | "kp_enter" => "return",
+        "esc" => "escape",
+        "del" => "delete",
+        "bs" => "backspace",
+        "pageup" | "pgup" => "page_up",
+        "pagedown" | "pgdn" => "page_down",
+        "space" => " ",
+        "plus" => "+",
+        "minus" => "-",
+        "comma" => ",",
+        "period" => ".",
+        "slash" => "/",
+        _ => &lower,
+    };
+    canonical.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseKeyError(pub String);
+
+impl std::error::Error for ParseKeyError {}
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid key: {}", self.0)
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseKeyError(s.to_string());
+        // The key itself comes last, and may be `+`
+        let (mods, key) = match s.strip_suffix("++") {
+            Some(mods) => (mods, "+"),
+            None => match s.rsplit_once('+') {
+                Some((mods, key)) => (mods, key),
+                None => ("", s),
+            },
+        };
+        if key.is_empty() {
+            return Err(err());
+        }
+
+        let mut chord = KeyChord::new(key);
+        for m in mods.split('+').filter(|m| !m.is_empty()) {
+            match m.to_lowercase().as_str() {
+                "ctrl" | "control" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                "shift" => chord.shift = true,
+                "meta" | "super" | "cmd" => chord.meta = true,
+                _ => return Err(err()),
+            }
+        }
+        Ok(chord)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (on, name) in [
+            (self.ctrl, "ctrl+"),
+            (self.alt, "alt+"),
+            (self.shift, "shift+"),
+            (self.meta, "meta+"),
+        ] {
+            if on {
+                f.write_str(name)?;
+            }
+        }
+        match self.key.as_str() {
+            " " => f.write_str("space"),
+            key => f.write_str(key),
+        }
+    }
+}
+
+/// Parses a space separated sequence of chords, like `ctrl+k ctrl+c`
+pub fn parse_key_sequence(s: &str) -> Result<Vec<KeyChord>, ParseKeyError> {
+    let keys: Vec<KeyChord> = s
+        .split_whitespace()
+        .map(KeyChord::from_str)
+        .collect::<Result<_, _>>()?;
+    if keys.is_empty() {
+        return Err(ParseKeyError(s.to_string()));
+    }
+    Ok(keys)
+}
+
+/// Formats a sequence of chords the way `parse_key_sequence` reads them
+pub fn format_key_sequence(keys: &[KeyChord]) -> String {
+    keys.iter()
+        .map(|k| k.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
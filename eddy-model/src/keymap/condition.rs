@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// The state that binding conditions are checked against
+#[derive(Debug, Clone, Default)]
+pub struct KeyContext {
+    flags: HashSet<String>,
+    values: HashMap<String, String>,
+}
+
+impl KeyContext {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets a flag like `has_selection`
+    pub fn set_flag(&mut self, flag: &str) {
+        self.flags.insert(flag.to_string());
+    }
+
+    /// Sets a value like `language`, for conditions like `language == rust`
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+}
+
+/// When a binding applies, written like
+/// `has_selection && language == rust || !editor_focused`.  `&&` binds
+/// tighter than `||`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Flag(String),
+    Eq(String, String),
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn eval(&self, ctx: &KeyContext) -> bool {
+        match self {
+            Condition::Flag(flag) => ctx.has_flag(flag),
+            Condition::Eq(key, value) => ctx.get(key) == Some(value.as_str()),
+            Condition::Not(c) => !c.eval(ctx),
+            Condition::And(cs) => cs.iter().all(|c| c.eval(ctx)),
+            Condition::Or(cs) => cs.iter().any(|c| c.eval(ctx)),
+        }
+    }
+
+    fn parse_term(s: &str) -> Result<Self, ParseConditionError> {
+        let s = s.trim();
+        let err = || ParseConditionError(s.to_string());
+        if let Some((key, value)) = s.split_once("!=") {
+            let eq = Condition::parse_term(&format!("{key}=={value}"))?;
+            return Ok(Condition::Not(Box::new(eq)));
+        }
+        if let Some((key, value)) = s.split_once("==") {
+            let (key, value) = (key.trim(), value.trim());
+            if !is_ident(key) || value.is_empty() {
+                return Err(err());
+            }
+            return Ok(Condition::Eq(key.to_string(), value.to_string()));
+        }
+        if let Some(rest) = s.strip_prefix('!') {
+            return Ok(Condition::Not(Box::new(Condition::parse_term(rest)?)));
+        }
+        if !is_ident(s) {
+            return Err(err());
+        }
+        Ok(Condition::Flag(s.to_string()))
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseConditionError(pub String);
+
+impl std::error::Error for ParseConditionError {}
+
+impl fmt::Display for ParseConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid condition: {}", self.0)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ParseConditionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ors = s
+            .split("||")
+            .map(|part| {
+                let mut ands = part
+                    .split("&&")
+                    .map(Condition::parse_term)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(if ands.len() == 1 {
+                    ands.remove(0)
+                } else {
+                    Condition::And(ands)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(if ors.len() == 1 {
+            ors.remove(0)
+        } else {
+            Condition::Or(ors)
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, cs: &[Condition], sep: &str| {
+            for (i, c) in cs.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(sep)?;
+                }
+                write!(f, "{c}")?;
+            }
+            Ok(())
+        };
+        match self {
+            Condition::Flag(flag) => f.write_str(flag),
+            Condition::Eq(key, value) => write!(f, "{key} == {value}"),
+            Condition::Not(c) => match c.as_ref() {
+                Condition::Eq(key, value) => write!(f, "{key} != {value}"),
+                c => write!(f, "!{c}"),
+            },
+            Condition::And(cs) => join(f, cs, " && "),
+            Condition::Or(cs) => join(f, cs, " || "),
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use super::{
+    format_key_sequence, parse_key_sequence, Condition, KeyChord, KeyContext, ParseConditionError,
+    ParseKeyError,
+};
+use crate::Command;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_KEYMAP: &str = include_str!("default.toml");
+const EMACS_KEYMAP: &str = include_str!("emacs.toml");
+
+/// Where a binding was defined.  User bindings take precedence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BindingSource {
+    Default,
+    User,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub keys: Vec<KeyChord>,
+    /// `None` unbinds the keys
+    pub command: Option<Command>,
+    pub when: Option<Condition>,
+    pub source: BindingSource,
+}
+
+impl Binding {
+    fn applies(&self, ctx: &KeyContext) -> bool {
+        self.when.as_ref().map(|c| c.eval(ctx)).unwrap_or(true)
+    }
+
+    fn same_trigger(&self, other: &Binding) -> bool {
+        self.keys == other.keys && self.when == other.when
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_key_sequence(&self.keys))?;
+        match &self.command {
+            Some(cmd) => write!(f, " => {}", command_name(cmd))?,
+            None => write!(f, " => (unbound)")?,
+        }
+        if let Some(when) = &self.when {
+            write!(f, " when {when}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The name a command is written as in keymap files
+pub fn command_name(cmd: &Command) -> String {
+    match serde_json::to_value(cmd) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(v) => v.to_string(),
+        Err(_) => format!("{cmd:?}"),
+    }
+}
+
+#[derive(Deserialize)]
+struct KeymapFile {
+    #[serde(default)]
+    bindings: Vec<BindingEntry>,
+}
+
+#[derive(Deserialize)]
+struct BindingEntry {
+    keys: String,
+    command: Option<Command>,
+    when: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(PathBuf, io::Error),
+    Toml(toml::de::Error),
+    Key(ParseKeyError),
+    Condition(ParseConditionError),
+}
+
+impl std::error::Error for KeymapError {}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeymapError::Io(p, e) => write!(f, "cannot read {}: {}", p.display(), e),
+            KeymapError::Toml(e) => write!(f, "invalid keymap: {e}"),
+            KeymapError::Key(e) => write!(f, "invalid keymap: {e}"),
+            KeymapError::Condition(e) => write!(f, "invalid keymap: {e}"),
+        }
+    }
+}
+
+/// Two bindings from the same file that fight over the same keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapConflict {
+    pub first: Binding,
+    pub second: Binding,
+    /// True if `first`'s keys are only a prefix of `second`'s, so `second`
+    /// can never be reached
+    pub prefix: bool,
+}
+
+impl fmt::Display for KeymapConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix {
+            write!(f, "{} shadows {}", self.first, self.second)
+        } else {
+            write!(f, "{} conflicts with {}", self.first, self.second)
+        }
+    }
+}
+
+/// What a key press amounts to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyResult {
+    Command(Command),
+    /// A command to run this many times, from a prefix argument like
+    /// Emacs' C-u
+    Repeat(Command, usize),
+    /// The keys so far start a longer binding
+    Pending,
+    /// Nothing is bound to these keys
+    Unbound(Vec<KeyChord>),
+    /// A modal input layer, like vim's, used up the key
+    Handled,
+}
+
+/// The overall style of key handling a window uses
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyProfile {
+    /// The keymap, as is
+    #[default]
+    Standard,
+    /// Modal editing on top of the keymap, which is only used in insert mode
+    Vim,
+    /// The keymap's Emacs bindings, with the mark, kills and prefix
+    /// arguments
+    Emacs,
+}
+
+impl KeyProfile {
+    pub const ALL: [KeyProfile; 3] = [KeyProfile::Standard, KeyProfile::Vim, KeyProfile::Emacs];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyProfile::Standard => "standard",
+            KeyProfile::Vim => "vim",
+            KeyProfile::Emacs => "emacs",
+        }
+    }
+
+    /// The name shown in menus
+    pub fn label(self) -> &'static str {
+        match self {
+            KeyProfile::Standard => "Standard",
+            KeyProfile::Vim => "Vim",
+            KeyProfile::Emacs => "Emacs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// Key bindings, in the order they were defined.  Later bindings override
+/// earlier ones with the same keys.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    /// Keys pressed so far of a multi-key sequence
+    pending: Vec<KeyChord>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap =
+            Self::from_toml(DEFAULT_KEYMAP, BindingSource::Default).expect("default keymap");
+        keymap.extend(Self::from_toml(EMACS_KEYMAP, BindingSource::Default).expect("emacs keymap"));
+        keymap
+    }
+}
+
+/// The user's keymap file, `$XDG_CONFIG_HOME/eddy/keymap.toml` or
+/// `~/.config/eddy/keymap.toml`
+pub fn user_keymap_path() -> PathBuf {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        .unwrap_or_else(std::env::temp_dir);
+    config_home.join("eddy").join("keymap.toml")
+}
+
+impl Keymap {
+    pub fn from_toml(s: &str, source: BindingSource) -> Result<Self, KeymapError> {
+        let file: KeymapFile = toml::from_str(s).map_err(KeymapError::Toml)?;
+        let bindings = file
+            .bindings
+            .into_iter()
+            .map(|e| {
+                Ok(Binding {
+                    keys: parse_key_sequence(&e.keys).map_err(KeymapError::Key)?,
+                    command: e.command,
+                    when: e
+                        .when
+                        .map(|w| w.parse())
+                        .transpose()
+                        .map_err(KeymapError::Condition)?,
+                    source,
+                })
+            })
+            .collect::<Result<_, KeymapError>>()?;
+        Ok(Self {
+            bindings,
+            pending: Vec::new(),
+        })
+    }
+
+    /// The default bindings, with the user's file applied on top if it
+    /// exists
+    pub fn load(user_path: &Path) -> Result<Self, KeymapError> {
+        let mut keymap = Keymap::default();
+        match fs::read_to_string(user_path) {
+            Ok(s) => keymap.extend(Keymap::from_toml(&s, BindingSource::User)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(KeymapError::Io(user_path.to_owned(), e)),
+        }
+        Ok(keymap)
+    }
+
+    /// Adds bindings that take precedence over the current ones
+    pub fn extend(&mut self, other: Keymap) {
+        self.bindings.extend(other.bindings);
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// The bindings that are in effect, leaving out ones that were
+    /// overridden or unbound.  With a context, only bindings whose
+    /// conditions hold are included.
+    pub fn active_bindings(&self, ctx: Option<&KeyContext>) -> Vec<&Binding> {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter(|(i, b)| {
+                b.command.is_some()
+                    && !self.bindings[i + 1..]
+                        .iter()
+                        .any(|later| later.same_trigger(b))
+                    && ctx.map(|ctx| b.applies(ctx)).unwrap_or(true)
+            })
+            .map(|(_, b)| b)
+            .collect()
+    }
+
+    /// Pairs of bindings from the same source that can't both work
+    pub fn conflicts(&self) -> Vec<KeymapConflict> {
+        let mut ret = Vec::new();
+        for (i, a) in self.bindings.iter().enumerate() {
+            for b in &self.bindings[i + 1..] {
+                if a.source != b.source || a.when != b.when {
+                    continue;
+                }
+                let (short, long) = if a.keys.len() <= b.keys.len() {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                if long.keys.starts_with(&short.keys) {
+                    ret.push(KeymapConflict {
+                        first: short.clone(),
+                        second: long.clone(),
+                        prefix: short.keys.len() < long.keys.len(),
+                    });
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns true if a multi-key sequence is in progress
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// The keys typed so far of a multi-key sequence
+    pub fn pending(&self) -> &[KeyChord] {
+        &self.pending
+    }
+
+    /// Forgets a partially typed sequence
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Feeds in a key press.  A sequence that could still become a longer
+    /// binding waits for more keys.
+    pub fn press(&mut self, chord: KeyChord, ctx: &KeyContext) -> KeyResult {
+        self.pending.push(chord);
+        let keys = &self.pending;
+
+        let mut exact = None;
+        let mut longer = false;
+        for b in self.bindings.iter().rev().filter(|b| b.applies(ctx)) {
+            if b.keys == *keys {
+                if exact.is_none() {
+                    exact = Some(b.command.clone());
+                }
+            } else if b.keys.starts_with(keys) && b.command.is_some() {
+                longer = true;
+            }
+        }
+
+        if longer {
+            return KeyResult::Pending;
+        }
+        let keys = std::mem::take(&mut self.pending);
+        match exact.flatten() {
+            Some(cmd) => KeyResult::Command(cmd),
+            None => KeyResult::Unbound(keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(s: &str) -> KeyChord {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_default_keymap_has_no_conflicts() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.conflicts(), vec![]);
+    }
+
+    #[test]
+    fn test_sequences_and_conditions() {
+        let mut keymap = Keymap::default();
+        keymap.extend(
+            Keymap::from_toml(
+                r#"
+                [[bindings]]
+                keys = "ctrl+k ctrl+c"
+                command = "copy"
+                when = "has_selection && language == rust"
+
+                [[bindings]]
+                keys = "ctrl+a"
+                "#,
+                BindingSource::User,
+            )
+            .unwrap(),
+        );
+
+        let mut ctx = KeyContext::new();
+        ctx.set_flag("has_selection");
+        ctx.set("language", "rust");
+        assert_eq!(keymap.press(chord("ctrl+k"), &ctx), KeyResult::Pending);
+        assert_eq!(
+            keymap.press(chord("ctrl+c"), &ctx),
+            KeyResult::Command(Command::Copy)
+        );
+
+        ctx.set("language", "go");
+        assert_eq!(
+            keymap.press(chord("ctrl+k"), &ctx),
+            KeyResult::Unbound(vec![chord("ctrl+k")])
+        );
+        assert_eq!(
+            keymap.press(chord("ctrl+a"), &ctx),
+            KeyResult::Unbound(vec![chord("ctrl+a")])
+        );
+        assert_eq!(
+            keymap.press(chord("ctrl+shift+z"), &ctx),
+            KeyResult::Command(Command::Redo)
+        );
+    }
+
+    #[test]
+    fn test_emacs_profile() {
+        let mut keymap = Keymap::default();
+        let mut ctx = KeyContext::new();
+        assert_eq!(
+            keymap.press(chord("ctrl+x"), &ctx),
+            KeyResult::Command(Command::Cut)
+        );
+
+        ctx.set("key_profile", KeyProfile::Emacs.name());
+        assert_eq!(keymap.press(chord("ctrl+x"), &ctx), KeyResult::Pending);
+        assert_eq!(
+            keymap.press(chord("ctrl+s"), &ctx),
+            KeyResult::Command(Command::Save)
+        );
+        assert_eq!(
+            keymap.press(chord("ctrl+s"), &ctx),
+            KeyResult::Command(Command::IsearchForward)
+        );
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let keymap = Keymap::from_toml(
+            r#"
+            [[bindings]]
+            keys = "ctrl+k"
+            command = "cut"
+
+            [[bindings]]
+            keys = "ctrl+k ctrl+c"
+            command = "copy"
+            "#,
+            BindingSource::User,
+        )
+        .unwrap();
+        let conflicts = keymap.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].prefix);
+    }
+}
@@ -0,0 +1,7 @@
+mod chord;
+mod condition;
+mod map;
+
+pub use chord::*;
+pub use condition::*;
+pub use map::*;
@@ -0,0 +1,134 @@
+//! Bracket pairs found in the syntax tree, so brackets inside strings and
+//! comments are left alone.
+
+use eddy_ts::{Node, Tree};
+use ropey::Rope;
+use std::ops::Range;
+
+const BRACKETS: [&str; 6] = ["(", ")", "[", "]", "{", "}"];
+
+fn is_bracket(node: Option<Node<'_>>) -> bool {
+    node.is_some_and(|n| !n.is_named() && BRACKETS.contains(&n.kind()))
+}
+
+/// Whether a node starts or ends with a bracket, like a block or an
+/// argument list
+fn is_pair(node: Node<'_>) -> bool {
+    let count = node.child_count();
+    count >= 2 && (is_bracket(node.child(0)) || is_bracket(node.child(count - 1)))
+}
+
+/// Whether the text still has the bracket the tree says is there, as the
+/// tree may be from before the last edits
+fn in_text(rope: &Rope, node: Node<'_>) -> bool {
+    let range = node.byte_range();
+    range.end <= rope.len_bytes() && rope.byte_slice(range) == node.kind()
+}
+
+/// The brackets within some bytes, and how many pairs each is nested in
+pub fn bracket_depths(tree: &Tree, rope: &Rope, bytes: Range<usize>) -> Vec<(Range<usize>, usize)> {
+    let mut found = Vec::new();
+    collect(tree.root_node(), 0, rope, &bytes, &mut found);
+    found
+}
+
+fn collect(
+    node: Node<'_>,
+    depth: usize,
+    rope: &Rope,
+    bytes: &Range<usize>,
+    found: &mut Vec<(Range<usize>, usize)>,
+) {
+    let pair = is_pair(node);
+    let mut cursor = node.walk();
+    if cursor.goto_first_child_for_byte(bytes.start).is_none() {
+        return;
+    }
+    loop {
+        let child = cursor.node();
+        if child.start_byte() >= bytes.end {
+            break;
+        }
+        if pair && is_bracket(Some(child)) {
+            if in_text(rope, child) {
+                found.push((child.byte_range(), depth));
+            }
+        } else if child.child_count() > 0 {
+            collect(child, depth + usize::from(pair), rope, bytes, found);
+        }
+        if !cursor.goto_next_sibling() {
+            break;
+        }
+    }
+}
+
+/// From the opening bracket to the closing one of the innermost pair a
+/// byte is between
+pub fn enclosing_pair(tree: &Tree, rope: &Rope, byte: usize) -> Option<Range<usize>> {
+    let mut node = tree.root_node().descendant_for_byte_range(byte, byte)?;
+    loop {
+        let count = node.child_count();
+        if count >= 2 {
+            let (open, close) = (node.child(0)?, node.child(count - 1)?);
+            let bracketed = is_bracket(Some(open)) && is_bracket(Some(close));
+            if bracketed
+                && open.end_byte() <= byte
+                && byte <= close.start_byte()
+                && in_text(rope, open)
+                && in_text(rope, close)
+            {
+                return Some(open.start_byte()..close.end_byte());
+            }
+        }
+        node = node.parent()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::language_by_name;
+    use eddy_ts::Parser;
+
+    #[test]
+    fn test_brackets() {
+        let text = "fn f(a: [u8; 2]) {\n    g(\"(\", a[0]); // )\n}\n";
+        let mut parser = Parser::new();
+        parser
+            .set_language(language_by_name("rust").unwrap().grammar.unwrap()())
+            .unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let rope = Rope::from_str(text);
+        let depths = |bytes: Range<usize>| -> Vec<(&str, usize)> {
+            bracket_depths(&tree, &rope, bytes)
+                .into_iter()
+                .map(|(r, depth)| (&text[r], depth))
+                .collect()
+        };
+        assert_eq!(
+            depths(0..text.len()),
+            vec![
+                ("(", 0),
+                ("[", 1),
+                ("]", 1),
+                (")", 0),
+                ("{", 0),
+                ("(", 1),
+                ("[", 2),
+                ("]", 2),
+                (")", 1),
+                ("}", 0),
+            ]
+        );
+        // Just the second line
+        let line = 19..text.find("//").unwrap();
+        assert_eq!(depths(line).len(), 4);
+
+        let at = text.find("a[").unwrap();
+        let call = enclosing_pair(&tree, &rope, at).unwrap();
+        assert_eq!(&text[call], "(\"(\", a[0])");
+        let body = enclosing_pair(&tree, &rope, text.find("g(").unwrap()).unwrap();
+        assert_eq!(body, text.find('{').unwrap()..text.len() - 1);
+        assert_eq!(enclosing_pair(&tree, &rope, 1), None);
+    }
+}
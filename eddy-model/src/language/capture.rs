@@ -1,51 +1,53 @@
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum Capture {
-    Attribute,
-    Comment,
-    Constant,
-    ConstantBuiltin,
-    Constructor,
-    Escape,
-    Function,
-    FunctionMacro,
-    FunctionMethod,
-    Keyword,
-    Label,
-    Operator,
-    Property,
-    PunctuationBracket,
-    PunctuationDelimiter,
-    String,
-    Type,
-    TypeBuiltin,
-    VariableBuiltin,
-    VariableParameter,
-}
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// Every capture name seen so far, so each is only kept once
+static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// A highlight scope named by a query's capture, like
+/// `function.method.call`.  Each dotted part makes the scope more specific.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Capture(&'static str);
 
 impl Capture {
+    /// `None` for names starting with `_`, which queries only use to match
     pub fn from_name(name: &str) -> Option<Capture> {
-        match name {
-            "attribute" => Some(Capture::Attribute),
-            "comment" => Some(Capture::Comment),
-            "constant" => Some(Capture::Constant),
-            "constant.builtin" => Some(Capture::ConstantBuiltin),
-            "constructor" => Some(Capture::Constructor),
-            "escape" => Some(Capture::Escape),
-            "function" => Some(Capture::Function),
-            "function.macro" => Some(Capture::FunctionMacro),
-            "function.method" => Some(Capture::FunctionMethod),
-            "keyword" => Some(Capture::Keyword),
-            "label" => Some(Capture::Label),
-            "operator" => Some(Capture::Operator),
-            "property" => Some(Capture::Property),
-            "punctuation.bracket" => Some(Capture::PunctuationBracket),
-            "punctuation.delimiter" => Some(Capture::PunctuationDelimiter),
-            "string" => Some(Capture::String),
-            "type" => Some(Capture::Type),
-            "type.builtin" => Some(Capture::TypeBuiltin),
-            "variable.builtin" => Some(Capture::VariableBuiltin),
-            "variable.parameter" => Some(Capture::VariableParameter),
-            _ => None,
+        if name.is_empty() || name.starts_with('_') {
+            return None;
         }
+        let mut names = NAMES.lock().expect("capture names");
+        if let Some(name) = names.get(name) {
+            return Some(Capture(name));
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        names.insert(name);
+        Some(Capture(name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0
+    }
+
+    /// The scope and the ones it falls back to, most specific first:
+    /// `function.method.call`, `function.method`, then `function`
+    pub fn scopes(&self) -> impl Iterator<Item = &'static str> {
+        let name = self.0;
+        std::iter::once(name).chain(name.rmatch_indices('.').map(move |(i, _)| &name[..i]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_scopes() {
+        let c = Capture::from_name("function.method.call").unwrap();
+        assert_eq!(c, Capture::from_name("function.method.call").unwrap());
+        assert_eq!(
+            c.scopes().collect::<Vec<_>>(),
+            vec!["function.method.call", "function.method", "function"]
+        );
+        assert!(Capture::from_name("_name").is_none());
     }
 }
@@ -0,0 +1,153 @@
+//! Working out which language a file is in.
+
+use super::registry::{language_by_name, LanguageConfig, LANGUAGES};
+use crate::files::glob_to_regex;
+use regex::Regex;
+use ropey::Rope;
+use std::path::Path;
+
+/// How many lines at each end of a file a Vim modeline may be on
+const MODELINE_LINES: usize = 5;
+/// How many lines are looked through for one that isn't blank
+const CONTENT_LINES: usize = 20;
+/// Lines are only looked at up to this many chars
+const MAX_LINE_CHARS: usize = 500;
+
+/// The language of a file, going by its exact name, then globs, its
+/// extension, a `#!` line, an Emacs or Vim modeline, and last what its
+/// first line looks like
+pub fn detect_language(path: Option<&Path>, rope: &Rope) -> Option<&'static LanguageConfig> {
+    path.and_then(language_for_path)
+        .or_else(|| language_from_shebang(rope))
+        .or_else(|| language_from_modeline(rope))
+        .or_else(|| language_from_content(rope))
+}
+
+/// The language of a file going by its name alone
+pub fn language_for_path(path: &Path) -> Option<&'static LanguageConfig> {
+    let file_name = path.file_name()?.to_str()?;
+    if let Some(lang) = LANGUAGES.iter().find(|l| l.filenames.contains(&file_name)) {
+        return Some(lang);
+    }
+    if let Some(lang) = LANGUAGES
+        .iter()
+        .find(|l| l.globs.iter().any(|g| glob_matches(g, file_name)))
+    {
+        return Some(lang);
+    }
+    let ext = path.extension()?.to_str()?;
+    LANGUAGES
+        .iter()
+        .find(|l| l.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+fn glob_matches(glob: &str, file_name: &str) -> bool {
+    Regex::new(&format!("^{}$", glob_to_regex(glob)))
+        .map(|re| re.is_match(file_name))
+        .unwrap_or_default()
+}
+
+/// The start of a line, without its line ending
+fn line(rope: &Rope, idx: usize) -> Option<String> {
+    if idx >= rope.len_lines() {
+        return None;
+    }
+    let line: String = rope.line(idx).chars().take(MAX_LINE_CHARS).collect();
+    Some(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Goes by the program a `#!` line runs, like `#!/usr/bin/env python3`
+fn language_from_shebang(rope: &Rope) -> Option<&'static LanguageConfig> {
+    let first = line(rope, 0)?;
+    let mut words = first.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Skip env's own options and variables
+        program = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    LANGUAGES.iter().find(|l| l.interpreters.contains(&program))
+}
+
+/// Goes by an Emacs modeline on the first line, or the second after a `#!`
+/// line, like `-*- mode: python -*-`.  Otherwise by a Vim modeline near the
+/// start or end, like `vim: set ft=python:`.
+fn language_from_modeline(rope: &Rope) -> Option<&'static LanguageConfig> {
+    let emacs = Regex::new(r"-\*-(.*?)-\*-").expect("emacs modeline regex");
+    for first in (0..2).filter_map(|i| line(rope, i)) {
+        let Some(caps) = emacs.captures(&first) else {
+            continue;
+        };
+        let inner = caps[1].trim();
+        let mode = if inner.contains(':') {
+            inner.split(';').find_map(|var| {
+                let (key, value) = var.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case("mode")
+                    .then(|| value.trim())
+            })
+        } else {
+            Some(inner)
+        };
+        if let Some(lang) = mode.and_then(language_by_name) {
+            return Some(lang);
+        }
+    }
+
+    let vim = Regex::new(r"(?:^|\s)(?:vim?|ex):.*?\b(?:ft|filetype|syntax)=([\w+-]+)")
+        .expect("vim modeline regex");
+    let len = rope.len_lines();
+    let lines = (0..MODELINE_LINES.min(len))
+        .chain(len.saturating_sub(MODELINE_LINES).max(MODELINE_LINES)..len);
+    lines
+        .filter_map(|i| line(rope, i))
+        .find_map(|l| vim.captures(&l).and_then(|c| language_by_name(&c[1])))
+}
+
+/// Goes by what the first line that isn't blank looks like
+fn language_from_content(rope: &Rope) -> Option<&'static LanguageConfig> {
+    let first = (0..CONTENT_LINES)
+        .map_while(|i| line(rope, i))
+        .find(|l| !l.trim().is_empty())?;
+    LANGUAGES.iter().find(|l| {
+        l.first_line
+            .and_then(|re| Regex::new(re).ok())
+            .is_some_and(|re| re.is_match(first.trim_start()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(path: &str, text: &str) -> Option<&'static str> {
+        let path = (!path.is_empty()).then(|| Path::new(path));
+        detect_language(path, &Rope::from_str(text)).map(|l| l.name)
+    }
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect("src/main.rs", ""), Some("rust"));
+        assert_eq!(detect("Makefile", ""), Some("make"));
+        assert_eq!(detect("Dockerfile.dev", ""), Some("dockerfile"));
+        assert_eq!(detect("/home/me/.bashrc", ""), Some("shell"));
+        assert_eq!(detect("x.H", ""), Some("c"));
+        assert_eq!(detect("run", "#!/usr/bin/env python3\n"), Some("python"));
+        assert_eq!(
+            detect("run", "#!/usr/bin/env -S node --x\n"),
+            Some("javascript")
+        );
+        assert_eq!(detect("run", "#!/bin/bash\n"), Some("shell"));
+        assert_eq!(
+            detect("x", "# -*- mode: js; coding: utf-8 -*-\n"),
+            Some("javascript")
+        );
+        assert_eq!(detect("x", "#!/bin/x\n// -*- rust -*-\n"), Some("rust"));
+        assert_eq!(detect("x", "a\nb\n# vim: set ft=go ts=4:\n"), Some("go"));
+        assert_eq!(detect("", "\n<!DOCTYPE html>\n<html>\n"), Some("html"));
+        assert_eq!(detect("", "package main\n"), Some("go"));
+        assert_eq!(detect("notes", "hello\n"), None);
+        // The name wins over the contents
+        assert_eq!(detect("a.rs", "#!/bin/sh\n"), Some("rust"));
+    }
+}
@@ -0,0 +1,258 @@
+use super::capture::Capture;
+use super::locals::{local_allowed, Locals};
+use super::util::RopeTextProvider;
+use crate::Point;
+use eddy_ts::{Query, QueryCursor, Tree};
+use ropey::Rope;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// A highlighted piece of a line, in bytes from the start of the line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub capture: Capture,
+}
+
+/// A syntax tree and the query that highlights it
+pub struct HighlightSource<'a> {
+    pub tree: &'a Tree,
+    pub query: &'a Query,
+    pub captures_by_id: &'a [Option<Capture>],
+    /// References to local variables are highlighted like their
+    /// definitions
+    pub locals: Option<&'a Locals>,
+}
+
+/// The highlights of every line, kept between edits.  Edits and changes to
+/// the syntax tree mark lines to highlight again, and only those lines are
+/// run through the highlights query.
+#[derive(Debug, Clone, Default)]
+pub struct HighlightCache {
+    /// `None` for lines that need highlighting again
+    lines: Vec<Option<Vec<HighlightSpan>>>,
+}
+
+impl HighlightCache {
+    /// The highlights of a line, parents before the nodes inside them
+    pub fn line(&self, line: usize) -> &[HighlightSpan] {
+        self.lines
+            .get(line)
+            .and_then(Option::as_deref)
+            .unwrap_or_default()
+    }
+
+    /// Forgets every line's highlights
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Makes room for an edit that replaced the lines from `start` to
+    /// `old_end` with the lines from `start` to `new_end`
+    pub fn edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        if start >= self.lines.len() {
+            return;
+        }
+        let old_end = old_end.min(self.lines.len() - 1);
+        self.lines
+            .splice(start..=old_end, (start..=new_end).map(|_| None));
+    }
+
+    /// Like `edit`, but keeps showing the old highlights of the line the
+    /// caret is on after the edit, for when the new ones aren't ready yet
+    pub fn shift(&mut self, start: Point, old_end: Point, new_end: Point) {
+        if start.line >= self.lines.len() {
+            return;
+        }
+        let old_end_line = old_end.line.min(self.lines.len() - 1);
+        // With nothing before the edit on its line, that line is the rest of
+        // the one the edit ended in
+        let (kept, line) = if start.col == 0 {
+            (self.lines[old_end_line].take(), new_end.line)
+        } else {
+            (self.lines[start.line].take(), start.line)
+        };
+        self.lines.splice(
+            start.line..=old_end_line,
+            (start.line..=new_end.line).map(|_| None),
+        );
+        self.lines[line] = kept;
+    }
+
+    /// Marks the lines a byte range touches to be highlighted again
+    pub fn invalidate(&mut self, rope: &Rope, bytes: Range<usize>) {
+        let first = rope.byte_to_line(bytes.start.min(rope.len_bytes()));
+        let last = rope.byte_to_line(bytes.end.min(rope.len_bytes()));
+        for line in self.lines.iter_mut().take(last + 1).skip(first) {
+            *line = None;
+        }
+    }
+
+    /// Highlights the lines that need it, with one query for each run of
+    /// them
+    pub fn update(&mut self, rope: &Rope, sources: &[HighlightSource<'_>]) {
+        self.lines.resize(rope.len_lines(), None);
+        let mut line = 0;
+        while line < self.lines.len() {
+            if self.lines[line].is_some() {
+                line += 1;
+                continue;
+            }
+            let end = (line..self.lines.len())
+                .find(|l| self.lines[*l].is_some())
+                .unwrap_or(self.lines.len());
+            self.highlight_lines(rope, sources, line..end);
+            line = end;
+        }
+    }
+
+    fn highlight_lines(
+        &mut self,
+        rope: &Rope,
+        sources: &[HighlightSource<'_>],
+        lines: Range<usize>,
+    ) {
+        let line_start = |line: usize| {
+            if line < rope.len_lines() {
+                rope.line_to_byte(line)
+            } else {
+                rope.len_bytes()
+            }
+        };
+        let bytes = line_start(lines.start)..line_start(lines.end);
+
+        // A node can be captured more than once, and the last capture wins.
+        // Injected languages come after the ones they're in.
+        let mut nodes: Vec<(Range<usize>, Capture)> = Vec::new();
+        let mut by_id: HashMap<(usize, usize), usize> = HashMap::new();
+        for (i, source) in sources.iter().enumerate() {
+            let mut cursor = QueryCursor::new();
+            cursor.set_byte_range(bytes.clone());
+            let matches = cursor.captures(
+                source.query,
+                source.tree.root_node(),
+                RopeTextProvider::new(rope),
+            );
+            let mut referenced = HashSet::new();
+            for (m, ci) in matches {
+                let c = m.captures[ci];
+                let Some(mut capture) = source
+                    .captures_by_id
+                    .get(c.index as usize)
+                    .copied()
+                    .flatten()
+                else {
+                    continue;
+                };
+                let range = c.node.byte_range();
+                if let Some(locals) = source.locals {
+                    if !local_allowed(source.query, m.pattern_index, locals.is_local(&range)) {
+                        continue;
+                    }
+                    if let Some(defined) = locals.definition_of(&range).and_then(|d| d.capture) {
+                        referenced.insert(range.start);
+                        capture = defined;
+                    }
+                }
+                match by_id.get(&(i, c.node.id())) {
+                    Some(&at) => nodes[at].1 = capture,
+                    None => {
+                        by_id.insert((i, c.node.id()), nodes.len());
+                        nodes.push((range, capture));
+                    }
+                }
+            }
+            // References the highlights query doesn't capture at all
+            for (reference, definition) in source
+                .locals
+                .into_iter()
+                .flat_map(|locals| locals.references_in(bytes.clone()))
+            {
+                if let (false, Some(capture)) = (
+                    referenced.contains(&reference.range.start),
+                    definition.capture,
+                ) {
+                    nodes.push((reference.range.clone(), capture));
+                }
+            }
+        }
+
+        let mut found: Vec<Vec<HighlightSpan>> = vec![Vec::new(); lines.len()];
+        for (range, capture) in nodes {
+            let start = range.start.max(bytes.start);
+            let end = range.end.min(bytes.end);
+            if start >= end {
+                continue;
+            }
+            for line in rope.byte_to_line(start)..=rope.byte_to_line(end - 1) {
+                let (ls, le) = (line_start(line), line_start(line + 1));
+                found[line - lines.start].push(HighlightSpan {
+                    start: start.max(ls) - ls,
+                    end: end.min(le) - ls,
+                    capture,
+                });
+            }
+        }
+        for (line, mut spans) in lines.zip(found) {
+            spans.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+            self.lines[line] = Some(spans);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Layer;
+    use crate::language::{language_by_name, TreeSitterLayer};
+
+    fn point(rope: &Rope, char: usize) -> Point {
+        let line = rope.char_to_line(char);
+        Point {
+            byte: rope.char_to_byte(char),
+            char,
+            line,
+            col: char - rope.line_to_char(line),
+        }
+    }
+
+    #[test]
+    fn test_highlights_follow_edits() {
+        let mut rope = Rope::from_str("fn a() {}\nlet x = 1;\n");
+        let mut layer = TreeSitterLayer::new(language_by_name("rust").unwrap()).unwrap();
+        layer.update_highlights(&rope);
+        let captures = |layer: &TreeSitterLayer, line| -> Vec<(usize, usize, Capture)> {
+            layer
+                .highlights()
+                .unwrap()
+                .line(line)
+                .iter()
+                .map(|s| (s.start, s.end, s.capture))
+                .collect()
+        };
+        assert!(captures(&layer, 0).contains(&(0, 2, Capture::from_name("keyword").unwrap())));
+
+        // Opening a comment changes lines after the edit too
+        rope.insert(0, "/* ");
+        layer.edit_tree_insert(point(&rope, 0), point(&rope, 3));
+        let end = rope.len_chars() - 1;
+        rope.insert(end, " */");
+        layer.edit_tree_insert(point(&rope, end), point(&rope, end + 3));
+        layer.update_highlights(&rope);
+        assert_eq!(
+            captures(&layer, 1),
+            vec![(0, 13, Capture::from_name("comment").unwrap())]
+        );
+
+        // Adding lines moves the cached ones along
+        let old_end = point(&rope, 3);
+        rope.remove(0..3);
+        layer.edit_tree_remove(point(&rope, 0), old_end);
+        rope.insert(0, "\n\n");
+        layer.edit_tree_insert(point(&rope, 0), point(&rope, 2));
+        layer.update_highlights(&rope);
+        assert!(captures(&layer, 2).contains(&(0, 2, Capture::from_name("keyword").unwrap())));
+        assert!(captures(&layer, 3).contains(&(0, 3, Capture::from_name("keyword").unwrap())));
+    }
+}
@@ -1,16 +1,41 @@
-use super::go::GoLayer;
-use super::rust::RustLayer;
+use super::registry::LanguageConfig;
+use super::treesitter::TreeSitterLayer;
 use crate::language::capture::Capture;
+use crate::language::highlight::HighlightCache;
+use crate::language::locals::Locals;
 use crate::{Point, Range};
 use eddy_ts::Tree;
 use ropey::Rope;
-use std::path::Path;
-pub trait Layer {
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+/// How long a parse runs before checking back.  It carries on from where
+/// it stopped unless it was cancelled.
+pub const PARSE_TIMEOUT_MICROS: u64 = 50_000;
+
+pub trait Layer: Send {
+    /// The name of the language, like `rust`, or `None` for plain text
+    fn language(&self) -> Option<&'static str> {
+        None
+    }
     fn capture(&self, idx: usize) -> Option<Capture>;
-    fn capture_from_node(&self, id: usize) -> Option<Capture>;
-    fn update_highlights(&mut self, rope: &Rope);
+    /// The highlights as of the last `update_highlights`
+    fn highlights(&self) -> Option<&HighlightCache>;
+    /// Parses the text again, and highlights the lines that changed.
+    /// Returns false if the parse ran out of time or was cancelled.  Calling
+    /// it again with the same text carries on where it stopped.
+    fn update_highlights(&mut self, rope: &Rope) -> bool;
+    /// Stops parsing when the flag isn't zero
+    fn set_cancellation_flag(&mut self, _flag: Arc<AtomicUsize>) {}
+    /// Throws away a stopped parse, before parsing different text
+    fn reset_parse(&mut self) {}
     fn unset_tree(&mut self);
     fn tree(&self) -> Option<&Tree>;
+    /// The variables and their references as of the last
+    /// `update_highlights`, for languages with a locals query
+    fn locals(&self) -> Option<Locals> {
+        None
+    }
     /// edit the tree, so tree-sitter can know what changed. All units are in code points.
     fn edit_tree(&mut self, start: Point, old_end: Point, new_end: Point);
     /// edit the tree, so tree-sitter can know what changed. All units are in code points.
@@ -23,34 +48,55 @@ pub trait Layer {
     }
 }
 
-pub fn layer_from_path(path: &Path) -> Box<dyn Layer> {
-    if let Some(ext) = path.extension() {
-        if ext == "rs" {
-            return Box::new(RustLayer::new());
-        }
-        if ext == "go" {
-            return Box::new(GoLayer::new());
+/// A layer that parses a language, or a `NilLayer` for languages without a
+/// grammar and plain text
+pub fn layer_for(config: Option<&'static LanguageConfig>) -> Box<dyn Layer> {
+    let Some(config) = config else {
+        return Box::new(NilLayer::new());
+    };
+    if config.grammar.is_none() {
+        return Box::new(NilLayer::named(config.name));
+    }
+    match TreeSitterLayer::new(config) {
+        Ok(layer) => Box::new(layer),
+        Err(e) => {
+            log::error!("unable to load {}: {e}", config.name);
+            Box::new(NilLayer::named(config.name))
         }
     }
-    Box::new(NilLayer::new())
 }
 
-pub struct NilLayer {}
+/// Doesn't parse or highlight anything
+pub struct NilLayer {
+    language: Option<&'static str>,
+}
 
 impl NilLayer {
     pub fn new() -> Self {
-        NilLayer {}
+        NilLayer { language: None }
+    }
+
+    /// For a language eddy can't parse
+    pub fn named(language: &'static str) -> Self {
+        NilLayer {
+            language: Some(language),
+        }
     }
 }
 
 impl Layer for NilLayer {
+    fn language(&self) -> Option<&'static str> {
+        self.language
+    }
     fn capture(&self, _idx: usize) -> Option<Capture> {
         None
     }
-    fn capture_from_node(&self, _id: usize) -> Option<Capture> {
+    fn highlights(&self) -> Option<&HighlightCache> {
         None
     }
-    fn update_highlights(&mut self, _rope: &Rope) {}
+    fn update_highlights(&mut self, _rope: &Rope) -> bool {
+        true
+    }
     fn unset_tree(&mut self) {}
     fn tree(&self) -> Option<&Tree> {
         None
@@ -0,0 +1,586 @@
+//! What a locals query says about the names in a file: the scopes, the
+//! variables and parameters defined in them, and the identifiers that
+//! refer back to those definitions.
+
+use super::capture::Capture;
+use super::treesitter::Queries;
+use super::util::RopeTextProvider;
+use eddy_ts::{InputEdit, Node, Query, QueryCursor, Tree};
+use ropey::Rope;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A name defined in a scope, like a variable or a parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub range: Range<usize>,
+    /// How the highlights query captures the definition, which its
+    /// references are highlighted as too
+    pub capture: Option<Capture>,
+    /// Where references can start to see it
+    visible: usize,
+    /// Whether it's outside every scope
+    root: bool,
+}
+
+/// An identifier that refers to a definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub range: Range<usize>,
+    /// Index into the definitions
+    pub definition: usize,
+}
+
+/// A scope while the query's captures are walked through
+struct Scope {
+    start: usize,
+    end: usize,
+    /// Whether names from the scopes around it can be seen in it
+    inherits: bool,
+    /// Indexes into the definitions that can be seen so far, in the order
+    /// they were found
+    definitions: Vec<usize>,
+}
+
+/// The definitions and references of a tree, in bytes.  Both are sorted by
+/// where they start.
+#[derive(Debug, Clone, Default)]
+pub struct Locals {
+    definitions: Vec<Definition>,
+    references: Vec<Reference>,
+}
+
+impl Locals {
+    /// Walks the scopes of a tree, resolving every reference to the
+    /// innermost definition of its name that it can see.  Returns `None` if
+    /// the cancellation flag was set first.
+    pub fn new(
+        rope: &Rope,
+        tree: &Tree,
+        queries: &Queries,
+        cancel: Option<&AtomicUsize>,
+    ) -> Option<Self> {
+        find(rope, tree, queries, 0..rope.len_bytes(), Vec::new(), cancel)
+    }
+
+    /// Finds the locals again after the tree changed within some bytes.
+    /// Nothing outside a scope can see what's defined in it, so only the
+    /// outermost scopes around the changes are walked again.
+    pub fn update(
+        &self,
+        rope: &Rope,
+        tree: &Tree,
+        queries: &Queries,
+        changed: &[Range<usize>],
+        cancel: Option<&AtomicUsize>,
+    ) -> Option<Self> {
+        let Some(query) = &queries.locals else {
+            return Some(Locals::default());
+        };
+        let mut regions = Vec::new();
+        for bytes in changed {
+            let mut cursor = QueryCursor::new();
+            cursor.set_byte_range(bytes.start..bytes.end.max(bytes.start + 1));
+            let mut outermost: Option<Range<usize>> = None;
+            for (m, ci) in cursor.captures(query, tree.root_node(), RopeTextProvider::new(rope)) {
+                let c = m.captures[ci];
+                if c.node.start_byte() > bytes.start {
+                    break;
+                }
+                let range = c.node.byte_range();
+                if query.capture_names()[c.index as usize] == "local.scope"
+                    && bytes.end <= range.end
+                    && outermost.as_ref().is_none_or(|o| range.len() > o.len())
+                {
+                    outermost = Some(range);
+                }
+            }
+            match outermost {
+                Some(region) => regions.push(region),
+                // Outside every scope, anything after it may see it
+                None => return Locals::new(rope, tree, queries, cancel),
+            }
+        }
+        regions.sort_by_key(|r| r.start);
+        regions.dedup_by(|r, before| r.start < before.end);
+        let in_region = |byte: usize| regions.iter().any(|r| r.contains(&byte));
+        if self
+            .definitions
+            .iter()
+            .any(|d| d.root && in_region(d.range.start))
+        {
+            return Locals::new(rope, tree, queries, cancel);
+        }
+
+        // What's outside the regions stays as it was
+        let mut locals = Locals::default();
+        let mut moved_to = vec![None; self.definitions.len()];
+        for (d, definition) in self.definitions.iter().enumerate() {
+            if !in_region(definition.range.start) {
+                moved_to[d] = Some(locals.definitions.len());
+                locals.definitions.push(definition.clone());
+            }
+        }
+        for r in &self.references {
+            if let (false, Some(definition)) = (in_region(r.range.start), moved_to[r.definition]) {
+                locals.references.push(Reference {
+                    range: r.range.clone(),
+                    definition,
+                });
+            }
+        }
+        for region in regions.iter().cloned() {
+            let outer: Vec<usize> = (0..self.definitions.len())
+                .filter(|&d| {
+                    self.definitions[d].root && self.definitions[d].visible <= region.start
+                })
+                .collect();
+            let seen = outer.iter().map(|&d| self.definitions[d].clone()).collect();
+            let found = find(rope, tree, queries, region, seen, cancel)?;
+            let offset = locals.definitions.len();
+            let at = |d: usize| match outer.get(d) {
+                Some(&old) => moved_to[old].expect("a definition outside the regions"),
+                None => offset + d - outer.len(),
+            };
+            locals
+                .references
+                .extend(found.references.into_iter().map(|r| Reference {
+                    definition: at(r.definition),
+                    ..r
+                }));
+            locals
+                .definitions
+                .extend(found.definitions.into_iter().skip(outer.len()));
+        }
+        locals.sort();
+        Some(locals)
+    }
+
+    pub fn definitions(&self) -> &[Definition] {
+        &self.definitions
+    }
+
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    /// The definition an identifier refers to
+    pub fn definition_of(&self, range: &Range<usize>) -> Option<&Definition> {
+        let at = self
+            .references
+            .binary_search_by_key(&range.start, |r| r.range.start)
+            .ok()?;
+        let reference = &self.references[at];
+        (reference.range == *range).then(|| &self.definitions[reference.definition])
+    }
+
+    /// Whether a node is a definition, or refers to one
+    pub fn is_local(&self, range: &Range<usize>) -> bool {
+        let defined = self
+            .definitions
+            .binary_search_by_key(&range.start, |d| d.range.start)
+            .is_ok_and(|at| self.definitions[at].range == *range);
+        defined || self.definition_of(range).is_some()
+    }
+
+    /// The references that start within some bytes, and what they refer to
+    pub fn references_in(
+        &self,
+        bytes: Range<usize>,
+    ) -> impl Iterator<Item = (&Reference, &Definition)> {
+        let start = self
+            .references
+            .partition_point(|r| r.range.start < bytes.start);
+        self.references[start..]
+            .iter()
+            .take_while(move |r| r.range.start < bytes.end)
+            .map(|r| (r, &self.definitions[r.definition]))
+    }
+
+    /// The definition of the name at a byte and all the references to it,
+    /// for showing where a variable is used
+    pub fn occurrences(&self, byte: usize) -> Vec<Range<usize>> {
+        let contains = |r: &Range<usize>| r.start <= byte && byte <= r.end;
+        let definition = self
+            .references
+            .iter()
+            .find(|r| contains(&r.range))
+            .map(|r| r.definition)
+            .or_else(|| self.definitions.iter().position(|d| contains(&d.range)));
+        let Some(definition) = definition else {
+            return Vec::new();
+        };
+        let mut ranges = vec![self.definitions[definition].range.clone()];
+        ranges.extend(
+            self.references
+                .iter()
+                .filter(|r| r.definition == definition)
+                .map(|r| r.range.clone()),
+        );
+        ranges.sort_by_key(|r| r.start);
+        ranges
+    }
+
+    /// The bytes whose highlights differ between these locals and older
+    /// ones, as references come and go or their definitions change
+    pub fn changed_since(&self, old: &Locals) -> Vec<Range<usize>> {
+        let highlighted = |locals: &Locals| -> HashSet<(Range<usize>, Option<Capture>)> {
+            locals
+                .references
+                .iter()
+                .map(|r| (r.range.clone(), locals.definitions[r.definition].capture))
+                .collect()
+        };
+        let (new, old) = (highlighted(self), highlighted(old));
+        new.symmetric_difference(&old)
+            .map(|(range, _)| range.clone())
+            .collect()
+    }
+
+    /// Moves the definitions and references along with an edit
+    pub fn edit(&mut self, edit: &InputEdit) {
+        let shift = |byte: &mut usize| {
+            if *byte >= edit.old_end_byte {
+                *byte = *byte + edit.new_end_byte - edit.old_end_byte;
+            } else if *byte > edit.start_byte {
+                *byte = edit.start_byte;
+            }
+        };
+        for d in &mut self.definitions {
+            shift(&mut d.visible);
+        }
+        let ranges = self
+            .definitions
+            .iter_mut()
+            .map(|d| &mut d.range)
+            .chain(self.references.iter_mut().map(|r| &mut r.range));
+        for range in ranges {
+            shift(&mut range.start);
+            shift(&mut range.end);
+        }
+    }
+
+    /// Adds the locals of another tree, like an injected language's
+    pub fn extend(&mut self, other: Locals) {
+        let offset = self.definitions.len();
+        self.definitions.extend(other.definitions);
+        self.references
+            .extend(other.references.into_iter().map(|r| Reference {
+                definition: r.definition + offset,
+                ..r
+            }));
+        self.sort();
+    }
+
+    /// Puts the definitions and references back in order
+    fn sort(&mut self) {
+        let mut order: Vec<usize> = (0..self.definitions.len()).collect();
+        order.sort_by_key(|&d| self.definitions[d].range.start);
+        let mut moved_to = vec![0; order.len()];
+        for (to, &from) in order.iter().enumerate() {
+            moved_to[from] = to;
+        }
+        self.definitions = order.iter().map(|&d| self.definitions[d].clone()).collect();
+        for r in &mut self.references {
+            r.definition = moved_to[r.definition];
+        }
+        self.references.sort_by_key(|r| r.range.start);
+    }
+}
+
+/// The definitions and references within some bytes, which are all of the
+/// text or a scope nothing outside of it can see into.  `outer` are the
+/// definitions it can see from around it, and come first in what's found.
+fn find(
+    rope: &Rope,
+    tree: &Tree,
+    queries: &Queries,
+    bytes: Range<usize>,
+    outer: Vec<Definition>,
+    cancel: Option<&AtomicUsize>,
+) -> Option<Locals> {
+    let Some(query) = &queries.locals else {
+        return Some(Locals::default());
+    };
+    let mut locals = Locals {
+        definitions: outer,
+        references: Vec::new(),
+    };
+    let mut scopes = vec![Scope {
+        start: 0,
+        end: usize::MAX,
+        inherits: false,
+        definitions: (0..locals.definitions.len()).collect(),
+    }];
+    // Definitions that can't be seen until further on, and their scopes
+    let mut hidden: Vec<(usize, usize)> = Vec::new();
+    let mut defined = HashSet::new();
+    // The node of each new definition, for their captures
+    let mut nodes = HashMap::new();
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(bytes);
+    let captures = cursor.captures(query, tree.root_node(), RopeTextProvider::new(rope));
+    for (m, ci) in captures {
+        if cancelled(cancel) {
+            return None;
+        }
+        let c = m.captures[ci];
+        let range = c.node.byte_range();
+        while let Some(at) = hidden
+            .iter()
+            .position(|&(d, _)| locals.definitions[d].visible <= range.start)
+        {
+            let (d, scope) = hidden.remove(at);
+            scopes[scope].definitions.push(d);
+        }
+        while scopes.len() > 1 && scopes.last().is_some_and(|s| s.end <= range.start) {
+            scopes.pop();
+        }
+        let name = query.capture_names()[c.index as usize].as_str();
+        if name == "local.scope" {
+            let inherits = !query
+                .property_settings(m.pattern_index)
+                .iter()
+                .any(|p| &*p.key == "local.scope-inherits" && p.value.as_deref() == Some("false"));
+            scopes.push(Scope {
+                start: range.start,
+                end: range.end,
+                inherits,
+                definitions: Vec::new(),
+            });
+        } else if name == "local.definition" || name.starts_with("local.definition.") {
+            if !defined.insert(range.start) {
+                continue;
+            }
+            let scope = scopes.len() - 1;
+            let d = locals.definitions.len();
+            let visible = visible_from(c.node, &scopes[scope]);
+            nodes.insert(c.node.id(), d);
+            locals.definitions.push(Definition {
+                name: rope.byte_slice(range.clone()).to_string(),
+                range: range.clone(),
+                capture: None,
+                visible: visible.unwrap_or(range.start),
+                root: scope == 0,
+            });
+            match visible {
+                Some(_) => hidden.push((d, scope)),
+                None => scopes[scope].definitions.push(d),
+            }
+        } else if name == "local.reference" && !defined.contains(&range.start) {
+            let text = rope.byte_slice(range.clone());
+            let found = scopes.iter().rev().try_for_each(|scope| {
+                let found = scope
+                    .definitions
+                    .iter()
+                    .rev()
+                    .find(|&&d| locals.definitions[d].name == text);
+                match (found, scope.inherits) {
+                    (Some(&d), _) => Err(Some(d)),
+                    (None, true) => Ok(()),
+                    (None, false) => Err(None),
+                }
+            });
+            if let Err(Some(definition)) = found {
+                locals.references.push(Reference { range, definition });
+            }
+        }
+    }
+    capture_definitions(rope, tree, queries, &mut locals, &nodes, cancel)?;
+    Some(locals)
+}
+
+/// Where a definition can first be seen, if not right away: after the
+/// declaration it's in, so the `x` in `let x = x + 1` is an older one
+fn visible_from(node: Node<'_>, scope: &Scope) -> Option<usize> {
+    let mut node = node.parent()?;
+    while scope.start < node.start_byte() || node.end_byte() < scope.end {
+        let kind = node.kind();
+        if kind.ends_with("declaration") || kind.ends_with("declarator") {
+            return Some(node.end_byte());
+        }
+        node = node.parent()?;
+    }
+    None
+}
+
+/// Sets how the highlights query captures each definition that has a node
+/// and is referred to, leaving out the patterns that are only for names
+/// that aren't local.  The others are highlighted as they are anyway.
+fn capture_definitions(
+    rope: &Rope,
+    tree: &Tree,
+    queries: &Queries,
+    locals: &mut Locals,
+    nodes: &HashMap<usize, usize>,
+    cancel: Option<&AtomicUsize>,
+) -> Option<()> {
+    let highlights = &queries.highlights;
+    let referenced: HashSet<usize> = locals.references.iter().map(|r| r.definition).collect();
+    let definitions = &mut locals.definitions;
+    let mut cursor = QueryCursor::new();
+    for &d in nodes.values().filter(|d| referenced.contains(d)) {
+        if cancelled(cancel) {
+            return None;
+        }
+        cursor.set_byte_range(definitions[d].range.clone());
+        let captures = cursor.captures(highlights, tree.root_node(), RopeTextProvider::new(rope));
+        for (m, ci) in captures {
+            let c = m.captures[ci];
+            if nodes.get(&c.node.id()) != Some(&d)
+                || !local_allowed(highlights, m.pattern_index, true)
+            {
+                continue;
+            }
+            if let Some(found) = queries
+                .captures_by_id
+                .get(c.index as usize)
+                .copied()
+                .flatten()
+            {
+                definitions[d].capture = Some(found);
+            }
+        }
+    }
+    Some(())
+}
+
+fn cancelled(flag: Option<&AtomicUsize>) -> bool {
+    flag.is_some_and(|f| f.load(Ordering::Relaxed) != 0)
+}
+
+/// Whether a pattern's `#is? local` or `#is-not? local` lets it match a
+/// node that is or isn't local
+pub fn local_allowed(query: &Query, pattern: usize, is_local: bool) -> bool {
+    query
+        .property_predicates(pattern)
+        .iter()
+        .filter(|(p, _)| &*p.key == "local")
+        .all(|(_, positive)| *positive == is_local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::{language_by_name, Queries};
+    use eddy_ts::Parser;
+
+    fn find_locals(language: &str, text: &str) -> (Rope, Locals) {
+        let config = language_by_name(language).unwrap();
+        let queries = Queries::of(config).unwrap();
+        let mut parser = Parser::new();
+        parser.set_language(config.grammar.unwrap()()).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        let rope = Rope::from_str(text);
+        let locals = Locals::new(&rope, &tree, &queries, None).unwrap();
+        (rope, locals)
+    }
+
+    /// Each reference, and the start of the line its definition is on
+    fn resolved(rope: &Rope, locals: &Locals) -> Vec<(String, usize)> {
+        locals
+            .references()
+            .iter()
+            .map(|r| {
+                let d = &locals.definitions()[r.definition];
+                (d.name.clone(), rope.byte_to_line(d.range.start))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_locals() {
+        let text = "fn f(a: u8) -> u8 {\n    let b = a;\n    {\n        let a = 1;\n        a + b\n    }\n}\nfn g() { a }\n";
+        let (rope, locals) = find_locals("rust", text);
+        assert_eq!(
+            resolved(&rope, &locals),
+            vec![("a".into(), 0), ("a".into(), 3), ("b".into(), 1)]
+        );
+        let param = Capture::from_name("variable.parameter");
+        assert_eq!(locals.definitions()[0].capture, param);
+
+        // From the parameter
+        let at = text.find("= a").unwrap() + 2;
+        assert_eq!(locals.definition_of(&(at..at + 1)).unwrap().capture, param);
+        assert_eq!(locals.occurrences(at), vec![5..6, at..at + 1]);
+
+        // A binding can't be seen from its own value
+        let text = "fn f(a: u8) -> u8 {\n    let a = a * 2;\n    a\n}\n";
+        let (rope, locals) = find_locals("rust", text);
+        assert_eq!(
+            resolved(&rope, &locals),
+            vec![("a".into(), 0), ("a".into(), 1)]
+        );
+        let at = text.find("= a").unwrap() + 2;
+        assert_eq!(locals.occurrences(at), vec![5..6, at..at + 1]);
+    }
+
+    #[test]
+    fn test_update_locals() {
+        let config = language_by_name("rust").unwrap();
+        let queries = Queries::of(config).unwrap();
+        let mut parser = Parser::new();
+        parser.set_language(config.grammar.unwrap()()).unwrap();
+        let text = "fn f(a: u8) {\n    a;\n}\nfn g(b: u8) {\n    b;\n}\n";
+        let mut tree = parser.parse(text, None).unwrap();
+        let locals = Locals::new(&Rope::from_str(text), &tree, &queries, None).unwrap();
+
+        // Renaming the parameter of `g` leaves `f` alone
+        let at = text.find("b:").unwrap();
+        let edited = text.replacen("b:", "c:", 1);
+        let edit = InputEdit {
+            start_byte: at,
+            old_end_byte: at + 1,
+            new_end_byte: at + 1,
+            start_position: tree.root_node().start_position(),
+            old_end_position: tree.root_node().start_position(),
+            new_end_position: tree.root_node().start_position(),
+        };
+        tree.edit(&edit);
+        let tree = parser.parse(&edited, Some(&tree)).unwrap();
+        let rope = Rope::from_str(&edited);
+        let changed = at..at + 1;
+        let updated = locals
+            .update(&rope, &tree, &queries, std::slice::from_ref(&changed), None)
+            .unwrap();
+        assert_eq!(resolved(&rope, &updated), vec![("a".into(), 0)]);
+        assert_eq!(updated.definitions().len(), 2);
+        let reference = edited.rfind('b').unwrap();
+        assert!(updated
+            .changed_since(&locals)
+            .contains(&(reference..reference + 1)));
+    }
+
+    #[test]
+    fn test_javascript_locals() {
+        let text = "function f(x, console) {\n  return x + console;\n}\nconsole.log(x);\n";
+        let (rope, locals) = find_locals("javascript", text);
+        assert_eq!(
+            resolved(&rope, &locals),
+            vec![("x".into(), 0), ("console".into(), 0)]
+        );
+        assert!(locals
+            .definitions()
+            .iter()
+            .all(|d| d.capture == Capture::from_name("variable.parameter")));
+    }
+
+    #[test]
+    fn test_go_and_c_locals() {
+        let (rope, locals) = find_locals(
+            "go",
+            "package p\nfunc f(n int) int {\n\tm := n\n\treturn m\n}\n",
+        );
+        assert_eq!(
+            resolved(&rope, &locals),
+            vec![("n".into(), 1), ("m".into(), 2)]
+        );
+        let (rope, locals) = find_locals("c", "int f(int *p) {\n  int q = *p;\n  return q;\n}\n");
+        assert_eq!(
+            resolved(&rope, &locals),
+            vec![("p".into(), 0), ("q".into(), 1)]
+        );
+    }
+}
@@ -1,8 +1,22 @@
+pub mod brackets;
 pub mod capture;
-pub mod go;
+pub mod detect;
+pub mod highlight;
 pub mod layer;
-pub mod rust;
+pub mod locals;
+pub mod registry;
+pub mod tags;
+pub mod treesitter;
 pub mod util;
+pub mod worker;
 
+pub use brackets::*;
 pub use capture::*;
+pub use detect::*;
+pub use highlight::*;
 pub use layer::*;
+pub use locals::*;
+pub use registry::*;
+pub use tags::*;
+pub use treesitter::*;
+pub use worker::*;
@@ -0,0 +1,219 @@
+//! The languages eddy knows, and how to tell which one a file is in.
+
+use eddy_ts::{language, Language};
+use std::fmt;
+
+/// Everything eddy knows about a language
+pub struct LanguageConfig {
+    /// Like `rust`
+    pub name: &'static str,
+    /// For people, like `Rust`
+    pub title: &'static str,
+    /// Other names modelines and injections use, like `rs`
+    pub aliases: &'static [&'static str],
+    /// Languages without a grammar are named, but not highlighted
+    pub grammar: Option<fn() -> Language>,
+    pub highlights: &'static str,
+    /// Empty if the language has no injections query
+    pub injections: &'static str,
+    /// Empty if the language has no locals query
+    pub locals: &'static str,
+    /// Empty if the language has no tags query
+    pub tags: &'static str,
+    /// Without the dot
+    pub extensions: &'static [&'static str],
+    /// Whole file names, for files that go without an extension
+    pub filenames: &'static [&'static str],
+    /// Globs matched against file names, like `Dockerfile.*`
+    pub globs: &'static [&'static str],
+    /// Programs named on a `#!` line, without their version
+    pub interpreters: &'static [&'static str],
+    /// A regex the first line that isn't blank matches, as a last resort
+    pub first_line: Option<&'static str>,
+    pub line_comment: Option<&'static str>,
+    /// The start and end of a block comment
+    pub block_comment: Option<(&'static str, &'static str)>,
+}
+
+impl LanguageConfig {
+    const DEFAULT: LanguageConfig = LanguageConfig {
+        name: "",
+        title: "",
+        aliases: &[],
+        grammar: None,
+        highlights: "",
+        injections: "",
+        locals: "",
+        tags: "",
+        extensions: &[],
+        filenames: &[],
+        globs: &[],
+        interpreters: &[],
+        first_line: None,
+        line_comment: None,
+        block_comment: None,
+    };
+
+    /// Whether `name` is this language's name or one of its aliases
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Debug for LanguageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LanguageConfig")
+            .field("name", &self.name)
+            .field("extensions", &self.extensions)
+            .field("filenames", &self.filenames)
+            .finish()
+    }
+}
+
+pub static LANGUAGES: &[LanguageConfig] = &[
+    LanguageConfig {
+        name: "c",
+        title: "C",
+        grammar: Some(language::c),
+        highlights: language::C_HIGHLIGHTS,
+        locals: language::C_LOCALS,
+        tags: language::C_TAGS,
+        extensions: &["c", "h"],
+        first_line: Some(r#"^#include\s*[<"]"#),
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "dockerfile",
+        title: "Dockerfile",
+        aliases: &["docker"],
+        filenames: &["Dockerfile", "Containerfile"],
+        globs: &["Dockerfile.*", "*.dockerfile"],
+        line_comment: Some("#"),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "go",
+        title: "Go",
+        aliases: &["golang"],
+        grammar: Some(language::go),
+        highlights: language::GO_HIGHLIGHTS,
+        locals: language::GO_LOCALS,
+        tags: language::GO_TAGS,
+        extensions: &["go"],
+        first_line: Some(r"^package\s+\w+\s*$"),
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "html",
+        title: "HTML",
+        aliases: &["htm", "xhtml"],
+        grammar: Some(language::html),
+        highlights: language::HTML_HIGHLIGHTS,
+        injections: language::HTML_INJECTIONS,
+        extensions: &["html", "htm", "xhtml"],
+        first_line: Some(r"(?i)^<(!doctype\s+html|html)\b"),
+        block_comment: Some(("<!--", "-->")),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "javascript",
+        title: "JavaScript",
+        aliases: &["js", "jsx", "node"],
+        grammar: Some(language::javascript),
+        highlights: language::JS_HIGHLIGHTS,
+        injections: language::JS_INJECTIONS,
+        locals: language::JS_LOCALS,
+        tags: language::JS_TAGS,
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        interpreters: &["node", "nodejs", "deno"],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "make",
+        title: "Makefile",
+        aliases: &["makefile"],
+        extensions: &["mk", "mak"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        interpreters: &["make"],
+        line_comment: Some("#"),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "markdown",
+        title: "Markdown",
+        aliases: &["md"],
+        extensions: &["md", "markdown"],
+        block_comment: Some(("<!--", "-->")),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "python",
+        title: "Python",
+        aliases: &["py"],
+        extensions: &["py", "pyi", "pyw"],
+        interpreters: &["python"],
+        line_comment: Some("#"),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "rust",
+        title: "Rust",
+        aliases: &["rs"],
+        grammar: Some(language::rust),
+        highlights: language::RUST_HIGHLIGHTS,
+        locals: language::RUST_LOCALS,
+        tags: language::RUST_TAGS,
+        injections: language::RUST_INJECTIONS,
+        extensions: &["rs"],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        ..LanguageConfig::DEFAULT
+    },
+    LanguageConfig {
+        name: "shell",
+        title: "Shell",
+        aliases: &["sh", "bash", "zsh"],
+        extensions: &["sh", "bash", "zsh"],
+        filenames: &[
+            ".bashrc",
+            ".bash_profile",
+            ".bash_logout",
+            ".bash_aliases",
+            ".profile",
+            ".zshrc",
+            ".zprofile",
+        ],
+        interpreters: &["sh", "bash", "zsh", "dash", "ksh"],
+        line_comment: Some("#"),
+        ..LanguageConfig::DEFAULT
+    },
+];
+
+/// A language by its name or one of its aliases
+pub fn language_by_name(name: &str) -> Option<&'static LanguageConfig> {
+    LANGUAGES.iter().find(|l| l.is_named(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::TreeSitterLayer;
+
+    #[test]
+    fn test_every_language_loads() {
+        for lang in LANGUAGES.iter().filter(|l| l.grammar.is_some()) {
+            if let Err(e) = TreeSitterLayer::new(lang) {
+                panic!("{}: {e}", lang.name);
+            }
+        }
+        assert_eq!(language_by_name("JS").map(|l| l.name), Some("javascript"));
+        assert!(language_by_name("cobol").is_none());
+    }
+}
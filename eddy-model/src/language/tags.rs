@@ -0,0 +1,324 @@
+//! The functions, types and constants a tags query finds, nested the way
+//! they are in the file, for outlines and breadcrumbs.
+
+use super::registry::LanguageConfig;
+use super::treesitter::Queries;
+use super::util::RopeTextProvider;
+use crate::fuzzy::{FuzzyMatch, FuzzyMatcher};
+use crate::Range;
+use eddy_ts::{QueryCursor, Tree};
+use ropey::Rope;
+use std::collections::HashMap;
+use std::fmt;
+
+/// What a symbol is, from its `@definition.*` capture
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Method,
+    Class,
+    Interface,
+    Type,
+    Implementation,
+    Module,
+    Constant,
+    Macro,
+}
+
+impl SymbolKind {
+    fn from_capture(name: &str) -> Option<Self> {
+        Some(match name.strip_prefix("definition.")? {
+            "function" => SymbolKind::Function,
+            "method" => SymbolKind::Method,
+            "class" => SymbolKind::Class,
+            "interface" => SymbolKind::Interface,
+            "type" => SymbolKind::Type,
+            "implementation" => SymbolKind::Implementation,
+            "module" => SymbolKind::Module,
+            "constant" => SymbolKind::Constant,
+            "macro" => SymbolKind::Macro,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SymbolKind::Function => "function",
+            SymbolKind::Method => "method",
+            SymbolKind::Class => "class",
+            SymbolKind::Interface => "interface",
+            SymbolKind::Type => "type",
+            SymbolKind::Implementation => "impl",
+            SymbolKind::Module => "module",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Macro => "macro",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A definition, and the definitions inside it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The whole definition, in chars
+    pub range: Range,
+    /// Just the name, in chars
+    pub name_range: Range,
+    /// The line the name is on
+    pub line: usize,
+    pub children: Vec<Symbol>,
+}
+
+/// The outline of a file, with methods inside their types and so on
+pub fn symbols(config: &'static LanguageConfig, tree: &Tree, rope: &Rope) -> Vec<Symbol> {
+    let mut roots = Vec::new();
+    let mut open: Vec<Symbol> = Vec::new();
+    for symbol in find_symbols(config, tree, rope, None) {
+        while let Some(last) = open.pop() {
+            if symbol.range.end <= last.range.end {
+                open.push(last);
+                break;
+            }
+            match open.last_mut() {
+                Some(parent) => parent.children.push(last),
+                None => roots.push(last),
+            }
+        }
+        open.push(symbol);
+    }
+    while let Some(last) = open.pop() {
+        match open.last_mut() {
+            Some(parent) => parent.children.push(last),
+            None => roots.push(last),
+        }
+    }
+    roots
+}
+
+/// A symbol in a flat list, like a row of the symbol picker
+#[derive(Debug, Clone)]
+pub struct SymbolEntry {
+    /// Without its children
+    pub symbol: Symbol,
+    /// How many symbols it's inside of
+    pub depth: usize,
+    /// Where the query matched the name
+    pub fuzzy: FuzzyMatch,
+}
+
+/// The symbols whose names match a query, best first, or all of them in
+/// outline order if the query is empty
+pub fn symbol_entries(symbols: Vec<Symbol>, query: &str) -> Vec<SymbolEntry> {
+    let mut matcher = FuzzyMatcher::new(query);
+    let mut entries = Vec::new();
+    let mut todo: Vec<(usize, Symbol)> = symbols.into_iter().rev().map(|s| (0, s)).collect();
+    while let Some((depth, mut symbol)) = todo.pop() {
+        let children = std::mem::take(&mut symbol.children);
+        todo.extend(children.into_iter().rev().map(|s| (depth + 1, s)));
+        if let Some(fuzzy) = matcher.matches(&symbol.name) {
+            entries.push(SymbolEntry {
+                symbol,
+                depth,
+                fuzzy,
+            });
+        }
+    }
+    // Matches that score the same stay in outline order
+    entries.sort_by_key(|e| std::cmp::Reverse(e.fuzzy.score));
+    entries
+}
+
+/// The symbols a char is inside of, outermost first and without their
+/// children, for breadcrumbs
+pub fn symbols_at(
+    config: &'static LanguageConfig,
+    tree: &Tree,
+    rope: &Rope,
+    char: usize,
+) -> Vec<Symbol> {
+    let byte = rope.char_to_byte(char.min(rope.len_chars()));
+    find_symbols(config, tree, rope, Some(byte..byte + 1))
+        .into_iter()
+        .filter(|s| s.range.start <= char && char < s.range.end)
+        .collect()
+}
+
+/// Every symbol that touches some bytes, in order, with the outer ones
+/// before those inside them
+fn find_symbols(
+    config: &'static LanguageConfig,
+    tree: &Tree,
+    rope: &Rope,
+    bytes: Option<std::ops::Range<usize>>,
+) -> Vec<Symbol> {
+    if config.tags.is_empty() {
+        return Vec::new();
+    }
+    let queries = match Queries::of(config) {
+        Ok(queries) => queries,
+        Err(e) => {
+            log::error!("unable to load {}: {e}", config.name);
+            return Vec::new();
+        }
+    };
+    let Some(query) = &queries.tags else {
+        return Vec::new();
+    };
+    let len = rope.len_bytes();
+    let chars = |r: std::ops::Range<usize>| Range {
+        start: rope.byte_to_char(r.start.min(len)),
+        end: rope.byte_to_char(r.end.min(len)),
+    };
+
+    // A node some patterns match more than once is what the first of them
+    // says it is, like a method rather than a function
+    let mut found: HashMap<(usize, usize), (usize, Symbol)> = HashMap::new();
+    let mut cursor = QueryCursor::new();
+    if let Some(bytes) = bytes {
+        cursor.set_byte_range(bytes);
+    }
+    for m in cursor.matches(query, tree.root_node(), RopeTextProvider::new(rope)) {
+        let mut name = None;
+        let mut definition = None;
+        for c in m.captures {
+            match query.capture_names()[c.index as usize].as_str() {
+                "name" => name = Some(c.node),
+                capture => {
+                    if let Some(kind) = SymbolKind::from_capture(capture) {
+                        definition = Some((c.node, kind));
+                    }
+                }
+            }
+        }
+        let (Some(name), Some((node, kind))) = (name, definition) else {
+            continue;
+        };
+        let key = (node.start_byte(), node.end_byte());
+        if found.get(&key).is_some_and(|(p, _)| *p <= m.pattern_index) {
+            continue;
+        }
+        let text = rope.byte_slice(name.start_byte().min(len)..name.end_byte().min(len));
+        let symbol = Symbol {
+            name: text
+                .to_string()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            kind,
+            range: chars(node.byte_range()),
+            name_range: chars(name.byte_range()),
+            line: rope.byte_to_line(name.start_byte().min(len)),
+            children: Vec::new(),
+        };
+        found.insert(key, (m.pattern_index, symbol));
+    }
+    let mut symbols: Vec<Symbol> = found.into_values().map(|(_, s)| s).collect();
+    symbols.sort_by(|a, b| {
+        a.range
+            .start
+            .cmp(&b.range.start)
+            .then(b.range.end.cmp(&a.range.end))
+    });
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::language_by_name;
+    use eddy_ts::Parser;
+
+    fn parse(language: &str, text: &str) -> (&'static LanguageConfig, Tree, Rope) {
+        let config = language_by_name(language).unwrap();
+        let mut parser = Parser::new();
+        parser.set_language(config.grammar.unwrap()()).unwrap();
+        let tree = parser.parse(text, None).unwrap();
+        (config, tree, Rope::from_str(text))
+    }
+
+    /// Each symbol as `kind name`, indented by how deep it is
+    fn outline(symbols: &[Symbol], depth: usize, lines: &mut Vec<String>) {
+        for s in symbols {
+            lines.push(format!("{}{} {}", "  ".repeat(depth), s.kind, s.name));
+            outline(&s.children, depth + 1, lines);
+        }
+    }
+
+    fn outline_of(language: &str, text: &str) -> Vec<String> {
+        let (config, tree, rope) = parse(language, text);
+        let mut lines = Vec::new();
+        outline(&symbols(config, &tree, &rope), 0, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn test_rust_symbols() {
+        let text = "const N: u8 = 1;\nstruct A;\nimpl A {\n    fn new() -> A {\n        fn inner() {}\n        A\n    }\n}\ntrait T {\n    fn t(&self);\n}\nmod m {\n    pub fn f() {}\n}\n";
+        assert_eq!(
+            outline_of("rust", text),
+            vec![
+                "constant N",
+                "class A",
+                "impl A",
+                "  method new",
+                "    function inner",
+                "interface T",
+                "  method t",
+                "module m",
+                "  function f",
+            ]
+        );
+
+        let (config, tree, rope) = parse("rust", text);
+        let at = text.find("fn inner").unwrap() + 3;
+        let path: Vec<String> = symbols_at(config, &tree, &rope, at)
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(path, vec!["A", "new", "inner"]);
+        let new = &symbols(config, &tree, &rope)[2].children[0];
+        assert_eq!(new.line, 3);
+        assert_eq!(&text[new.name_range.start..new.name_range.end], "new");
+
+        let names = |query| -> Vec<(String, usize)> {
+            symbol_entries(symbols(config, &tree, &rope), query)
+                .into_iter()
+                .map(|e| (e.symbol.name, e.depth))
+                .collect()
+        };
+        assert_eq!(names("").len(), 9);
+        assert_eq!(names("")[3], ("new".to_string(), 1));
+        assert_eq!(names("in"), vec![("inner".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_go_and_javascript_symbols() {
+        let text = "package p\nconst N = 1\ntype S struct{}\ntype I interface {\n\tM()\n}\nfunc (s S) M() {}\nfunc F() {}\n";
+        assert_eq!(
+            outline_of("go", text),
+            vec![
+                "constant N",
+                "type S",
+                "type I",
+                "  method M",
+                "method M",
+                "function F",
+            ]
+        );
+        let text = "const n = 1;\nclass A {\n  m() {}\n}\nconst f = () => {};\nfunction g() {}\n";
+        assert_eq!(
+            outline_of("javascript", text),
+            vec![
+                "constant n",
+                "class A",
+                "  method m",
+                "function f",
+                "function g",
+            ]
+        );
+    }
+}
@@ -0,0 +1,543 @@
+use super::registry::{language_by_name, LanguageConfig};
+use super::{Layer, PARSE_TIMEOUT_MICROS};
+use crate::language::capture::Capture;
+use crate::language::highlight::{HighlightCache, HighlightSource};
+use crate::language::locals::Locals;
+use crate::language::util::{input_edit, RopeTextProvider};
+use crate::Point;
+use eddy_ts::{InputEdit, Node, Parser, Query, QueryCursor, QueryError, Tree};
+use ropey::Rope;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+
+/// How deep languages can be injected into each other
+const MAX_INJECTION_DEPTH: usize = 4;
+
+/// The compiled queries of a language, shared by every layer of it
+pub struct Queries {
+    pub highlights: Query,
+    pub captures_by_id: Vec<Option<Capture>>,
+    pub injections: Option<Query>,
+    pub locals: Option<Query>,
+    pub tags: Option<Query>,
+}
+
+impl Queries {
+    /// Compiles a language's queries the first time they're asked for
+    pub fn of(config: &'static LanguageConfig) -> Result<Arc<Queries>, QueryError> {
+        static CACHE: Mutex<Vec<(&'static str, Arc<Queries>)>> = Mutex::new(Vec::new());
+        let mut cache = CACHE.lock().expect("queries");
+        if let Some((_, queries)) = cache.iter().find(|(name, _)| *name == config.name) {
+            return Ok(queries.clone());
+        }
+        let grammar = config.grammar.expect("a language with a grammar")();
+        let highlights = Query::new(grammar, config.highlights)?;
+        let captures_by_id = highlights
+            .capture_names()
+            .iter()
+            .map(|cn| Capture::from_name(cn))
+            .collect();
+        let injections = if config.injections.is_empty() {
+            None
+        } else {
+            Some(Query::new(grammar, config.injections)?)
+        };
+        let locals = if config.locals.is_empty() {
+            None
+        } else {
+            Some(Query::new(grammar, config.locals)?)
+        };
+        let tags = if config.tags.is_empty() {
+            None
+        } else {
+            Some(Query::new(grammar, config.tags)?)
+        };
+        let queries = Arc::new(Queries {
+            highlights,
+            captures_by_id,
+            injections,
+            locals,
+            tags,
+        });
+        cache.push((config.name, queries.clone()));
+        Ok(queries)
+    }
+}
+
+/// A language parsed within some ranges of the text, and the languages
+/// injected into it
+struct Syntax {
+    config: &'static LanguageConfig,
+    queries: Arc<Queries>,
+    parser: Parser,
+    tree: Option<Tree>,
+    locals: Locals,
+    /// The bytes edited or reparsed since the locals were last found
+    locals_changed: Vec<Range<usize>>,
+    /// Where the language is in the text, or empty for all of it
+    ranges: Vec<eddy_ts::Range>,
+    depth: usize,
+    injections: Vec<Syntax>,
+    /// Kept alive for the parser, which stops when it's set
+    cancel: Option<Arc<AtomicUsize>>,
+}
+
+impl Syntax {
+    fn new(
+        config: &'static LanguageConfig,
+        depth: usize,
+        cancel: Option<Arc<AtomicUsize>>,
+    ) -> Result<Self, QueryError> {
+        let queries = Queries::of(config)?;
+        let mut parser = Parser::new();
+        let grammar = config.grammar.expect("a language with a grammar")();
+        if let Err(e) = parser.set_language(grammar) {
+            log::error!("{}: {e}", config.name);
+        }
+        parser.set_timeout_micros(PARSE_TIMEOUT_MICROS);
+        let mut syntax = Self {
+            config,
+            queries,
+            parser,
+            tree: None,
+            locals: Locals::default(),
+            locals_changed: Vec::new(),
+            ranges: Vec::new(),
+            depth,
+            injections: Vec::new(),
+            cancel: None,
+        };
+        if let Some(flag) = cancel {
+            syntax.set_cancellation_flag(flag);
+        }
+        Ok(syntax)
+    }
+
+    fn set_cancellation_flag(&mut self, flag: Arc<AtomicUsize>) {
+        // The parser keeps a pointer to the flag, which lives as long as
+        // the syntax keeps the Arc
+        unsafe { self.parser.set_cancellation_flag(Some(&flag)) };
+        for injection in &mut self.injections {
+            injection.set_cancellation_flag(flag.clone());
+        }
+        self.cancel = Some(flag);
+    }
+
+    fn reset_parse(&mut self) {
+        self.parser.reset();
+        for injection in &mut self.injections {
+            injection.reset_parse();
+        }
+    }
+
+    fn edit(&mut self, edit: &InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(edit);
+        }
+        self.locals.edit(edit);
+        // Only the bytes are kept up to date, to find the injection again
+        // after the edit.  The ranges are found again before parsing.
+        let shift = |byte: &mut usize| {
+            if *byte >= edit.old_end_byte {
+                *byte = *byte + edit.new_end_byte - edit.old_end_byte;
+            } else if *byte > edit.start_byte {
+                *byte = edit.start_byte;
+            }
+        };
+        for range in &mut self.ranges {
+            shift(&mut range.start_byte);
+            shift(&mut range.end_byte);
+        }
+        for range in &mut self.locals_changed {
+            shift(&mut range.start);
+            shift(&mut range.end);
+        }
+        self.locals_changed.push(edit.start_byte..edit.new_end_byte);
+        for injection in &mut self.injections {
+            injection.edit(edit);
+        }
+    }
+
+    /// Parses the text again, then the languages injected into it, adding
+    /// the bytes whose highlights may have changed to `changed`.  Returns
+    /// false if parsing stopped before it was done.
+    fn parse(&mut self, rope: &Rope, changed: &mut Vec<Range<usize>>) -> bool {
+        let old_tree = self.tree.take();
+        let tree = self.parser.parse_with(
+            &mut |byte_idx, _pos| {
+                if byte_idx > rope.len_bytes() {
+                    return [].as_ref();
+                }
+                let (s, chunk_byte_idx, _, _) = rope.chunk_at_byte(byte_idx);
+                &s.as_bytes()[byte_idx - chunk_byte_idx..]
+            },
+            old_tree.as_ref(),
+        );
+        let Some(tree) = tree else {
+            // Stopped by the timeout or the cancellation flag.  The old tree
+            // still matches the edits, for when the parse starts over.
+            self.tree = old_tree;
+            return false;
+        };
+        // Besides the lines that were edited, highlight again wherever the
+        // structure of the tree changed
+        match &old_tree {
+            Some(old_tree) => changed.extend(
+                old_tree
+                    .changed_ranges(&tree)
+                    .map(|r| r.start_byte..r.end_byte),
+            ),
+            None => changed.push(tree.root_node().byte_range()),
+        }
+        self.tree = Some(tree);
+        self.update_locals(rope, changed) && self.parse_injections(rope, changed)
+    }
+
+    /// Finds the definitions and references again where the text or the
+    /// tree changed, adding the references whose highlights changed to
+    /// `changed`.  Returns false if it was cancelled.
+    fn update_locals(&mut self, rope: &Rope, changed: &mut Vec<Range<usize>>) -> bool {
+        let (Some(_), Some(tree)) = (&self.queries.locals, &self.tree) else {
+            return true;
+        };
+        self.locals_changed.extend(changed.iter().cloned());
+        let cancel = self.cancel.as_deref();
+        let Some(locals) =
+            self.locals
+                .update(rope, tree, &self.queries, &self.locals_changed, cancel)
+        else {
+            return false;
+        };
+        self.locals_changed.clear();
+        changed.extend(locals.changed_since(&self.locals));
+        self.locals = locals;
+        true
+    }
+
+    fn parse_injections(&mut self, rope: &Rope, changed: &mut Vec<Range<usize>>) -> bool {
+        let found = if self.depth < MAX_INJECTION_DEPTH {
+            self.find_injections(rope)
+        } else {
+            Vec::new()
+        };
+
+        // Injections that are still there keep their trees, so they're
+        // parsed again incrementally
+        let mut old = std::mem::take(&mut self.injections);
+        for (config, ranges) in found {
+            let at = old.iter().position(|s| {
+                s.config.name == config.name
+                    && s.ranges.first().map(|r| r.start_byte) == Some(ranges[0].start_byte)
+            });
+            let mut syntax = match at {
+                Some(at) => old.swap_remove(at),
+                None => match Syntax::new(config, self.depth + 1, self.cancel.clone()) {
+                    Ok(syntax) => syntax,
+                    Err(e) => {
+                        log::error!("unable to load {}: {e}", config.name);
+                        continue;
+                    }
+                },
+            };
+            if let Err(e) = syntax.parser.set_included_ranges(&ranges) {
+                log::error!("bad {} injection: {e}", config.name);
+                continue;
+            }
+            syntax.ranges = ranges;
+            self.injections.push(syntax);
+        }
+        for gone in old {
+            if let Some(tree) = &gone.tree {
+                changed.push(tree.root_node().byte_range());
+            }
+        }
+
+        self.injections
+            .iter_mut()
+            .all(|injection| injection.parse(rope, changed))
+    }
+
+    /// The languages the injections query finds, and the ranges of each
+    fn find_injections(&self, rope: &Rope) -> Vec<(&'static LanguageConfig, Vec<eddy_ts::Range>)> {
+        let (Some(query), Some(tree)) = (&self.queries.injections, &self.tree) else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        let mut cursor = QueryCursor::new();
+        for m in cursor.matches(query, tree.root_node(), RopeTextProvider::new(rope)) {
+            let mut language = None;
+            let mut include_children = false;
+            for prop in query.property_settings(m.pattern_index) {
+                match &*prop.key {
+                    "injection.language" => language = prop.value.as_deref().map(str::to_string),
+                    "injection.include-children" => include_children = true,
+                    _ => {}
+                }
+            }
+            let mut contents = Vec::new();
+            for c in m.captures {
+                match query.capture_names()[c.index as usize].as_str() {
+                    "injection.language" => {
+                        let r = c.node.byte_range();
+                        language = Some(rope.byte_slice(r).to_string());
+                    }
+                    "injection.content" => contents.push(c.node),
+                    _ => {}
+                }
+            }
+            let Some(config) = language
+                .as_deref()
+                .and_then(language_by_name)
+                .filter(|c| c.grammar.is_some())
+            else {
+                continue;
+            };
+            for node in contents {
+                let ranges = content_ranges(node, include_children);
+                if !ranges.is_empty() {
+                    found.push((config, ranges));
+                }
+            }
+        }
+        found
+    }
+
+    /// Adds this syntax's highlights query, then its injections'
+    fn highlight_sources<'a>(&'a self, sources: &mut Vec<HighlightSource<'a>>) {
+        if let Some(tree) = &self.tree {
+            sources.push(HighlightSource {
+                tree,
+                query: &self.queries.highlights,
+                captures_by_id: &self.queries.captures_by_id,
+                locals: self.queries.locals.as_ref().map(|_| &self.locals),
+            });
+        }
+        for injection in &self.injections {
+            injection.highlight_sources(sources);
+        }
+    }
+
+    /// Adds this syntax's locals, then its injections'
+    fn collect_locals(&self, locals: &mut Locals) {
+        locals.extend(self.locals.clone());
+        for injection in &self.injections {
+            injection.collect_locals(locals);
+        }
+    }
+
+    /// How many languages are injected, all the way down
+    fn injection_count(&self) -> usize {
+        self.injections
+            .iter()
+            .map(|i| 1 + i.injection_count())
+            .sum()
+    }
+}
+
+/// The ranges of an injected node.  Unless the query says to include them,
+/// its children are left out, as they belong to the outer language.
+fn content_ranges(node: Node<'_>, include_children: bool) -> Vec<eddy_ts::Range> {
+    let whole = node.range();
+    if include_children {
+        return vec![whole];
+    }
+    let mut ranges = Vec::new();
+    let mut start = (whole.start_byte, whole.start_point);
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.start_byte() > start.0 {
+            ranges.push(eddy_ts::Range {
+                start_byte: start.0,
+                end_byte: child.start_byte(),
+                start_point: start.1,
+                end_point: child.start_position(),
+            });
+        }
+        start = (child.end_byte(), child.end_position());
+    }
+    if whole.end_byte > start.0 {
+        ranges.push(eddy_ts::Range {
+            start_byte: start.0,
+            end_byte: whole.end_byte,
+            start_point: start.1,
+            end_point: whole.end_point,
+        });
+    }
+    ranges
+}
+
+/// Parses and highlights any language in the registry, along with the
+/// languages injected into it
+pub struct TreeSitterLayer {
+    syntax: Syntax,
+    highlights: HighlightCache,
+}
+
+impl TreeSitterLayer {
+    /// The language must have a grammar
+    pub fn new(config: &'static LanguageConfig) -> Result<Self, QueryError> {
+        Ok(Self {
+            syntax: Syntax::new(config, 0, None)?,
+            highlights: HighlightCache::default(),
+        })
+    }
+
+    pub fn config(&self) -> &'static LanguageConfig {
+        self.syntax.config
+    }
+
+    /// The names of the languages injected into the text, all the way down
+    pub fn injected_languages(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        let mut todo: Vec<&Syntax> = self.syntax.injections.iter().collect();
+        while let Some(syntax) = todo.pop() {
+            names.push(syntax.config.name);
+            todo.extend(syntax.injections.iter());
+        }
+        names
+    }
+}
+
+impl Layer for TreeSitterLayer {
+    fn language(&self) -> Option<&'static str> {
+        Some(self.syntax.config.name)
+    }
+    fn capture(&self, idx: usize) -> Option<Capture> {
+        self.syntax.queries.captures_by_id.get(idx).and_then(|c| *c)
+    }
+    fn highlights(&self) -> Option<&HighlightCache> {
+        Some(&self.highlights)
+    }
+    fn set_cancellation_flag(&mut self, flag: Arc<AtomicUsize>) {
+        self.syntax.set_cancellation_flag(flag);
+    }
+    fn reset_parse(&mut self) {
+        self.syntax.reset_parse();
+    }
+    fn unset_tree(&mut self) {
+        self.syntax.tree = None;
+        self.syntax.locals = Locals::default();
+        self.syntax.locals_changed.clear();
+        self.syntax.injections.clear();
+        self.highlights.clear();
+    }
+    fn tree(&self) -> Option<&Tree> {
+        self.syntax.tree.as_ref()
+    }
+    fn locals(&self) -> Option<Locals> {
+        let mut locals = Locals::default();
+        self.syntax.collect_locals(&mut locals);
+        Some(locals)
+    }
+    fn edit_tree(&mut self, start: Point, old_end: Point, new_end: Point) {
+        self.highlights.edit(start.line, old_end.line, new_end.line);
+        self.syntax.edit(&input_edit(start, old_end, new_end));
+    }
+
+    fn update_highlights(&mut self, rope: &Rope) -> bool {
+        let mut changed = Vec::new();
+        let done = self.syntax.parse(rope, &mut changed);
+        // A stopped parse may not find these again when it carries on, so
+        // they're marked now
+        for range in changed {
+            self.highlights.invalidate(rope, range);
+        }
+        if !done {
+            return false;
+        }
+        let mut sources = Vec::new();
+        self.syntax.highlight_sources(&mut sources);
+        self.highlights.update(rope, &sources);
+        true
+    }
+}
+
+impl fmt::Debug for TreeSitterLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TreeSitterLayer")
+            .field("config", &self.syntax.config)
+            .field("injections", &self.syntax.injection_count())
+            .field("highlights", &self.highlights)
+            .field("tree", &self.syntax.tree)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::HighlightSpan;
+
+    #[test]
+    fn test_injections() {
+        let html = language_by_name("html").unwrap();
+        let mut layer = TreeSitterLayer::new(html).unwrap();
+        let mut rope = Rope::from_str("<p>hi</p>\n<script>\nlet x = 1;\n</script>\n");
+        assert!(layer.update_highlights(&rope));
+        assert_eq!(layer.injected_languages(), vec!["javascript"]);
+        let keyword = Capture::from_name("keyword").unwrap();
+        let has_keyword = |layer: &TreeSitterLayer, line| {
+            layer
+                .highlights()
+                .unwrap()
+                .line(line)
+                .iter()
+                .any(|s| s.capture == keyword)
+        };
+        assert!(has_keyword(&layer, 2));
+
+        // Edits carry over into the injected tree
+        let point = |rope: &Rope, char: usize| {
+            let line = rope.char_to_line(char);
+            Point {
+                byte: rope.char_to_byte(char),
+                char,
+                line,
+                col: char - rope.line_to_char(line),
+            }
+        };
+        let at = rope.line_to_char(2);
+        rope.insert(at, "const y = 2;\n");
+        layer.edit_tree_insert(point(&rope, at), point(&rope, at + 13));
+        assert!(layer.update_highlights(&rope));
+        assert_eq!(layer.injected_languages(), vec!["javascript"]);
+        assert!(has_keyword(&layer, 2));
+        assert!(has_keyword(&layer, 3));
+        assert!(!has_keyword(&layer, 0));
+    }
+
+    #[test]
+    fn test_locals_highlights() {
+        let rust = language_by_name("rust").unwrap();
+        let mut layer = TreeSitterLayer::new(rust).unwrap();
+        let rope = Rope::from_str("fn f(a: u8) -> u8 {\n    a\n}\n");
+        assert!(layer.update_highlights(&rope));
+        assert_eq!(
+            layer.highlights().unwrap().line(1),
+            &[HighlightSpan {
+                start: 4,
+                end: 5,
+                capture: Capture::from_name("variable.parameter").unwrap(),
+            }]
+        );
+
+        // A parameter named like a builtin is still a parameter
+        let js = language_by_name("javascript").unwrap();
+        let mut layer = TreeSitterLayer::new(js).unwrap();
+        let rope = Rope::from_str("console;\nfunction f(console) {\n  console;\n}\n");
+        assert!(layer.update_highlights(&rope));
+        let captures = |line| -> Vec<&str> {
+            layer
+                .highlights()
+                .unwrap()
+                .line(line)
+                .iter()
+                .filter(|s| s.start < 9)
+                .map(|s| s.capture.name())
+                .collect()
+        };
+        assert_eq!(captures(2), vec!["variable.parameter"]);
+    }
+}
@@ -1,6 +1,28 @@
-use eddy_ts::{Node, TextProvider};
+use crate::Point;
+use eddy_ts::{InputEdit, Node, TextProvider};
 use ropey::Rope;
 
+/// Describes an edit the way tree-sitter wants it
+pub fn input_edit(start: Point, old_end: Point, new_end: Point) -> InputEdit {
+    InputEdit {
+        start_byte: start.byte,
+        old_end_byte: old_end.byte,
+        new_end_byte: new_end.byte,
+        start_position: eddy_ts::Point {
+            row: start.line,
+            column: start.col,
+        },
+        old_end_position: eddy_ts::Point {
+            row: old_end.line,
+            column: old_end.col,
+        },
+        new_end_position: eddy_ts::Point {
+            row: new_end.line,
+            column: new_end.col,
+        },
+    }
+}
+
 pub struct RopeTextProvider<'a> {
     rope: &'a Rope,
 }
@@ -0,0 +1,425 @@
+//! Parsing and highlighting on a thread of their own, so that typing in a
+//! large file doesn't wait for them.
+
+use super::highlight::{HighlightCache, HighlightSpan};
+use super::layer::Layer;
+use super::locals::Locals;
+use super::util::input_edit;
+use crate::backend::PeekableReceiver;
+use crate::Point;
+use eddy_ts::Tree;
+use log::error;
+use ropey::Rope;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+type Wakeup = Arc<dyn Fn() + Send + Sync>;
+
+/// An edit to the text, in the units `Layer::edit_tree` takes
+#[derive(Debug, Copy, Clone)]
+struct TreeEdit {
+    start: Point,
+    old_end: Point,
+    new_end: Point,
+}
+
+/// Text to parse, and how it changed since the last job
+struct Job {
+    version: u64,
+    rope: Rope,
+    /// Forget the old tree instead of editing it
+    reset: bool,
+    edits: Vec<TreeEdit>,
+}
+
+impl Job {
+    /// Folds a newer job into this one, as only the newest text is worth
+    /// parsing
+    fn merge(&mut self, newer: Job) {
+        self.version = newer.version;
+        self.rope = newer.rope;
+        self.reset |= newer.reset;
+        self.edits.extend(newer.edits);
+    }
+}
+
+/// What parsing a version of the text came up with
+struct Parsed {
+    version: u64,
+    highlights: HighlightCache,
+    tree: Option<Tree>,
+    locals: Locals,
+}
+
+/// Keeps a buffer's layer on a worker thread.  The text is sent over as a
+/// snapshot whenever it changes, and the highlights of the last finished
+/// parse are shown until the next one is done.
+pub struct Highlighter {
+    language: Option<&'static str>,
+    /// `None` for layers that don't parse anything
+    jobs: Option<Sender<Job>>,
+    results: PeekableReceiver<Parsed>,
+    /// Set to stop a parse that a newer edit made stale
+    cancel: Arc<AtomicUsize>,
+    wakeup: Arc<Mutex<Option<Wakeup>>>,
+    /// The version of the text the last job was sent with
+    version: u64,
+    /// Results for older versions can't be brought up to date, as the
+    /// edits since then weren't known
+    oldest_usable: u64,
+    reset: bool,
+    /// Edits not sent to the worker yet
+    edits: Vec<TreeEdit>,
+    /// Edits and the version they first appear in, to bring the results
+    /// for older versions up to date
+    sent_edits: Vec<(u64, TreeEdit)>,
+    highlights: HighlightCache,
+    tree: Option<Tree>,
+    locals: Locals,
+}
+
+impl Highlighter {
+    pub fn new(layer: Box<dyn Layer>) -> Self {
+        Self::spawn(layer, Arc::new(Mutex::new(None)))
+    }
+
+    /// Starts over with another layer, keeping the wakeup
+    pub fn set_layer(&mut self, layer: Box<dyn Layer>) {
+        *self = Self::spawn(layer, self.wakeup.clone());
+    }
+
+    fn spawn(layer: Box<dyn Layer>, wakeup: Arc<Mutex<Option<Wakeup>>>) -> Self {
+        let (result_sender, results) = channel();
+        let cancel = Arc::new(AtomicUsize::new(0));
+        let language = layer.language();
+        let jobs = if layer.highlights().is_some() {
+            let (sender, receiver) = channel();
+            let (cancel, wakeup) = (cancel.clone(), wakeup.clone());
+            let spawned = thread::Builder::new()
+                .name("highlighter".to_string())
+                .spawn(move || run(layer, receiver, result_sender, cancel, wakeup));
+            match spawned {
+                Ok(_) => Some(sender),
+                Err(e) => {
+                    error!("unable to start highlighter: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Self {
+            language,
+            jobs,
+            results: PeekableReceiver::new(results),
+            cancel,
+            wakeup,
+            version: 0,
+            oldest_usable: 0,
+            reset: false,
+            edits: Vec::new(),
+            sent_edits: Vec::new(),
+            highlights: HighlightCache::default(),
+            tree: None,
+            locals: Locals::default(),
+        }
+    }
+
+    /// Called when new highlights are ready, from the worker thread
+    pub fn set_wakeup(&mut self, wakeup: Arc<dyn Fn() + Send + Sync>) {
+        *self.wakeup.lock().expect("highlighter wakeup") = Some(wakeup);
+    }
+
+    pub fn language(&self) -> Option<&'static str> {
+        self.language
+    }
+
+    /// The highlights of a line as of the last finished parse.  The lines
+    /// edited since may be a little off.
+    pub fn line_highlights(&self, line: usize) -> &[HighlightSpan] {
+        self.highlights.line(line)
+    }
+
+    /// The syntax tree of the last finished parse
+    pub fn tree(&self) -> Option<&Tree> {
+        self.tree.as_ref()
+    }
+
+    /// The local variables as of the last finished parse
+    pub fn locals(&self) -> &Locals {
+        &self.locals
+    }
+
+    /// Notes an edit, to send with the next snapshot.  All units are in
+    /// code points.
+    pub fn edit_tree(&mut self, start: Point, old_end: Point, new_end: Point) {
+        if self.jobs.is_none() {
+            return;
+        }
+        let edit = TreeEdit {
+            start,
+            old_end,
+            new_end,
+        };
+        self.highlights.shift(start, old_end, new_end);
+        let input = input_edit(start, old_end, new_end);
+        if let Some(tree) = &mut self.tree {
+            tree.edit(&input);
+        }
+        self.locals.edit(&input);
+        self.edits.push(edit);
+        self.sent_edits.push((self.version + 1, edit));
+    }
+
+    pub fn edit_tree_remove(&mut self, start: Point, old_end: Point) {
+        self.edit_tree(start, old_end, start);
+    }
+
+    pub fn edit_tree_insert(&mut self, start: Point, new_end: Point) {
+        self.edit_tree(start, start, new_end);
+    }
+
+    /// Parses the next snapshot from scratch, for when the edits that led
+    /// to it aren't known.  Until that parse is done there is no tree, as
+    /// the old one is for different text.
+    pub fn unset_tree(&mut self) {
+        self.reset = true;
+        self.oldest_usable = self.version + 1;
+        self.edits.clear();
+        self.sent_edits.clear();
+        self.highlights.clear();
+        self.tree = None;
+        self.locals = Locals::default();
+    }
+
+    /// Sends a snapshot of the text to be parsed and highlighted, stopping
+    /// the parse of the last one if it's still going
+    pub fn update(&mut self, rope: &Rope) {
+        let Some(jobs) = &self.jobs else {
+            return;
+        };
+        self.version += 1;
+        if self.reset {
+            self.oldest_usable = self.version;
+        }
+        let job = Job {
+            version: self.version,
+            rope: rope.clone(),
+            reset: std::mem::take(&mut self.reset),
+            edits: std::mem::take(&mut self.edits),
+        };
+        self.cancel.store(1, Ordering::SeqCst);
+        if jobs.send(job).is_err() {
+            error!("highlighter stopped");
+            self.jobs = None;
+        }
+    }
+
+    /// Whether a parse finished since the last `apply_results`
+    pub fn has_results(&self) -> bool {
+        self.results.has_read()
+    }
+
+    /// Shows the highlights of the newest finished parse.  Returns false if
+    /// there weren't any.
+    pub fn apply_results(&mut self) -> bool {
+        let mut newest = None;
+        while let Ok(parsed) = self.results.try_recv() {
+            if parsed.version >= self.oldest_usable {
+                newest = Some(parsed);
+            }
+        }
+        let Some(mut parsed) = newest else {
+            return false;
+        };
+        // Edits made after the snapshot was taken move its lines along
+        self.sent_edits.retain(|(v, _)| *v > parsed.version);
+        for (_, e) in &self.sent_edits {
+            parsed.highlights.shift(e.start, e.old_end, e.new_end);
+            let input = input_edit(e.start, e.old_end, e.new_end);
+            if let Some(tree) = &mut parsed.tree {
+                tree.edit(&input);
+            }
+            parsed.locals.edit(&input);
+        }
+        self.highlights = parsed.highlights;
+        self.tree = parsed.tree;
+        self.locals = parsed.locals;
+        true
+    }
+}
+
+impl Drop for Highlighter {
+    fn drop(&mut self) {
+        // Closing the channel ends the worker once its parse stops
+        self.cancel.store(1, Ordering::SeqCst);
+    }
+}
+
+impl fmt::Debug for Highlighter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Highlighter")
+            .field("language", &self.language)
+            .field("version", &self.version)
+            .field("highlights", &self.highlights)
+            .field("tree", &self.tree)
+            .finish()
+    }
+}
+
+/// The worker's loop.  Every waiting job is folded into one, and a parse
+/// stopped by a newer job starts over with it.  The flag is only set right
+/// before a job is sent, so once the waiting jobs are taken it's cleared, and
+/// a parse it stops always has a newer job to go on with.
+fn run(
+    mut layer: Box<dyn Layer>,
+    jobs: Receiver<Job>,
+    results: Sender<Parsed>,
+    cancel: Arc<AtomicUsize>,
+    wakeup: Arc<Mutex<Option<Wakeup>>>,
+) {
+    layer.set_cancellation_flag(cancel.clone());
+    loop {
+        let Ok(mut job) = jobs.recv() else {
+            return;
+        };
+        while let Ok(newer) = jobs.try_recv() {
+            job.merge(newer);
+        }
+        cancel.store(0, Ordering::SeqCst);
+        if job.reset {
+            layer.unset_tree();
+        }
+        for e in &job.edits {
+            layer.edit_tree(e.start, e.old_end, e.new_end);
+        }
+
+        let start = Instant::now();
+        // A parse that runs out of time carries on where it left off, until
+        // it finishes or a newer job cancels it
+        let finished = loop {
+            if layer.update_highlights(&job.rope) {
+                break true;
+            }
+            if cancel.load(Ordering::SeqCst) != 0 {
+                break false;
+            }
+        };
+        if !finished {
+            log::debug!("parse of version {} cancelled", job.version);
+            layer.reset_parse();
+            continue;
+        }
+        log::debug!("update_highlights took {}ms", start.elapsed().as_millis());
+
+        let parsed = Parsed {
+            version: job.version,
+            highlights: layer.highlights().cloned().unwrap_or_default(),
+            tree: layer.tree().cloned(),
+            locals: layer.locals().unwrap_or_default(),
+        };
+        if results.send(parsed).is_err() {
+            return;
+        }
+        if let Some(wakeup) = wakeup.lock().expect("highlighter wakeup").as_ref() {
+            wakeup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Capture;
+    use crate::language::{language_by_name, TreeSitterLayer};
+    use std::time::Duration;
+
+    fn point(rope: &Rope, char: usize) -> Point {
+        let line = rope.char_to_line(char);
+        Point {
+            byte: rope.char_to_byte(char),
+            char,
+            line,
+            col: char - rope.line_to_char(line),
+        }
+    }
+
+    fn wait(hl: &mut Highlighter) {
+        let start = Instant::now();
+        while !hl.has_results() {
+            assert!(start.elapsed() < Duration::from_secs(10), "no results");
+            thread::sleep(Duration::from_millis(1));
+        }
+        hl.apply_results();
+    }
+
+    fn starts_with_keyword(hl: &Highlighter, line: usize) -> bool {
+        hl.line_highlights(line)
+            .iter()
+            .any(|s| s.start == 0 && s.capture == Capture::from_name("keyword").unwrap())
+    }
+
+    #[test]
+    fn test_highlights_on_worker() {
+        let mut rope = Rope::from_str("fn a() {}\n");
+        let mut hl = Highlighter::new(Box::new(
+            TreeSitterLayer::new(language_by_name("rust").unwrap()).unwrap(),
+        ));
+        hl.update(&rope);
+        wait(&mut hl);
+        assert!(starts_with_keyword(&hl, 0));
+
+        // Until the new parse is done, the old highlights move with the
+        // edit
+        rope.insert(0, "\n\n");
+        hl.edit_tree_insert(point(&rope, 0), point(&rope, 2));
+        assert!(hl.line_highlights(0).is_empty());
+        assert!(starts_with_keyword(&hl, 2));
+        rope.insert(4, " ");
+        hl.edit_tree_insert(point(&rope, 4), point(&rope, 5));
+        assert!(starts_with_keyword(&hl, 2));
+
+        // Lines edited after a snapshot still line up with its results
+        hl.update(&rope);
+        rope.insert(0, "let x = 1;\n");
+        hl.edit_tree_insert(point(&rope, 0), point(&rope, 11));
+        hl.update(&rope);
+        while !starts_with_keyword(&hl, 0) {
+            wait(&mut hl);
+            assert!(starts_with_keyword(&hl, 3));
+        }
+        assert_eq!(hl.tree().unwrap().root_node().end_byte(), rope.len_bytes());
+    }
+
+    #[test]
+    fn test_edits_to_large_file() {
+        // Big enough for tree-sitter to check the cancellation flag
+        let mut rope = Rope::from_str(&"fn a() { let x = [1, 2, 3]; }\n".repeat(5000));
+        let mut hl = Highlighter::new(Box::new(
+            TreeSitterLayer::new(language_by_name("rust").unwrap()).unwrap(),
+        ));
+        hl.update(&rope);
+        wait(&mut hl);
+        for _ in 0..3 {
+            rope.insert(0, "\n");
+            hl.edit_tree_insert(point(&rope, 0), point(&rope, 1));
+            hl.update(&rope);
+            wait(&mut hl);
+        }
+        assert!(starts_with_keyword(&hl, 3));
+
+        // Undo can't say what changed, so the old tree is gone until the
+        // text is parsed again
+        hl.unset_tree();
+        assert!(hl.tree().is_none());
+        assert!(hl.line_highlights(3).is_empty());
+        let rope = Rope::from_str("fn a() {}\n");
+        hl.update(&rope);
+        wait(&mut hl);
+        assert_eq!(hl.tree().unwrap().root_node().end_byte(), rope.len_bytes());
+    }
+}
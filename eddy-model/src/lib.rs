@@ -2,41 +2,61 @@
 #![allow(unused_imports)]
 #![warn(missing_debug_implementations, rust_2018_idioms)]
 
+mod anchor;
 mod backend;
+mod bookmark;
 mod buffer;
+mod clipboard;
+mod command;
+pub mod diff;
+pub mod emacs;
 pub mod files;
+pub mod fuzzy;
 pub(crate) mod graphemes;
 mod history;
+pub mod jump_list;
+pub mod keymap;
 mod language;
 mod line_ending;
 mod lsp;
+pub mod palette;
 mod point;
 mod project;
+pub mod quick_open;
 mod range;
 mod selection;
+mod session;
+pub mod snippet;
 pub mod style;
 mod tab_mode;
+pub mod vim;
 mod window;
 
 use std::collections::BTreeMap;
 use std::fmt;
+use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+pub use anchor::*;
+pub use bookmark::*;
 pub use buffer::*;
+pub use clipboard::*;
+pub use command::*;
+pub use language::{Symbol, SymbolEntry, SymbolKind};
 pub use point::*;
 pub use range::*;
 pub use selection::*;
+pub use session::*;
 use style::Theme;
 pub use window::*;
 
-#[derive(Debug)]
-pub enum Command {}
-
 pub struct Model {
     next_win_id: u64,
     pub wins: BTreeMap<u64, Window>,
     pub theme: Theme,
+    /// The directory eddy was started in.  Sessions are saved per workspace.
+    pub workspace: PathBuf,
     wakeup: Arc<dyn Fn() + Send + Sync>,
 }
 
@@ -46,10 +66,22 @@ impl fmt::Debug for Model {
             .field("next_win_id", &self.next_win_id)
             .field("wins", &self.wins)
             .field("theme", &self.theme)
+            .field("workspace", &self.workspace)
             .finish()
     }
 }
 
+/// The directory eddy was started in.  That can be gone or unreadable, so
+/// the home directory, or the root, stands in for it.
+pub(crate) fn working_dir() -> PathBuf {
+    std::env::current_dir().unwrap_or_else(|e| {
+        log::error!("unable to get the current directory: {e}");
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/"))
+    })
+}
+
 impl Model {
     #[allow(clippy::new_without_default)]
     pub fn new(wakeup: Arc<dyn Fn() + Send + Sync>) -> Self {
@@ -57,18 +89,60 @@ impl Model {
             next_win_id: 0,
             wins: BTreeMap::new(),
             theme: Theme::default(),
+            workspace: working_dir(),
             wakeup: wakeup.clone(),
         }
     }
 
     pub fn new_win(&mut self) -> u64 {
+        let mut win = Window::new(self.wakeup.clone());
+        win.load_config();
+        win.index_project(0);
+        self.add_win(win)
+    }
+
+    fn add_win(&mut self, win: Window) -> u64 {
         let win_id = self.next_win_id;
         self.next_win_id += 1;
-        let win = Window::new(self.wakeup.clone());
         self.wins.insert(win_id, win);
         win_id
     }
 
+    /// Writes the state of every window to the workspace's session file.
+    /// Unsaved text is journaled first, so untitled buffers can come back.
+    pub fn save_session(&mut self) -> io::Result<()> {
+        let windows = self
+            .wins
+            .values_mut()
+            .map(|w| {
+                w.journal_all();
+                w.session()
+            })
+            .collect();
+        Session { windows }.store(&Session::path_for(&self.workspace))
+    }
+
+    /// Opens the windows saved in the workspace's session file.  Returns
+    /// false if there was no session to restore.
+    pub fn restore_session(&mut self) -> bool {
+        let session = match Session::load(&Session::path_for(&self.workspace)) {
+            Ok(session) => session,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log::error!("unable to load session: {e}");
+                }
+                return false;
+            }
+        };
+        for ws in &session.windows {
+            let mut win = Window::new(self.wakeup.clone());
+            win.load_config();
+            win.restore_session(ws);
+            self.add_win(win);
+        }
+        !session.windows.is_empty()
+    }
+
     pub fn has_events(&self) -> bool {
         self.wins.values().any(|w| w.has_events())
     }
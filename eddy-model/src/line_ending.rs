@@ -1,3 +1,4 @@
+use ropey::Rope;
 use std::borrow::Cow;
 
 #[derive(Debug)]
@@ -8,12 +9,67 @@ pub enum LineEnding {
 }
 
 impl LineEnding {
-    pub fn normalize<'a>(&self, s: &'a str) -> Cow<'a, str> {
+    /// The line ending of the first line of `rope`, or `Lf` if it has none
+    pub fn detect(rope: &Rope) -> Self {
+        let line = rope.line(0);
+        let len = line.len_chars();
+        match (
+            len.checked_sub(2).map(|i| line.char(i)),
+            len.checked_sub(1).map(|i| line.char(i)),
+        ) {
+            (Some('\r'), Some('\n')) => LineEnding::Crlf,
+            (_, Some('\r')) => LineEnding::Cr,
+            _ => LineEnding::Lf,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
         match self {
-            LineEnding::Lf => normalize(s, "\n"),
-            LineEnding::Cr => normalize(s, "\r"),
-            LineEnding::Crlf => normalize(s, "\r\n"),
+            LineEnding::Lf => "\n",
+            LineEnding::Cr => "\r",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn normalize<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        let valid = match self {
+            LineEnding::Lf => is_valid_lf(s),
+            LineEnding::Cr => is_valid_cr(s),
+            LineEnding::Crlf => is_valid_crlf(s),
+        };
+        if valid {
+            return Cow::Borrowed(s);
+        }
+        Cow::Owned(self.normalize_with_offsets(s).0)
+    }
+
+    /// Like `normalize`, but also returns where each char of `s` ends up,
+    /// followed by the length of the result.  The `\n` of a `\r\n` ends up
+    /// at the start of its line break, like the `\r`.
+    pub fn normalize_with_offsets(&self, s: &str) -> (String, Vec<usize>) {
+        let ending = self.as_str();
+        let mut ret = String::with_capacity(s.len());
+        let mut offsets = Vec::with_capacity(s.len() + 1);
+        let mut len = 0;
+        let mut iter = s.chars().peekable();
+        while let Some(ch) = iter.next() {
+            offsets.push(len);
+            match ch {
+                '\r' | '\n' => {
+                    if ch == '\r' && iter.next_if_eq(&'\n').is_some() {
+                        offsets.push(len);
+                    }
+                    ret.push_str(ending);
+                    len += ending.len();
+                }
+                any => {
+                    ret.push(any);
+                    len += 1;
+                }
+            }
         }
+        offsets.push(len);
+        (ret, offsets)
     }
 }
 
@@ -59,44 +115,6 @@ fn is_valid_crlf(s: &str) -> bool {
     true
 }
 
-fn normalize<'a>(s: &'a str, ending: &str) -> Cow<'a, str> {
-    if is_valid_lf(s) {
-        return Cow::Borrowed(s);
-    }
-
-    let mut prev_was_cr = false;
-    let mut ret = String::new();
-
-    let mut iter = s.chars();
-    while let Some(ch) = iter.next() {
-        match ch {
-            '\n' if prev_was_cr => match iter.next() {
-                Some('\r') => {
-                    prev_was_cr = true;
-                    ret.push_str(ending);
-                }
-                Some(any) => {
-                    prev_was_cr = false;
-                    ret.push(any);
-                }
-                None => {
-                    prev_was_cr = false;
-                }
-            },
-            '\r' => {
-                prev_was_cr = true;
-                ret.push_str(ending);
-            }
-            any => {
-                prev_was_cr = false;
-                ret.push(any);
-            }
-        }
-    }
-
-    Cow::Owned(ret)
-}
-
 struct Normalized<I> {
     iter: I,
     prev_was_cr: bool,
@@ -142,8 +160,27 @@ mod tests {
     #[test]
     fn test_to_lf() {
         assert_eq!(
-            normalize("one\none\rone\r\nthree\r\r\n\none\r", "\n"),
+            LineEnding::Lf.normalize("one\none\rone\r\nthree\r\r\n\none\r"),
             "one\none\none\nthree\n\n\none\n"
         );
     }
+
+    #[test]
+    fn test_to_crlf() {
+        let (text, offsets) = LineEnding::Crlf.normalize_with_offsets("a\nb\r\nc");
+        assert_eq!(text, "a\r\nb\r\nc");
+        assert_eq!(offsets, vec![0, 1, 3, 4, 4, 6, 7]);
+        assert!(matches!(
+            LineEnding::Crlf.normalize("a\r\n"),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(
+            LineEnding::detect(&Rope::from_str("a\r\nb\n")),
+            LineEnding::Crlf
+        ));
+        assert!(matches!(
+            LineEnding::detect(&Rope::from_str("a")),
+            LineEnding::Lf
+        ));
+    }
 }
@@ -241,6 +241,15 @@ impl LanguageServerClient {
         self.send_notification("initialized", params);
     }
 
+    pub fn language_id(&self) -> &str {
+        &self.language_id
+    }
+
+    /// Whether the document in a view was opened with the server
+    pub fn is_open(&self, view_id: ViewId) -> bool {
+        self.opened_documents.contains_key(&view_id)
+    }
+
     /// Send textDocument/didOpen Notification to the Language Server
     pub fn send_did_open(&mut self, view_id: ViewId, document_uri: Uri, document_text: String) {
         self.opened_documents.insert(view_id, document_uri.clone());
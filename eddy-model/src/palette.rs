@@ -0,0 +1,260 @@
+use crate::fuzzy::{FuzzyMatch, FuzzyMatcher};
+use crate::keymap::{format_key_sequence, KeyContext, Keymap};
+use crate::Command;
+use std::path::PathBuf;
+
+/// How many recently run commands are remembered
+const MAX_RECENT: usize = 20;
+/// The most extra points a recently run command gets
+const RECENT_BONUS: i64 = 60;
+
+/// Every command the palette lists that runs as is
+const COMMANDS: &[Command] = &[
+    Command::Save,
+    Command::OpenFile,
+    Command::QuickOpen,
+    Command::NewView(None),
+    Command::CloseView,
+    Command::Undo,
+    Command::Redo,
+    Command::Cut,
+    Command::Copy,
+    Command::PasteSystemClipboard,
+    Command::ShowClipboardHistory,
+    Command::YankPop,
+    Command::KillLine,
+    Command::SelectAll,
+    Command::SetMark,
+    Command::ExchangePointAndMark,
+    Command::KeyboardQuit,
+    Command::IsearchForward,
+    Command::IsearchBackward,
+    Command::ToggleBookmark,
+    Command::NextBookmark,
+    Command::PreviousBookmark,
+    Command::ShowBookmarks,
+    Command::ShowSymbols,
+    Command::JumpBack,
+    Command::JumpForward,
+    Command::InsertNewline,
+    Command::InsertTab,
+    Command::ExpandSnippet,
+    Command::DeleteForward,
+    Command::DeleteBackward,
+    Command::MoveLeft,
+    Command::MoveRight,
+    Command::MoveUp,
+    Command::MoveDown,
+    Command::MoveWordLeft,
+    Command::MoveWordRight,
+    Command::MoveToLeftEndOfLine,
+    Command::MoveToRightEndOfLine,
+    Command::MoveToBeginningOfDocument,
+    Command::MoveToEndOfDocument,
+    Command::PageUp,
+    Command::PageDown,
+    Command::MoveLeftAndModifySelection,
+    Command::MoveRightAndModifySelection,
+    Command::MoveUpAndModifySelection,
+    Command::MoveDownAndModifySelection,
+    Command::MoveWordLeftAndModifySelection,
+    Command::MoveWordRightAndModifySelection,
+    Command::MoveToLeftEndOfLineAndModifySelection,
+    Command::MoveToRightEndOfLineAndModifySelection,
+    Command::MoveToBeginningOfDocumentAndModifySelection,
+    Command::MoveToEndOfDocumentAndModifySelection,
+    Command::PageUpAndModifySelection,
+    Command::PageDownAndModifySelection,
+];
+
+/// Commands that ask for an argument before they run
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Prompt {
+    GoToLine,
+    SaveAs,
+    OpenPath,
+    SetBookmark,
+    SetLanguage,
+}
+
+impl Prompt {
+    pub const ALL: [Prompt; 5] = [
+        Prompt::GoToLine,
+        Prompt::SaveAs,
+        Prompt::OpenPath,
+        Prompt::SetBookmark,
+        Prompt::SetLanguage,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Prompt::GoToLine => "Go to Line",
+            Prompt::SaveAs => "Save As",
+            Prompt::OpenPath => "Open Path",
+            Prompt::SetBookmark => "Set Named Bookmark",
+            Prompt::SetLanguage => "Set Language",
+        }
+    }
+
+    /// What to show in the empty input
+    pub fn placeholder(self) -> &'static str {
+        match self {
+            Prompt::GoToLine => "Line number",
+            Prompt::SaveAs => "Path to save to",
+            Prompt::OpenPath => "Path of the file to open",
+            Prompt::SetBookmark => "Name of the bookmark",
+            Prompt::SetLanguage => "Language, like rust, or plain",
+        }
+    }
+
+    /// The command to run with what was typed in
+    pub fn command(self, input: &str) -> Result<Command, String> {
+        let input = input.trim();
+        match self {
+            Prompt::GoToLine => input
+                .parse()
+                .ok()
+                .filter(|line| *line > 0)
+                .map(Command::GoToLine)
+                .ok_or_else(|| format!("Not a line number: {input}")),
+            Prompt::SaveAs | Prompt::OpenPath if input.is_empty() => {
+                Err("No path given".to_string())
+            }
+            Prompt::SaveAs => Ok(Command::SaveAs(PathBuf::from(input))),
+            Prompt::OpenPath => Ok(Command::NewView(Some(PathBuf::from(input)))),
+            Prompt::SetBookmark if input.is_empty() => Err("No name given".to_string()),
+            Prompt::SetBookmark => Ok(Command::SetBookmark(input.to_string())),
+            Prompt::SetLanguage if input.is_empty() => Err("No language given".to_string()),
+            Prompt::SetLanguage => Ok(Command::SetLanguage(input.to_string())),
+        }
+    }
+}
+
+/// What picking a palette entry does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteAction {
+    Run(Command),
+    Prompt(Prompt),
+}
+
+/// A command in the palette that matched the query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteEntry {
+    pub title: String,
+    pub action: PaletteAction,
+    /// The keys bound to the command, if any
+    pub keys: Option<String>,
+    pub fuzzy: FuzzyMatch,
+}
+
+/// A readable name for a command, like "Move Word Left"
+pub fn command_title(cmd: &Command) -> String {
+    let name = match serde_json::to_value(cmd) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(serde_json::Value::Object(map)) => map.keys().next().cloned().unwrap_or_default(),
+        _ => format!("{cmd:?}"),
+    };
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The command palette, which remembers what was run from it so those
+/// commands come first
+#[derive(Debug, Clone, Default)]
+pub struct CommandPalette {
+    /// Titles of commands run, most recent first
+    recent: Vec<String>,
+}
+
+impl CommandPalette {
+    /// The entries that match a query, best first.  Key bindings are looked
+    /// up in `ctx`, the context of the view the palette runs commands on.
+    pub fn entries(&self, query: &str, keymap: &Keymap, ctx: &KeyContext) -> Vec<PaletteEntry> {
+        let bindings = keymap.active_bindings(Some(ctx));
+        let keys_for = |cmd: &Command| {
+            bindings
+                .iter()
+                .find(|b| b.command.as_ref() == Some(cmd))
+                .map(|b| format_key_sequence(&b.keys))
+        };
+        let candidates = COMMANDS
+            .iter()
+            .map(|cmd| {
+                (
+                    command_title(cmd),
+                    PaletteAction::Run(cmd.clone()),
+                    keys_for(cmd),
+                )
+            })
+            .chain(
+                Prompt::ALL
+                    .into_iter()
+                    .map(|p| (p.title().to_string(), PaletteAction::Prompt(p), None)),
+            );
+
+        let mut matcher = FuzzyMatcher::new(query);
+        let mut entries: Vec<PaletteEntry> = candidates
+            .filter_map(|(title, action, keys)| {
+                let mut fuzzy = matcher.matches(&title)?;
+                if let Some(rank) = self.recent.iter().position(|t| *t == title) {
+                    fuzzy.score += RECENT_BONUS * (MAX_RECENT - rank) as i64 / MAX_RECENT as i64;
+                }
+                Some(PaletteEntry {
+                    title,
+                    action,
+                    keys,
+                    fuzzy,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.fuzzy
+                .score
+                .cmp(&a.fuzzy.score)
+                .then_with(|| a.title.cmp(&b.title))
+        });
+        entries
+    }
+
+    /// Remembers that an entry was run
+    pub fn record(&mut self, title: &str) {
+        self.recent.retain(|t| t != title);
+        self.recent.insert(0, title.to_string());
+        self.recent.truncate(MAX_RECENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette_entries() {
+        let keymap = Keymap::default();
+        let ctx = KeyContext::new();
+        let mut palette = CommandPalette::default();
+
+        let entries = palette.entries("undo", &keymap, &ctx);
+        assert_eq!(entries[0].title, "Undo");
+        assert_eq!(entries[0].keys.as_deref(), Some("ctrl+z"));
+
+        let entries = palette.entries("goline", &keymap, &ctx);
+        assert_eq!(entries[0].action, PaletteAction::Prompt(Prompt::GoToLine));
+        assert_eq!(Prompt::GoToLine.command("12"), Ok(Command::GoToLine(12)));
+        assert!(Prompt::GoToLine.command("x").is_err());
+
+        // Recently run commands rank higher
+        let first = |p: &CommandPalette| p.entries("mr", &keymap, &ctx)[0].title.clone();
+        assert_eq!(first(&palette), "Move Right");
+        palette.record("Move Word Right");
+        assert_eq!(first(&palette), "Move Word Right");
+    }
+}
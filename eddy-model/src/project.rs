@@ -1,7 +1,9 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::backend::FileTree;
+use crate::files::IgnoreRules;
 use crate::ProjectId;
 
 #[derive(Debug, Clone)]
@@ -9,6 +11,54 @@ pub struct Project {
     pub name: String,
     pub dir: PathBuf,
     pub files: Option<BTreeMap<OsString, FileNode>>,
+    /// Every file in the project, once the backend has walked it
+    pub index: Option<FileIndex>,
+}
+
+/// The files of a project that aren't ignored, for finding them by name
+#[derive(Debug, Clone, Default)]
+pub struct FileIndex {
+    /// Paths relative to the project's directory
+    pub files: BTreeSet<PathBuf>,
+    /// The directories that aren't ignored, which are watched for files
+    /// coming and going
+    pub dirs: BTreeSet<PathBuf>,
+    pub ignore: IgnoreRules,
+}
+
+impl From<FileTree> for FileIndex {
+    fn from(tree: FileTree) -> Self {
+        Self {
+            files: tree.files.into_iter().collect(),
+            dirs: tree.dirs.into_iter().collect(),
+            ignore: tree.ignore,
+        }
+    }
+}
+
+impl FileIndex {
+    /// Adds a file that was created, unless it's ignored
+    pub fn insert(&mut self, rel_path: &Path) {
+        if !self.ignore.is_ignored(rel_path, false) {
+            self.files.insert(rel_path.to_owned());
+        }
+    }
+
+    /// Removes a file, or everything under a directory, that was deleted.
+    /// Returns the directories that went with it.
+    pub fn remove(&mut self, rel_path: &Path) -> Vec<PathBuf> {
+        self.files.retain(|p| !p.starts_with(rel_path));
+        let removed: Vec<PathBuf> = self
+            .dirs
+            .iter()
+            .filter(|d| d.starts_with(rel_path))
+            .cloned()
+            .collect();
+        for dir in &removed {
+            self.dirs.remove(dir);
+        }
+        removed
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -41,6 +91,7 @@ impl Project {
             name: name.to_string(),
             dir,
             files: None,
+            index: None,
         }
     }
 
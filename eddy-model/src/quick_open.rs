@@ -0,0 +1,240 @@
+//! Finding files in the window's projects by typing a few letters of their
+//! paths.
+
+use crate::fuzzy::{FuzzyMatch, FuzzyMatcher};
+use crate::project::Project;
+use crate::ProjectId;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// How many recently opened files are remembered
+const MAX_RECENT: usize = 50;
+/// The most extra points a recently opened file gets
+const RECENT_BONUS: i64 = 60;
+/// Extra points when the last part of the query matches the file name
+const NAME_BONUS: i64 = 40;
+/// How many entries are listed at most
+const MAX_ENTRIES: usize = 200;
+
+/// Where in a file to go after opening it, as typed after its name like
+/// `file.rs:120:5`.  Both count from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FilePosition {
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+/// Splits a `:line` or `:line:column` suffix off a query
+pub fn parse_position(input: &str) -> (&str, Option<FilePosition>) {
+    let input = input.trim();
+    let number = |s: &str| s.parse::<usize>().ok().filter(|n| *n > 0);
+    if let Some((rest, last)) = input.rsplit_once(':') {
+        if let Some(n) = number(last) {
+            if let Some((path, line)) = rest.rsplit_once(':') {
+                if let Some(line) = number(line) {
+                    let pos = FilePosition {
+                        line,
+                        column: Some(n),
+                    };
+                    return (path, Some(pos));
+                }
+            }
+            return (
+                rest,
+                Some(FilePosition {
+                    line: n,
+                    column: None,
+                }),
+            );
+        }
+    }
+    (input, None)
+}
+
+/// A file that matched the query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuickOpenEntry {
+    pub project: ProjectId,
+    pub path: PathBuf,
+    /// The path relative to its project, which `fuzzy` indexes into
+    pub label: String,
+    pub fuzzy: FuzzyMatch,
+}
+
+/// A quick open query, split up for matching against paths
+struct PathQuery {
+    /// The last `/` separated part of the query, for the file name
+    name: FuzzyMatcher,
+    /// The parts before it
+    parts: Vec<FuzzyMatcher>,
+    whole: FuzzyMatcher,
+}
+
+impl PathQuery {
+    fn new(query: &str) -> Option<Self> {
+        let mut parts: Vec<FuzzyMatcher> = query
+            .split('/')
+            .filter(|p| !p.is_empty())
+            .map(FuzzyMatcher::new)
+            .collect();
+        Some(Self {
+            name: parts.pop()?,
+            parts,
+            whole: FuzzyMatcher::new(query),
+        })
+    }
+}
+
+/// Matches each `/` separated part of the query against a part of the
+/// path, in order, with the last one against the file name.  A query that
+/// can't be split up that way is matched against the whole path.
+fn match_path(query: &mut PathQuery, path: &str) -> Option<FuzzyMatch> {
+    let segments: Vec<&str> = path.split('/').collect();
+
+    let mut starts = Vec::with_capacity(segments.len());
+    let mut offset = 0;
+    for seg in &segments {
+        starts.push(offset);
+        offset += seg.chars().count() + 1;
+    }
+    let last = segments.len() - 1;
+
+    let by_segments = (|| {
+        let name = query.name.matches(segments[last])?;
+        let mut score = name.score + NAME_BONUS;
+        let mut indices = Vec::new();
+        let mut seg = 0;
+        for part in &mut query.parts {
+            let (i, m) = (seg..last).find_map(|i| part.matches(segments[i]).map(|m| (i, m)))?;
+            score += m.score;
+            indices.extend(m.indices.iter().map(|j| starts[i] + j));
+            seg = i + 1;
+        }
+        indices.extend(name.indices.iter().map(|j| starts[last] + j));
+        Some(FuzzyMatch { score, indices })
+    })();
+    by_segments.or_else(|| query.whole.matches(path))
+}
+
+/// Quick open, which remembers the files opened from it so they come first
+#[derive(Debug, Clone, Default)]
+pub struct QuickOpen {
+    /// Files opened, most recent first
+    recent: Vec<PathBuf>,
+}
+
+impl QuickOpen {
+    /// The indexed files that match a query, best first.  The path is
+    /// relative to each project, and any `:line:column` suffix is ignored.
+    pub fn entries(
+        &self,
+        query: &str,
+        projects: &BTreeMap<ProjectId, Project>,
+    ) -> Vec<QuickOpenEntry> {
+        let (query, _) = parse_position(query);
+        let mut path_query = PathQuery::new(query);
+        let mut entries = Vec::new();
+        for (&proj_id, proj) in projects {
+            let Some(index) = &proj.index else {
+                continue;
+            };
+            for rel in &index.files {
+                let label = rel.to_string_lossy().replace('\\', "/");
+                let fuzzy = if query.is_empty() {
+                    Some(FuzzyMatch {
+                        score: 0,
+                        indices: Vec::new(),
+                    })
+                } else {
+                    path_query.as_mut().and_then(|q| match_path(q, &label))
+                };
+                let Some(mut fuzzy) = fuzzy else {
+                    continue;
+                };
+                let path = proj.dir.join(rel);
+                if let Some(rank) = self.recent.iter().position(|p| *p == path) {
+                    fuzzy.score += RECENT_BONUS * (MAX_RECENT - rank) as i64 / MAX_RECENT as i64;
+                }
+                entries.push(QuickOpenEntry {
+                    project: proj_id,
+                    path,
+                    label,
+                    fuzzy,
+                });
+            }
+        }
+        entries.sort_by(|a, b| {
+            b.fuzzy
+                .score
+                .cmp(&a.fuzzy.score)
+                .then_with(|| a.label.len().cmp(&b.label.len()))
+                .then_with(|| a.label.cmp(&b.label))
+        });
+        entries.truncate(MAX_ENTRIES);
+        entries
+    }
+
+    /// Remembers that a file was opened
+    pub fn record(&mut self, path: &Path) {
+        self.recent.retain(|p| p != path);
+        self.recent.insert(0, path.to_owned());
+        self.recent.truncate(MAX_RECENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::FileIndex;
+
+    #[test]
+    fn test_quick_open_entries() {
+        assert_eq!(
+            parse_position("src/main.rs:120:5"),
+            (
+                "src/main.rs",
+                Some(FilePosition {
+                    line: 120,
+                    column: Some(5)
+                })
+            )
+        );
+        assert_eq!(parse_position("main.rs:7").1.map(|p| p.line), Some(7));
+        assert_eq!(parse_position("c:main.rs"), ("c:main.rs", None));
+
+        let mut index = FileIndex::default();
+        for path in [
+            "eddy/src/main.rs",
+            "eddy-model/src/window.rs",
+            "eddy-model/src/view.rs",
+            "README.md",
+        ] {
+            index.insert(Path::new(path));
+        }
+        let mut proj = Project::new("eddy", PathBuf::from("/src/eddy"));
+        proj.index = Some(index);
+        let projects = BTreeMap::from([(0, proj)]);
+        let mut quick_open = QuickOpen::default();
+
+        let labels = |q: &QuickOpen, query| -> Vec<String> {
+            q.entries(query, &projects)
+                .into_iter()
+                .map(|e| e.label)
+                .collect()
+        };
+        // File names are matched first, then directories in order
+        assert_eq!(labels(&quick_open, "win")[0], "eddy-model/src/window.rs");
+        assert_eq!(
+            labels(&quick_open, "model/v"),
+            vec!["eddy-model/src/view.rs"]
+        );
+        assert_eq!(labels(&quick_open, "main.rs:10"), vec!["eddy/src/main.rs"]);
+        let m = &quick_open.entries("e/main", &projects)[0].fuzzy;
+        assert_eq!(m.indices, vec![0, 9, 10, 11, 12]);
+
+        // Recently opened files rank higher
+        assert_eq!(labels(&quick_open, "")[0], "README.md");
+        quick_open.record(Path::new("/src/eddy/eddy-model/src/view.rs"));
+        assert_eq!(labels(&quick_open, "")[0], "eddy-model/src/view.rs");
+    }
+}
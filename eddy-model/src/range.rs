@@ -1,6 +1,6 @@
 // Range is basically just like the std `Range<usize>` except its specifically
 // for this purpose and not an iterator, so I can implement `Copy`.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Range {
     pub start: usize,
     pub end: usize,
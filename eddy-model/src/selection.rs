@@ -1,7 +1,8 @@
 use crate::Range;
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Selection {
     /// start of a selection region, in character indexes
     pub start: usize,
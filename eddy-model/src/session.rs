@@ -0,0 +1,118 @@
+use crate::files::{data_dir, flatten_path, save_rope, RecoveryKey, SaveOptions};
+use crate::{Bookmark, ClipboardEntry, Selection};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything needed to put a workspace back the way it was left
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub windows: Vec<WindowSession>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WindowSession {
+    /// Views in tab order
+    pub views: Vec<ViewSession>,
+    /// Index into `views` of the focused view
+    pub focused: Option<usize>,
+    pub projects: Vec<ProjectSession>,
+    pub sidebar_width: Option<i32>,
+    /// Only saved when the window is set to persist its clipboard history
+    #[serde(default)]
+    pub clipboard_history: Vec<ClipboardEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewSession {
+    pub path: Option<PathBuf>,
+    /// Where the buffer's unsaved text was journaled, if anywhere
+    pub recovery_key: Option<RecoveryKey>,
+    pub selections: Vec<Selection>,
+    pub scroll: ScrollPos,
+    /// The buffer's bookmarks, kept with the first view of each file or
+    /// untitled buffer only
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectSession {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+/// The scroll offsets of a view, in pixels
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrollPos {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Session {
+    /// The file the session for `workspace` is kept in
+    pub fn path_for(workspace: &Path) -> PathBuf {
+        data_dir()
+            .join("sessions")
+            .join(format!("{}.json", flatten_path(workspace)))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let s = fs::read_to_string(path)?;
+        serde_json::from_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        save_rope(&Rope::from_str(&s), path, &SaveOptions::default())
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_roundtrip() {
+        let path = std::env::temp_dir().join(format!("eddy-session-{}.json", std::process::id()));
+        let session = Session {
+            windows: vec![WindowSession {
+                views: vec![ViewSession {
+                    path: None,
+                    recovery_key: Some(RecoveryKey::Untitled("1".into())),
+                    selections: vec![Selection {
+                        start: 1,
+                        end: 3,
+                        horiz: None,
+                    }],
+                    scroll: ScrollPos { x: 0.0, y: 120.0 },
+                    bookmarks: vec![Bookmark {
+                        pos: 2,
+                        name: Some("here".into()),
+                    }],
+                }],
+                focused: Some(0),
+                projects: vec![],
+                sidebar_width: Some(250),
+                clipboard_history: vec![],
+            }],
+        };
+        session.store(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let view = &loaded.windows[0].views[0];
+        assert_eq!(view.recovery_key, Some(RecoveryKey::Untitled("1".into())));
+        assert_eq!(view.selections[0].end, 3);
+        assert_eq!(view.scroll.y, 120.0);
+        assert_eq!(view.bookmarks[0].name.as_deref(), Some("here"));
+        assert_eq!(loaded.windows[0].sidebar_width, Some(250));
+    }
+}
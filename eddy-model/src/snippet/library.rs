@@ -0,0 +1,193 @@
+use super::{ParseSnippetError, Snippet};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Snippets that apply to every language are kept under this name
+pub const GLOBAL: &str = "global";
+
+/// Snippets that come with eddy, by language
+const DEFAULT_SNIPPETS: &[(&str, &str)] = &[("rust", include_str!("rust.toml"))];
+
+/// A snippet, as written in a snippets file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SnippetDef {
+    /// Typing this and pressing Tab inserts the snippet
+    pub prefix: String,
+    pub body: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl SnippetDef {
+    pub fn snippet(&self) -> Result<Snippet, ParseSnippetError> {
+        self.body.parse()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SnippetFile {
+    #[serde(default)]
+    snippets: Vec<SnippetDef>,
+}
+
+#[derive(Debug)]
+pub enum SnippetFileError {
+    Io(PathBuf, io::Error),
+    Toml(PathBuf, toml::de::Error),
+    Snippet(PathBuf, String, ParseSnippetError),
+}
+
+impl std::error::Error for SnippetFileError {}
+
+impl fmt::Display for SnippetFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnippetFileError::Io(p, e) => write!(f, "cannot read {}: {}", p.display(), e),
+            SnippetFileError::Toml(p, e) => write!(f, "invalid snippets in {}: {}", p.display(), e),
+            SnippetFileError::Snippet(p, prefix, e) => {
+                write!(f, "snippet {prefix:?} in {}: {}", p.display(), e)
+            }
+        }
+    }
+}
+
+/// The user's snippets directory, `$XDG_CONFIG_HOME/eddy/snippets` or
+/// `~/.config/eddy/snippets`
+pub fn user_snippets_dir() -> PathBuf {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+        .unwrap_or_else(std::env::temp_dir);
+    config_home.join("eddy").join("snippets")
+}
+
+/// Every snippet there is, by language
+#[derive(Debug, Clone)]
+pub struct SnippetLibrary {
+    /// Later snippets replace earlier ones with the same prefix
+    by_language: HashMap<String, Vec<SnippetDef>>,
+}
+
+impl Default for SnippetLibrary {
+    fn default() -> Self {
+        let mut library = Self {
+            by_language: HashMap::new(),
+        };
+        for (language, toml) in DEFAULT_SNIPPETS {
+            let file: SnippetFile = toml::from_str(toml).expect("default snippets");
+            library.add(language, file.snippets);
+        }
+        library
+    }
+}
+
+impl SnippetLibrary {
+    /// The default snippets, and the ones in each `<language>.toml` file in
+    /// `dir`.  Files that can't be loaded are skipped, and returned as errors.
+    pub fn load(dir: &Path) -> (Self, Vec<SnippetFileError>) {
+        let mut library = Self::default();
+        let mut errors = Vec::new();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return (library, errors),
+            Err(e) => {
+                errors.push(SnippetFileError::Io(dir.to_owned(), e));
+                return (library, errors);
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let Some(language) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                continue;
+            };
+            match Self::read_file(&path) {
+                Ok(snippets) => library.add(&language, snippets),
+                Err(e) => errors.push(e),
+            }
+        }
+        (library, errors)
+    }
+
+    fn read_file(path: &Path) -> Result<Vec<SnippetDef>, SnippetFileError> {
+        let s = fs::read_to_string(path).map_err(|e| SnippetFileError::Io(path.to_owned(), e))?;
+        let file: SnippetFile =
+            toml::from_str(&s).map_err(|e| SnippetFileError::Toml(path.to_owned(), e))?;
+        for def in &file.snippets {
+            if let Err(e) = def.snippet() {
+                return Err(SnippetFileError::Snippet(
+                    path.to_owned(),
+                    def.prefix.clone(),
+                    e,
+                ));
+            }
+        }
+        Ok(file.snippets)
+    }
+
+    pub fn add(&mut self, language: &str, snippets: Vec<SnippetDef>) {
+        self.by_language
+            .entry(language.to_string())
+            .or_default()
+            .extend(snippets);
+    }
+
+    /// The snippets usable in a language, the language's own first, without
+    /// the ones replaced by a later snippet with the same prefix
+    pub fn snippets(&self, language: Option<&str>) -> Vec<&SnippetDef> {
+        let mut found: Vec<&SnippetDef> = Vec::new();
+        for lang in language.into_iter().chain([GLOBAL]) {
+            for def in self.by_language.get(lang).into_iter().flatten().rev() {
+                if !found.iter().any(|d| d.prefix == def.prefix) {
+                    found.push(def);
+                }
+            }
+        }
+        found
+    }
+
+    /// The snippet to expand for a prefix
+    pub fn find(&self, language: Option<&str>, prefix: &str) -> Option<&SnippetDef> {
+        self.snippets(language)
+            .into_iter()
+            .find(|d| d.prefix == prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_library_lookup() {
+        let mut library = SnippetLibrary::default();
+        assert!(library.find(Some("rust"), "fn").is_some());
+        assert!(library.find(Some("go"), "fn").is_none());
+
+        let def = |prefix: &str, body: &str| SnippetDef {
+            prefix: prefix.to_string(),
+            body: body.to_string(),
+            description: String::new(),
+        };
+        library.add(GLOBAL, vec![def("todo", "TODO: $0"), def("fn", "global")]);
+        library.add("rust", vec![def("todo", "// TODO: $0")]);
+        assert_eq!(library.find(Some("go"), "todo").unwrap().body, "TODO: $0");
+        assert_eq!(
+            library.find(Some("rust"), "todo").unwrap().body,
+            "// TODO: $0"
+        );
+        assert_ne!(library.find(Some("rust"), "fn").unwrap().body, "global");
+        assert_eq!(library.find(None, "fn").unwrap().body, "global");
+        for def in library.snippets(Some("rust")) {
+            assert!(def.snippet().is_ok(), "{}", def.prefix);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod library;
+mod parse;
+mod session;
+
+pub use library::*;
+pub use parse::*;
+pub use session::*;
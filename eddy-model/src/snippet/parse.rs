@@ -0,0 +1,419 @@
+use crate::Range;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// A piece of a snippet body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnippetElement {
+    Text(String),
+    /// `$1` or `${1:placeholder}`.  Stops with the same index mirror each
+    /// other.
+    TabStop {
+        index: usize,
+        placeholder: Vec<SnippetElement>,
+    },
+    /// `${1|one,two,three|}`
+    Choice {
+        index: usize,
+        options: Vec<String>,
+    },
+    /// `$TM_FILENAME` or `${TM_FILENAME:default}`
+    Variable {
+        name: String,
+        default: Vec<SnippetElement>,
+    },
+}
+
+/// A snippet in the LSP/TextMate syntax, like `fn ${1:name}($2) {\n\t$0\n}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub elements: Vec<SnippetElement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSnippetError {
+    /// The char offset the problem was found at
+    pub pos: usize,
+    pub msg: &'static str,
+}
+
+impl std::error::Error for ParseSnippetError {}
+
+impl fmt::Display for ParseSnippetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid snippet at {}: {}", self.pos, self.msg)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn error(&self, msg: &'static str) -> ParseSnippetError {
+        ParseSnippetError { pos: self.pos, msg }
+    }
+
+    fn int(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn var_name(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self
+            .peek()
+            .is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c == '_' || c.is_ascii_alphanumeric())
+        {
+            self.pos += 1;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Parses elements up to the end, or up to an unescaped `}` inside a
+    /// placeholder
+    fn elements(&mut self, nested: bool) -> Result<Vec<SnippetElement>, ParseSnippetError> {
+        let mut elements = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '}' if nested => break,
+                '\\' if matches!(self.chars.get(self.pos + 1), Some('$' | '}' | '\\')) => {
+                    text.push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                }
+                '$' => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    match self.dollar()? {
+                        Some(element) => {
+                            if !text.is_empty() {
+                                elements.push(SnippetElement::Text(std::mem::take(&mut text)));
+                            }
+                            elements.push(element);
+                        }
+                        None => {
+                            // A `$` that starts nothing is just a `$`
+                            self.pos = start + 1;
+                            text.push('$');
+                        }
+                    }
+                }
+                c => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            elements.push(SnippetElement::Text(text));
+        }
+        Ok(elements)
+    }
+
+    /// Parses what comes after a `$`
+    fn dollar(&mut self) -> Result<Option<SnippetElement>, ParseSnippetError> {
+        if let Some(index) = self.int() {
+            return Ok(Some(SnippetElement::TabStop {
+                index,
+                placeholder: Vec::new(),
+            }));
+        }
+        if let Some(name) = self.var_name() {
+            return Ok(Some(SnippetElement::Variable {
+                name,
+                default: Vec::new(),
+            }));
+        }
+        if !self.eat('{') {
+            return Ok(None);
+        }
+        let element = if let Some(index) = self.int() {
+            if self.eat(':') {
+                SnippetElement::TabStop {
+                    index,
+                    placeholder: self.elements(true)?,
+                }
+            } else if self.eat('|') {
+                SnippetElement::Choice {
+                    index,
+                    options: self.choices()?,
+                }
+            } else {
+                SnippetElement::TabStop {
+                    index,
+                    placeholder: Vec::new(),
+                }
+            }
+        } else if let Some(name) = self.var_name() {
+            let default = if self.eat(':') {
+                self.elements(true)?
+            } else {
+                Vec::new()
+            };
+            SnippetElement::Variable { name, default }
+        } else {
+            return Err(self.error("expected a tab stop or variable"));
+        };
+        if !self.eat('}') {
+            return Err(self.error("expected `}`"));
+        }
+        Ok(Some(element))
+    }
+
+    /// Parses `one,two|`, after the opening `|`
+    fn choices(&mut self) -> Result<Vec<String>, ParseSnippetError> {
+        let mut options = vec![String::new()];
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated choice")),
+                Some('\\') if matches!(self.chars.get(self.pos + 1), Some(',' | '|' | '\\')) => {
+                    options.last_mut().unwrap().push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                    continue;
+                }
+                Some(',') => options.push(String::new()),
+                Some('|') => {
+                    self.pos += 1;
+                    return Ok(options);
+                }
+                Some(c) => options.last_mut().unwrap().push(c),
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+impl FromStr for Snippet {
+    type Err = ParseSnippetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0,
+        };
+        let elements = parser.elements(false)?;
+        Ok(Snippet { elements })
+    }
+}
+
+/// Where one tab stop ended up in an expanded snippet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabStop {
+    pub index: usize,
+    /// The stop and its mirrors, as char offsets into the expanded text
+    pub ranges: Vec<Range>,
+    /// The options of a choice stop
+    pub choices: Vec<String>,
+}
+
+/// A snippet's text with its variables filled in and its indentation
+/// adjusted, and where its tab stops are in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub text: String,
+    /// In the order Tab visits them, ending with `$0`
+    pub stops: Vec<TabStop>,
+}
+
+/// How deep placeholders can mirror each other before giving up, so that
+/// `${1:$1}` can't recurse forever
+const MAX_DEPTH: usize = 8;
+
+struct Expander<'a> {
+    vars: &'a dyn Fn(&str) -> Option<String>,
+    /// Put after each newline
+    indent: &'a str,
+    /// What a tab in the snippet becomes
+    indent_unit: &'a str,
+    /// The first placeholder given for each index, which its mirrors copy
+    placeholders: HashMap<usize, &'a [SnippetElement]>,
+    text: String,
+    len: usize,
+    stops: BTreeMap<usize, TabStop>,
+}
+
+impl<'a> Expander<'a> {
+    fn push_text(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    self.text.push('\n');
+                    self.text.push_str(self.indent);
+                    self.len += 1 + self.indent.chars().count();
+                }
+                '\t' => {
+                    self.text.push_str(self.indent_unit);
+                    self.len += self.indent_unit.chars().count();
+                }
+                c => {
+                    self.text.push(c);
+                    self.len += 1;
+                }
+            }
+        }
+    }
+
+    fn stop(&mut self, index: usize, start: usize, choices: &[String]) {
+        let stop = self.stops.entry(index).or_insert_with(|| TabStop {
+            index,
+            ranges: Vec::new(),
+            choices: Vec::new(),
+        });
+        stop.ranges.push(Range {
+            start,
+            end: self.len,
+        });
+        if stop.choices.is_empty() {
+            stop.choices = choices.to_vec();
+        }
+    }
+
+    fn expand(&mut self, elements: &'a [SnippetElement], depth: usize) {
+        for element in elements {
+            let start = self.len;
+            match element {
+                SnippetElement::Text(text) => self.push_text(text),
+                SnippetElement::TabStop { index, placeholder } => {
+                    let placeholder = match self.placeholders.get(index) {
+                        Some(p) if placeholder.is_empty() => *p,
+                        _ => placeholder.as_slice(),
+                    };
+                    if depth < MAX_DEPTH {
+                        self.expand(placeholder, depth + 1);
+                    }
+                    self.stop(*index, start, &[]);
+                }
+                SnippetElement::Choice { index, options } => {
+                    if let Some(first) = options.first() {
+                        self.push_text(first);
+                    }
+                    self.stop(*index, start, options);
+                }
+                SnippetElement::Variable { name, default } => match (self.vars)(name) {
+                    Some(value) => self.push_text(&value),
+                    None if !default.is_empty() => self.expand(default, depth),
+                    // Unknown variables are left for the user to fill in
+                    None => self.push_text(name),
+                },
+            }
+        }
+    }
+}
+
+/// Finds the first non-empty placeholder for each index, looking inside
+/// placeholders too
+fn collect_placeholders<'a>(
+    elements: &'a [SnippetElement],
+    out: &mut HashMap<usize, &'a [SnippetElement]>,
+) {
+    for element in elements {
+        match element {
+            SnippetElement::TabStop { index, placeholder } => {
+                if !placeholder.is_empty() {
+                    out.entry(*index).or_insert(placeholder);
+                }
+                collect_placeholders(placeholder, out);
+            }
+            SnippetElement::Variable { default, .. } => collect_placeholders(default, out),
+            _ => {}
+        }
+    }
+}
+
+impl Snippet {
+    /// Expands the snippet for inserting at a line indented by `indent`.
+    /// Variables are looked up with `vars`.
+    pub fn expand(
+        &self,
+        vars: &dyn Fn(&str) -> Option<String>,
+        indent: &str,
+        indent_unit: &str,
+    ) -> Expansion {
+        let mut placeholders = HashMap::new();
+        collect_placeholders(&self.elements, &mut placeholders);
+        let mut expander = Expander {
+            vars,
+            indent,
+            indent_unit,
+            placeholders,
+            text: String::new(),
+            len: 0,
+            stops: BTreeMap::new(),
+        };
+        expander.expand(&self.elements, 0);
+
+        let end = expander.len;
+        let last = expander.stops.remove(&0).unwrap_or(TabStop {
+            index: 0,
+            ranges: vec![Range { start: end, end }],
+            choices: Vec::new(),
+        });
+        let mut stops: Vec<TabStop> = expander.stops.into_values().collect();
+        stops.push(last);
+        Expansion {
+            text: expander.text,
+            stops,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_expand() {
+        let snippet: Snippet =
+            "fn ${1:name}(${2|a,b\\,c|}) -> $1 {\n\t$0\n} \\$5 $$TM_FILENAME ${X:x}"
+                .parse()
+                .unwrap();
+        let vars = |name: &str| (name == "TM_FILENAME").then(|| "lib.rs".to_string());
+        let exp = snippet.expand(&vars, "    ", "  ");
+        assert_eq!(exp.text, "fn name(a) -> name {\n      \n    } $5 $lib.rs x");
+
+        let range = |start, end| Range { start, end };
+        assert_eq!(exp.stops.len(), 3);
+        assert_eq!(exp.stops[0].ranges, vec![range(3, 7), range(14, 18)]);
+        assert_eq!(exp.stops[1].choices, vec!["a", "b,c"]);
+        assert_eq!(exp.stops[1].ranges, vec![range(8, 9)]);
+        assert_eq!(exp.stops[2].index, 0);
+        assert_eq!(exp.stops[2].ranges, vec![range(27, 27)]);
+
+        // Without a `$0` the snippet ends after its text
+        let exp = "a$1b"
+            .parse::<Snippet>()
+            .unwrap()
+            .expand(&|_| None, "", "\t");
+        assert_eq!(exp.stops[1].ranges, vec![range(2, 2)]);
+
+        assert!("${1:oops".parse::<Snippet>().is_err());
+    }
+}
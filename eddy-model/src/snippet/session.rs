@@ -0,0 +1,171 @@
+use crate::Range;
+
+/// One tab stop of an inserted snippet, with its mirrors
+#[derive(Debug, Clone)]
+struct ActiveStop {
+    /// Where the stop and its mirrors are in the buffer.  With several
+    /// carets, each caret's copy of the snippet adds its own.
+    ranges: Vec<Range>,
+    choices: Vec<String>,
+}
+
+/// A snippet being filled in, one tab stop at a time.  The stops' ranges
+/// follow edits to the buffer, and text typed at the edge of the current
+/// stop grows it.
+#[derive(Debug, Clone)]
+pub struct SnippetSession {
+    /// In the order Tab visits them, ending with `$0`
+    stops: Vec<ActiveStop>,
+    current: usize,
+    /// The current stop was removed along with the text around it
+    removed: bool,
+}
+
+impl SnippetSession {
+    /// Starts at the first stop.  Each stop is a list of ranges and the
+    /// options it can be picked from.
+    pub fn new(stops: Vec<(Vec<Range>, Vec<String>)>) -> Self {
+        Self {
+            stops: stops
+                .into_iter()
+                .map(|(ranges, choices)| ActiveStop { ranges, choices })
+                .collect(),
+            current: 0,
+            removed: false,
+        }
+    }
+
+    /// The ranges of the stop being filled in
+    pub fn ranges(&self) -> &[Range] {
+        self.stops
+            .get(self.current)
+            .map(|s| s.ranges.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The options of the current stop, if it's a choice
+    pub fn choices(&self) -> &[String] {
+        self.stops
+            .get(self.current)
+            .map(|s| s.choices.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns true at `$0`, where the snippet is done
+    pub fn is_finished(&self) -> bool {
+        self.current + 1 >= self.stops.len()
+    }
+
+    /// Returns true once the text of the current stop has been cut away,
+    /// and there's nothing left to fill in
+    pub fn is_removed(&self) -> bool {
+        self.removed
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1).min(self.stops.len().saturating_sub(1));
+    }
+
+    pub fn prev(&mut self) {
+        self.current = self.current.saturating_sub(1);
+    }
+
+    /// Returns true if `pos` is in or at the edge of the current stop
+    pub fn contains(&self, pos: usize) -> bool {
+        self.ranges().iter().any(|r| r.start <= pos && pos <= r.end)
+    }
+
+    pub(crate) fn on_insert(&mut self, char_idx: usize, size: usize) {
+        for (i, stop) in self.stops.iter_mut().enumerate() {
+            for r in &mut stop.ranges {
+                // Text typed at the edge of the current stop goes in it,
+                // and outside any other stop there
+                if r.start > char_idx || (r.start == char_idx && i != self.current) {
+                    r.start += size;
+                }
+                if r.end > char_idx || (r.end == char_idx && i == self.current) {
+                    r.end += size;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn on_remove(&mut self, range: Range) {
+        // Emptying a stop is part of filling it in, but removing the text
+        // on both sides of it too takes the snippet away
+        let swallowed = |r: &Range| range.start < r.start && r.end < range.end;
+        let ranges = self.ranges();
+        self.removed |= !ranges.is_empty() && ranges.iter().all(swallowed);
+        let size = range.end - range.start;
+        let shift = |pos: usize| {
+            if pos >= range.end {
+                pos - size
+            } else {
+                pos.min(range.start)
+            }
+        };
+        for stop in &mut self.stops {
+            for r in &mut stop.ranges {
+                r.start = shift(r.start);
+                r.end = shift(r.end);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_follows_edits() {
+        let range = |start, end| Range { start, end };
+        // `$1 $1 $2` with empty stops, then `$0`
+        let mut session = SnippetSession::new(vec![
+            (vec![range(0, 0), range(1, 1)], vec![]),
+            (vec![range(2, 2)], vec![]),
+            (vec![range(2, 2)], vec![]),
+        ]);
+        // Typing into both mirrors grows them, and pushes later stops along
+        session.on_insert(1, 2);
+        session.on_insert(0, 2);
+        assert_eq!(session.ranges(), &[range(0, 2), range(3, 5)]);
+        session.next();
+        assert_eq!(session.ranges(), &[range(6, 6)]);
+        assert!(!session.is_finished());
+
+        session.on_remove(range(4, 6));
+        assert_eq!(session.ranges(), &[range(4, 4)]);
+        session.prev();
+        assert_eq!(session.ranges(), &[range(0, 2), range(3, 4)]);
+        assert!(session.contains(4));
+        assert!(!session.contains(5));
+        session.next();
+        session.next();
+        assert!(session.is_finished());
+
+        session.prev();
+        session.on_remove(range(0, 2));
+        assert!(!session.is_removed());
+        session.on_remove(range(1, 5));
+        assert!(session.is_removed());
+
+        // `${1:foo}${2:bar}`: typing over `$2` leaves `$1` alone
+        let mut session = SnippetSession::new(vec![
+            (vec![range(0, 3)], vec![]),
+            (vec![range(3, 6)], vec![]),
+            (vec![range(6, 6)], vec![]),
+        ]);
+        session.next();
+        session.on_remove(range(3, 6));
+        session.on_insert(3, 1);
+        assert_eq!(session.ranges(), &[range(3, 4)]);
+        session.prev();
+        assert_eq!(session.ranges(), &[range(0, 3)]);
+        // And typing at the end of `$1` leaves `$2` alone
+        session.on_insert(3, 1);
+        assert_eq!(session.ranges(), &[range(0, 4)]);
+        session.next();
+        assert_eq!(session.ranges(), &[range(4, 5)]);
+    }
+}
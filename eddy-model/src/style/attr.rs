@@ -1,9 +1,33 @@
 use super::Color;
+use serde::Deserialize;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Attr {
     ForegroundColor(Color),
     BackgroundColor(Color),
+    Weight(Weight),
+    Italic,
+    /// A line under the text, in the foreground color unless it has its own
+    Underline {
+        style: UnderlineStyle,
+        color: Option<Color>,
+    },
+    Strikethrough,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Weight {
+    Normal,
+    Bold,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnderlineStyle {
+    Single,
+    Double,
+    /// A wavy line, like the ones under misspelled words
+    Curly,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
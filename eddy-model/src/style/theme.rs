@@ -1,4 +1,4 @@
-use super::Color;
+use super::{Attr, AttrSpan, Color, UnderlineStyle, Weight};
 use crate::language::capture::Capture;
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -10,18 +10,66 @@ pub struct ThemeAttributes {
     pub fg: Option<Color>,
     pub bg: Option<Color>,
     pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<UnderlineStyle>,
+    /// The underline's color, if it isn't the foreground's
+    pub underline_color: Option<Color>,
+    pub strikethrough: Option<bool>,
 }
 
 impl ThemeAttributes {
     fn from_file_attrs(tfa: ThemeFileAttributes) -> Self {
-        let fg = tfa.fg.and_then(|s| Color::from_str(&s).ok());
-        let bg = tfa.bg.and_then(|s| Color::from_str(&s).ok());
+        let color = |s: Option<String>| s.and_then(|s| Color::from_str(&s).ok());
         ThemeAttributes {
-            fg,
-            bg,
+            fg: color(tfa.fg),
+            bg: color(tfa.bg),
             bold: tfa.bold,
+            italic: tfa.italic,
+            underline: tfa.underline,
+            underline_color: color(tfa.underline_color),
+            strikethrough: tfa.strikethrough,
         }
     }
+
+    /// The text attributes these stand for
+    pub fn attrs(&self) -> Vec<Attr> {
+        let mut attrs = Vec::new();
+        if let Some(fg) = self.fg {
+            attrs.push(Attr::ForegroundColor(fg));
+        }
+        if let Some(bg) = self.bg {
+            attrs.push(Attr::BackgroundColor(bg));
+        }
+        if let Some(bold) = self.bold {
+            attrs.push(Attr::Weight(if bold {
+                Weight::Bold
+            } else {
+                Weight::Normal
+            }));
+        }
+        if self.italic == Some(true) {
+            attrs.push(Attr::Italic);
+        }
+        if let Some(style) = self.underline {
+            attrs.push(Attr::Underline {
+                style,
+                color: self.underline_color,
+            });
+        }
+        if self.strikethrough == Some(true) {
+            attrs.push(Attr::Strikethrough);
+        }
+        attrs
+    }
+
+    /// Spans of each of the attributes, over the same bytes
+    pub fn spans(&self, start_idx: usize, end_idx: usize) -> impl Iterator<Item = AttrSpan> {
+        self.attrs().into_iter().map(move |attr| AttrSpan {
+            attr,
+            start_idx,
+            end_idx,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -33,7 +81,13 @@ pub struct Theme {
     pub line_highlight: ThemeAttributes,
     pub selection: ThemeAttributes,
     pub cursor: Color,
-    highlights: HashMap<Capture, ThemeAttributes>,
+    /// Brackets are colored by how deeply they're nested, going round
+    /// these.  Empty to leave them be.
+    pub rainbow: Vec<ThemeAttributes>,
+    /// The innermost pair of brackets around the caret, and what's in it
+    pub enclosing_block: ThemeAttributes,
+    /// Keyed by scope, like `function.method`
+    highlights: HashMap<String, ThemeAttributes>,
 }
 
 impl Default for Theme {
@@ -48,10 +102,18 @@ gutter_line_highlight = {bg="#504945", fg="#fe8019"}
 cursor = "#fdf4c1"
 selection = {bg = "#4e4e4e"}
 line_number = {fg = "#7c6f64"}
+enclosing_block = {bg = "#32302f"}
+rainbow = [
+    {fg = "#fabd2f"},
+    {fg = "#d3869b"},
+    {fg = "#83a598"},
+    {fg = "#8ec07c"},
+    {fg = "#fe8019"},
+]
 
 [highlights]
 "attribute"             = {fg = "#fe8019"}
-"comment"               = {fg = "#7c6f64"}
+"comment"               = {fg = "#7c6f64", italic = true}
 "constant"              = {fg = "#d3869b"}
 "constant.builtin"      = {fg = "#fe8019"}
 "constructor"           = {fg = "#d3869b"}
@@ -59,13 +121,15 @@ line_number = {fg = "#7c6f64"}
 "function"              = {fg = "#fabd2f"}
 "function.macro"        = {fg = "#fe8019"}
 "function.method"       = {fg = "#fabd2f"}
-"keyword"               = {fg = "#fb4933"}
+"keyword"               = {fg = "#fb4933", bold = true}
 "label"                 = {fg = "#83a598"}
 "operator"              = {fg = "#fdf4c1"}
 "property"              = {fg = "#83a598"}
 "punctuation.bracket"   = {fg = "#fdf4c1"}
 "punctuation.delimiter" = {fg = "#fdf4c1"}
 "string"                = {fg = "#b8bb26"}
+"string.special"        = {fg = "#8ec07c"}
+"tag"                   = {fg = "#fb4933"}
 "type"                  = {fg = "#d3869b"}
 "type.builtin"          = {fg = "#fe8019"}
 "variable.builtin"      = {fg = "#fe8019"}
@@ -83,13 +147,15 @@ impl FromStr for Theme {
         let gutter_line_highlight = ThemeAttributes::from_file_attrs(tf.gutter_line_highlight);
         let line_highlight = ThemeAttributes::from_file_attrs(tf.line_highlight);
         let selection = ThemeAttributes::from_file_attrs(tf.selection);
+        let rainbow = tf
+            .rainbow
+            .into_iter()
+            .map(ThemeAttributes::from_file_attrs)
+            .collect();
+        let enclosing_block = ThemeAttributes::from_file_attrs(tf.enclosing_block);
         let mut highlights = HashMap::new();
         for (name, value) in tf.highlights {
-            let cap = Capture::from_name(&name);
-            let theme_attributes = ThemeAttributes::from_file_attrs(value);
-            if let Some(cap) = cap {
-                highlights.insert(cap, theme_attributes);
-            }
+            highlights.insert(name, ThemeAttributes::from_file_attrs(value));
         }
         Ok(Theme {
             fg: Color::from_str(&tf.fg)?,
@@ -99,13 +165,24 @@ impl FromStr for Theme {
             line_highlight,
             cursor: Color::from_str(&tf.cursor)?,
             selection,
+            rainbow,
+            enclosing_block,
             highlights,
         })
     }
 }
 impl Theme {
+    /// The attributes of the most specific scope the theme styles, so
+    /// `function.method.call` falls back to `function.method`, then
+    /// `function`
     pub fn attributes(&self, c: Capture) -> Option<ThemeAttributes> {
-        self.highlights.get(&c).copied()
+        c.scopes()
+            .find_map(|scope| self.highlights.get(scope).copied())
+    }
+
+    /// The attributes of a bracket nested `depth` pairs deep
+    pub fn rainbow(&self, depth: usize) -> Option<ThemeAttributes> {
+        (!self.rainbow.is_empty()).then(|| self.rainbow[depth % self.rainbow.len()])
     }
 }
 
@@ -118,11 +195,81 @@ struct ThemeFile {
     pub line_highlight: ThemeFileAttributes,
     pub cursor: String,
     pub selection: ThemeFileAttributes,
+    #[serde(default)]
+    pub rainbow: Vec<ThemeFileAttributes>,
+    #[serde(default)]
+    pub enclosing_block: ThemeFileAttributes,
     pub highlights: HashMap<String, ThemeFileAttributes>,
 }
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 struct ThemeFileAttributes {
     pub fg: Option<String>,
     pub bg: Option<String>,
     pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<UnderlineStyle>,
+    pub underline_color: Option<String>,
+    pub strikethrough: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_text_attributes() {
+        let text = r##"
+fg = "#ffffff"
+bg = "#000000"
+line_highlight = {}
+gutter = {}
+gutter_line_highlight = {}
+cursor = "#ffffff"
+selection = {}
+
+[highlights]
+"keyword" = {bold = true, italic = true, strikethrough = true}
+"string"  = {underline = "curly", underline_color = "#ff0000"}
+"##;
+        let theme = Theme::from_str(text).unwrap();
+        assert_eq!(
+            theme
+                .attributes(Capture::from_name("keyword").unwrap())
+                .unwrap()
+                .attrs(),
+            vec![
+                Attr::Weight(Weight::Bold),
+                Attr::Italic,
+                Attr::Strikethrough
+            ]
+        );
+        let red = Color::from_str("#ff0000").unwrap();
+        assert_eq!(
+            theme
+                .attributes(Capture::from_name("string").unwrap())
+                .unwrap()
+                .attrs(),
+            vec![Attr::Underline {
+                style: UnderlineStyle::Curly,
+                color: Some(red),
+            }]
+        );
+        // Scopes the theme doesn't name fall back to the ones they're in
+        let keyword = theme.attributes(Capture::from_name("keyword").unwrap());
+        let scoped = theme.attributes(Capture::from_name("keyword.control.return").unwrap());
+        assert_eq!(scoped, keyword);
+        assert!(theme
+            .attributes(Capture::from_name("variable").unwrap())
+            .is_none());
+
+        // Themes without a rainbow leave brackets be, and ones with one go
+        // round it
+        assert!(theme.rainbow(0).is_none());
+        let default = Theme::default();
+        assert!(default.rainbow(0).is_some());
+        assert_eq!(default.rainbow(default.rainbow.len()), default.rainbow(0));
+
+        // Unknown underline styles are mistakes in the theme
+        assert!(Theme::from_str(&text.replace("curly", "wavy")).is_err());
+    }
 }
@@ -0,0 +1,404 @@
+use super::{last_line, line_content_end};
+use crate::Range;
+use regex::RegexBuilder;
+use ropey::Rope;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A line in an ex command range
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// `.`
+    Current,
+    /// `$`
+    Last,
+    /// A line number, counting from 1
+    Line(usize),
+    /// `'a`, the line of a mark
+    Mark(char),
+}
+
+/// The lines an ex command works on, like `%` or `'<,'>`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+}
+
+impl LineRange {
+    pub const CURRENT: LineRange = LineRange {
+        start: Address::Current,
+        end: Address::Current,
+    };
+    pub const ALL: LineRange = LineRange {
+        start: Address::Line(1),
+        end: Address::Last,
+    };
+}
+
+/// A command typed after `:`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExCommand {
+    /// `:w`, optionally to another file
+    Write(Option<PathBuf>),
+    /// `:q` or `:q!`
+    Quit { force: bool },
+    /// `:wq` or `:x`
+    WriteQuit,
+    /// `:s/pattern/replacement/flags`.  Patterns use vim's magic syntax.
+    Substitute {
+        range: LineRange,
+        pattern: String,
+        replacement: String,
+        global: bool,
+        ignore_case: bool,
+    },
+    /// A bare line number, like `:42`
+    Goto(Address),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExError(pub String);
+
+impl std::error::Error for ExError {}
+
+impl fmt::Display for ExError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn parse_address(s: &str) -> Option<(Address, &str)> {
+    let mut chars = s.chars();
+    match chars.next()? {
+        '.' => Some((Address::Current, chars.as_str())),
+        '$' => Some((Address::Last, chars.as_str())),
+        '\'' => {
+            let mark = chars.next()?;
+            Some((Address::Mark(mark), chars.as_str()))
+        }
+        c if c.is_ascii_digit() => {
+            let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            Some((Address::Line(s[..end].parse().ok()?), &s[end..]))
+        }
+        _ => None,
+    }
+}
+
+fn parse_range(s: &str) -> (Option<LineRange>, &str) {
+    if let Some(rest) = s.strip_prefix('%') {
+        return (Some(LineRange::ALL), rest);
+    }
+    let Some((start, rest)) = parse_address(s) else {
+        return (None, s);
+    };
+    if let Some((end, rest)) = rest.strip_prefix(',').and_then(parse_address) {
+        return (Some(LineRange { start, end }), rest);
+    }
+    (Some(LineRange { start, end: start }), rest)
+}
+
+/// Splits `/pattern/replacement/flags` on its delimiter, which can be
+/// escaped with a backslash
+fn split_substitute(s: &str) -> Option<(String, String, &str)> {
+    let mut chars = s.char_indices();
+    let (_, delim) = chars.next()?;
+    if delim.is_alphanumeric() || delim.is_whitespace() || delim == '\\' {
+        return None;
+    }
+    let mut parts = vec![String::new()];
+    let mut rest = "";
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, d)) if d == delim => parts.last_mut()?.push(d),
+                Some((_, d)) => {
+                    parts.last_mut()?.push('\\');
+                    parts.last_mut()?.push(d);
+                }
+                None => parts.last_mut()?.push('\\'),
+            }
+        } else if c == delim {
+            if parts.len() == 2 {
+                rest = &s[i + c.len_utf8()..];
+                break;
+            }
+            parts.push(String::new());
+        } else {
+            parts.last_mut()?.push(c);
+        }
+    }
+    let mut parts = parts.into_iter();
+    let pattern = parts.next()?;
+    let replacement = parts.next().unwrap_or_default();
+    Some((pattern, replacement, rest))
+}
+
+impl FromStr for ExCommand {
+    type Err = ExError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ExError(format!("Not an editor command: {s}"));
+        let (range, rest) = parse_range(s.trim());
+        let rest = rest.trim_start();
+        let name_end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let (name, args) = rest.split_at(name_end);
+
+        if name.is_empty() && args.is_empty() {
+            return range.map(|r| ExCommand::Goto(r.end)).ok_or_else(err);
+        }
+        if matches!(name, "s" | "substitute") {
+            let (pattern, replacement, flags) = split_substitute(args).ok_or_else(err)?;
+            return Ok(ExCommand::Substitute {
+                range: range.unwrap_or(LineRange::CURRENT),
+                pattern,
+                replacement,
+                global: flags.contains('g'),
+                ignore_case: flags.contains('i'),
+            });
+        }
+        if range.is_some() {
+            return Err(err());
+        }
+        let arg = args.trim();
+        match (name, arg) {
+            ("w" | "write", "") => Ok(ExCommand::Write(None)),
+            ("w" | "write", path) => Ok(ExCommand::Write(Some(PathBuf::from(path)))),
+            ("q" | "quit", "") => Ok(ExCommand::Quit { force: false }),
+            ("q" | "quit", "!") => Ok(ExCommand::Quit { force: true }),
+            ("wq" | "x" | "xit", "") => Ok(ExCommand::WriteQuit),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Turns a vim pattern in magic mode, where `\(`, `\|`, `\+` and `\{n,m}`
+/// are special and their bare characters are literal, into the regex
+/// crate's syntax.  `\c` anywhere makes the pattern ignore case, and `\C`
+/// makes it match case.
+fn convert_pattern(pattern: &str) -> String {
+    let mut out = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d @ ('(' | ')' | '|' | '+' | '?')) => out.push(d),
+                Some('=') => out.push('?'),
+                Some('<') => out.push_str(r"\b{start}"),
+                Some('>') => out.push_str(r"\b{end}"),
+                Some('{') => {
+                    let mut count = String::new();
+                    for d in chars.by_ref() {
+                        if d == '}' {
+                            break;
+                        }
+                        // `\{n,m\}` may close with a backslash too
+                        if d != '\\' {
+                            count.push(d);
+                        }
+                    }
+                    // A leading `-` matches as few as possible
+                    let lazy = count.starts_with('-');
+                    let count = count.trim_start_matches('-');
+                    if count.is_empty() {
+                        out.push('*');
+                    } else if count.starts_with(',') {
+                        out.push_str(&format!("{{0{count}}}"));
+                    } else {
+                        out.push_str(&format!("{{{count}}}"));
+                    }
+                    if lazy {
+                        out.push('?');
+                    }
+                }
+                Some('c') => out.insert_str(0, "(?i)"),
+                Some('C') => out.insert_str(0, "(?-i)"),
+                Some(d @ ('s' | 'S' | 'd' | 'D' | 'w' | 'W' | 'n' | 't')) => {
+                    out.push('\\');
+                    out.push(d);
+                }
+                // Back references and anything else unknown are left for
+                // the regex crate to reject
+                Some(d) if d.is_ascii_digit() => {
+                    out.push('\\');
+                    out.push(d);
+                }
+                Some(d) => out.push_str(&regex::escape(&d.to_string())),
+                None => out.push_str(r"\\"),
+            },
+            '(' | ')' | '|' | '+' | '?' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '[' => {
+                // Classes are copied as they are, except that a `[` in one
+                // would start a nested class
+                out.push('[');
+                if chars.peek() == Some(&'^') {
+                    out.push('^');
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    out.push_str(r"\]");
+                    chars.next();
+                }
+                while let Some(d) = chars.next() {
+                    match d {
+                        ']' => break,
+                        '[' => out.push_str(r"\["),
+                        '\\' => {
+                            out.push('\\');
+                            out.extend(chars.next());
+                        }
+                        d => out.push(d),
+                    }
+                }
+                out.push(']');
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Turns a vim replacement string, where `&` and `\0` are the whole match
+/// and `\1` to `\9` are groups, into the regex crate's syntax.  As in vim,
+/// `\r` breaks the line and `\n` is a NUL.
+fn convert_replacement(replacement: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => out.push_str(&format!("${{{d}}}")),
+                Some('r') => out.push('\n'),
+                Some('n') => out.push('\0'),
+                Some('t') => out.push('\t'),
+                Some('&') => out.push('&'),
+                Some(d) => out.push(d),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The edits a substitution makes to lines `first..=last`, one per match
+pub fn substitute(
+    rope: &Rope,
+    first: usize,
+    last: usize,
+    pattern: &str,
+    replacement: &str,
+    global: bool,
+    ignore_case: bool,
+) -> Result<Vec<(Range, String)>, ExError> {
+    let re = RegexBuilder::new(&convert_pattern(pattern))
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| ExError(e.to_string()))?;
+    let replacement = convert_replacement(replacement);
+
+    let mut edits = Vec::new();
+    for line in first..=last.min(last_line(rope)) {
+        let line_start = rope.line_to_char(line);
+        let text = rope
+            .slice(line_start..line_content_end(rope, line))
+            .to_string();
+        for caps in re.captures_iter(&text) {
+            let m = caps.get(0).expect("whole match");
+            let mut new = String::new();
+            caps.expand(&replacement, &mut new);
+            let start = line_start + text[..m.start()].chars().count();
+            let end = start + m.as_str().chars().count();
+            edits.push((Range { start, end }, new));
+            if !global {
+                break;
+            }
+        }
+    }
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ex_commands() {
+        assert_eq!("w".parse(), Ok(ExCommand::Write(None)));
+        assert_eq!("q!".parse(), Ok(ExCommand::Quit { force: true }));
+        assert_eq!("12".parse(), Ok(ExCommand::Goto(Address::Line(12))));
+        assert_eq!(
+            r"%s/a\/b/[&]/gi".parse(),
+            Ok(ExCommand::Substitute {
+                range: LineRange::ALL,
+                pattern: "a/b".into(),
+                replacement: "[&]".into(),
+                global: true,
+                ignore_case: true,
+            })
+        );
+        assert_eq!(
+            "'<,'>s/x/y".parse::<ExCommand>().map(|c| match c {
+                ExCommand::Substitute { range, .. } => Some(range),
+                _ => None,
+            }),
+            Ok(Some(LineRange {
+                start: Address::Mark('<'),
+                end: Address::Mark('>'),
+            }))
+        );
+        assert!("frobnicate".parse::<ExCommand>().is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let rope = Rope::from_str("foo foo\nbar foo\n");
+        let edits = substitute(&rope, 0, 1, r"f\(o\)o", r"<\1&>", false, false).unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                (Range { start: 0, end: 3 }, "<ofoo>".to_string()),
+                (Range { start: 12, end: 15 }, "<ofoo>".to_string()),
+            ]
+        );
+
+        let rope = Rope::from_str("(foo) food bar+ baaar\n");
+        let matches = |pattern| {
+            substitute(&rope, 0, 0, pattern, "", true, false)
+                .unwrap()
+                .into_iter()
+                .map(|(r, _)| rope.slice(r.start..r.end).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matches(r"\<foo\>"), vec!["foo"]);
+        assert_eq!(matches(r"(foo)"), vec!["(foo)"]);
+        assert_eq!(matches(r"bar+\|food"), vec!["food", "bar+"]);
+        assert_eq!(matches(r"ba\{2,}r"), vec!["baaar"]);
+        assert_eq!(matches(r"ba\+r"), vec!["bar", "baaar"]);
+        assert_eq!(matches(r"\cFOO\>"), vec!["foo"]);
+        assert_eq!(matches(r"[(]f"), vec!["(f"]);
+
+        let rope = Rope::from_str("a,b\n");
+        let edits = substitute(&rope, 0, 0, ",", r"\r", true, false).unwrap();
+        assert_eq!(edits, vec![(Range { start: 1, end: 2 }, "\n".to_string())]);
+        let edits = substitute(&rope, 0, 0, ",", r"\n", true, false).unwrap();
+        assert_eq!(edits, vec![(Range { start: 1, end: 2 }, "\0".to_string())]);
+    }
+
+    #[test]
+    fn test_convert_pattern() {
+        assert_eq!(convert_pattern(r"\(a\|b\)\{2}"), "(a|b){2}");
+        assert_eq!(convert_pattern(r"a\{-1,}"), "a{1,}?");
+        assert_eq!(convert_pattern(r"a\{,3}"), "a{0,3}");
+        assert_eq!(convert_pattern(r"x(y)"), r"x\(y\)");
+        assert_eq!(convert_pattern(r"\.\*"), r"\.\*");
+    }
+}
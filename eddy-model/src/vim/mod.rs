@@ -0,0 +1,9 @@
+mod ex;
+mod motion;
+mod register;
+mod state;
+
+pub use ex::*;
+pub use motion::*;
+pub use register::*;
+pub use state::*;
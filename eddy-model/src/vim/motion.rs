@@ -0,0 +1,442 @@
+use crate::Range;
+use ropey::Rope;
+use std::cmp::{max, min};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Word,
+    Punct,
+}
+
+fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punct
+    }
+}
+
+/// The last line that has text on it.  A trailing newline doesn't start
+/// another line, as far as vim is concerned.
+pub fn last_line(rope: &Rope) -> usize {
+    let lines = rope.len_lines();
+    if lines > 1 && rope.line(lines - 1).len_chars() == 0 {
+        lines - 2
+    } else {
+        lines - 1
+    }
+}
+
+/// The char index just past the last character of a line, before its line
+/// ending
+pub fn line_content_end(rope: &Rope, line: usize) -> usize {
+    let slice = rope.line(line);
+    let mut len = slice.len_chars();
+    if len > 0 && slice.char(len - 1) == '\n' {
+        len -= 1;
+        if len > 0 && slice.char(len - 1) == '\r' {
+            len -= 1;
+        }
+    }
+    rope.line_to_char(line) + len
+}
+
+/// The first character of a line that isn't a space or tab
+pub fn first_non_blank(rope: &Rope, line: usize) -> usize {
+    let start = rope.line_to_char(line);
+    let end = line_content_end(rope, line);
+    (start..end)
+        .find(|&i| !matches!(rope.char(i), ' ' | '\t'))
+        .unwrap_or(end)
+}
+
+/// Keeps a normal mode cursor on a character, rather than past the end of
+/// its line
+pub fn clamp_to_line(rope: &Rope, pos: usize) -> usize {
+    let pos = min(pos, rope.len_chars());
+    let line = rope.char_to_line(pos);
+    let start = rope.line_to_char(line);
+    let end = line_content_end(rope, line);
+    if end > start && pos >= end {
+        end - 1
+    } else {
+        min(pos, end)
+    }
+}
+
+/// Where a cursor moves to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `w`
+    WordForward,
+    /// `b`
+    WordBackward,
+    /// `e`
+    WordEnd,
+    /// `0`
+    LineStart,
+    /// `^`
+    FirstNonBlank,
+    /// `$`
+    LineEnd,
+    /// `gg`, or the line given by a count
+    FirstLine,
+    /// `G`, or the line given by a count
+    LastLine,
+}
+
+/// How much text an operator covers when used with a motion
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MotionKind {
+    /// Up to, but not including, where the motion ends
+    Exclusive,
+    /// Up to and including the character the motion ends on
+    Inclusive,
+    /// Whole lines
+    Linewise,
+}
+
+impl Motion {
+    pub fn from_key(c: char) -> Option<Self> {
+        use Motion::*;
+        Some(match c {
+            'h' => Left,
+            'l' | ' ' => Right,
+            'k' => Up,
+            'j' => Down,
+            'w' => WordForward,
+            'b' => WordBackward,
+            'e' => WordEnd,
+            '0' => LineStart,
+            '^' => FirstNonBlank,
+            '$' => LineEnd,
+            'G' => LastLine,
+            _ => return None,
+        })
+    }
+
+    pub fn kind(self) -> MotionKind {
+        use Motion::*;
+        match self {
+            Up | Down | FirstLine | LastLine => MotionKind::Linewise,
+            WordEnd | LineEnd => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+
+    /// Where the motion takes a cursor at `pos`.  The count repeats the
+    /// motion, or picks the line for `gg` and `G`.
+    pub fn apply(self, rope: &Rope, pos: usize, count: Option<usize>) -> usize {
+        use Motion::*;
+        let n = count.unwrap_or(1).max(1);
+        let line = rope.char_to_line(pos);
+        let line_start = rope.line_to_char(line);
+        let last = last_line(rope);
+        match self {
+            Left => max(pos.saturating_sub(n), line_start),
+            Right => min(pos + n, line_content_end(rope, line)),
+            Up | Down => {
+                let target = if self == Up {
+                    line.saturating_sub(n)
+                } else {
+                    min(line + n, last)
+                };
+                let col = pos - line_start;
+                min(
+                    rope.line_to_char(target) + col,
+                    line_content_end(rope, target),
+                )
+            }
+            WordForward => (0..n).fold(pos, |p, _| next_word_start(rope, p)),
+            WordBackward => (0..n).fold(pos, |p, _| prev_word_start(rope, p)),
+            WordEnd => (0..n).fold(pos, |p, _| next_word_end(rope, p)),
+            LineStart => line_start,
+            FirstNonBlank => first_non_blank(rope, line),
+            LineEnd => {
+                let target = min(line + n - 1, last);
+                let start = rope.line_to_char(target);
+                max(line_content_end(rope, target).saturating_sub(1), start)
+            }
+            FirstLine => first_non_blank(rope, min(count.unwrap_or(1).max(1) - 1, last)),
+            LastLine => {
+                first_non_blank(rope, min(count.map(|c| c.max(1) - 1).unwrap_or(last), last))
+            }
+        }
+    }
+}
+
+fn next_word_start(rope: &Rope, pos: usize) -> usize {
+    let len = rope.len_chars();
+    if pos >= len {
+        return len;
+    }
+    let mut i = pos;
+    let start_class = class(rope.char(i));
+    if start_class != CharClass::Space {
+        while i < len && class(rope.char(i)) == start_class {
+            i += 1;
+        }
+    }
+    while i < len {
+        let c = rope.char(i);
+        if c == '\n' {
+            i += 1;
+            // An empty line counts as a word
+            if i < len && rope.char(i) == '\n' {
+                return i;
+            }
+        } else if class(c) == CharClass::Space {
+            i += 1;
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+fn prev_word_start(rope: &Rope, pos: usize) -> usize {
+    if pos == 0 {
+        return 0;
+    }
+    let mut i = pos - 1;
+    while i > 0 && class(rope.char(i)) == CharClass::Space {
+        i -= 1;
+    }
+    let c = class(rope.char(i));
+    while i > 0 && c != CharClass::Space && class(rope.char(i - 1)) == c {
+        i -= 1;
+    }
+    i
+}
+
+fn next_word_end(rope: &Rope, pos: usize) -> usize {
+    let len = rope.len_chars();
+    if pos + 1 >= len {
+        return pos;
+    }
+    let mut i = pos + 1;
+    while i < len && class(rope.char(i)) == CharClass::Space {
+        i += 1;
+    }
+    if i >= len {
+        return len - 1;
+    }
+    let c = class(rope.char(i));
+    while i + 1 < len && class(rope.char(i + 1)) == c {
+        i += 1;
+    }
+    i
+}
+
+/// A piece of text around the cursor that an operator can act on, like `iw`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextObject {
+    /// `iw`
+    InnerWord,
+    /// `i(`, `i[`, `i{` or `i<`
+    InnerBracket { open: char, close: char },
+    /// `it`, the contents of an XML or HTML tag
+    InnerTag,
+}
+
+impl TextObject {
+    /// The object for the key typed after `i`
+    pub fn from_key(c: char) -> Option<Self> {
+        let bracket = |open, close| TextObject::InnerBracket { open, close };
+        Some(match c {
+            'w' => TextObject::InnerWord,
+            '(' | ')' | 'b' => bracket('(', ')'),
+            '[' | ']' => bracket('[', ']'),
+            '{' | '}' | 'B' => bracket('{', '}'),
+            '<' | '>' => bracket('<', '>'),
+            't' => TextObject::InnerTag,
+            _ => return None,
+        })
+    }
+
+    /// The range of the object around `pos`.  A count picks an enclosing
+    /// bracket or tag further out.
+    pub fn range(self, rope: &Rope, pos: usize, count: Option<usize>) -> Option<Range> {
+        let n = count.unwrap_or(1).max(1);
+        match self {
+            TextObject::InnerWord => inner_word(rope, pos),
+            TextObject::InnerBracket { open, close } => {
+                let mut start = find_open(rope, pos, open, close, true)?;
+                for _ in 1..n {
+                    start = find_open(rope, start, open, close, false)?;
+                }
+                let end = find_close(rope, start, open, close)?;
+                Some(Range {
+                    start: start + 1,
+                    end,
+                })
+            }
+            TextObject::InnerTag => inner_tag(rope, pos, n),
+        }
+    }
+}
+
+fn inner_word(rope: &Rope, pos: usize) -> Option<Range> {
+    if pos >= rope.len_chars() || rope.char(pos) == '\n' {
+        return None;
+    }
+    let c = class(rope.char(pos));
+    let same = |i: usize| rope.char(i) != '\n' && class(rope.char(i)) == c;
+    let mut start = pos;
+    while start > 0 && same(start - 1) {
+        start -= 1;
+    }
+    let mut end = pos + 1;
+    while end < rope.len_chars() && same(end) {
+        end += 1;
+    }
+    Some(Range { start, end })
+}
+
+/// Finds the bracket that opens the block around `pos`.  With `at_pos`, a
+/// bracket right at `pos` counts.
+fn find_open(rope: &Rope, pos: usize, open: char, close: char, at_pos: bool) -> Option<usize> {
+    if at_pos && pos < rope.len_chars() && rope.char(pos) == open {
+        return Some(pos);
+    }
+    let mut depth = 0;
+    let mut i = pos;
+    while i > 0 {
+        i -= 1;
+        let c = rope.char(i);
+        if c == close {
+            depth += 1;
+        } else if c == open {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+fn find_close(rope: &Rope, open_idx: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in rope.chars_at(open_idx + 1).enumerate() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some(open_idx + 1 + i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// An opening tag that hasn't been closed yet, while scanning
+struct OpenTag {
+    name: String,
+    start: usize,
+    end: usize,
+}
+
+fn inner_tag(rope: &Rope, pos: usize, n: usize) -> Option<Range> {
+    let chars: Vec<char> = rope.chars().collect();
+    let mut stack: Vec<OpenTag> = Vec::new();
+    // (open tag start, contents, close tag end)
+    let mut pairs: Vec<(usize, Range, usize)> = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '<' {
+            i += 1;
+            continue;
+        }
+        let Some(gt) = chars[i..].iter().position(|&c| c == '>').map(|p| i + p) else {
+            break;
+        };
+        let closing = chars.get(i + 1) == Some(&'/');
+        let name_start = if closing { i + 2 } else { i + 1 };
+        let name: String = chars[name_start..gt]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+            .collect();
+        let self_closing = chars[gt - 1] == '/';
+        if !name.is_empty() && !self_closing {
+            if closing {
+                if let Some(idx) = stack.iter().rposition(|t| t.name == name) {
+                    let open = stack.remove(idx);
+                    stack.truncate(idx);
+                    pairs.push((
+                        open.start,
+                        Range {
+                            start: open.end,
+                            end: i,
+                        },
+                        gt + 1,
+                    ));
+                }
+            } else {
+                stack.push(OpenTag {
+                    name,
+                    start: i,
+                    end: gt + 1,
+                });
+            }
+        }
+        i = gt + 1;
+    }
+
+    let mut around: Vec<&(usize, Range, usize)> = pairs
+        .iter()
+        .filter(|(start, _, end)| *start <= pos && pos < *end)
+        .collect();
+    around.sort_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+    around.get(n - 1).map(|(_, contents, _)| *contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_motions() {
+        let rope = Rope::from_str("foo.bar  baz\n\nqux");
+        assert_eq!(Motion::WordForward.apply(&rope, 0, None), 3);
+        assert_eq!(Motion::WordForward.apply(&rope, 4, None), 9);
+        assert_eq!(Motion::WordForward.apply(&rope, 9, None), 13);
+        assert_eq!(Motion::WordEnd.apply(&rope, 4, None), 6);
+        assert_eq!(Motion::WordBackward.apply(&rope, 9, None), 4);
+        assert_eq!(Motion::LastLine.apply(&rope, 0, None), 14);
+        assert_eq!(Motion::FirstLine.apply(&rope, 14, Some(2)), 13);
+    }
+
+    #[test]
+    fn test_text_objects() {
+        let rope = Rope::from_str("f(a, (b), c) <p><b>x</b> y</p>");
+        let text = |r: Option<Range>| r.map(|r| rope.slice(r.start..r.end).to_string());
+        let paren = TextObject::from_key('(').unwrap();
+        assert_eq!(text(paren.range(&rope, 6, None)), Some("b".into()));
+        assert_eq!(
+            text(paren.range(&rope, 6, Some(2))),
+            Some("a, (b), c".into())
+        );
+        assert_eq!(
+            text(TextObject::InnerWord.range(&rope, 2, None)),
+            Some("a".into())
+        );
+        assert_eq!(
+            text(TextObject::InnerTag.range(&rope, 19, None)),
+            Some("x".into())
+        );
+        assert_eq!(
+            text(TextObject::InnerTag.range(&rope, 25, None)),
+            Some("<b>x</b> y".into())
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use crate::ClipboardEntry;
+use std::collections::HashMap;
+
+/// The register used when none is named
+pub const UNNAMED_REGISTER: char = '"';
+/// The register that holds the last yank
+pub const YANK_REGISTER: char = '0';
+
+/// Vim's registers: the unnamed register, the yank register `0`, and the
+/// named registers `a` to `z`.  Storing into `A` to `Z` appends to the
+/// lowercase register.
+#[derive(Debug, Clone, Default)]
+pub struct Registers {
+    regs: HashMap<char, ClipboardEntry>,
+}
+
+impl Registers {
+    pub fn get(&self, name: Option<char>) -> Option<&ClipboardEntry> {
+        let name = name.unwrap_or(UNNAMED_REGISTER).to_ascii_lowercase();
+        self.regs.get(&name)
+    }
+
+    /// Records text that was yanked or deleted.  The unnamed register always
+    /// gets it, and yanks also go in the yank register.
+    pub fn store(&mut self, name: Option<char>, entry: ClipboardEntry, yank: bool) {
+        let entry = match name {
+            Some(c) if c.is_ascii_uppercase() => {
+                let lower = c.to_ascii_lowercase();
+                let entry = match self.regs.remove(&lower) {
+                    Some(old) => old.append(&entry),
+                    None => entry,
+                };
+                self.regs.insert(lower, entry.clone());
+                entry
+            }
+            Some(c) if c != UNNAMED_REGISTER => {
+                self.regs.insert(c, entry.clone());
+                entry
+            }
+            _ => entry,
+        };
+        if yank {
+            self.regs.insert(YANK_REGISTER, entry.clone());
+        }
+        self.regs.insert(UNNAMED_REGISTER, entry);
+    }
+}
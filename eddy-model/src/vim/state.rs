@@ -0,0 +1,1340 @@
+use super::{
+    clamp_to_line, first_non_blank, last_line, line_content_end, substitute, Address, ExCommand,
+    Motion, MotionKind, Registers, TextObject,
+};
+use crate::keymap::KeyChord;
+use crate::{AnchorId, Buffer, ClipboardEntry, Command, Range, Selection, ViewId};
+use ropey::Rope;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum VimMode {
+    #[default]
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    /// Typing an ex command after `:`
+    CommandLine,
+}
+
+impl VimMode {
+    /// The name shown for the mode in a status line
+    pub fn name(self) -> &'static str {
+        match self {
+            VimMode::Normal => "NORMAL",
+            VimMode::Insert => "INSERT",
+            VimMode::Visual => "VISUAL",
+            VimMode::VisualLine => "VISUAL LINE",
+            VimMode::VisualBlock => "VISUAL BLOCK",
+            VimMode::CommandLine => "COMMAND",
+        }
+    }
+
+    /// Returns true if the caret should be drawn as a block over the
+    /// character it's on
+    pub fn block_caret(self) -> bool {
+        matches!(self, VimMode::Normal | VimMode::CommandLine)
+    }
+
+    pub fn is_visual(self) -> bool {
+        matches!(
+            self,
+            VimMode::Visual | VimMode::VisualLine | VimMode::VisualBlock
+        )
+    }
+}
+
+/// What the window should do after vim has seen a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VimResult {
+    /// The key was used up
+    Handled,
+    /// Insert mode doesn't handle the key itself, so it goes through the
+    /// keymap as usual
+    PassThrough,
+    /// Window commands to run, like `Save` for `:w`
+    Commands(Vec<Command>),
+    /// Keys to feed back in, for `.`
+    Replay(Vec<KeyChord>),
+}
+
+/// A key, the way vim's command parser sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Escape,
+    Return,
+    Backspace,
+    Named(String),
+}
+
+impl Key {
+    fn from_chord(chord: &KeyChord) -> Self {
+        if chord.ctrl && !chord.alt && !chord.meta {
+            let mut chars = chord.key.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                return if c == '[' { Key::Escape } else { Key::Ctrl(c) };
+            }
+        }
+        match chord.key.as_str() {
+            "escape" => Key::Escape,
+            "return" => Key::Return,
+            "backspace" => Key::Backspace,
+            _ => match chord.text().and_then(|t| t.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Named(chord.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Dedent,
+}
+
+impl Operator {
+    fn from_key(c: char) -> Option<Self> {
+        Some(match c {
+            'd' => Operator::Delete,
+            'c' => Operator::Change,
+            'y' => Operator::Yank,
+            '>' => Operator::Indent,
+            '<' => Operator::Dedent,
+            _ => return None,
+        })
+    }
+}
+
+/// What an operator or a cursor movement covers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    /// A jump to a mark, either to its line or to its exact position
+    Mark {
+        name: char,
+        linewise: bool,
+    },
+    /// The visual selection
+    Selection,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum InsertAt {
+    /// `i`
+    Caret,
+    /// `a`
+    After,
+    /// `I`
+    LineStart,
+    /// `A`
+    LineEnd,
+    /// `o`
+    LineBelow,
+    /// `O`
+    LineAbove,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Action {
+    Move(Target),
+    Operate(Operator, Target),
+    /// An operator typed twice, like `dd`
+    OperateLines(Operator),
+    Paste {
+        before: bool,
+    },
+    Undo,
+    Redo,
+    Insert(InsertAt),
+    Visual(VimMode),
+    /// `.`
+    Repeat,
+    SetMark(char),
+    CommandLine,
+    /// `o` in visual mode
+    SwapAnchor,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct NormalCommand {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+enum Parsed<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+type Keys<'a> = std::iter::Peekable<std::slice::Iter<'a, Key>>;
+
+fn parse_count(keys: &mut Keys<'_>) -> Option<usize> {
+    let mut count: Option<usize> = None;
+    while let Some(Key::Char(c)) = keys.peek() {
+        let Some(digit) = c.to_digit(10) else {
+            break;
+        };
+        // A leading 0 is the motion to the start of the line
+        if count.is_none() && digit == 0 {
+            break;
+        }
+        count = Some(
+            count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit as usize),
+        );
+        keys.next();
+    }
+    count
+}
+
+fn multiply_counts(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (None, None) => None,
+        _ => Some(a.unwrap_or(1) * b.unwrap_or(1)),
+    }
+}
+
+fn parse_target(key: &Key, keys: &mut Keys<'_>) -> Parsed<Target> {
+    let motion = match key {
+        Key::Char('g') => match keys.next() {
+            None => return Parsed::Incomplete,
+            Some(Key::Char('g')) => Some(Motion::FirstLine),
+            Some(_) => None,
+        },
+        Key::Char(q @ ('\'' | '`')) => {
+            return match keys.next() {
+                None => Parsed::Incomplete,
+                Some(Key::Char(name)) => Parsed::Done(Target::Mark {
+                    name: *name,
+                    linewise: *q == '\'',
+                }),
+                Some(_) => Parsed::Invalid,
+            }
+        }
+        Key::Char(c) => Motion::from_key(*c),
+        Key::Backspace => Some(Motion::Left),
+        Key::Named(name) => match name.as_str() {
+            "left" => Some(Motion::Left),
+            "right" => Some(Motion::Right),
+            "up" => Some(Motion::Up),
+            "down" => Some(Motion::Down),
+            "home" => Some(Motion::LineStart),
+            "end" => Some(Motion::LineEnd),
+            _ => None,
+        },
+        _ => None,
+    };
+    match motion {
+        Some(m) => Parsed::Done(Target::Motion(m)),
+        None => Parsed::Invalid,
+    }
+}
+
+fn parse_object(keys: &mut Keys<'_>) -> Parsed<Target> {
+    match keys.next() {
+        None => Parsed::Incomplete,
+        Some(Key::Char(c)) => match TextObject::from_key(*c) {
+            Some(obj) => Parsed::Done(Target::Object(obj)),
+            None => Parsed::Invalid,
+        },
+        Some(_) => Parsed::Invalid,
+    }
+}
+
+/// Parses a normal or visual mode command, like `"a3dw`
+fn parse_command(keys: &[Key], visual: bool) -> Parsed<NormalCommand> {
+    use Parsed::*;
+
+    let mut keys = keys.iter().peekable();
+    let mut register = None;
+    if keys.peek() == Some(&&Key::Char('"')) {
+        keys.next();
+        match keys.next() {
+            None => return Incomplete,
+            Some(Key::Char(c)) => register = Some(*c),
+            Some(_) => return Invalid,
+        }
+    }
+    let mut count = parse_count(&mut keys);
+    let Some(key) = keys.next() else {
+        return Incomplete;
+    };
+
+    let action = match key {
+        Key::Char(c) if visual && Operator::from_key(*c).is_some() => {
+            Action::Operate(Operator::from_key(*c).unwrap(), Target::Selection)
+        }
+        Key::Char('x') if visual => Action::Operate(Operator::Delete, Target::Selection),
+        Key::Char('s') if visual => Action::Operate(Operator::Change, Target::Selection),
+        Key::Char('o') if visual => Action::SwapAnchor,
+        Key::Char('i') if visual => match parse_object(&mut keys) {
+            Done(target) => Action::Move(target),
+            Incomplete => return Incomplete,
+            Invalid => return Invalid,
+        },
+        Key::Char(c) if Operator::from_key(*c).is_some() => {
+            let op = Operator::from_key(*c).unwrap();
+            count = multiply_counts(count, parse_count(&mut keys));
+            let target = match keys.next() {
+                None => return Incomplete,
+                Some(Key::Char(c2)) if c2 == c => {
+                    return Done(NormalCommand {
+                        register,
+                        count,
+                        action: Action::OperateLines(op),
+                    })
+                }
+                Some(Key::Char('i')) => parse_object(&mut keys),
+                Some(key) => parse_target(key, &mut keys),
+            };
+            match target {
+                Done(target) => Action::Operate(op, target),
+                Incomplete => return Incomplete,
+                Invalid => return Invalid,
+            }
+        }
+        Key::Char('x') => Action::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+        Key::Char('X') => Action::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        Key::Char('D') => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        Key::Char('C') => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        Key::Char('s') => Action::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        Key::Char('S') => Action::OperateLines(Operator::Change),
+        Key::Char('Y') => Action::OperateLines(Operator::Yank),
+        Key::Char('p') => Action::Paste { before: false },
+        Key::Char('P') => Action::Paste { before: true },
+        Key::Char('u') => Action::Undo,
+        Key::Ctrl('r') => Action::Redo,
+        Key::Char('i') => Action::Insert(InsertAt::Caret),
+        Key::Char('a') => Action::Insert(InsertAt::After),
+        Key::Char('I') => Action::Insert(InsertAt::LineStart),
+        Key::Char('A') => Action::Insert(InsertAt::LineEnd),
+        Key::Char('o') => Action::Insert(InsertAt::LineBelow),
+        Key::Char('O') => Action::Insert(InsertAt::LineAbove),
+        Key::Char('.') => Action::Repeat,
+        Key::Char('m') => match keys.next() {
+            None => return Incomplete,
+            Some(Key::Char(c)) if c.is_ascii_alphabetic() => Action::SetMark(*c),
+            Some(_) => return Invalid,
+        },
+        Key::Char('v') => Action::Visual(VimMode::Visual),
+        Key::Char('V') => Action::Visual(VimMode::VisualLine),
+        Key::Ctrl('v') => Action::Visual(VimMode::VisualBlock),
+        Key::Char(':') => Action::CommandLine,
+        key => match parse_target(key, &mut keys) {
+            Done(target) => Action::Move(target),
+            Incomplete => return Incomplete,
+            Invalid => return Invalid,
+        },
+    };
+    Done(NormalCommand {
+        register,
+        count,
+        action,
+    })
+}
+
+/// The text an operator acts on
+#[derive(Debug, Clone)]
+struct Region {
+    ranges: Vec<Range>,
+    linewise: bool,
+}
+
+fn lines_region(rope: &Rope, first: usize, last: usize) -> Region {
+    let start = rope.line_to_char(first);
+    let end = if last + 1 < rope.len_lines() {
+        rope.line_to_char(last + 1)
+    } else {
+        rope.len_chars()
+    };
+    Region {
+        ranges: vec![Range { start, end }],
+        linewise: true,
+    }
+}
+
+fn caret(pos: usize) -> Selection {
+    Selection {
+        start: pos,
+        end: pos,
+        horiz: None,
+    }
+}
+
+/// A change to repeat with `.`.  Its keys are kept without their count, as
+/// a count given to `.` replaces it.
+#[derive(Debug, Clone, Default)]
+struct Change {
+    keys: Vec<KeyChord>,
+    count: Option<usize>,
+}
+
+impl Change {
+    /// Takes the counts out of a command's keys, like the `2` and `3` of
+    /// `"a2d3w`.  `count` is what they came to.
+    fn new(keys: Vec<KeyChord>, count: Option<usize>) -> Self {
+        let mut keys = keys.into_iter().peekable();
+        let skip_count = |keys: &mut std::iter::Peekable<std::vec::IntoIter<KeyChord>>| {
+            // A leading 0 is the motion to the start of the line
+            let mut digits = 0;
+            while keys
+                .next_if(|chord| match Key::from_chord(chord) {
+                    Key::Char(c) => c.is_ascii_digit() && (digits > 0 || c != '0'),
+                    _ => false,
+                })
+                .is_some()
+            {
+                digits += 1;
+            }
+        };
+        let mut without = Vec::new();
+        if keys.peek().map(Key::from_chord) == Some(Key::Char('"')) {
+            without.extend(keys.by_ref().take(2));
+        }
+        skip_count(&mut keys);
+        if let Some(chord) = keys.next() {
+            let operator =
+                matches!(Key::from_chord(&chord), Key::Char(c) if Operator::from_key(c).is_some());
+            without.push(chord);
+            if operator {
+                skip_count(&mut keys);
+            }
+        }
+        without.extend(keys);
+        Self {
+            keys: without,
+            count,
+        }
+    }
+
+    /// The keys to replay the change with `count`, or with its own count
+    fn replay(&self, count: Option<usize>) -> Vec<KeyChord> {
+        let register = match self.keys.first().map(Key::from_chord) {
+            Some(Key::Char('"')) => self.keys.len().min(2),
+            _ => 0,
+        };
+        let mut keys = self.keys[..register].to_vec();
+        if let Some(count) = count.or(self.count) {
+            keys.extend(
+                count
+                    .to_string()
+                    .chars()
+                    .map(|d| KeyChord::new(&d.to_string())),
+            );
+        }
+        keys.extend_from_slice(&self.keys[register..]);
+        keys
+    }
+}
+
+/// The state of vim emulation in one view
+#[derive(Debug, Clone, Default)]
+pub struct Vim {
+    mode: VimMode,
+    /// Keys typed so far of an unfinished command
+    pending: Vec<KeyChord>,
+    /// Where the visual selection started
+    anchor: usize,
+    /// The cursor in visual mode, on the last selected character
+    cursor: usize,
+    /// Marks, held as anchors in the buffer so they move with its text
+    marks: HashMap<char, AnchorId>,
+    command_line: String,
+    /// The result of the last command, like an error from `:s`
+    message: Option<String>,
+    /// The last change, for `.`
+    last_change: Change,
+    /// A change that's still being typed in insert mode
+    recording: Option<Change>,
+    /// Where the buffer's history was before insert mode, so the whole
+    /// insert can be undone at once
+    insert_checkpoint: Option<usize>,
+}
+
+impl Vim {
+    pub fn mode(&self) -> VimMode {
+        self.mode
+    }
+
+    /// What a status line should show: the mode and any keys of an
+    /// unfinished command, the command line, or a message
+    pub fn status(&self) -> String {
+        if self.mode == VimMode::CommandLine {
+            return format!(":{}", self.command_line);
+        }
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
+        let mut status = match self.mode {
+            VimMode::Normal => String::new(),
+            mode => format!("-- {} --", mode.name()),
+        };
+        if !self.pending.is_empty() {
+            if !status.is_empty() {
+                status.push_str("  ");
+            }
+            for chord in &self.pending {
+                status.push_str(&chord.text().unwrap_or_else(|| chord.to_string()));
+            }
+        }
+        status
+    }
+
+    /// Shows a message in the status line until the next key
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    /// The position of the primary caret
+    fn caret_pos(&self, buf: &Buffer, view_id: ViewId) -> usize {
+        buf.selections(view_id)
+            .first()
+            .map(|s| s.cursor())
+            .unwrap_or_default()
+    }
+
+    fn set_caret(&self, buf: &mut Buffer, view_id: ViewId, pos: usize) {
+        buf.replace_selections(view_id, &[caret(pos)]);
+    }
+
+    pub fn press(
+        &mut self,
+        buf: &mut Buffer,
+        view_id: ViewId,
+        registers: &mut Registers,
+        chord: &KeyChord,
+    ) -> VimResult {
+        let key = Key::from_chord(chord);
+        self.message = None;
+        match self.mode {
+            VimMode::Insert => {
+                if let Some(recording) = &mut self.recording {
+                    recording.keys.push(chord.clone());
+                }
+                if key != Key::Escape {
+                    return VimResult::PassThrough;
+                }
+                self.mode = VimMode::Normal;
+                if let Some(recording) = self.recording.take() {
+                    self.last_change = recording;
+                }
+                if let Some(checkpoint) = self.insert_checkpoint.take() {
+                    buf.squash_history(checkpoint);
+                }
+                // Leaving insert mode steps back onto the last character typed
+                let pos = self.caret_pos(buf, view_id);
+                let rope = buf.rope();
+                let line_start = rope.line_to_char(rope.char_to_line(pos));
+                self.set_caret(buf, view_id, max(pos.saturating_sub(1), line_start));
+                VimResult::Handled
+            }
+            VimMode::CommandLine => self.command_line_key(buf, view_id, key),
+            mode => {
+                if key == Key::Escape {
+                    if self.pending.is_empty() && mode.is_visual() {
+                        self.leave_visual(buf, view_id);
+                    }
+                    self.pending.clear();
+                    return VimResult::Handled;
+                }
+                self.pending.push(chord.clone());
+                let keys: Vec<Key> = self.pending.iter().map(Key::from_chord).collect();
+                match parse_command(&keys, mode.is_visual()) {
+                    Parsed::Incomplete => VimResult::Handled,
+                    Parsed::Invalid => {
+                        self.pending.clear();
+                        VimResult::Handled
+                    }
+                    Parsed::Done(cmd) => {
+                        let keys = std::mem::take(&mut self.pending);
+                        self.run(buf, view_id, registers, cmd, keys)
+                    }
+                }
+            }
+        }
+    }
+
+    fn run(
+        &mut self,
+        buf: &mut Buffer,
+        view_id: ViewId,
+        registers: &mut Registers,
+        cmd: NormalCommand,
+        keys: Vec<KeyChord>,
+    ) -> VimResult {
+        let visual = self.mode.is_visual();
+        let pos = if visual {
+            self.cursor
+        } else {
+            clamp_to_line(buf.rope(), self.caret_pos(buf, view_id))
+        };
+        let checkpoint = buf.history_checkpoint();
+        let NormalCommand {
+            register,
+            count,
+            action,
+        } = cmd;
+        let is_change = !visual
+            && match action {
+                Action::Operate(op, _) | Action::OperateLines(op) => op != Operator::Yank,
+                Action::Paste { .. } | Action::Insert(_) => true,
+                _ => false,
+            };
+
+        match action {
+            Action::Move(target) => self.move_to(buf, view_id, pos, target, count),
+            Action::Operate(op, target) => {
+                if let Some(region) = self.region(buf, pos, target, count, op) {
+                    self.operate(buf, view_id, registers, register, op, region, pos);
+                }
+            }
+            Action::OperateLines(op) => {
+                let rope = buf.rope();
+                let line = rope.char_to_line(pos);
+                let last = min(line + count.unwrap_or(1).max(1) - 1, last_line(rope));
+                let region = lines_region(rope, line, last);
+                self.operate(buf, view_id, registers, register, op, region, pos);
+            }
+            Action::Paste { before } => {
+                self.paste(buf, view_id, registers, register, before, count, pos)
+            }
+            Action::Undo | Action::Redo => {
+                for _ in 0..count.unwrap_or(1) {
+                    if action == Action::Undo {
+                        buf.undo(view_id);
+                    } else {
+                        buf.redo(view_id);
+                    }
+                }
+                let pos = clamp_to_line(buf.rope(), self.caret_pos(buf, view_id));
+                self.set_caret(buf, view_id, pos);
+            }
+            Action::Insert(at) => self.insert(buf, view_id, pos, at),
+            Action::Visual(mode) => {
+                if self.mode == mode {
+                    self.leave_visual(buf, view_id);
+                } else {
+                    if !visual {
+                        self.anchor = pos;
+                        self.cursor = pos;
+                    }
+                    self.mode = mode;
+                    self.show_visual(buf, view_id);
+                }
+            }
+            Action::Repeat => {
+                return if self.last_change.keys.is_empty() {
+                    VimResult::Handled
+                } else {
+                    VimResult::Replay(self.last_change.replay(count))
+                };
+            }
+            Action::SetMark(name) => self.set_mark(buf, name, pos),
+            Action::CommandLine => {
+                self.command_line.clear();
+                if visual {
+                    self.leave_visual(buf, view_id);
+                    self.command_line.push_str("'<,'>");
+                }
+                self.mode = VimMode::CommandLine;
+            }
+            Action::SwapAnchor => {
+                std::mem::swap(&mut self.anchor, &mut self.cursor);
+                self.show_visual(buf, view_id);
+            }
+        }
+
+        if self.mode == VimMode::Insert {
+            self.insert_checkpoint = Some(checkpoint);
+        }
+        if is_change {
+            let change = Change::new(keys, count);
+            if self.mode == VimMode::Insert {
+                self.recording = Some(change);
+            } else {
+                self.last_change = change;
+            }
+        }
+        VimResult::Handled
+    }
+
+    fn set_mark(&mut self, buf: &mut Buffer, name: char, pos: usize) {
+        let id = buf.add_anchor(pos);
+        if let Some(old) = self.marks.insert(name, id) {
+            buf.remove_anchor(old);
+        }
+    }
+
+    fn mark_pos(&self, buf: &Buffer, name: char) -> Option<usize> {
+        let pos = buf.anchor(*self.marks.get(&name)?)?;
+        let rope = buf.rope();
+        Some(min(pos, line_content_end(rope, rope.char_to_line(pos))))
+    }
+
+    /// Lets go of the anchors in the buffer that hold the marks
+    pub fn remove_marks(&mut self, buf: &mut Buffer) {
+        for (_, id) in self.marks.drain() {
+            buf.remove_anchor(id);
+        }
+    }
+
+    fn move_to(
+        &mut self,
+        buf: &mut Buffer,
+        view_id: ViewId,
+        pos: usize,
+        target: Target,
+        count: Option<usize>,
+    ) {
+        let rope = buf.rope();
+        let to = match target {
+            Target::Motion(motion) => motion.apply(rope, pos, count),
+            Target::Mark { name, linewise } => match self.mark_pos(buf, name) {
+                Some(p) if linewise => first_non_blank(rope, rope.char_to_line(p)),
+                Some(p) => p,
+                None => {
+                    self.message = Some("Mark not set".to_string());
+                    return;
+                }
+            },
+            Target::Object(obj) => {
+                // Only visual mode moves by text objects, selecting them
+                if let Some(range) = obj.range(rope, pos, count).filter(|r| r.end > r.start) {
+                    self.anchor = range.start;
+                    self.cursor = range.end - 1;
+                    self.show_visual(buf, view_id);
+                }
+                return;
+            }
+            Target::Selection => return,
+        };
+        if self.mode.is_visual() {
+            self.cursor = to;
+            self.show_visual(buf, view_id);
+        } else {
+            let to = clamp_to_line(rope, to);
+            self.set_caret(buf, view_id, to);
+        }
+    }
+
+    fn visual_region(&self, rope: &Rope) -> Region {
+        let (a, b) = (min(self.anchor, self.cursor), max(self.anchor, self.cursor));
+        match self.mode {
+            VimMode::VisualLine => lines_region(rope, rope.char_to_line(a), rope.char_to_line(b)),
+            VimMode::VisualBlock => {
+                let column = |pos: usize| pos - rope.line_to_char(rope.char_to_line(pos));
+                let (ca, cb) = (column(self.anchor), column(self.cursor));
+                let (left, right) = (min(ca, cb), max(ca, cb));
+                let ranges = (rope.char_to_line(a)..=rope.char_to_line(b))
+                    .filter_map(|line| {
+                        let line_start = rope.line_to_char(line);
+                        let end = line_content_end(rope, line);
+                        let start = line_start + left;
+                        (start < end).then(|| Range {
+                            start,
+                            end: min(line_start + right + 1, end),
+                        })
+                    })
+                    .collect();
+                Region {
+                    ranges,
+                    linewise: false,
+                }
+            }
+            _ => Region {
+                ranges: vec![Range {
+                    start: a,
+                    end: min(b + 1, rope.len_chars()),
+                }],
+                linewise: false,
+            },
+        }
+    }
+
+    /// Selects the visual region in the buffer, so it gets drawn
+    fn show_visual(&self, buf: &mut Buffer, view_id: ViewId) {
+        let region = self.visual_region(buf.rope());
+        let backwards = self.cursor < self.anchor;
+        let sels: Vec<Selection> = region
+            .ranges
+            .iter()
+            .map(|r| Selection {
+                start: if backwards { r.end } else { r.start },
+                end: if backwards { r.start } else { r.end },
+                horiz: None,
+            })
+            .collect();
+        if sels.is_empty() {
+            self.set_caret(buf, view_id, self.cursor);
+        } else {
+            buf.replace_selections(view_id, &sels);
+        }
+    }
+
+    fn leave_visual(&mut self, buf: &mut Buffer, view_id: ViewId) {
+        self.mark_visual(buf);
+        self.mode = VimMode::Normal;
+        let pos = clamp_to_line(buf.rope(), self.cursor);
+        self.set_caret(buf, view_id, pos);
+    }
+
+    /// Remembers the visual selection in the `<` and `>` marks
+    fn mark_visual(&mut self, buf: &mut Buffer) {
+        let (a, b) = (min(self.anchor, self.cursor), max(self.anchor, self.cursor));
+        self.set_mark(buf, '<', a);
+        self.set_mark(buf, '>', b);
+    }
+
+    fn region(
+        &self,
+        buf: &Buffer,
+        pos: usize,
+        target: Target,
+        count: Option<usize>,
+        op: Operator,
+    ) -> Option<Region> {
+        let rope = buf.rope();
+        let (to, kind) = match target {
+            Target::Selection => return Some(self.visual_region(rope)),
+            Target::Object(obj) => {
+                return obj.range(rope, pos, count).map(|r| Region {
+                    ranges: vec![r],
+                    linewise: false,
+                })
+            }
+            Target::Mark { name, linewise } => {
+                let kind = if linewise {
+                    MotionKind::Linewise
+                } else {
+                    MotionKind::Exclusive
+                };
+                (self.mark_pos(buf, name)?, kind)
+            }
+            Target::Motion(motion) => {
+                let on_word = pos < rope.len_chars() && !rope.char(pos).is_whitespace();
+                // `cw` changes to the end of the word, leaving the space after it
+                let motion = if op == Operator::Change && motion == Motion::WordForward && on_word {
+                    Motion::WordEnd
+                } else {
+                    motion
+                };
+                let mut to = motion.apply(rope, pos, count);
+                // `dw` on the last word of a line stops at the end of the line
+                let line = rope.char_to_line(pos);
+                if motion == Motion::WordForward && rope.char_to_line(to) > line {
+                    to = max(line_content_end(rope, line), pos);
+                }
+                (to, motion.kind())
+            }
+        };
+
+        let (a, b) = (min(pos, to), max(pos, to));
+        Some(match kind {
+            MotionKind::Exclusive => Region {
+                ranges: vec![Range { start: a, end: b }],
+                linewise: false,
+            },
+            MotionKind::Inclusive => {
+                let end = if b < rope.len_chars() && rope.char(b) != '\n' {
+                    b + 1
+                } else {
+                    b
+                };
+                Region {
+                    ranges: vec![Range { start: a, end }],
+                    linewise: false,
+                }
+            }
+            MotionKind::Linewise => lines_region(rope, rope.char_to_line(a), rope.char_to_line(b)),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn operate(
+        &mut self,
+        buf: &mut Buffer,
+        view_id: ViewId,
+        registers: &mut Registers,
+        register: Option<char>,
+        op: Operator,
+        region: Region,
+        pos: usize,
+    ) {
+        let rope = buf.rope().clone();
+        let was_visual = self.mode.is_visual();
+        if was_visual {
+            self.mark_visual(buf);
+            self.mode = VimMode::Normal;
+        }
+        let Some(first) = region.ranges.first().map(|r| r.start) else {
+            return;
+        };
+        let empty = region.ranges.iter().all(|r| r.start == r.end);
+        if empty && op != Operator::Change {
+            self.set_caret(buf, view_id, clamp_to_line(&rope, pos));
+            return;
+        }
+
+        if matches!(op, Operator::Delete | Operator::Change | Operator::Yank) && !empty {
+            let chunks = region
+                .ranges
+                .iter()
+                .map(|r| {
+                    let mut text = rope.slice(r.start..r.end).to_string();
+                    if region.linewise && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    text
+                })
+                .collect();
+            let entry = ClipboardEntry {
+                chunks,
+                linewise: region.linewise,
+            };
+            registers.store(register, entry, op == Operator::Yank);
+        }
+
+        match op {
+            Operator::Yank => {
+                let to = if region.linewise && !was_visual {
+                    pos
+                } else {
+                    first
+                };
+                self.set_caret(buf, view_id, clamp_to_line(&rope, to));
+            }
+            Operator::Delete => {
+                let mut ranges = region.ranges.clone();
+                // Deleting the last lines takes the line break before them
+                // too, if there's none after them
+                if let Some(r) = ranges.last_mut() {
+                    let len = rope.len_chars();
+                    if region.linewise && r.end == len && r.start > 0 && rope.char(len - 1) != '\n'
+                    {
+                        r.start -= 1;
+                    }
+                }
+                let edits: Vec<(Range, String)> =
+                    ranges.iter().map(|r| (*r, String::new())).collect();
+                buf.edit(view_id, &edits, &[caret(ranges[0].start)]);
+                let rope = buf.rope();
+                let start = min(ranges[0].start, rope.len_chars());
+                let to = if region.linewise {
+                    first_non_blank(rope, rope.char_to_line(start))
+                } else {
+                    start
+                };
+                let to = clamp_to_line(rope, to);
+                self.set_caret(buf, view_id, to);
+            }
+            Operator::Change => {
+                let mut ranges = region.ranges.clone();
+                if region.linewise {
+                    // Keep the line break and indentation of changed lines
+                    for r in &mut ranges {
+                        let first_line = rope.char_to_line(r.start);
+                        let last_line = rope.char_to_line(max(r.end, r.start + 1) - 1);
+                        r.start = first_non_blank(&rope, first_line);
+                        r.end = max(line_content_end(&rope, last_line), r.start);
+                    }
+                }
+                let mut removed = 0;
+                let carets: Vec<Selection> = ranges
+                    .iter()
+                    .map(|r| {
+                        let sel = caret(r.start - removed);
+                        removed += r.end - r.start;
+                        sel
+                    })
+                    .collect();
+                let edits: Vec<(Range, String)> =
+                    ranges.iter().map(|r| (*r, String::new())).collect();
+                buf.edit(view_id, &edits, &carets);
+                self.mode = VimMode::Insert;
+            }
+            Operator::Indent | Operator::Dedent => {
+                let last = region.ranges.last().map(|r| r.end).unwrap_or(first);
+                let first_line = rope.char_to_line(first);
+                let last_line = rope.char_to_line(max(last, first + 1) - 1);
+                let unit = buf.indent_unit();
+                let width = unit.chars().count();
+                let mut edits = Vec::new();
+                for line in first_line..=last_line {
+                    let start = rope.line_to_char(line);
+                    let end = line_content_end(&rope, line);
+                    if op == Operator::Indent {
+                        if end > start {
+                            edits.push((Range { start, end: start }, unit.clone()));
+                        }
+                        continue;
+                    }
+                    let mut n = 0;
+                    for c in rope.chars_at(start).take(end - start) {
+                        match c {
+                            '\t' if n == 0 => {
+                                n = 1;
+                                break;
+                            }
+                            ' ' if n < width => n += 1,
+                            _ => break,
+                        }
+                    }
+                    if n > 0 {
+                        edits.push((
+                            Range {
+                                start,
+                                end: start + n,
+                            },
+                            String::new(),
+                        ));
+                    }
+                }
+                buf.edit(view_id, &edits, &[caret(rope.line_to_char(first_line))]);
+                let to = first_non_blank(buf.rope(), first_line);
+                self.set_caret(buf, view_id, to);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn paste(
+        &mut self,
+        buf: &mut Buffer,
+        view_id: ViewId,
+        registers: &Registers,
+        register: Option<char>,
+        before: bool,
+        count: Option<usize>,
+        pos: usize,
+    ) {
+        let Some(entry) = registers.get(register) else {
+            self.message = Some("Nothing in register".to_string());
+            return;
+        };
+        let rope = buf.rope();
+        let text = entry.text().repeat(count.unwrap_or(1).max(1));
+        let line = rope.char_to_line(pos);
+
+        if entry.linewise {
+            let (at, text, target_line) = if before {
+                (rope.line_to_char(line), text, line)
+            } else if line + 1 < rope.len_lines() {
+                (rope.line_to_char(line + 1), text, line + 1)
+            } else {
+                // The last line has no line break to paste after
+                let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                (rope.len_chars(), text, line + 1)
+            };
+            buf.edit(
+                view_id,
+                &[(Range { start: at, end: at }, text)],
+                &[caret(at)],
+            );
+            let to = first_non_blank(buf.rope(), target_line);
+            self.set_caret(buf, view_id, to);
+        } else {
+            let at = if before {
+                pos
+            } else {
+                min(pos + 1, line_content_end(rope, line))
+            };
+            let len = text.chars().count();
+            buf.edit(
+                view_id,
+                &[(Range { start: at, end: at }, text)],
+                &[caret(at + len)],
+            );
+            self.set_caret(buf, view_id, at + len.saturating_sub(1));
+        }
+    }
+
+    fn insert(&mut self, buf: &mut Buffer, view_id: ViewId, pos: usize, at: InsertAt) {
+        let rope = buf.rope();
+        let line = rope.char_to_line(pos);
+        let start = rope.line_to_char(line);
+        let end = line_content_end(rope, line);
+        let indent = rope.slice(start..first_non_blank(rope, line)).to_string();
+        match at {
+            InsertAt::Caret => self.set_caret(buf, view_id, pos),
+            InsertAt::After => self.set_caret(buf, view_id, min(pos + 1, end)),
+            InsertAt::LineStart => {
+                let to = first_non_blank(rope, line);
+                self.set_caret(buf, view_id, to);
+            }
+            InsertAt::LineEnd => self.set_caret(buf, view_id, end),
+            InsertAt::LineBelow => {
+                let text = format!("\n{indent}");
+                let to = end + text.chars().count();
+                buf.edit(view_id, &[(Range { start: end, end }, text)], &[caret(to)]);
+            }
+            InsertAt::LineAbove => {
+                let to = start + indent.chars().count();
+                let text = format!("{indent}\n");
+                buf.edit(
+                    view_id,
+                    &[(Range { start, end: start }, text)],
+                    &[caret(to)],
+                );
+            }
+        }
+        self.mode = VimMode::Insert;
+    }
+
+    fn command_line_key(&mut self, buf: &mut Buffer, view_id: ViewId, key: Key) -> VimResult {
+        match key {
+            Key::Escape => {
+                self.mode = VimMode::Normal;
+                self.command_line.clear();
+            }
+            Key::Backspace if self.command_line.pop().is_none() => {
+                self.mode = VimMode::Normal;
+            }
+            Key::Return => {
+                self.mode = VimMode::Normal;
+                let line = std::mem::take(&mut self.command_line);
+                match line.parse::<ExCommand>() {
+                    Ok(cmd) => return self.run_ex(buf, view_id, cmd),
+                    Err(e) => self.message = Some(e.to_string()),
+                }
+            }
+            Key::Char(c) => self.command_line.push(c),
+            _ => {}
+        }
+        VimResult::Handled
+    }
+
+    /// The line an ex address refers to, counting from 0
+    fn resolve(&self, buf: &Buffer, pos: usize, addr: Address) -> Option<usize> {
+        let rope = buf.rope();
+        let last = last_line(rope);
+        match addr {
+            Address::Current => Some(rope.char_to_line(pos)),
+            Address::Last => Some(last),
+            Address::Line(n) => Some(min(n.saturating_sub(1), last)),
+            Address::Mark(name) => self.mark_pos(buf, name).map(|p| rope.char_to_line(p)),
+        }
+    }
+
+    fn run_ex(&mut self, buf: &mut Buffer, view_id: ViewId, cmd: ExCommand) -> VimResult {
+        let pos = clamp_to_line(buf.rope(), self.caret_pos(buf, view_id));
+        match cmd {
+            ExCommand::Write(None) => return VimResult::Commands(vec![Command::Save]),
+            ExCommand::Write(Some(path)) => {
+                return VimResult::Commands(vec![Command::SaveAs(path)])
+            }
+            ExCommand::WriteQuit => {
+                return VimResult::Commands(vec![Command::Save, Command::CloseView])
+            }
+            ExCommand::Quit { force } => {
+                // An untitled buffer that was never typed in is never dirty
+                if force || buf.pristine || buf.len_chars() == 0 {
+                    return VimResult::Commands(vec![Command::CloseView]);
+                }
+                self.message = Some("No write since last change (add ! to override)".to_string());
+            }
+            ExCommand::Goto(addr) => {
+                if let Some(line) = self.resolve(buf, pos, addr) {
+                    let to = first_non_blank(buf.rope(), line);
+                    self.set_caret(buf, view_id, to);
+                }
+            }
+            ExCommand::Substitute {
+                range,
+                pattern,
+                replacement,
+                global,
+                ignore_case,
+            } => {
+                let rope = buf.rope();
+                let (Some(a), Some(b)) = (
+                    self.resolve(buf, pos, range.start),
+                    self.resolve(buf, pos, range.end),
+                ) else {
+                    self.message = Some("Mark not set".to_string());
+                    return VimResult::Handled;
+                };
+                let (first, last) = (min(a, b), max(a, b));
+                match substitute(
+                    rope,
+                    first,
+                    last,
+                    &pattern,
+                    &replacement,
+                    global,
+                    ignore_case,
+                ) {
+                    Err(e) => self.message = Some(e.to_string()),
+                    Ok(edits) if edits.is_empty() => {
+                        self.message = Some(format!("Pattern not found: {pattern}"))
+                    }
+                    Ok(edits) => {
+                        let last_line = rope.char_to_line(edits[edits.len() - 1].0.start);
+                        buf.edit(view_id, &edits, &[caret(edits[0].0.start)]);
+                        let to = first_non_blank(buf.rope(), min(last_line, buf.len_lines() - 1));
+                        self.set_caret(buf, view_id, to);
+                        if edits.len() > 1 {
+                            self.message = Some(format!("{} substitutions", edits.len()));
+                        }
+                    }
+                }
+            }
+        }
+        VimResult::Handled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(s: &str) -> Vec<KeyChord> {
+        s.chars()
+            .map(|c| {
+                let mut chord = KeyChord::new(&c.to_lowercase().to_string());
+                chord.shift = c.is_uppercase();
+                chord
+            })
+            .collect()
+    }
+
+    /// Feeds keys to vim, typing into the buffer in insert mode the way the
+    /// window would
+    fn feed(vim: &mut Vim, buf: &mut Buffer, regs: &mut Registers, chords: &[KeyChord]) {
+        for chord in chords {
+            match vim.press(buf, 1, regs, chord) {
+                VimResult::PassThrough => buf.insert(1, &chord.text().unwrap()),
+                VimResult::Replay(keys) => feed(vim, buf, regs, &keys),
+                _ => {}
+            }
+        }
+    }
+
+    fn setup(text: &str) -> (Vim, Buffer, Registers) {
+        let mut buf = Buffer::new(0);
+        buf.init_view(1);
+        buf.edit(
+            1,
+            &[(Range { start: 0, end: 0 }, text.to_string())],
+            &[caret(0)],
+        );
+        (Vim::default(), buf, Registers::default())
+    }
+
+    #[test]
+    fn test_operators_and_counts() {
+        let (mut vim, mut buf, mut regs) = setup("one two three four\nsecond\nthird\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("d2w"));
+        assert_eq!(buf.to_string(), "three four\nsecond\nthird\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("jddp"));
+        assert_eq!(buf.to_string(), "three four\nthird\nsecond\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("gg>j"));
+        assert_eq!(buf.to_string(), "    three four\n    third\nsecond\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("u"));
+        assert_eq!(buf.to_string(), "three four\nthird\nsecond\n");
+    }
+
+    #[test]
+    fn test_change_and_repeat() {
+        let (mut vim, mut buf, mut regs) = setup("foo(a, b) foo(c)\n");
+        let mut chords = keys("wci(x");
+        chords.push(KeyChord::new("escape"));
+        feed(&mut vim, &mut buf, &mut regs, &chords);
+        assert_eq!(buf.to_string(), "foo(x) foo(c)\n");
+        assert_eq!(vim.mode(), VimMode::Normal);
+        feed(&mut vim, &mut buf, &mut regs, &keys("www."));
+        assert_eq!(buf.to_string(), "foo(x) foo(x)\n");
+        // The whole change is undone at once
+        feed(&mut vim, &mut buf, &mut regs, &keys("u"));
+        assert_eq!(buf.to_string(), "foo(x) foo(c)\n");
+
+        // A count on `.` replaces the change's own
+        let (mut vim, mut buf, mut regs) = setup("1 2 3 4 5 6 7 8 9 10\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("2dw"));
+        assert_eq!(buf.to_string(), "3 4 5 6 7 8 9 10\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("3."));
+        assert_eq!(buf.to_string(), "6 7 8 9 10\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("."));
+        assert_eq!(buf.to_string(), "9 10\n");
+        // The count goes after the register
+        feed(&mut vim, &mut buf, &mut regs, &keys("u\"ad2w1."));
+        assert_eq!(buf.to_string(), "9 10\n");
+        assert_eq!(
+            regs.get(Some('a')).map(|r| r.text()),
+            Some("8 ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_visual_registers_and_marks() {
+        let (mut vim, mut buf, mut regs) = setup("alpha\nbeta\ngamma\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("maVj\"ay"));
+        assert_eq!(regs.get(Some('a')).unwrap().text(), "alpha\nbeta\n");
+        feed(&mut vim, &mut buf, &mut regs, &keys("G'a"));
+        assert_eq!(vim.caret_pos(&buf, 1), 0);
+        feed(&mut vim, &mut buf, &mut regs, &keys("G\"aP"));
+        assert_eq!(buf.to_string(), "alpha\nbeta\nalpha\nbeta\ngamma\n");
+
+        // Marks move with the text, through edits and undo
+        feed(&mut vim, &mut buf, &mut regs, &keys("Gkmbggdd`b"));
+        assert_eq!(buf.to_string(), "beta\nalpha\nbeta\ngamma\n");
+        assert_eq!(vim.caret_pos(&buf, 1), buf.rope().line_to_char(2));
+        feed(&mut vim, &mut buf, &mut regs, &keys("u`b"));
+        assert_eq!(vim.caret_pos(&buf, 1), buf.rope().line_to_char(3));
+    }
+
+    #[test]
+    fn test_ex_substitute() {
+        let (mut vim, mut buf, mut regs) = setup("a-a\nb-a\n");
+        let mut chords = keys(":%s/a/x/g");
+        chords.push(KeyChord::new("return"));
+        feed(&mut vim, &mut buf, &mut regs, &chords);
+        assert_eq!(buf.to_string(), "x-x\nb-x\n");
+
+        let mut chords = keys(":q");
+        chords.push(KeyChord::new("return"));
+        let results: Vec<VimResult> = chords
+            .iter()
+            .map(|c| vim.press(&mut buf, 1, &mut regs, c))
+            .collect();
+        assert_eq!(results.last(), Some(&VimResult::Handled));
+        assert!(vim.status().starts_with("No write"));
+    }
+
+    #[test]
+    fn test_crlf() {
+        let path = std::env::temp_dir().join(format!("eddy-vim-crlf-{}.txt", std::process::id()));
+        std::fs::write(&path, "one\r\n  a,b\r\n").unwrap();
+        let mut buf = Buffer::from_file(0, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        buf.init_view(1);
+        let (mut vim, mut regs) = (Vim::default(), Registers::default());
+
+        // The caret lands after the whole line break
+        feed(&mut vim, &mut buf, &mut regs, &keys("ox"));
+        assert_eq!(buf.to_string(), "one\r\nx\r\n  a,b\r\n");
+        let mut chords = vec![KeyChord::new("escape")];
+        chords.extend(keys("jOy"));
+        feed(&mut vim, &mut buf, &mut regs, &chords);
+        assert_eq!(buf.to_string(), "one\r\nx\r\n  y\r\n  a,b\r\n");
+
+        let mut chords = vec![KeyChord::new("escape")];
+        chords.extend(keys("j:s/,/\\r/"));
+        chords.push(KeyChord::new("return"));
+        feed(&mut vim, &mut buf, &mut regs, &chords);
+        assert_eq!(buf.to_string(), "one\r\nx\r\n  y\r\n  a\r\nb\r\n");
+    }
+}
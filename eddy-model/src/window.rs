@@ -1,35 +1,83 @@
 use crate::backend::{Backend, DirEntry};
+use crate::emacs::Emacs;
+use crate::files::{
+    FileChange, FileStat, FileWatcher, Recovery, RecoveryFile, RecoveryKey, SaveOptions,
+};
+use crate::jump_list::{Jump, JumpKind, JumpList, FAR_LINES};
+use crate::keymap::{
+    format_key_sequence, user_keymap_path, KeyChord, KeyContext, KeyProfile, KeyResult, Keymap,
+};
+use crate::language::{self, language_by_name, Symbol, SymbolEntry};
 use crate::lsp::{self, LanguageServerClient, ResultQueue};
-use crate::project::{FileNode, Project};
+use crate::palette::{CommandPalette, PaletteEntry};
+use crate::project::{FileIndex, FileNode, Project};
+use crate::quick_open::{FilePosition, QuickOpen, QuickOpenEntry};
+use crate::snippet::{user_snippets_dir, Snippet, SnippetElement, SnippetLibrary};
 use crate::style::{AttrSpan, Theme};
-use crate::Buffer;
+use crate::vim::{Registers, Vim, VimMode, VimResult};
+use crate::{
+    BookmarkEntry, Buffer, ClipboardEntry, ClipboardHistory, Command, CommandOutput,
+    ConflictResolution, ExternalChange, ProjectSession, ScrollPos, Selection, UiRequest,
+    ViewSession, WindowSession,
+};
 use anyhow::Context;
-use log::debug;
+use log::{debug, error, warn};
 use lsp_types::Uri;
-use ropey::RopeSlice;
+use ropey::{Rope, RopeSlice};
 use serde_json::Value;
 use std::borrow::BorrowMut;
 use std::cell::RefCell;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fmt;
 use std::future::Future;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::process::{Command, Stdio};
+use std::process::Stdio;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use url::Url;
 
 pub type BufferId = usize;
 pub type ViewId = usize;
 pub type ProjectId = usize;
 
+/// How long a buffer has to go without edits before it's journaled
+const JOURNAL_IDLE: Duration = Duration::from_secs(1);
+/// Dirty buffers are journaled at least this often, even while being edited
+const JOURNAL_INTERVAL: Duration = Duration::from_secs(30);
+/// How far paging moves in views the frontend hasn't measured
+const DEFAULT_PAGE_LINES: usize = 20;
+
+/// When buffers with unsaved changes get written to their files without
+/// being asked
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum AutosavePolicy {
+    #[default]
+    Off,
+    /// Save once the buffer has gone this long without an edit
+    AfterDelay(Duration),
+    /// Save when the window loses focus
+    OnFocusLoss,
+}
+
 // pub struct JoinHandle<R>{};
 
+/// Remembers the last paste, so that a yank-pop can swap it for an older
+/// entry as long as nothing else changed in the meantime
+#[derive(Debug, Copy, Clone)]
+struct LastPaste {
+    view_id: ViewId,
+    /// The clipboard history entry that was pasted, if it came from there
+    index: Option<usize>,
+    /// The buffer's revision right after the paste
+    revision: u64,
+}
+
 pub struct Window {
     pub views: BTreeMap<ViewId, BufferId>,
     buffers: BTreeMap<BufferId, Buffer>,
@@ -40,6 +88,47 @@ pub struct Window {
     pub backend: Backend,
     pub dir_entries: Option<Vec<DirEntry>>,
     pub projects: BTreeMap<ProjectId, Project>,
+    pub save_options: SaveOptions,
+    pub autosave: AutosavePolicy,
+    /// Scroll offsets of views, as last reported by the frontend
+    pub scroll: HashMap<ViewId, ScrollPos>,
+    /// How many lines each view shows, as last reported by the frontend
+    pub page_lines: HashMap<ViewId, usize>,
+    pub sidebar_width: Option<i32>,
+    /// Everything recently cut or copied in this window
+    pub clipboard_history: ClipboardHistory,
+    /// Save the clipboard history with the session
+    pub persist_clipboard_history: bool,
+    last_paste: Option<LastPaste>,
+    /// The view the last command killed a line in, so a kill straight after
+    /// adds to it
+    last_kill: Option<ViewId>,
+    watcher: FileWatcher,
+    recovery: Recovery,
+    /// The key and revision each buffer was last journaled with
+    journaled: HashMap<BufferId, (RecoveryKey, u64)>,
+    last_journal: Instant,
+    pub keymap: Keymap,
+    keymap_path: PathBuf,
+    /// Why the user's keymap couldn't be loaded, if it couldn't
+    pub keymap_error: Option<String>,
+    /// How key presses are interpreted
+    pub key_profile: KeyProfile,
+    /// The state of vim emulation in each view
+    vim: HashMap<ViewId, Vim>,
+    /// Vim's registers, shared by every view
+    pub registers: Registers,
+    /// The state of Emacs emulation in each view
+    emacs: HashMap<ViewId, Emacs>,
+    pub palette: CommandPalette,
+    pub quick_open: QuickOpen,
+    /// The default snippets and the user's
+    pub snippets: SnippetLibrary,
+    snippets_dir: PathBuf,
+    /// Where the caret jumped from, to go back to
+    jump_list: JumpList,
+    /// Wakes up the frontend when something finishes in the background
+    wakeup: Arc<dyn Fn() + Send + Sync>,
 }
 
 impl fmt::Debug for Window {
@@ -55,24 +144,58 @@ impl fmt::Debug for Window {
     }
 }
 
+/// Where the caret was before a command that may jump
+#[derive(Debug, Copy, Clone)]
+struct JumpOrigin {
+    view_id: ViewId,
+    buf_id: BufferId,
+    pos: usize,
+    /// A search was in progress, and `pos` is where it started
+    searching: bool,
+}
+
 impl Window {
     #[allow(clippy::new_without_default)]
     pub fn new(wakeup: Arc<dyn Fn() + Send + Sync>) -> Self {
+        let dir = crate::working_dir();
         let mut projects = BTreeMap::new();
-        projects.insert(
-            0,
-            Project::new("TestProject", std::env::current_dir().expect("cwd")),
-        );
+        projects.insert(0, Project::new("TestProject", dir.clone()));
         let mut win = Self {
             views: BTreeMap::new(),
             buffers: BTreeMap::new(),
             theme: Theme::default(),
             ls_client: None,
-            dir: std::env::current_dir().expect("cwd"),
+            dir,
             focused_view: None,
-            backend: Backend::ssh("brain", "127.0.0.1:22", None, wakeup),
+            backend: Backend::ssh("brain", "127.0.0.1:22", None, wakeup.clone()),
             dir_entries: None,
             projects,
+            save_options: SaveOptions::default(),
+            autosave: AutosavePolicy::default(),
+            scroll: HashMap::new(),
+            page_lines: HashMap::new(),
+            sidebar_width: None,
+            clipboard_history: ClipboardHistory::default(),
+            persist_clipboard_history: false,
+            last_paste: None,
+            last_kill: None,
+            watcher: FileWatcher::new(wakeup.clone()),
+            recovery: Recovery::default(),
+            journaled: HashMap::new(),
+            last_journal: Instant::now(),
+            keymap: Keymap::default(),
+            keymap_path: user_keymap_path(),
+            keymap_error: None,
+            key_profile: KeyProfile::default(),
+            vim: HashMap::new(),
+            registers: Registers::default(),
+            emacs: HashMap::new(),
+            palette: CommandPalette::default(),
+            quick_open: QuickOpen::default(),
+            snippets: SnippetLibrary::default(),
+            snippets_dir: user_snippets_dir(),
+            jump_list: JumpList::default(),
+            wakeup,
         };
 
         win.refresh_dir(0, &PathBuf::new());
@@ -92,9 +215,10 @@ impl Window {
         dbg!("new view", view_id, buf_id);
         let mut buffer = if let Some(path) = path {
             let buf = Buffer::from_file(buf_id, path)?;
+            self.watcher.watch(path);
 
             dbg!(path);
-            if let Some("rs") = path.extension().and_then(OsStr::to_str) {
+            if buf.language() == Some("rust") {
                 // let mut child = Command::new("rust-analyzer")
                 //     .stdin(Stdio::piped())
                 //     .stdout(Stdio::piped())
@@ -143,17 +267,45 @@ impl Window {
             Buffer::new(buf_id)
         };
         buffer.init_view(view_id);
+        buffer.set_wakeup(self.wakeup.clone());
         self.buffers.insert(buf_id, buffer);
+        self.focused_view = Some(view_id);
 
         Ok(view_id)
     }
 
     pub fn close_view(&mut self, view_id: usize) {
         debug!("close view {view_id}");
-        self.views.remove(&view_id);
+        if let Some(mut vim) = self.vim.remove(&view_id) {
+            self.forget_marks(view_id, &mut vim);
+        }
+        let buf_id = self.views.remove(&view_id);
+        self.scroll.remove(&view_id);
+        self.page_lines.remove(&view_id);
+        self.emacs.remove(&view_id);
         if self.focused_view == Some(view_id) {
             self.focused_view = None;
         }
+        if let Some(buf_id) = buf_id {
+            if !self.views.values().any(|b| *b == buf_id) {
+                if let Some(path) = self.buffers.get(&buf_id).and_then(|b| b.path.clone()) {
+                    if !self.is_open_elsewhere(buf_id, &path) {
+                        self.watcher.unwatch(&path);
+                    }
+                }
+                // Closing the last view discards the buffer's unsaved text
+                self.unjournal(buf_id);
+                self.jump_list.remove_buffer(buf_id);
+            }
+        }
+    }
+
+    /// Returns true if a buffer other than `buf_id` that's still in a view
+    /// has the file at `path` open
+    fn is_open_elsewhere(&self, buf_id: BufferId, path: &Path) -> bool {
+        self.views.values().any(|id| {
+            *id != buf_id && self.buffers.get(id).and_then(|b| b.path.as_deref()) == Some(path)
+        })
     }
 
     pub fn refresh_dir(&mut self, proj_id: ProjectId, path: &Path) {
@@ -245,12 +397,1237 @@ impl Window {
 
     pub fn has_events(&self) -> bool {
         self.backend.has_resp()
+            || self.watcher.has_events()
+            || self.buffers.values().any(Buffer::has_highlights)
     }
 
     pub fn handle_events(&mut self) {
+        for buf in self.buffers.values_mut() {
+            buf.apply_highlights();
+        }
         while let Some((resp, cb)) = self.backend.try_recv_response_cb() {
             cb(self, resp);
         }
+        // Whether a file is open is decided before any event is handled, as
+        // a rename starts watching the file's new path
+        let mut changes = Vec::new();
+        while let Some((path, change)) = self.watcher.try_recv() {
+            let watched = self.watcher.is_watched(&path);
+            changes.push((path, change, watched));
+        }
+        for (path, change, watched) in changes {
+            if path == self.keymap_path {
+                self.reload_keymap();
+                continue;
+            }
+            if path.starts_with(&self.snippets_dir) {
+                self.reload_snippets();
+                continue;
+            }
+            if watched {
+                self.on_file_changed(&path, change);
+            }
+            self.update_file_index(&path);
+        }
+    }
+
+    /// Has the backend list every file in a project for quick open, and
+    /// watches the project's directories so the list keeps up as files come
+    /// and go.  Ignored directories aren't watched, so build output and
+    /// `.git` don't have to be walked by notify or heard about.
+    pub fn index_project(&mut self, proj_id: ProjectId) {
+        let Some(dir) = self.projects.get(&proj_id).map(|p| p.dir.clone()) else {
+            return;
+        };
+        self.backend.walk(
+            &dir,
+            Box::new(move |win, tree| {
+                let Some(proj) = win.projects.get_mut(&proj_id) else {
+                    return;
+                };
+                let index = FileIndex::from(tree);
+                let old_dirs = proj.index.take().map(|i| i.dirs).unwrap_or_default();
+                for rel in index.dirs.difference(&old_dirs) {
+                    win.watcher.watch_dir(&proj.dir.join(rel));
+                }
+                for rel in old_dirs.difference(&index.dirs) {
+                    win.watcher.unwatch_dir(&proj.dir.join(rel));
+                }
+                proj.index = Some(index);
+            }),
+        );
+    }
+
+    /// Stops watching the directories of every project's file index
+    fn unwatch_projects(&mut self) {
+        for proj in self.projects.values() {
+            for rel in proj.index.iter().flat_map(|i| &i.dirs) {
+                self.watcher.unwatch_dir(&proj.dir.join(rel));
+            }
+        }
+    }
+
+    /// Brings the file indexes up to date with a path that was created,
+    /// removed or renamed.  notify only says which path changed, and the
+    /// backend is asked what's there now.  Only a changed `.gitignore`, or
+    /// a directory that appears with files already in it, needs the project
+    /// walked again.
+    fn update_file_index(&mut self, path: &Path) {
+        let mut reindex = Vec::new();
+        let mut look_up = false;
+        for (&proj_id, proj) in &self.projects {
+            let (Some(_), Ok(rel)) = (&proj.index, path.strip_prefix(&proj.dir)) else {
+                continue;
+            };
+            if rel.file_name() == Some(OsStr::new(".gitignore")) {
+                reindex.push(proj_id);
+            } else {
+                look_up = true;
+            }
+        }
+        for proj_id in reindex {
+            self.index_project(proj_id);
+        }
+        if look_up {
+            let path2 = path.to_owned();
+            self.backend.entry(
+                path,
+                Box::new(move |win, entry| win.index_entry(&path2, entry)),
+            );
+        }
+    }
+
+    /// Adds or removes `path` in the file indexes, given what the backend
+    /// found there.  New directories that aren't ignored are watched, and
+    /// removed ones aren't anymore.
+    fn index_entry(&mut self, path: &Path, entry: Option<DirEntry>) {
+        let mut new_dirs = Vec::new();
+        for (&proj_id, proj) in &mut self.projects {
+            let Some(index) = &mut proj.index else {
+                continue;
+            };
+            let Ok(rel) = path.strip_prefix(&proj.dir) else {
+                continue;
+            };
+            match &entry {
+                Some(entry) if entry.is_dir() => {
+                    if !index.ignore.is_ignored(rel, true) && index.dirs.insert(rel.to_owned()) {
+                        self.watcher.watch_dir(path);
+                        new_dirs.push(proj_id);
+                    }
+                }
+                Some(_) => index.insert(rel),
+                None => {
+                    for dir in index.remove(rel) {
+                        self.watcher.unwatch_dir(&proj.dir.join(dir));
+                    }
+                }
+            }
+        }
+        if new_dirs.is_empty() {
+            return;
+        }
+        self.backend.walk(
+            path,
+            Box::new(move |win, tree| {
+                // Only the directory itself is watched so far
+                if tree.files.is_empty() && tree.dirs.len() <= 1 {
+                    return;
+                }
+                for &proj_id in &new_dirs {
+                    win.index_project(proj_id);
+                }
+            }),
+        );
+    }
+
+    /// The indexed files that match a quick open query, best first
+    pub fn quick_open_entries(&self, query: &str) -> Vec<QuickOpenEntry> {
+        self.quick_open.entries(query, &self.projects)
+    }
+
+    /// Opens a file picked from quick open in a new view, going to a
+    /// position in it if one was typed
+    pub fn quick_open_file(
+        &mut self,
+        path: &Path,
+        position: Option<FilePosition>,
+    ) -> Result<ViewId, anyhow::Error> {
+        let from = self.focused_view.and_then(|v| self.jump_origin(v));
+        let view_id = self.new_view(Some(path))?;
+        self.quick_open.record(path);
+        if let Some(pos) = position {
+            let column = pos.column.unwrap_or(1);
+            self.buffer_mut(view_id)
+                .go_to_position(view_id, pos.line - 1, column - 1);
+        }
+        if let Some(from) = from {
+            self.track_jump(from, view_id, JumpKind::Always);
+        }
+        Ok(view_id)
+    }
+
+    /// The bookmarks in every buffer, by file and then position.  Each
+    /// points to the focused view if it shows the buffer, or else the first
+    /// view that does.
+    pub fn bookmark_entries(&self) -> Vec<BookmarkEntry> {
+        let mut entries = Vec::new();
+        for (buf_id, buf) in &self.buffers {
+            let Some(view_id) = self
+                .focused_view
+                .filter(|v| self.views.get(v) == Some(buf_id))
+                .or_else(|| {
+                    self.views
+                        .iter()
+                        .find(|(_, b)| *b == buf_id)
+                        .map(|(v, _)| *v)
+                })
+            else {
+                continue;
+            };
+            let file = self.display_name(view_id);
+            for mark in buf.bookmarks().iter() {
+                let line = buf.char_to_line(mark.pos);
+                entries.push(BookmarkEntry {
+                    view_id,
+                    pos: mark.pos,
+                    line,
+                    name: mark.name.clone(),
+                    file: file.clone(),
+                    preview: buf.line(line).to_string().trim().to_string(),
+                });
+            }
+        }
+        entries.sort_by(|a, b| a.file.cmp(&b.file).then(a.pos.cmp(&b.pos)));
+        entries
+    }
+
+    /// Focuses the view a bookmark was listed with, and puts the caret on it
+    pub fn go_to_bookmark(&mut self, entry: &BookmarkEntry) {
+        if !self.views.contains_key(&entry.view_id) {
+            return;
+        }
+        let from = self.focused_view.and_then(|v| self.jump_origin(v));
+        let buf = self.buffer_mut(entry.view_id);
+        let pos = entry.pos.min(buf.len_chars());
+        buf.replace_selections(
+            entry.view_id,
+            &[Selection {
+                start: pos,
+                end: pos,
+                horiz: None,
+            }],
+        );
+        self.focused_view = Some(entry.view_id);
+        if let Some(from) = from {
+            self.track_jump(from, entry.view_id, JumpKind::Always);
+        }
+    }
+
+    /// Puts the caret on the name of a symbol from `Buffer::symbols`
+    pub fn go_to_symbol(&mut self, view_id: ViewId, symbol: &Symbol) {
+        if !self.views.contains_key(&view_id) {
+            return;
+        }
+        let from = self.jump_origin(view_id);
+        let buf = self.buffer_mut(view_id);
+        let pos = symbol.name_range.start.min(buf.len_chars());
+        buf.replace_selections(
+            view_id,
+            &[Selection {
+                start: pos,
+                end: pos,
+                horiz: None,
+            }],
+        );
+        if let Some(from) = from {
+            self.track_jump(from, view_id, JumpKind::Always);
+        }
+    }
+
+    /// Where a jump by the next command in a view would be from: the caret,
+    /// or where a search in progress started
+    fn jump_origin(&self, view_id: ViewId) -> Option<JumpOrigin> {
+        let buf_id = *self.views.get(&view_id)?;
+        let search = self.emacs.get(&view_id).and_then(Emacs::search_origin);
+        let pos = match search {
+            Some(pos) => pos,
+            None => self
+                .buffers
+                .get(&buf_id)?
+                .selections(view_id)
+                .first()?
+                .cursor(),
+        };
+        Some(JumpOrigin {
+            view_id,
+            buf_id,
+            pos,
+            searching: search.is_some(),
+        })
+    }
+
+    /// Records where the caret was if it has jumped away from there, to
+    /// `to_view`.  Ending a search counts as a jump from where it started.
+    fn track_jump(&mut self, from: JumpOrigin, to_view: ViewId, kind: JumpKind) {
+        let searched =
+            from.searching && self.jump_origin(from.view_id).is_some_and(|o| !o.searching);
+        let kind = if searched { JumpKind::Always } else { kind };
+        if kind == JumpKind::Never {
+            return;
+        }
+        let Some(to) = self.jump_origin(to_view) else {
+            return;
+        };
+        if to.buf_id == from.buf_id {
+            let buf = &self.buffers[&from.buf_id];
+            let from_line = buf.char_to_line(from.pos.min(buf.len_chars()));
+            let lines = from_line.abs_diff(buf.char_to_line(to.pos));
+            let far_enough = match kind {
+                JumpKind::IfFar => FAR_LINES,
+                _ => 1,
+            };
+            if lines < far_enough {
+                return;
+            }
+        }
+        self.record_jump(from.view_id, from.buf_id, from.pos);
+    }
+
+    /// Adds a position to the jump list, unless the last jump was from the
+    /// same line
+    fn record_jump(&mut self, view_id: ViewId, buf_id: BufferId, pos: usize) {
+        let Some(buf) = self.buffers.get_mut(&buf_id) else {
+            return;
+        };
+        let pos = pos.min(buf.len_chars());
+        if let Some(last) = self.jump_list.last() {
+            let last_pos = if last.buf_id == buf_id {
+                buf.anchor(last.anchor)
+            } else {
+                None
+            };
+            if last_pos.is_some_and(|p| buf.char_to_line(p) == buf.char_to_line(pos)) {
+                return;
+            }
+        }
+        let anchor = buf.add_anchor(pos);
+        let dropped = self.jump_list.push(Jump {
+            view_id,
+            buf_id,
+            anchor,
+        });
+        for jump in dropped {
+            self.forget_jump(jump);
+        }
+    }
+
+    fn forget_jump(&mut self, jump: Jump) {
+        if let Some(buf) = self.buffers.get_mut(&jump.buf_id) {
+            buf.remove_anchor(jump.anchor);
+        }
+    }
+
+    /// Goes back to where the caret was before the last jump, in whichever
+    /// view it was in
+    pub fn jump_back(&mut self, view_id: ViewId) {
+        self.move_through_jumps(view_id, true);
+    }
+
+    pub fn jump_forward(&mut self, view_id: ViewId) {
+        self.move_through_jumps(view_id, false);
+    }
+
+    fn move_through_jumps(&mut self, view_id: ViewId, back: bool) {
+        let Some(here) = self.jump_origin(view_id) else {
+            return;
+        };
+        loop {
+            let taken = if back {
+                self.jump_list.take_back()
+            } else {
+                self.jump_list.take_forward()
+            };
+            let Some(jump) = taken else {
+                return;
+            };
+            let Some(buf) = self.buffers.get_mut(&jump.buf_id) else {
+                continue;
+            };
+            let pos = buf.anchor(jump.anchor).unwrap_or_default();
+            buf.remove_anchor(jump.anchor);
+            // Skip jumps to where the caret already is
+            if jump.buf_id == here.buf_id && buf.char_to_line(pos) == buf.char_to_line(here.pos) {
+                continue;
+            }
+            // The view the jump was made in, or another one on its buffer
+            let target = Some(jump.view_id)
+                .filter(|v| self.views.get(v) == Some(&jump.buf_id))
+                .or_else(|| {
+                    self.views
+                        .iter()
+                        .find(|(_, b)| **b == jump.buf_id)
+                        .map(|(v, _)| *v)
+                });
+            let Some(target) = target else {
+                continue;
+            };
+
+            let buf = self.buffers.get_mut(&here.buf_id).expect("buffer");
+            let current = Jump {
+                view_id,
+                buf_id: here.buf_id,
+                anchor: buf.add_anchor(here.pos),
+            };
+            if back {
+                self.jump_list.left_back(current);
+            } else {
+                self.jump_list.left_forward(current);
+            }
+            self.buffer_mut(target).replace_selections(
+                target,
+                &[Selection {
+                    start: pos,
+                    end: pos,
+                    horiz: None,
+                }],
+            );
+            self.focused_view = Some(target);
+            return;
+        }
+    }
+
+    /// Loads the user's keymap and snippets, and watches them for changes.
+    /// Until then the window only has the defaults.
+    pub fn load_config(&mut self) {
+        self.reload_keymap();
+        self.reload_snippets();
+        self.watch_config();
+    }
+
+    /// Loads the default bindings and the user's keymap file again.  If the
+    /// file is broken, the old bindings stay and `keymap_error` says why.
+    pub fn reload_keymap(&mut self) {
+        match Keymap::load(&self.keymap_path) {
+            Ok(keymap) => {
+                for conflict in keymap.conflicts() {
+                    warn!("keymap: {conflict}");
+                }
+                self.keymap = keymap;
+                self.keymap_error = None;
+            }
+            Err(e) => {
+                error!("{e}");
+                self.keymap_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Starts watching the keymap file and the snippets directory once
+    /// their directories exist, which may not be until after startup.
+    /// Returns true if either just started.
+    fn watch_config(&mut self) -> bool {
+        let mut started = false;
+        if !self.watcher.is_watched(&self.keymap_path)
+            && self.keymap_path.parent().is_some_and(Path::exists)
+        {
+            self.watcher.watch(&self.keymap_path);
+            started = true;
+        }
+        if !self.watcher.is_tree_watched(&self.snippets_dir) && self.snippets_dir.exists() {
+            self.watcher.watch_tree(&self.snippets_dir.clone());
+            started = true;
+        }
+        started
+    }
+
+    /// Loads the default snippets and the user's snippet files again.
+    /// Files that are broken are left out.
+    pub fn reload_snippets(&mut self) {
+        let (snippets, errors) = SnippetLibrary::load(&self.snippets_dir);
+        for e in errors {
+            error!("{e}");
+        }
+        self.snippets = snippets;
+    }
+
+    /// Expands the snippet whose prefix was typed right before every caret,
+    /// or inserts a tab if there isn't one
+    fn expand_snippet(&mut self, view_id: ViewId) {
+        let buf = self.buffer(view_id);
+        let sels = buf.selections(view_id);
+        let found = self
+            .snippets
+            .snippets(buf.language())
+            .into_iter()
+            .filter(|def| {
+                sels.iter()
+                    .all(|s| s.is_caret() && prefix_before(buf.rope(), s.cursor(), &def.prefix))
+            })
+            .max_by_key(|def| def.prefix.chars().count())
+            .cloned();
+        let Some(def) = found else {
+            self.buffer_mut(view_id).insert_tab(view_id);
+            return;
+        };
+        let snippet = match def.snippet() {
+            Ok(snippet) => snippet,
+            Err(e) => {
+                // The body still goes in, as plain text
+                error!("snippet {:?}: {e}", def.prefix);
+                Snippet {
+                    elements: vec![SnippetElement::Text(def.body.clone())],
+                }
+            }
+        };
+        let len = def.prefix.chars().count();
+        let prefixes: Vec<Selection> = sels
+            .iter()
+            .map(|s| Selection {
+                start: s.cursor() - len,
+                end: s.cursor(),
+                horiz: None,
+            })
+            .collect();
+        self.insert_snippet(view_id, &snippet, &prefixes);
+    }
+
+    /// Inserts a snippet in place of some selections
+    fn insert_snippet(&mut self, view_id: ViewId, snippet: &Snippet, sels: &[Selection]) {
+        let clipboard = self.clipboard_history.latest().map(ClipboardEntry::text);
+        let vars = |name: &str| match name {
+            "CLIPBOARD" => clipboard.clone(),
+            _ => None,
+        };
+        let buf = self.buffer_mut(view_id);
+        buf.replace_selections(view_id, sels);
+        buf.insert_snippet(view_id, snippet, &vars);
+    }
+
+    /// The state of a view that key binding conditions can test.  The
+    /// frontend adds its own flags, like `search_panel_focused`.
+    pub fn key_context(&self, view_id: ViewId) -> KeyContext {
+        let mut ctx = KeyContext::new();
+        let buf = self.buffer(view_id);
+        if buf.selections(view_id).iter().any(|s| !s.is_caret()) {
+            ctx.set_flag("has_selection");
+        }
+        if let Some(language) = buf.language() {
+            ctx.set("language", language);
+        }
+        ctx.set("key_profile", self.key_profile.name());
+        if buf.in_snippet(view_id) {
+            ctx.set_flag("in_snippet");
+        }
+        if !buf.snippet_choices(view_id).is_empty() {
+            ctx.set_flag("snippet_choice");
+        }
+        if let Some(mode) = self.vim_mode(view_id) {
+            ctx.set("vim_mode", &mode.name().to_lowercase());
+        }
+        ctx
+    }
+
+    /// Feeds a key press into the keymap.  `flags` are set in the context
+    /// on top of the view's own.  With the vim profile, vim sees the key
+    /// first, and only keys typed in insert mode reach the keymap.  With
+    /// Emacs, a C-u argument repeats the command the keys come to.
+    pub fn press_key(&mut self, view_id: ViewId, chord: KeyChord, flags: &[&str]) -> KeyResult {
+        if self.key_profile == KeyProfile::Vim {
+            let from = self.jump_origin(view_id);
+            let revision = |win: &Self| from.map(|o| win.buffers[&o.buf_id].revision());
+            let before = revision(self);
+            match self.press_vim_key(view_id, &chord) {
+                VimResult::Handled => {
+                    // Vim moves the caret itself, so far moves that don't
+                    // edit count as jumps
+                    if let (Some(from), true) = (from, revision(self) == before) {
+                        self.track_jump(from, view_id, JumpKind::IfFar);
+                    }
+                    return KeyResult::Handled;
+                }
+                VimResult::PassThrough => {}
+                VimResult::Commands(cmds) => {
+                    for cmd in cmds {
+                        if let Err(e) = self.execute(view_id, cmd) {
+                            error!("{e:#}");
+                            if let Some(vim) = self.vim.get_mut(&view_id) {
+                                vim.set_message(e.to_string());
+                            }
+                            break;
+                        }
+                    }
+                    return KeyResult::Handled;
+                }
+                VimResult::Replay(keys) => {
+                    self.replay_keys(view_id, keys, flags);
+                    return KeyResult::Handled;
+                }
+            }
+        }
+
+        let searching = self.emacs.get(&view_id).is_some_and(Emacs::is_searching);
+        let from = self.jump_origin(view_id);
+        if (self.key_profile == KeyProfile::Emacs || searching)
+            && self.press_emacs_key(view_id, &chord)
+        {
+            if let Some(from) = from {
+                self.track_jump(from, view_id, JumpKind::Never);
+            }
+            return KeyResult::Handled;
+        }
+
+        let mut ctx = self.key_context(view_id);
+        for flag in flags {
+            ctx.set_flag(flag);
+        }
+        let result = self.keymap.press(chord, &ctx);
+        if matches!(
+            result,
+            KeyResult::Pending | KeyResult::Command(Command::UniversalArgument)
+        ) {
+            return result;
+        }
+        let Some(count) = self.emacs.get_mut(&view_id).and_then(Emacs::take_argument) else {
+            return result;
+        };
+        // The frontend runs repeats itself, so it sees what each run cut
+        match result {
+            KeyResult::Command(cmd) => KeyResult::Repeat(cmd, count),
+            KeyResult::Unbound(keys) => match (&keys[..], keys[0].text()) {
+                ([_], Some(text)) => KeyResult::Command(Command::Insert(text.repeat(count))),
+                _ => KeyResult::Unbound(keys),
+            },
+            result => result,
+        }
+    }
+
+    fn press_emacs_key(&mut self, view_id: ViewId, chord: &KeyChord) -> bool {
+        let Some(buf) = self
+            .views
+            .get(&view_id)
+            .and_then(|buf_id| self.buffers.get_mut(buf_id))
+        else {
+            return false;
+        };
+        let emacs = self.emacs.entry(view_id).or_default();
+        emacs.press(buf, view_id, chord)
+    }
+
+    fn press_vim_key(&mut self, view_id: ViewId, chord: &KeyChord) -> VimResult {
+        let Some(buf) = self
+            .views
+            .get(&view_id)
+            .and_then(|buf_id| self.buffers.get_mut(buf_id))
+        else {
+            return VimResult::PassThrough;
+        };
+        let vim = self.vim.entry(view_id).or_default();
+        vim.press(buf, view_id, &mut self.registers, chord)
+    }
+
+    /// Presses keys again for vim's `.`, typing the ones nothing is bound to
+    fn replay_keys(&mut self, view_id: ViewId, keys: Vec<KeyChord>, flags: &[&str]) {
+        for chord in keys {
+            match self.press_key(view_id, chord.clone(), flags) {
+                KeyResult::Command(cmd) => {
+                    if let Err(e) = self.execute(view_id, cmd) {
+                        error!("{e:#}");
+                    }
+                }
+                KeyResult::Repeat(cmd, count) => {
+                    for _ in 0..count {
+                        if let Err(e) = self.execute(view_id, cmd.clone()) {
+                            error!("{e:#}");
+                            break;
+                        }
+                    }
+                }
+                KeyResult::Unbound(_) => {
+                    if let Some(text) = chord.text() {
+                        self.buffer_mut(view_id).insert(view_id, &text);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Switches how keys are interpreted, starting every view afresh
+    pub fn set_key_profile(&mut self, profile: KeyProfile) {
+        self.key_profile = profile;
+        for (view_id, mut vim) in std::mem::take(&mut self.vim) {
+            self.forget_marks(view_id, &mut vim);
+        }
+        self.emacs.clear();
+        self.keymap.reset();
+    }
+
+    /// Lets go of the anchors behind a view's vim marks
+    fn forget_marks(&mut self, view_id: ViewId, vim: &mut Vim) {
+        if let Some(buf) = self
+            .views
+            .get(&view_id)
+            .and_then(|buf_id| self.buffers.get_mut(buf_id))
+        {
+            vim.remove_marks(buf);
+        }
+    }
+
+    /// The view's vim mode, if vim is the key profile
+    pub fn vim_mode(&self, view_id: ViewId) -> Option<VimMode> {
+        (self.key_profile == KeyProfile::Vim)
+            .then(|| self.vim.get(&view_id).map(Vim::mode).unwrap_or_default())
+    }
+
+    /// The name of the buffer's language, to show in the status bar
+    pub fn language_title(&self, view_id: ViewId) -> &'static str {
+        self.buffer(view_id)
+            .language()
+            .and_then(language_by_name)
+            .map_or("Plain Text", |config| config.title)
+    }
+
+    /// The symbols in a view's buffer that match a query, for the symbol
+    /// picker
+    pub fn symbol_entries(&self, view_id: ViewId, query: &str) -> Vec<SymbolEntry> {
+        language::symbol_entries(self.buffer(view_id).symbols(), query)
+    }
+
+    /// The symbols the caret is in, like `Buffer › insert`, to show in
+    /// the status bar
+    pub fn breadcrumbs(&self, view_id: ViewId) -> String {
+        let path = self.buffer(view_id).symbol_path(view_id);
+        let names: Vec<&str> = path.iter().map(|s| s.name.as_str()).collect();
+        names.join(" › ")
+    }
+
+    /// Switches the buffer to another language, by its name or one of its
+    /// aliases.  "plain" and "text" turn highlighting off.
+    pub fn set_language(&mut self, view_id: ViewId, name: &str) -> Result<(), anyhow::Error> {
+        let name = name.trim();
+        let config = if ["plain", "text", "plain text"]
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(name))
+        {
+            None
+        } else {
+            match language_by_name(name) {
+                Some(config) => Some(config),
+                None => anyhow::bail!("unknown language: {name}"),
+            }
+        };
+        let buf = self.buffer_mut(view_id);
+        buf.set_language(config);
+
+        // The server only knows the document under the language it was
+        // opened with
+        let (language, path, text) = (buf.language(), buf.path.clone(), buf.to_string());
+        if let Some(ls_client) = &self.ls_client {
+            let mut ls_client = ls_client.lock().expect("lsp");
+            if ls_client.is_open(view_id) {
+                ls_client.send_did_close(view_id);
+            }
+            if let (true, Some(path)) = (
+                ls_client.is_initialized && language == Some(ls_client.language_id()),
+                path,
+            ) {
+                let document_uri =
+                    Uri::from_str(&format!("file://{}", path.display())).expect("uri from path");
+                ls_client.send_did_open(view_id, document_uri, text);
+            }
+        }
+        Ok(())
+    }
+
+    /// What a status line under the view should show, for key profiles
+    /// that have state: vim's mode, or Emacs' search and prefix keys
+    pub fn key_status(&self, view_id: ViewId) -> Option<String> {
+        let emacs = self.emacs.get(&view_id);
+        match self.key_profile {
+            KeyProfile::Vim => Some(self.vim.get(&view_id).map(Vim::status).unwrap_or_default()),
+            KeyProfile::Emacs if self.keymap.is_pending() => {
+                Some(format!("{}-", format_key_sequence(self.keymap.pending())))
+            }
+            KeyProfile::Emacs => Some(emacs.map(Emacs::status).unwrap_or_default()),
+            KeyProfile::Standard => emacs
+                .filter(|e| e.is_searching())
+                .map(Emacs::status)
+                .or_else(|| {
+                    let choices = self.buffer(view_id).snippet_choices(view_id);
+                    (!choices.is_empty()).then(|| format!("Choices: {}", choices.join(" | ")))
+                }),
+        }
+    }
+
+    /// The command palette's entries for a query, with the key bindings
+    /// that work in the view
+    pub fn palette_entries(&self, view_id: ViewId, query: &str) -> Vec<PaletteEntry> {
+        let mut ctx = self.key_context(view_id);
+        ctx.set_flag("editor_focused");
+        self.palette.entries(query, &self.keymap, &ctx)
+    }
+
+    fn page_lines(&self, view_id: ViewId) -> usize {
+        self.page_lines
+            .get(&view_id)
+            .copied()
+            .unwrap_or(DEFAULT_PAGE_LINES)
+    }
+
+    /// Checks the open files notify isn't watching through the backend, for
+    /// file systems that can't be watched.  Changes are noticed by comparing
+    /// mtime and size.
+    pub fn poll_file_changes(&mut self) {
+        let paths: BTreeSet<PathBuf> = self
+            .buffers
+            .values()
+            .filter_map(|b| b.path.clone())
+            .filter(|p| !self.watcher.is_notified(p))
+            .collect();
+        for path in paths {
+            let path2 = path.clone();
+            self.backend.stat(
+                &path,
+                Box::new(move |win, stat| {
+                    let Some(buf) = win
+                        .buffers
+                        .values()
+                        .find(|b| b.path.as_ref() == Some(&path2))
+                    else {
+                        return;
+                    };
+                    if stat == buf.disk_stat() {
+                        return;
+                    }
+                    if stat.is_none() {
+                        win.apply_file_change(&path2, FileChange::Removed, None, None);
+                        return;
+                    }
+                    let path3 = path2.clone();
+                    win.backend.read(
+                        &path2,
+                        Box::new(move |win, text| match text {
+                            Some(text) => win.apply_file_change(
+                                &path3,
+                                FileChange::Modified,
+                                Some(Rope::from_str(&text)),
+                                stat,
+                            ),
+                            None => debug!("unable to read {}", path3.display()),
+                        }),
+                    );
+                }),
+            );
+        }
+    }
+
+    /// Brings the buffers for `path` up to date with a change notify saw.
+    /// Pristine buffers are reloaded, others are flagged with an
+    /// `ExternalChange` for the user to resolve.
+    pub fn on_file_changed(&mut self, path: &Path, change: FileChange) {
+        // notify only watches the local file system, so the file is read
+        // from there
+        let change = match change {
+            FileChange::Removed if path.exists() => FileChange::Modified,
+            c => c,
+        };
+        debug!("file changed {}: {:?}", path.display(), change);
+
+        let theirs = match change {
+            FileChange::Modified => match std::fs::read_to_string(path) {
+                Ok(s) => Some(Rope::from_str(&s)),
+                Err(e) => {
+                    debug!("unable to read {}: {e}", path.display());
+                    return;
+                }
+            },
+            _ => None,
+        };
+        let stat = FileStat::from_path(path);
+        self.apply_file_change(path, change, theirs, stat);
+    }
+
+    /// Updates the buffers for `path` with what's now on disk: `theirs` is
+    /// the new text of a modified file, and `stat` its metadata
+    fn apply_file_change(
+        &mut self,
+        path: &Path,
+        change: FileChange,
+        theirs: Option<Rope>,
+        stat: Option<FileStat>,
+    ) {
+        if let FileChange::Renamed(to) = &change {
+            self.watcher.unwatch(path);
+            self.watcher.watch(to);
+        }
+        let buf_ids: Vec<BufferId> = self
+            .buffers
+            .iter()
+            .filter(|(_, b)| b.path.as_deref() == Some(path))
+            .map(|(id, _)| *id)
+            .collect();
+        for buf_id in buf_ids {
+            // Nothing is shown of buffers whose views were all closed
+            let Some(view_id) = self.view_for_buffer(buf_id) else {
+                continue;
+            };
+            let buf = self.buffers.get_mut(&buf_id).expect("buffer");
+            match (&change, &theirs) {
+                (FileChange::Renamed(to), _) => {
+                    buf.path = Some(to.clone());
+                    buf.recovery_key = RecoveryKey::Path(to.clone());
+                }
+                (FileChange::Modified, Some(theirs)) => {
+                    if buf.is_saved_text(theirs) {
+                        // Our own save, or a touch
+                        buf.set_disk_stat(stat);
+                    } else if buf.pristine {
+                        buf.reload(view_id, theirs.clone(), stat);
+                    } else {
+                        buf.set_disk_stat(stat);
+                        buf.external_change = Some(ExternalChange::Modified(theirs.clone()));
+                    }
+                }
+                _ => buf.mark_deleted(),
+            }
+        }
+    }
+
+    /// The view whose selections a change to a buffer that no view made is
+    /// recorded with: the focused view if it shows the buffer, or else the
+    /// first that does
+    fn view_for_buffer(&self, buf_id: BufferId) -> Option<ViewId> {
+        self.focused_view
+            .filter(|v| self.views.get(v) == Some(&buf_id))
+            .or_else(|| {
+                self.views
+                    .iter()
+                    .find(|(_, b)| **b == buf_id)
+                    .map(|(v, _)| *v)
+            })
+    }
+
+    /// Does the periodic work that isn't driven by events: journaling
+    /// buffers, autosaving, and watching config directories that have
+    /// appeared.  Meant to be called every second or so.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let force = now.duration_since(self.last_journal) >= JOURNAL_INTERVAL;
+        self.journal_buffers(|idle| force || idle >= JOURNAL_IDLE);
+        if force {
+            self.last_journal = now;
+        }
+
+        if let AutosavePolicy::AfterDelay(delay) = self.autosave {
+            self.autosave_buffers(|idle| idle >= delay);
+        }
+
+        // Files may have been written along with their directories
+        if self.watch_config() {
+            self.reload_keymap();
+            self.reload_snippets();
+        }
+    }
+
+    /// Journals every buffer with unsaved edits right away
+    pub fn journal_all(&mut self) {
+        self.journal_buffers(|_| true);
+    }
+
+    /// Called when the window loses focus
+    pub fn focus_lost(&mut self) {
+        if self.autosave == AutosavePolicy::OnFocusLoss {
+            self.autosave_buffers(|_| true);
+        }
+        self.journal_all();
+    }
+
+    /// Writes the text of dirty buffers that changed since they were last
+    /// journaled to their recovery files, if `ready` says they have been idle
+    /// long enough.  Recovery files of buffers that are no longer dirty are
+    /// removed.
+    fn journal_buffers(&mut self, ready: impl Fn(Duration) -> bool) {
+        let now = Instant::now();
+        let stale: Vec<BufferId> = self
+            .buffers
+            .values()
+            .filter(|buf| match self.journaled.get(&buf.id) {
+                Some((key, _)) => buf.pristine || *key != buf.recovery_key,
+                None => false,
+            })
+            .map(|buf| buf.id)
+            .collect();
+        for buf_id in stale {
+            self.unjournal(buf_id);
+        }
+        for buf in self.buffers.values() {
+            // Untitled buffers start out dirty, but there's nothing to lose
+            // until they're edited
+            if buf.pristine || buf.revision() == 0 {
+                continue;
+            }
+            let journaled = self.journaled.get(&buf.id);
+            if journaled.map(|(_, rev)| *rev) == Some(buf.revision()) {
+                continue;
+            }
+            let idle = buf
+                .last_edit()
+                .map(|t| now.duration_since(t))
+                .unwrap_or_default();
+            if !ready(idle) {
+                continue;
+            }
+            match self.recovery.write(&buf.recovery_key, buf.rope()) {
+                Ok(()) => {
+                    self.journaled
+                        .insert(buf.id, (buf.recovery_key.clone(), buf.revision()));
+                }
+                Err(e) => error!("unable to write recovery file: {e}"),
+            }
+        }
+    }
+
+    /// Saves dirty buffers that have a file, if `ready` says they have been
+    /// idle long enough.  Buffers with an unresolved external change are
+    /// left alone.
+    fn autosave_buffers(&mut self, ready: impl Fn(Duration) -> bool) {
+        let now = Instant::now();
+        let opts = self.save_options.clone();
+        for buf in self.buffers.values_mut() {
+            if buf.pristine || buf.path.is_none() || buf.external_change.is_some() {
+                continue;
+            }
+            let idle = buf
+                .last_edit()
+                .map(|t| now.duration_since(t))
+                .unwrap_or_default();
+            if !ready(idle) {
+                continue;
+            }
+            if let Err(e) = buf.save(&opts) {
+                error!("autosave failed: {e}");
+            }
+        }
+    }
+
+    /// Recovery files left behind by an earlier session.  Those of
+    /// sessions that are still running are in use, not left behind.
+    pub fn recoverable_files(&self) -> Vec<RecoveryFile> {
+        let ours: HashSet<&RecoveryKey> = self.buffers.values().map(|b| &b.recovery_key).collect();
+        self.recovery
+            .list()
+            .into_iter()
+            .filter(|f| !ours.contains(&f.key) && !f.is_owner_alive())
+            .collect()
+    }
+
+    /// Opens a view on the text in a recovery file.  Recovered files are
+    /// opened from disk, and then have the recovered text applied as an
+    /// unsaved edit.
+    pub fn restore_recovery(&mut self, file: &RecoveryFile) -> Result<ViewId, anyhow::Error> {
+        let view_id = match &file.key {
+            RecoveryKey::Path(p) if p.exists() => self.new_view(Some(p))?,
+            RecoveryKey::Path(p) => {
+                let view_id = self.new_view(None)?;
+                self.buffer_mut(view_id).path = Some(p.clone());
+                view_id
+            }
+            RecoveryKey::Untitled(_) => self.new_view(None)?,
+        };
+        let buf = self.buffer_mut(view_id);
+        buf.recovery_key = file.key.clone();
+        buf.replace_text(view_id, &Rope::from_str(&file.text));
+        let (buf_id, revision) = (buf.id, buf.revision());
+        self.journaled.insert(buf_id, (file.key.clone(), revision));
+        Ok(view_id)
+    }
+
+    /// Throws away a recovery file without restoring it, unless another
+    /// session has taken it up since it was listed
+    pub fn discard_recovery(&mut self, file: &RecoveryFile) {
+        let recoverable = self.recoverable_files();
+        if recoverable.iter().any(|f| f.swap_path == file.swap_path) {
+            self.remove_recovery(&file.key);
+        }
+    }
+
+    /// Forgets the recovery file `buf_id` was journaled to, and removes it,
+    /// unless another buffer was journaled to it too, as buffers of the same
+    /// file are.  That buffer's text is written there again instead.
+    fn unjournal(&mut self, buf_id: BufferId) {
+        let Some((key, _)) = self.journaled.remove(&buf_id) else {
+            return;
+        };
+        let sharing = self
+            .journaled
+            .iter()
+            .find(|(_, (k, _))| *k == key)
+            .map(|(id, _)| *id);
+        let Some(buf) = sharing.and_then(|id| self.buffers.get(&id)) else {
+            self.remove_recovery(&key);
+            return;
+        };
+        match self.recovery.write(&key, buf.rope()) {
+            Ok(()) => {
+                self.journaled.insert(buf.id, (key, buf.revision()));
+            }
+            Err(e) => error!("unable to write recovery file: {e}"),
+        }
+    }
+
+    fn remove_recovery(&self, key: &RecoveryKey) {
+        if let Err(e) = self.recovery.remove(key) {
+            error!("unable to remove recovery file: {e}");
+        }
+    }
+
+    /// Describes the window's views and projects, for saving the session
+    pub fn session(&self) -> WindowSession {
+        let mut seen = BTreeSet::new();
+        let views: Vec<ViewSession> = self
+            .views
+            .iter()
+            .map(|(view_id, buf_id)| {
+                let buf = &self.buffers[buf_id];
+                let key = (buf.path.clone(), buf.path.is_none().then_some(*buf_id));
+                let bookmarks = if seen.insert(key) {
+                    buf.bookmarks().iter().cloned().collect()
+                } else {
+                    Vec::new()
+                };
+                ViewSession {
+                    path: buf.path.clone(),
+                    recovery_key: self.journaled.get(buf_id).map(|(key, _)| key.clone()),
+                    selections: buf.selections(*view_id),
+                    scroll: self.scroll.get(view_id).copied().unwrap_or_default(),
+                    bookmarks,
+                }
+            })
+            .collect();
+        let focused = self
+            .focused_view
+            .and_then(|v| self.views.keys().position(|k| *k == v));
+        let projects = self
+            .projects
+            .values()
+            .map(|p| ProjectSession {
+                name: p.name.clone(),
+                dir: p.dir.clone(),
+            })
+            .collect();
+        let clipboard_history = if self.persist_clipboard_history {
+            self.clipboard_history.iter().cloned().collect()
+        } else {
+            Vec::new()
+        };
+        WindowSession {
+            views,
+            focused,
+            projects,
+            sidebar_width: self.sidebar_width,
+            clipboard_history,
+        }
+    }
+
+    /// Reopens the views and projects of a saved session, and indexes the
+    /// projects for quick open.  Buffers that had unsaved edits get their
+    /// journaled text back.  Files that can no
+    /// longer be opened are skipped.
+    pub fn restore_session(&mut self, session: &WindowSession) {
+        if !session.projects.is_empty() {
+            self.unwatch_projects();
+            self.projects.clear();
+            for (proj_id, p) in session.projects.iter().enumerate() {
+                self.projects
+                    .insert(proj_id, Project::new(&p.name, p.dir.clone()));
+                self.refresh_dir(proj_id, Path::new(""));
+            }
+        }
+        let proj_ids: Vec<ProjectId> = self.projects.keys().copied().collect();
+        for proj_id in proj_ids {
+            self.index_project(proj_id);
+        }
+        self.sidebar_width = session.sidebar_width;
+        if !session.clipboard_history.is_empty() {
+            self.persist_clipboard_history = true;
+            self.clipboard_history
+                .set_entries(session.clipboard_history.clone());
+        }
+
+        let recoverable = self.recoverable_files();
+        let mut view_ids = Vec::new();
+        for vs in &session.views {
+            let recovered = vs
+                .recovery_key
+                .as_ref()
+                .and_then(|key| recoverable.iter().find(|f| f.key == *key));
+            let res = match (recovered, &vs.path) {
+                (Some(file), _) => self.restore_recovery(file),
+                (None, Some(path)) => self.new_view(Some(path)),
+                // An untitled buffer that was never edited
+                (None, None) => self.new_view(None),
+            };
+            let view_id = match res {
+                Ok(view_id) => view_id,
+                Err(e) => {
+                    error!("unable to restore view: {e}");
+                    continue;
+                }
+            };
+            let buf = self.buffer_mut(view_id);
+            if !vs.selections.is_empty() {
+                buf.replace_selections(view_id, &vs.selections);
+                buf.fix_selections();
+            }
+            if !vs.bookmarks.is_empty() {
+                buf.set_bookmarks(vs.bookmarks.clone());
+            }
+            self.scroll.insert(view_id, vs.scroll);
+            view_ids.push(view_id);
+        }
+        self.focused_view = session
+            .focused
+            .and_then(|i| view_ids.get(i).copied())
+            .or(self.focused_view);
+    }
+
+    /// Views whose buffers have an external change waiting to be resolved,
+    /// one view per buffer
+    pub fn pending_external_changes(&self) -> Vec<ViewId> {
+        let mut seen = BTreeSet::new();
+        self.views
+            .iter()
+            .filter(|(_, buf_id)| {
+                self.buffers
+                    .get(buf_id)
+                    .map(|b| b.external_change.is_some())
+                    .unwrap_or_default()
+                    && seen.insert(**buf_id)
+            })
+            .map(|(view_id, _)| *view_id)
+            .collect()
+    }
+
+    /// Resolves the external change pending on a view's buffer.  Taking
+    /// theirs for a deleted file closes the view.  Returns the number of
+    /// merge conflicts left in the buffer.
+    pub fn resolve_external_change(
+        &mut self,
+        view_id: ViewId,
+        resolution: ConflictResolution,
+    ) -> usize {
+        let deleted = matches!(
+            self.buffer(view_id).external_change,
+            Some(ExternalChange::Deleted)
+        );
+        if deleted && resolution == ConflictResolution::TakeTheirs {
+            self.buffer_mut(view_id).external_change = None;
+            self.close_view(view_id);
+            return 0;
+        }
+        self.buffer_mut(view_id)
+            .resolve_external_change(view_id, resolution)
     }
 
     // pub async fn list_files(&self) -> Result<Vec<String>, anyhow::Error> {
@@ -322,166 +1699,719 @@ impl Window {
     }
 
     pub fn save(&mut self, view_id: usize) -> Result<(), anyhow::Error> {
-        self.buffer_mut(view_id).save()?;
+        let opts = self.save_options.clone();
+        self.buffer_mut(view_id).save(&opts)?;
 
         Ok(())
     }
 
     pub fn save_as(&mut self, view_id: usize, path: &Path) -> Result<(), anyhow::Error> {
         dbg!(path);
-        self.buffer_mut(view_id).save_as(path)?;
+        let opts = self.save_options.clone();
+        let old_path = self.buffer(view_id).path.clone();
+        self.buffer_mut(view_id).save_as(path, &opts)?;
+        let buf_id = self.views[&view_id];
+        if let Some(old_path) = old_path {
+            if !self.is_open_elsewhere(buf_id, &old_path) {
+                self.watcher.unwatch(&old_path);
+            }
+        }
+        self.watcher.watch(path);
         Ok(())
     }
 
-    pub fn insert(&mut self, view_id: ViewId, text: &str) {
-        self.buffer_mut(view_id).insert(view_id, text);
+    /// Runs a command on a view, and remembers where the caret was if the
+    /// command jumped away from there
+    pub fn execute(
+        &mut self,
+        view_id: ViewId,
+        cmd: Command,
+    ) -> Result<CommandOutput, anyhow::Error> {
+        let kind = JumpKind::of(&cmd);
+        let from = self.jump_origin(view_id);
+        let focused = self.focused_view;
+        let res = self.run_command(view_id, cmd);
+        if let Some(from) = from {
+            // Opening a view moves the caret there
+            let to = match self.focused_view {
+                Some(v) if self.focused_view != focused => v,
+                _ => view_id,
+            };
+            self.track_jump(from, to, kind);
+        }
+        res
     }
 
-    pub fn insert_newline(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).insert_newline(view_id);
-    }
+    fn run_command(
+        &mut self,
+        view_id: ViewId,
+        cmd: Command,
+    ) -> Result<CommandOutput, anyhow::Error> {
+        use Command::*;
 
-    pub fn insert_tab(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).insert_tab(view_id);
-    }
+        let after_kill = self.last_kill.take() == Some(view_id);
+        let cmd = match (self.emacs.get_mut(&view_id), self.views.get(&view_id)) {
+            (Some(emacs), Some(buf_id)) => match self.buffers.get_mut(buf_id) {
+                Some(buf) => emacs.before_command(buf, view_id, cmd),
+                None => cmd,
+            },
+            _ => cmd,
+        };
+        let lines = self.page_lines(view_id);
+        let buf = self.buffer_mut(view_id);
+        let mut out = CommandOutput::None;
+        match cmd {
+            Insert(text) => buf.insert(view_id, &text),
+            InsertNewline => buf.insert_newline(view_id),
+            InsertTab => buf.insert_tab(view_id),
+            InsertSnippet(body) => {
+                let snippet: Snippet = body.parse()?;
+                let sels = buf.selections(view_id);
+                self.insert_snippet(view_id, &snippet, &sels);
+            }
+            ExpandSnippet => self.expand_snippet(view_id),
+            NextTabStop => buf.next_tab_stop(view_id),
+            PreviousTabStop => buf.prev_tab_stop(view_id),
+            NextSnippetChoice => buf.next_snippet_choice(view_id),
+            DeleteForward => buf.delete_forward(view_id),
+            DeleteBackward => buf.delete_backward(view_id),
+            Cut => out = self.cut(view_id).into(),
+            Copy => out = self.copy(view_id).into(),
+            Paste(text) => self.paste(view_id, &text),
+            PasteSystemClipboard => out = CommandOutput::Ui(UiRequest::ReadClipboard),
+            ShowClipboardHistory => out = CommandOutput::Ui(UiRequest::ClipboardHistory),
+            PasteFromHistory(index) => self.paste_from_history(view_id, index),
+            YankPop => {
+                self.yank_pop(view_id);
+            }
+            KillLine => out = self.kill_line(view_id, after_kill).into(),
+            Undo => buf.undo(view_id),
+            Redo => buf.redo(view_id),
 
-    pub fn delete_forward(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).delete_forward(view_id);
-    }
+            MoveLeft => buf.move_left(view_id),
+            MoveRight => buf.move_right(view_id),
+            MoveUp => buf.move_up(view_id),
+            MoveDown => buf.move_down(view_id),
+            MoveWordLeft => buf.move_word_left(view_id),
+            MoveWordRight => buf.move_word_right(view_id),
+            MoveToLeftEndOfLine => buf.move_to_left_end_of_line(view_id),
+            MoveToRightEndOfLine => buf.move_to_right_end_of_line(view_id),
+            MoveToBeginningOfDocument => buf.move_to_beginning_of_document(view_id),
+            MoveToEndOfDocument => buf.move_to_end_of_document(view_id),
+            PageUp => buf.page_up(view_id, lines),
+            PageDown => buf.page_down(view_id, lines),
+            GoToLine(line) => buf.go_to_line(view_id, line.saturating_sub(1)),
 
-    pub fn delete_backward(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).delete_backward(view_id);
-    }
+            MoveLeftAndModifySelection => buf.move_left_and_modify_selection(view_id),
+            MoveRightAndModifySelection => buf.move_right_and_modify_selection(view_id),
+            MoveUpAndModifySelection => buf.move_up_and_modify_selection(view_id),
+            MoveDownAndModifySelection => buf.move_down_and_modify_selection(view_id),
+            MoveWordLeftAndModifySelection => buf.move_word_left_and_modify_selection(view_id),
+            MoveWordRightAndModifySelection => buf.move_word_right_and_modify_selection(view_id),
+            MoveToLeftEndOfLineAndModifySelection => {
+                buf.move_to_left_end_of_line_and_modify_selection(view_id)
+            }
+            MoveToRightEndOfLineAndModifySelection => {
+                buf.move_to_right_end_of_line_and_modify_selection(view_id)
+            }
+            MoveToBeginningOfDocumentAndModifySelection => {
+                buf.move_to_beginning_of_document_and_modify_selection(view_id)
+            }
+            MoveToEndOfDocumentAndModifySelection => {
+                buf.move_to_end_of_document_and_modify_selection(view_id)
+            }
+            PageUpAndModifySelection => buf.page_up_and_modify_selection(view_id, lines),
+            PageDownAndModifySelection => buf.page_down_and_modify_selection(view_id, lines),
+            SelectAll => buf.select_all(view_id),
+            SetMark => {
+                let (emacs, buf) = self.emacs_and_buffer(view_id);
+                emacs.set_mark(buf, view_id);
+            }
+            ExchangePointAndMark => {
+                let (emacs, buf) = self.emacs_and_buffer(view_id);
+                emacs.exchange_point_and_mark(buf, view_id);
+            }
+            KeyboardQuit => {
+                let (emacs, buf) = self.emacs_and_buffer(view_id);
+                emacs.keyboard_quit(buf, view_id);
+            }
+            PointSelect { line, byte } => buf.gesture_point_select(view_id, line, byte),
+            RangeSelect { line, byte } => buf.gesture_range_select(view_id, line, byte),
+            ToggleSelect { line, byte } => buf.gesture_toggle_sel(view_id, line, byte),
+            WordSelect { line, byte } => buf.gesture_word_select(view_id, line, byte),
+            LineSelect { line } => buf.gesture_line_select(view_id, line),
+            DragUpdate { line, byte } => buf.drag_update(view_id, line, byte),
+            DragEnd => buf.drag_end(view_id),
 
-    pub fn move_left(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_left(view_id);
-    }
+            IsearchForward | IsearchBackward => {
+                let (emacs, buf) = self.emacs_and_buffer(view_id);
+                emacs.isearch(buf, view_id, cmd == IsearchForward);
+            }
+            ToggleBookmark => buf.toggle_bookmark(view_id),
+            SetBookmark(name) => buf.set_bookmark(view_id, &name),
+            NextBookmark => buf.next_bookmark(view_id),
+            PreviousBookmark => buf.prev_bookmark(view_id),
+            ShowBookmarks => out = CommandOutput::Ui(UiRequest::Bookmarks),
+            ShowSymbols => out = CommandOutput::Ui(UiRequest::Symbols),
+            SetLanguage(name) => self.set_language(view_id, &name)?,
+            JumpBack => self.jump_back(view_id),
+            JumpForward => self.jump_forward(view_id),
 
-    pub fn move_right(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_right(view_id);
-    }
+            UniversalArgument => self.emacs.entry(view_id).or_default().universal_argument(),
 
-    pub fn move_up(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_up(view_id);
-    }
-    pub fn move_up_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_up_and_modify_selection(view_id);
+            OpenFile => out = CommandOutput::Ui(UiRequest::OpenFile),
+            QuickOpen => out = CommandOutput::Ui(UiRequest::QuickOpen),
+            Save => self.save(view_id)?,
+            SaveAs(path) => self.save_as(view_id, &path)?,
+            ResolveExternalChange(res) => {
+                out = CommandOutput::Conflicts(self.resolve_external_change(view_id, res))
+            }
+
+            ShowCommandPalette => out = CommandOutput::Ui(UiRequest::CommandPalette),
+            NewView(path) => out = CommandOutput::View(self.new_view(path.as_deref())?),
+            CloseView => self.close_view(view_id),
+            FocusView => self.focused_view = Some(view_id),
+        }
+        if let Some(buf) = self
+            .views
+            .get(&view_id)
+            .and_then(|buf_id| self.buffers.get_mut(buf_id))
+        {
+            buf.check_snippet(view_id);
+        }
+        Ok(out)
     }
 
-    pub fn move_down(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_down(view_id);
+    /// A view's Emacs state, along with its buffer
+    fn emacs_and_buffer(&mut self, view_id: ViewId) -> (&mut Emacs, &mut Buffer) {
+        let buf = self.buffers.get_mut(&self.views[&view_id]).expect("buffer");
+        (self.emacs.entry(view_id).or_default(), buf)
     }
 
-    pub fn move_down_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_down_and_modify_selection(view_id);
+    /// Cuts to the end of the line, like Emacs' C-k.  With `append`, the text
+    /// is added to the latest clipboard entry instead of making a new one.
+    pub fn kill_line(&mut self, view_id: ViewId, append: bool) -> Option<String> {
+        let entry = self.buffer_mut(view_id).kill_line(view_id)?;
+        self.last_kill = Some(view_id);
+        match self.clipboard_history.latest() {
+            Some(latest) if append => {
+                let entry = latest.append(&entry);
+                let text = entry.text();
+                self.clipboard_history.replace_latest(entry);
+                Some(text)
+            }
+            _ => Some(self.set_clipboard(entry)),
+        }
     }
 
-    pub fn move_word_left(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_word_left(view_id);
+    pub fn cut(&mut self, view_id: ViewId) -> Option<String> {
+        let entry = self.buffer_mut(view_id).cut(view_id)?;
+        Some(self.set_clipboard(entry))
     }
-    pub fn move_word_right(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_word_right(view_id);
+    pub fn copy(&mut self, view_id: ViewId) -> Option<String> {
+        let entry = self.buffer(view_id).copy(view_id)?;
+        Some(self.set_clipboard(entry))
     }
 
-    pub fn move_left_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_left_and_modify_selection(view_id);
+    /// Records a cut or copy in the clipboard history, and returns its text
+    fn set_clipboard(&mut self, entry: ClipboardEntry) -> String {
+        let text = entry.text();
+        self.clipboard_history.push(entry);
+        text
     }
 
-    pub fn move_right_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_right_and_modify_selection(view_id);
+    /// Pastes text from the system clipboard.  If it's what was last cut or
+    /// copied here, it's pasted with the shape it was copied in.
+    pub fn paste(&mut self, view_id: ViewId, text: &str) {
+        let (entry, index) = match self.clipboard_history.latest() {
+            Some(entry) if entry.text() == text => (entry.clone(), Some(0)),
+            _ => (ClipboardEntry::from_text(text), None),
+        };
+        self.paste_entry(view_id, &entry, index);
     }
 
-    pub fn move_word_left_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_word_left_and_modify_selection(view_id);
-    }
-    pub fn move_word_right_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_word_right_and_modify_selection(view_id);
-    }
-    pub fn move_to_left_end_of_line(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_to_left_end_of_line(view_id);
-    }
-    pub fn move_to_right_end_of_line(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_to_right_end_of_line(view_id);
-    }
-    pub fn move_to_left_end_of_line_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_to_left_end_of_line_and_modify_selection(view_id);
-    }
-    pub fn move_to_right_end_of_line_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_to_right_end_of_line_and_modify_selection(view_id);
+    /// Pastes an entry picked from the clipboard history
+    pub fn paste_from_history(&mut self, view_id: ViewId, index: usize) {
+        if let Some(entry) = self.clipboard_history.get(index).cloned() {
+            self.paste_entry(view_id, &entry, Some(index));
+        }
     }
-    pub fn move_to_beginning_of_document(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_to_beginning_of_document(view_id);
+
+    fn paste_entry(&mut self, view_id: ViewId, entry: &ClipboardEntry, index: Option<usize>) {
+        let buf = self.buffer_mut(view_id);
+        buf.paste(view_id, entry);
+        self.last_paste = Some(LastPaste {
+            view_id,
+            index,
+            revision: buf.revision(),
+        });
     }
 
-    pub fn move_to_end_of_document(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).move_to_end_of_document(view_id);
+    /// Replaces the text that was just pasted with the next older entry in
+    /// the clipboard history, cycling back to the newest after the oldest.
+    /// Returns false if the last thing done in the view wasn't a paste.
+    pub fn yank_pop(&mut self, view_id: ViewId) -> bool {
+        let Some(last) = self.last_paste else {
+            return false;
+        };
+        if last.view_id != view_id
+            || self.buffer(view_id).revision() != last.revision
+            || self.clipboard_history.is_empty()
+        {
+            return false;
+        }
+        let index = last.index.map(|i| i + 1).unwrap_or_default() % self.clipboard_history.len();
+        let Some(entry) = self.clipboard_history.get(index).cloned() else {
+            return false;
+        };
+
+        let buf = self.buffer_mut(view_id);
+        buf.undo(view_id);
+        self.paste_entry(view_id, &entry, Some(index));
+        true
     }
-    pub fn move_to_beginning_of_document_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_to_beginning_of_document_and_modify_selection(view_id);
+}
+
+// mod test {
+//     use super::*;
+//     #[test]
+//     fn test_views() {
+//         let mut ws = Window::new();
+//         let v1 = ws.new_view(None).unwrap();
+//         ws.close_view(v1);
+//         ws.new_view(None).unwrap();
+//         dbg!(ws);
+//     }
+// }
+
+/// Returns true if `prefix` is right before `pos`, and doesn't start in the
+/// middle of a word
+fn prefix_before(rope: &Rope, pos: usize, prefix: &str) -> bool {
+    let len = prefix.chars().count();
+    if len == 0 || len > pos || rope.slice(pos - len..pos) != prefix {
+        return false;
     }
-    pub fn move_to_end_of_document_and_modify_selection(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id)
-            .move_to_end_of_document_and_modify_selection(view_id);
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let starts_word = prefix.chars().next().is_some_and(is_word);
+    pos == len || !starts_word || !is_word(rope.char(pos - len - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A window with its config read from an empty temporary directory,
+    /// rather than the user's
+    fn test_window() -> Window {
+        let mut win = Window::new(Arc::new(|| {}));
+        let dir = std::env::temp_dir().join(format!("eddy-config-{}", std::process::id()));
+        win.keymap_path = dir.join("keymap.toml");
+        win.snippets_dir = dir.join("snippets");
+        win.load_config();
+        win
     }
-    pub fn page_down(&mut self, view_id: ViewId, lines_visible: usize) {
-        self.buffer_mut(view_id).page_down(view_id, lines_visible);
+
+    #[test]
+    fn test_execute_commands() {
+        let mut win = test_window();
+        let view_id = win.new_view(None).unwrap();
+        let cmds = [
+            Command::Insert("hello".into()),
+            Command::MoveToLeftEndOfLine,
+            Command::Insert("> ".into()),
+            Command::MoveToLeftEndOfLineAndModifySelection,
+        ];
+        for cmd in cmds {
+            win.execute(view_id, cmd).unwrap();
+        }
+        let out = win.execute(view_id, Command::Cut).unwrap();
+        assert_eq!(out, CommandOutput::Clipboard("> ".into()));
+        win.execute(view_id, Command::MoveToEndOfDocument).unwrap();
+        win.execute(view_id, Command::Paste("> ".into())).unwrap();
+        assert_eq!(win.buffer(view_id).to_string(), "hello> ");
     }
-    pub fn page_up(&mut self, view_id: ViewId, lines_visible: usize) {
-        self.buffer_mut(view_id).page_up(view_id, lines_visible);
+
+    #[test]
+    fn test_snippet_tab_stops() {
+        let mut win = test_window();
+        win.snippets.add(
+            crate::snippet::GLOBAL,
+            vec![crate::snippet::SnippetDef {
+                prefix: "pair".into(),
+                body: "(${1:a}, $1)$0".into(),
+                description: String::new(),
+            }],
+        );
+        let view_id = win.new_view(None).unwrap();
+        let run = |win: &mut Window, cmd| {
+            win.execute(view_id, cmd).unwrap();
+        };
+        run(&mut win, Command::Insert("x pair".into()));
+        run(&mut win, Command::ExpandSnippet);
+        assert_eq!(win.buffer(view_id).to_string(), "x (a, a)");
+        assert!(win.key_context(view_id).has_flag("in_snippet"));
+
+        // Both mirrors change together, and Tab moves on to the end
+        run(&mut win, Command::Insert("bc".into()));
+        assert_eq!(win.buffer(view_id).to_string(), "x (bc, bc)");
+        run(&mut win, Command::NextTabStop);
+        assert!(!win.buffer(view_id).in_snippet(view_id));
+        run(&mut win, Command::Insert("!".into()));
+        assert_eq!(win.buffer(view_id).to_string(), "x (bc, bc)!");
+
+        // Without a prefix, Tab is just a tab
+        run(&mut win, Command::ExpandSnippet);
+        assert_eq!(win.buffer(view_id).to_string(), "x (bc, bc)!\t");
+
+        // A body that doesn't parse goes in as it's written
+        win.snippets.add(
+            crate::snippet::GLOBAL,
+            vec![crate::snippet::SnippetDef {
+                prefix: "bad".into(),
+                body: "${1:a".into(),
+                description: String::new(),
+            }],
+        );
+        run(&mut win, Command::Insert(" bad".into()));
+        run(&mut win, Command::ExpandSnippet);
+        assert_eq!(win.buffer(view_id).to_string(), "x (bc, bc)!\t ${1:a");
+
+        // Cutting the snippet's line finishes it
+        run(&mut win, Command::Insert("\npair".into()));
+        run(&mut win, Command::ExpandSnippet);
+        run(&mut win, Command::Insert("d".into()));
+        assert!(win.buffer(view_id).in_snippet(view_id));
+        run(&mut win, Command::Cut);
+        assert_eq!(win.buffer(view_id).to_string(), "x (bc, bc)!\t ${1:a");
+        assert!(!win.buffer(view_id).in_snippet(view_id));
     }
-    pub fn page_up_and_modify_selection(&mut self, view_id: ViewId, lines_visible: usize) {
-        self.buffer_mut(view_id)
-            .page_up_and_modify_selection(view_id, lines_visible);
+
+    #[test]
+    fn test_bookmarks() {
+        let mut win = test_window();
+        let view_id = win.new_view(None).unwrap();
+        let run = |win: &mut Window, cmd| {
+            win.execute(view_id, cmd).unwrap();
+        };
+        run(&mut win, Command::Insert("one\ntwo\nthree".into()));
+        run(&mut win, Command::ToggleBookmark);
+        run(&mut win, Command::MoveToBeginningOfDocument);
+        run(&mut win, Command::SetBookmark("top".into()));
+
+        // Bookmarks move with the text
+        run(&mut win, Command::Insert("zero\n".into()));
+        let entries = win.bookmark_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].line, entries[0].name.as_deref()),
+            (1, Some("top"))
+        );
+        assert_eq!((entries[1].line, entries[1].preview.as_str()), (3, "three"));
+
+        let caret = |win: &Window| win.buffer(view_id).selections(view_id)[0].start;
+        run(&mut win, Command::NextBookmark);
+        assert_eq!(caret(&win), 18);
+        run(&mut win, Command::NextBookmark);
+        assert_eq!(caret(&win), 5);
+        run(&mut win, Command::PreviousBookmark);
+        assert_eq!(caret(&win), 18);
+
+        // They're saved with the session
+        let session = win.session();
+        assert_eq!(session.views[0].bookmarks.len(), 2);
+        // Once for each buffer, however many views it has
+        win.views.insert(view_id + 1, win.views[&view_id]);
+        let session = win.session();
+        assert_eq!(session.views.len(), 2);
+        assert!(session.views[1].bookmarks.is_empty());
+        win.views.remove(&(view_id + 1));
+
+        win.go_to_bookmark(&entries[1]);
+        run(&mut win, Command::MoveToLeftEndOfLine);
+        run(&mut win, Command::ToggleBookmark);
+        assert_eq!(win.bookmark_entries().len(), 1);
     }
-    pub fn page_down_and_modify_selection(&mut self, view_id: ViewId, lines_visible: usize) {
-        self.buffer_mut(view_id)
-            .page_down_and_modify_selection(view_id, lines_visible);
+
+    #[test]
+    fn test_jump_list() {
+        let mut win = test_window();
+        let view_id = win.new_view(None).unwrap();
+        let run = |win: &mut Window, view_id, cmd| {
+            win.execute(view_id, cmd).unwrap();
+        };
+        let line = |win: &Window, view_id| {
+            let buf = win.buffer(view_id);
+            buf.char_to_line(buf.selections(view_id)[0].cursor())
+        };
+        run(&mut win, view_id, Command::Insert("x\n".repeat(30)));
+        run(&mut win, view_id, Command::GoToLine(5));
+        // Arrows aren't jumps, and edits move the jumps along
+        run(&mut win, view_id, Command::MoveDown);
+        run(&mut win, view_id, Command::Insert("new\n".into()));
+        assert_eq!(line(&win, view_id), 6);
+
+        run(&mut win, view_id, Command::JumpBack);
+        assert_eq!(line(&win, view_id), 31);
+        run(&mut win, view_id, Command::JumpForward);
+        assert_eq!(line(&win, view_id), 6);
+        // So does undoing them
+        run(&mut win, view_id, Command::GoToLine(20));
+        run(&mut win, view_id, Command::Undo);
+        let buf = win.buffer(view_id);
+        let jump = buf.anchor(win.jump_list.last().unwrap().anchor).unwrap();
+        assert_eq!(buf.char_to_line(jump), 5);
+        run(&mut win, view_id, Command::Redo);
+
+        // Nearby clicks aren't jumps, far ones are
+        run(&mut win, view_id, Command::PointSelect { line: 2, byte: 0 });
+        run(
+            &mut win,
+            view_id,
+            Command::PointSelect { line: 20, byte: 0 },
+        );
+        run(&mut win, view_id, Command::JumpBack);
+        assert_eq!(line(&win, view_id), 2);
+
+        // Opening a file is a jump, and going back goes to the other view
+        let out = win.execute(view_id, Command::NewView(None)).unwrap();
+        let CommandOutput::View(new_view) = out else {
+            panic!("no view opened");
+        };
+        run(&mut win, new_view, Command::JumpBack);
+        assert_eq!(win.focused_view, Some(view_id));
+        assert_eq!(line(&win, view_id), 2);
+        run(&mut win, view_id, Command::JumpForward);
+        assert_eq!(win.focused_view, Some(new_view));
     }
-    pub fn select_all(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).select_all(view_id);
+
+    #[test]
+    fn test_set_language() {
+        let mut win = test_window();
+        let view_id = win.new_view(None).unwrap();
+        assert_eq!(win.language_title(view_id), "Plain Text");
+        win.execute(view_id, Command::SetLanguage("RS".into()))
+            .unwrap();
+        assert_eq!(win.buffer(view_id).language(), Some("rust"));
+        assert_eq!(win.language_title(view_id), "Rust");
+        assert!(win
+            .execute(view_id, Command::SetLanguage("cobol".into()))
+            .is_err());
+        assert_eq!(win.buffer(view_id).language(), Some("rust"));
+        win.execute(view_id, Command::SetLanguage("plain".into()))
+            .unwrap();
+        assert_eq!(win.buffer(view_id).language(), None);
+
+        // Saving under a new name only detects the language again if it
+        // wasn't picked
+        let dir = std::env::temp_dir();
+        let name = |ext: &str| dir.join(format!("eddy-language-{}.{ext}", std::process::id()));
+        let other_view = win.new_view(None).unwrap();
+        win.save_as(other_view, &name("rs")).unwrap();
+        assert_eq!(win.buffer(other_view).language(), Some("rust"));
+        win.save_as(view_id, &name("py")).unwrap();
+        assert_eq!(win.buffer(view_id).language(), None);
+        let _ = std::fs::remove_file(name("rs"));
+        let _ = std::fs::remove_file(name("py"));
     }
-    pub fn undo(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).undo(view_id);
+
+    #[test]
+    fn test_watch_config() {
+        let mut win = Window::new(Arc::new(|| {}));
+        let dir = std::env::temp_dir().join(format!("eddy-watch-config-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        win.keymap_path = dir.join("keymap.toml");
+        win.snippets_dir = dir.join("snippets");
+        assert!(!win.watch_config());
+        assert!(!win.watcher.is_watched(&win.keymap_path));
+
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(win.watch_config());
+        assert!(win.watcher.is_notified(&win.keymap_path));
+        assert!(!win.watch_config());
+        assert!(!win.watcher.is_tree_watched(&win.snippets_dir));
+
+        std::fs::create_dir(&win.snippets_dir).unwrap();
+        assert!(win.watch_config());
+        assert!(win.watcher.is_tree_watched(&win.snippets_dir));
+        assert!(!win.watch_config());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-    pub fn redo(&mut self, view_id: ViewId) {
-        self.buffer_mut(view_id).redo(view_id);
+
+    #[test]
+    fn test_file_changes() {
+        let mut win = test_window();
+        let recovery_dir =
+            std::env::temp_dir().join(format!("eddy-changes-recovery-{}", std::process::id()));
+        win.recovery = Recovery::new(recovery_dir.clone());
+        let file_name = format!("eddy-changes-{}.txt", std::process::id());
+        let path = std::env::temp_dir().join(".").join(&file_name);
+        std::fs::write(&path, "a\n").unwrap();
+        let view_id = win.new_view(Some(&path)).unwrap();
+        assert!(win.watcher.is_notified(&path));
+        assert!(win
+            .watcher
+            .is_notified(&std::env::temp_dir().join(&file_name)));
+
+        std::fs::write(&path, "a\nb\n").unwrap();
+        win.on_file_changed(&path, FileChange::Modified);
+        assert_eq!(win.buffer(view_id).to_string(), "a\nb\n");
+        assert_eq!(win.buffer(view_id).disk_stat(), FileStat::from_path(&path));
+
+        // The stat is kept even when the text is what was loaded
+        win.buffer_mut(view_id).set_disk_stat(None);
+        win.on_file_changed(&path, FileChange::Modified);
+        assert_eq!(win.buffer(view_id).disk_stat(), FileStat::from_path(&path));
+
+        std::fs::remove_file(&path).unwrap();
+        win.on_file_changed(&path, FileChange::Removed);
+        assert!(matches!(
+            win.buffer(view_id).external_change,
+            Some(ExternalChange::Deleted)
+        ));
+
+        // With the file gone, the buffer is the only copy left to recover
+        win.journal_all();
+        let files = win.recovery.list();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].text, "a\nb\n");
+        std::fs::remove_dir_all(&recovery_dir).unwrap();
     }
 
-    pub fn cut(&mut self, view_id: ViewId) -> Option<String> {
-        self.buffer_mut(view_id).cut(view_id)
+    #[test]
+    fn test_file_open_twice() {
+        let mut win = test_window();
+        let path = std::env::temp_dir().join(format!("eddy-twice-{}.txt", std::process::id()));
+        std::fs::write(&path, "a\n").unwrap();
+        let first = win.new_view(Some(&path)).unwrap();
+        let second = win.new_view(Some(&path)).unwrap();
+        assert_ne!(win.views[&first], win.views[&second]);
+
+        // The other buffer still hears about changes to the file
+        win.close_view(first);
+        assert!(win.watcher.is_notified(&path));
+        std::fs::write(&path, "a\nb\n").unwrap();
+        let start = std::time::Instant::now();
+        while win.buffer(second).to_string() != "a\nb\n" {
+            assert!(start.elapsed().as_secs() < 10, "not reloaded");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            win.handle_events();
+        }
+
+        win.close_view(second);
+        assert!(!win.watcher.is_watched(&path));
+
+        // Both buffers journal to the file's recovery file, which is kept
+        // while either has unsaved text
+        let recovery_dir =
+            std::env::temp_dir().join(format!("eddy-twice-recovery-{}", std::process::id()));
+        win.recovery = Recovery::new(recovery_dir.clone());
+        let first = win.new_view(Some(&path)).unwrap();
+        let second = win.new_view(Some(&path)).unwrap();
+        win.execute(first, Command::Insert("1".into())).unwrap();
+        win.execute(second, Command::Insert("2".into())).unwrap();
+        win.journal_all();
+        assert_eq!(win.recovery.list().len(), 1);
+        win.close_view(first);
+        let files = win.recovery.list();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].text, "2a\nb\n");
+        win.close_view(second);
+        assert!(win.recovery.list().is_empty());
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_dir_all(&recovery_dir);
     }
-    pub fn copy(&mut self, view_id: ViewId) -> Option<String> {
-        self.buffer_mut(view_id).copy(view_id)
+
+    #[test]
+    fn test_go_to_symbol() {
+        let mut win = test_window();
+        let view_id = win.new_view(None).unwrap();
+        let text = "struct A;\nimpl A {\n    fn b(&self) {}\n}\n";
+        win.execute(view_id, Command::Insert(text.into())).unwrap();
+        win.execute(view_id, Command::SetLanguage("rust".into()))
+            .unwrap();
+        let start = std::time::Instant::now();
+        while !win.buffer_mut(view_id).apply_highlights() {
+            assert!(start.elapsed().as_secs() < 10, "not parsed");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let symbols = win.buffer(view_id).symbols();
+        assert_eq!(symbols.len(), 2);
+        let b = symbols[1].children[0].clone();
+        win.go_to_symbol(view_id, &b);
+        let cursor = |win: &Window| win.buffer(view_id).selections(view_id)[0].cursor();
+        assert_eq!(cursor(&win), text.find("b(").unwrap());
+        let path: Vec<String> = win
+            .buffer(view_id)
+            .symbol_path(view_id)
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(path, vec!["A", "b"]);
+        assert_eq!(win.breadcrumbs(view_id), "A › b");
+
+        // Going to a symbol is a jump
+        win.execute(view_id, Command::JumpBack).unwrap();
+        assert_eq!(cursor(&win), text.len());
     }
 
-    pub fn gesture_point_select(&mut self, view_id: ViewId, line_idx: usize, line_byte_idx: usize) {
-        self.buffer_mut(view_id)
-            .gesture_point_select(view_id, line_idx, line_byte_idx);
+    #[test]
+    fn test_index_watches_dirs() {
+        let mut win = test_window();
+        let cwd = std::env::current_dir().unwrap();
+        assert!(!win.watcher.is_dir_watched(&cwd));
+
+        let dir = std::env::temp_dir().join(format!("eddy-index-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/deep")).unwrap();
+        std::fs::create_dir(dir.join("target")).unwrap();
+        let mut proj = Project::new("test", dir.clone());
+        let mut index = FileIndex::default();
+        index.dirs.insert(PathBuf::new());
+        index
+            .ignore
+            .add(crate::files::Gitignore::parse(Path::new(""), "/target\n"));
+        proj.index = Some(index);
+        win.projects.insert(0, proj);
+
+        let dir_entry = |name: &str| DirEntry {
+            name: name.into(),
+            mode: 0o040755,
+        };
+        win.index_entry(&dir.join("src"), Some(dir_entry("src")));
+        win.index_entry(&dir.join("src/deep"), Some(dir_entry("deep")));
+        win.index_entry(&dir.join("target"), Some(dir_entry("target")));
+        assert!(win.watcher.is_dir_watched(&dir.join("src")));
+        assert!(win.watcher.is_dir_watched(&dir.join("src/deep")));
+        assert!(!win.watcher.is_dir_watched(&dir.join("target")));
+
+        // Removing a directory stops watching everything under it
+        win.index_entry(&dir.join("src"), None);
+        assert!(!win.watcher.is_dir_watched(&dir.join("src")));
+        assert!(!win.watcher.is_dir_watched(&dir.join("src/deep")));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-    pub fn drag_update(&mut self, view_id: ViewId, line_idx: usize, line_byte_idx: usize) {
-        self.buffer_mut(view_id)
-            .drag_update(view_id, line_idx, line_byte_idx);
+
+    #[test]
+    fn test_universal_argument() {
+        let mut win = test_window();
+        win.set_key_profile(KeyProfile::Emacs);
+        let view_id = win.new_view(None).unwrap();
+        let press = |win: &mut Window, key: &str| win.press_key(view_id, key.parse().unwrap(), &[]);
+
+        win.execute(view_id, Command::UniversalArgument).unwrap();
+        assert_eq!(
+            press(&mut win, "x"),
+            KeyResult::Command(Command::Insert("xxxx".into()))
+        );
+        win.execute(view_id, Command::UniversalArgument).unwrap();
+        assert_eq!(
+            press(&mut win, "ctrl+k"),
+            KeyResult::Repeat(Command::KillLine, 4)
+        );
+        assert_eq!(
+            press(&mut win, "ctrl+k"),
+            KeyResult::Command(Command::KillLine)
+        );
     }
 }
-
-// mod test {
-//     use super::*;
-//     #[test]
-//     fn test_views() {
-//         let mut ws = Window::new();
-//         let v1 = ws.new_view(None).unwrap();
-//         ws.close_view(v1);
-//         ws.new_view(None).unwrap();
-//         dbg!(ws);
-//     }
-// }
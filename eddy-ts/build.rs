@@ -20,8 +20,13 @@ fn main() {
     cc::Build::new()
         .include(&dir)
         .file(dir.join("parser.c"))
-        // .file(dir.join("scanner.cc"))
         .compile("tree-sitter-html");
+    // The scanner is C++, which needs a build of its own
+    cc::Build::new()
+        .cpp(true)
+        .include(&dir)
+        .file(dir.join("scanner.cc"))
+        .compile("tree-sitter-html-scanner");
 
     // Javascript
     let dir: PathBuf = ["tree-sitter-javascript", "src"].iter().collect();
@@ -0,0 +1,32 @@
+(struct_specifier
+  name: (type_identifier) @name
+  body: (_)) @definition.class
+
+(union_specifier
+  name: (type_identifier) @name
+  body: (_)) @definition.class
+
+(enum_specifier
+  name: (type_identifier) @name
+  body: (_)) @definition.type
+
+(type_definition
+  declarator: (type_identifier) @name) @definition.type
+
+(function_definition
+  declarator: (function_declarator
+    declarator: (identifier) @name)) @definition.function
+
+(function_definition
+  declarator: (pointer_declarator
+    declarator: (function_declarator
+      declarator: (identifier) @name))) @definition.function
+
+(enumerator
+  name: (identifier) @name) @definition.constant
+
+(preproc_def
+  name: (identifier) @name) @definition.constant
+
+(preproc_function_def
+  name: (identifier) @name) @definition.macro
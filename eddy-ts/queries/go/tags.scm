@@ -0,0 +1,9 @@
+; Added to the grammar's own tags
+
+(method_spec
+  name: (field_identifier) @name) @definition.method
+
+(source_file
+  (const_declaration
+    (const_spec
+      name: (identifier) @name) @definition.constant))
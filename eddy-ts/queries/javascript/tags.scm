@@ -0,0 +1,13 @@
+; Added to the grammar's own tags
+
+(program
+  (lexical_declaration
+    "const"
+    (variable_declarator
+      name: (identifier) @name) @definition.constant))
+
+(export_statement
+  (lexical_declaration
+    "const"
+    (variable_declarator
+      name: (identifier) @name) @definition.constant))
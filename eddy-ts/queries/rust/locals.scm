@@ -0,0 +1,70 @@
+; Scopes
+;-------
+
+[
+  (block)
+  (function_item)
+  (closure_expression)
+  (for_expression)
+  (if_let_expression)
+  (while_let_expression)
+  (match_arm)
+] @local.scope
+
+; Definitions
+;------------
+
+(parameter
+  pattern: (identifier) @local.definition)
+
+(closure_parameters
+  (identifier) @local.definition)
+
+(let_declaration
+  pattern: (identifier) @local.definition)
+
+(for_expression
+  pattern: (identifier) @local.definition)
+
+(if_let_expression
+  pattern: (identifier) @local.definition)
+
+(while_let_expression
+  pattern: (identifier) @local.definition)
+
+; Capitalized names in patterns are constants and unit variants
+((match_pattern
+  (identifier) @local.definition)
+ (#match? @local.definition "^[a-z_]"))
+
+((tuple_pattern
+  (identifier) @local.definition)
+ (#match? @local.definition "^[a-z_]"))
+
+((tuple_struct_pattern
+  "(" (identifier) @local.definition)
+ (#match? @local.definition "^[a-z_]"))
+
+((slice_pattern
+  (identifier) @local.definition)
+ (#match? @local.definition "^[a-z_]"))
+
+(ref_pattern
+  (identifier) @local.definition)
+
+(mut_pattern
+  (identifier) @local.definition)
+
+(captured_pattern
+  (identifier) @local.definition)
+
+(field_pattern
+  name: (shorthand_field_identifier) @local.definition)
+
+(field_pattern
+  pattern: (identifier) @local.definition)
+
+; References
+;------------
+
+(identifier) @local.reference
@@ -0,0 +1,49 @@
+; Types
+(struct_item
+  name: (type_identifier) @name) @definition.class
+
+(enum_item
+  name: (type_identifier) @name) @definition.class
+
+(union_item
+  name: (type_identifier) @name) @definition.class
+
+(type_item
+  name: (type_identifier) @name) @definition.type
+
+(trait_item
+  name: (type_identifier) @name) @definition.interface
+
+(impl_item
+  type: (_) @name) @definition.implementation
+
+; Functions, with the ones in impls and traits as methods
+(impl_item
+  body: (declaration_list
+    (function_item
+      name: (identifier) @name) @definition.method))
+
+(trait_item
+  body: (declaration_list
+    [
+      (function_item
+        name: (identifier) @name)
+      (function_signature_item
+        name: (identifier) @name)
+    ] @definition.method))
+
+(function_item
+  name: (identifier) @name) @definition.function
+
+; Everything else
+(mod_item
+  name: (identifier) @name) @definition.module
+
+(macro_definition
+  name: (identifier) @name) @definition.macro
+
+(const_item
+  name: (identifier) @name) @definition.constant
+
+(static_item
+  name: (identifier) @name) @definition.constant
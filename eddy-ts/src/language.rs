@@ -21,28 +21,42 @@ pub fn c() -> Language {
     unsafe { tree_sitter_c() }
 }
 pub const C_HIGHLIGHTS: &str = include_str!("../tree-sitter-c/queries/highlights.scm");
-pub const C_INJECTIONS: &str = include_str!("../tree-sitter-c/queries/highlights.scm");
+pub const C_LOCALS: &str = include_str!("../queries/c/locals.scm");
+pub const C_TAGS: &str = include_str!("../queries/c/tags.scm");
 
 // Go
 pub fn go() -> Language {
     unsafe { tree_sitter_go() }
 }
 pub const GO_HIGHLIGHTS: &str = include_str!("../tree-sitter-go/queries/highlights.scm");
-pub const GO_INJECTIONS: &str = include_str!("../tree-sitter-go/queries/highlights.scm");
+pub const GO_LOCALS: &str = include_str!("../queries/go/locals.scm");
+pub const GO_TAGS: &str = concat!(
+    include_str!("../tree-sitter-go/queries/tags.scm"),
+    include_str!("../queries/go/tags.scm"),
+);
 
 // HTML
 pub fn html() -> Language {
     unsafe { tree_sitter_html() }
 }
 pub const HTML_HIGHLIGHTS: &str = include_str!("../tree-sitter-html/queries/highlights.scm");
-pub const HTML_INJECTIONS: &str = include_str!("../tree-sitter-html/queries/highlights.scm");
+pub const HTML_INJECTIONS: &str = include_str!("../tree-sitter-html/queries/injections.scm");
 
 // Javascript
 pub fn javascript() -> Language {
     unsafe { tree_sitter_javascript() }
 }
-pub const JS_HIGHLIGHTS: &str = include_str!("../tree-sitter-javascript/queries/highlights.scm");
-pub const JS_INJECTIONS: &str = include_str!("../tree-sitter-javascript/queries/highlights.scm");
+// The parameters come last, so they win over plain variables
+pub const JS_HIGHLIGHTS: &str = concat!(
+    include_str!("../tree-sitter-javascript/queries/highlights.scm"),
+    include_str!("../tree-sitter-javascript/queries/highlights-params.scm"),
+);
+pub const JS_INJECTIONS: &str = include_str!("../tree-sitter-javascript/queries/injections.scm");
+pub const JS_LOCALS: &str = include_str!("../tree-sitter-javascript/queries/locals.scm");
+pub const JS_TAGS: &str = concat!(
+    include_str!("../tree-sitter-javascript/queries/tags.scm"),
+    include_str!("../queries/javascript/tags.scm"),
+);
 
 // Rust
 pub fn rust() -> Language {
@@ -50,3 +64,5 @@ pub fn rust() -> Language {
 }
 pub const RUST_HIGHLIGHTS: &str = include_str!("../tree-sitter-rust/queries/highlights.scm");
 pub const RUST_INJECTIONS: &str = include_str!("../tree-sitter-rust/queries/injections.scm");
+pub const RUST_LOCALS: &str = include_str!("../queries/rust/locals.scm");
+pub const RUST_TAGS: &str = include_str!("../queries/rust/tags.scm");
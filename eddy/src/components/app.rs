@@ -3,6 +3,7 @@ use eddy_model::Model;
 use gflux::{Component, ComponentCtx, ComponentHandle};
 use glib::clone;
 use gtk::prelude::*;
+use log::error;
 use std::collections::{HashMap, HashSet};
 
 #[allow(dead_code)]
@@ -43,11 +44,25 @@ impl Component for AppComponent {
             #[strong]
             ctx,
             move |_app| {
-                ctx.with_model_mut(|m| m.new_win());
+                ctx.with_model_mut(|m| {
+                    if !m.restore_session() {
+                        m.new_win();
+                    }
+                });
                 ctx.rebuild();
             }
         ));
 
+        app.connect_shutdown(clone!(
+            #[strong]
+            ctx,
+            move |_app| {
+                if let Err(e) = ctx.with_model_mut(|m| m.save_session()) {
+                    error!("unable to save session: {e}");
+                }
+            }
+        ));
+
         Self {
             app,
             wins: HashMap::new(),
@@ -1,14 +1,25 @@
 use super::code_view_text::CodeViewTextComponent;
 use super::gutter::GutterComponent;
 
-use eddy_model::{Model, ViewId, Window};
+use eddy_model::{Model, ScrollPos, ViewId, Window};
 use gflux::{Component, ComponentCtx, ComponentHandle};
+use glib::clone;
 
 use gtk::prelude::*;
+use std::cell::Cell;
+use std::rc::Rc;
 
 #[allow(dead_code)]
 pub struct CodeViewComponent {
+    vbox: gtk::Box,
     hbox: gtk::Box,
+    /// Shows the key profile's state, like vim's mode
+    status: gtk::Label,
+    /// Shows the symbols the caret is in, like `Buffer › insert`
+    breadcrumbs: gtk::Label,
+    /// Shows the buffer's language
+    language: gtk::Label,
+    view_id: ViewId,
     cvt: ComponentHandle<CodeViewTextComponent>,
     gutter: ComponentHandle<GutterComponent>,
 }
@@ -20,7 +31,7 @@ impl Component for CodeViewComponent {
     type Params = ViewId;
 
     fn widget(&self) -> Self::Widget {
-        self.hbox.clone()
+        self.vbox.clone()
     }
 
     fn build(ctx: ComponentCtx<Self>, view_id: ViewId) -> Self {
@@ -48,12 +59,111 @@ impl Component for CodeViewComponent {
 
         hbox.append(&gutter.widget());
         hbox.append(&scrolled_window);
+        hbox.set_vexpand(true);
+
+        let status = gtk::Label::builder()
+            .xalign(0.0)
+            .margin_start(6)
+            .margin_end(6)
+            .css_classes(["monospace"])
+            .build();
+        let key_status = ctx.with_model(|ws| ws.key_status(view_id));
+        status.set_visible(key_status.is_some());
+        status.set_text(key_status.as_deref().unwrap_or_default());
+
+        let breadcrumbs = gtk::Label::builder()
+            .xalign(0.0)
+            .hexpand(true)
+            .ellipsize(pango::EllipsizeMode::Start)
+            .margin_start(6)
+            .margin_end(6)
+            .css_classes(["dim-label"])
+            .build();
+        breadcrumbs.set_text(&ctx.with_model(|ws| ws.breadcrumbs(view_id)));
+
+        let language = gtk::Label::builder()
+            .xalign(1.0)
+            .margin_start(6)
+            .margin_end(6)
+            .build();
+        language.set_text(ctx.with_model(|ws| ws.language_title(view_id)));
+
+        let status_bar = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        status_bar.append(&status);
+        status_bar.append(&breadcrumbs);
+        status_bar.append(&language);
+
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        vbox.append(&hbox);
+        vbox.append(&status_bar);
+
+        // Restore a saved scroll position once there's enough content to
+        // scroll to it
+        if let Some(pos) = ctx.with_model(|ws| ws.scroll.get(&view_id).copied()) {
+            for (adj, value) in [(&hadj, pos.x), (&vadj, pos.y)] {
+                let restored = Rc::new(Cell::new(false));
+                adj.connect_changed(move |adj| {
+                    if !restored.get() && adj.upper() - adj.page_size() >= value {
+                        restored.set(true);
+                        adj.set_value(value);
+                    }
+                });
+            }
+        }
+
+        // Remember where the view was scrolled to when it goes away, so the
+        // session can be saved with it
+        hbox.connect_unrealize(clone!(
+            #[strong]
+            ctx,
+            #[strong]
+            hadj,
+            #[strong]
+            vadj,
+            move |_| {
+                let pos = ScrollPos {
+                    x: hadj.value(),
+                    y: vadj.value(),
+                };
+                ctx.with_model_mut(|ws| {
+                    if ws.views.contains_key(&view_id) {
+                        ws.scroll.insert(view_id, pos);
+                    }
+                });
+            }
+        ));
 
         // cvt.set_hscroll_policy(gtk::ScrollablePolicy::Natural); TODO
-        Self { hbox, cvt, gutter }
+        Self {
+            vbox,
+            hbox,
+            status,
+            breadcrumbs,
+            language,
+            view_id,
+            cvt,
+            gutter,
+        }
     }
 
     fn rebuild(&mut self, ctx: ComponentCtx<Self>) {
+        let Some((key_status, breadcrumbs, language)) = ctx.with_model(|ws| {
+            ws.views.contains_key(&self.view_id).then(|| {
+                (
+                    ws.key_status(self.view_id),
+                    ws.breadcrumbs(self.view_id),
+                    ws.language_title(self.view_id),
+                )
+            })
+        }) else {
+            ctx.rebuild_children();
+            return;
+        };
+        self.status.set_visible(key_status.is_some());
+        self.status
+            .set_text(key_status.as_deref().unwrap_or_default());
+        self.breadcrumbs.set_text(&breadcrumbs);
+        self.language.set_text(language);
         ctx.rebuild_children();
     }
 }
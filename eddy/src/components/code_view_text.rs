@@ -9,6 +9,7 @@ use gtk::prelude::*;
 pub struct CodeViewTextComponent {
     cvt: CodeViewText,
     view_id: ViewId,
+    focused: bool,
 }
 
 impl Component for CodeViewTextComponent {
@@ -28,10 +29,21 @@ impl Component for CodeViewTextComponent {
         // cvt.set_vadjust(&vadj); TODO
 
         // cvt.set_hscroll_policy(gtk::ScrollablePolicy::Natural); TODO
-        Self { cvt, view_id }
+        Self {
+            cvt,
+            view_id,
+            focused: false,
+        }
     }
 
-    fn rebuild(&mut self, _ctx: ComponentCtx<Self>) {
+    fn rebuild(&mut self, ctx: ComponentCtx<Self>) {
         self.cvt.queue_draw();
+
+        // Jumps and quick open can move the caret in a view and focus it
+        let focused = ctx.with_model(|ws| ws.focused_view) == Some(self.view_id);
+        if focused && !self.focused {
+            self.cvt.scroll_to_carets();
+        }
+        self.focused = focused;
     }
 }
@@ -4,5 +4,6 @@ pub mod code_view_text;
 pub mod dirbar;
 pub mod dirbar2;
 pub mod gutter;
+pub mod outline;
 pub mod tab_label;
 pub mod window;
@@ -0,0 +1,105 @@
+use eddy_model::{Model, Symbol, ViewId, Window};
+use gflux::{Component, ComponentCtx};
+use glib::clone;
+use gtk::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The functions, types and constants in the focused view's buffer, nested
+/// the way they are in the text.  Activating one moves the caret to it.
+pub struct OutlineComponent {
+    tree_view: gtk::TreeView,
+    tree_store: gtk::TreeStore,
+    /// What each row's second column indexes into
+    symbols: Rc<RefCell<Vec<Symbol>>>,
+    /// The view, revision and parse the outline was last made from
+    shown: Option<(ViewId, u64, u64)>,
+}
+
+impl Component for OutlineComponent {
+    type GlobalModel = Model;
+    type Model = Window;
+    type Widget = gtk::TreeView;
+    type Params = ();
+
+    fn widget(&self) -> Self::Widget {
+        self.tree_view.clone()
+    }
+
+    fn build(ctx: ComponentCtx<Self>, _params: ()) -> Self {
+        let tree_store = gtk::TreeStore::new(&[String::static_type(), u32::static_type()]);
+        let tree_view = gtk::TreeView::new();
+        let column0 = gtk::TreeViewColumn::new();
+        let cell0 = gtk::CellRendererText::new();
+        column0.pack_start(&cell0, true);
+        column0.add_attribute(&cell0, "text", 0);
+        tree_view.set_model(Some(&tree_store));
+        tree_view.set_headers_visible(false);
+        tree_view.append_column(&column0);
+
+        let symbols: Rc<RefCell<Vec<Symbol>>> = Rc::default();
+        tree_view.connect_row_activated(clone!(
+            #[strong]
+            ctx,
+            #[strong]
+            tree_store,
+            #[strong]
+            symbols,
+            move |_, tp, _| {
+                let Some(ti) = tree_store.iter(tp) else {
+                    return;
+                };
+                let index: u32 = tree_store.get(&ti, 1);
+                let symbol = symbols.borrow().get(index as usize).cloned();
+                if let (Some(symbol), Some(view_id)) =
+                    (symbol, ctx.with_model(|ws| ws.focused_view))
+                {
+                    ctx.with_model_mut(|ws| ws.go_to_symbol(view_id, &symbol));
+                }
+            }
+        ));
+
+        Self {
+            tree_view,
+            tree_store,
+            symbols,
+            shown: None,
+        }
+    }
+
+    fn rebuild(&mut self, ctx: ComponentCtx<Self>) {
+        // Finding the symbols takes a query over the whole buffer, so it's
+        // only done again once the text has been parsed again
+        let shown = ctx.with_model(|ws| {
+            let view_id = ws.focused_view.filter(|v| ws.views.contains_key(v))?;
+            let buf = ws.buffer(view_id);
+            Some((view_id, buf.revision(), buf.parses()))
+        });
+        if shown == self.shown {
+            return;
+        }
+        self.shown = shown;
+        let symbols = match shown {
+            Some((view_id, _, _)) => ctx.with_model(|ws| ws.buffer(view_id).symbols()),
+            None => Vec::new(),
+        };
+
+        self.tree_store.clear();
+        let mut flat = Vec::new();
+        let mut todo: Vec<(Option<gtk::TreeIter>, Symbol)> =
+            symbols.into_iter().rev().map(|s| (None, s)).collect();
+        while let Some((parent, mut symbol)) = todo.pop() {
+            let text = format!("{} {}", symbol.kind, symbol.name);
+            let ti = self.tree_store.insert_with_values(
+                parent.as_ref(),
+                None,
+                &[(0, &text), (1, &(flat.len() as u32))],
+            );
+            let children = std::mem::take(&mut symbol.children);
+            todo.extend(children.into_iter().rev().map(|s| (Some(ti.clone()), s)));
+            flat.push(symbol);
+        }
+        *self.symbols.borrow_mut() = flat;
+        self.tree_view.expand_all();
+    }
+}
@@ -1,6 +1,10 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-use eddy_model::{Model, ViewId, Window};
+use eddy_model::files::{RecoveryFile, RecoveryKey};
+use eddy_model::keymap::KeyProfile;
+use eddy_model::{ConflictResolution, ExternalChange, Model, ViewId, Window};
 use gflux::{Component, ComponentCtx, ComponentHandle};
 use gio::SimpleAction;
 use glib::clone;
@@ -13,6 +17,7 @@ use crate::components::tab_label::TabLabelComponent;
 
 use super::code_view::CodeViewComponent;
 use super::dirbar::DirBarComponent;
+use super::outline::OutlineComponent;
 
 #[allow(dead_code)]
 pub struct WindowComponent {
@@ -24,10 +29,15 @@ pub struct WindowComponent {
 
     dir_bar: ComponentHandle<DirBarComponent>,
     dir_bar2: ComponentHandle<super::dirbar2::DirBarComponent>,
+    outline: ComponentHandle<OutlineComponent>,
     code_views: HashMap<ViewId, ComponentHandle<CodeViewComponent>>,
     tab_labels: HashMap<ViewId, ComponentHandle<TabLabelComponent>>,
     notebook: gtk::Notebook,
+    /// Maps notebook pages back to the views they show
+    page_views: Rc<RefCell<HashMap<gtk::Widget, ViewId>>>,
     last_views: HashSet<ViewId>,
+    /// Views already showing a prompt about an external file change
+    prompted_views: HashSet<ViewId>,
 }
 
 impl WindowComponent {
@@ -38,6 +48,18 @@ impl WindowComponent {
         menu_model.append_item(&gio::MenuItem::new(Some("Close"), Some("win.close_view")));
         menu_model.append_item(&gio::MenuItem::new(Some("Save"), Some("win.save")));
         menu_model.append_item(&gio::MenuItem::new(Some("Save As..."), Some("win.save_as")));
+        menu_model.append_item(&gio::MenuItem::new(
+            Some("Key Bindings"),
+            Some("win.key_bindings"),
+        ));
+        let profiles = gio::Menu::new();
+        for profile in KeyProfile::ALL {
+            profiles.append(
+                Some(profile.label()),
+                Some(&format!("win.key_profile::{}", profile.name())),
+            );
+        }
+        menu_model.append_submenu(Some("Key Profile"), &profiles);
         gtk::PopoverMenu::builder().menu_model(&menu_model).build()
     }
 }
@@ -75,14 +97,38 @@ impl Component for WindowComponent {
         let dir_bar2 = ctx.create_child(|s| s, |s| s, ());
         dir_bar_vbox.append(&dir_bar2.widget());
         let sidebar_scrolled_window = gtk::ScrolledWindow::builder().child(&dir_bar_vbox).build();
+        let outline = ctx.create_child(|s| s, |s| s, ());
+        let outline_scrolled_window = gtk::ScrolledWindow::builder()
+            .child(&outline.widget())
+            .build();
+        let sidebar_vpaned = gtk::Paned::new(Orientation::Vertical);
+        sidebar_vpaned.set_start_child(Some(&sidebar_scrolled_window));
+        sidebar_vpaned.set_end_child(Some(&outline_scrolled_window));
+        sidebar_vpaned.set_position(400);
 
         let notebook = gtk::Notebook::new();
+        let page_views: Rc<RefCell<HashMap<gtk::Widget, ViewId>>> = Default::default();
+        notebook.connect_switch_page(clone!(
+            #[strong]
+            ctx,
+            #[strong]
+            page_views,
+            move |_, page, _| {
+                let view_id = page_views.borrow().get(page).copied();
+                if let Some(view_id) = view_id {
+                    if ctx.with_model(|ws| ws.focused_view) != Some(view_id) {
+                        ctx.with_model_mut(|ws| ws.focused_view = Some(view_id));
+                    }
+                }
+            }
+        ));
         let code_views = HashMap::new();
         let tab_labels = HashMap::new();
 
         let sidebar_paned = gtk::Paned::new(Orientation::Horizontal);
-        sidebar_paned.set_start_child(Some(&sidebar_scrolled_window));
-        sidebar_paned.set_position(200);
+        sidebar_paned.set_start_child(Some(&sidebar_vpaned));
+        let sidebar_width = ctx.with_model(|ws| ws.sidebar_width);
+        sidebar_paned.set_position(sidebar_width.unwrap_or(200));
         sidebar_paned.set_resize_start_child(false);
         sidebar_paned.set_shrink_start_child(true);
 
@@ -190,9 +236,92 @@ impl Component for WindowComponent {
         ));
         window.add_action(&action_save_as);
 
+        let action_key_bindings = SimpleAction::new("key_bindings", None);
+        action_key_bindings.connect_activate(clone!(
+            #[weak]
+            window,
+            #[strong]
+            ctx,
+            move |_, _| show_key_bindings(&ctx, &window)
+        ));
+        window.add_action(&action_key_bindings);
+
+        let profile = ctx.with_model(|ws| ws.key_profile);
+        let action_key_profile = SimpleAction::new_stateful(
+            "key_profile",
+            Some(glib::VariantTy::STRING),
+            &profile.name().to_variant(),
+        );
+        action_key_profile.connect_activate(clone!(
+            #[strong]
+            ctx,
+            move |action, param| {
+                let Some(profile) = param.and_then(|p| p.str()).and_then(KeyProfile::from_name)
+                else {
+                    return;
+                };
+                ctx.with_model_mut(|ws| ws.set_key_profile(profile));
+                action.set_state(&profile.name().to_variant());
+            }
+        ));
+        window.add_action(&action_key_profile);
+
+        // Watching doesn't reach files behind the backend, so poll those
+        glib::timeout_add_seconds_local(
+            2,
+            clone!(
+                #[strong]
+                ctx,
+                move || {
+                    ctx.with_model_mut(|ws| ws.poll_file_changes());
+                    glib::ControlFlow::Continue
+                }
+            ),
+        );
+
+        window.connect_close_request(clone!(
+            #[strong]
+            ctx,
+            #[weak]
+            sidebar_paned,
+            #[upgrade_or]
+            glib::Propagation::Proceed,
+            move |_| {
+                let width = sidebar_paned.position();
+                ctx.with_model_mut(|ws| ws.sidebar_width = Some(width));
+                glib::Propagation::Proceed
+            }
+        ));
+
+        // Journal unsaved work and autosave
+        glib::timeout_add_seconds_local(
+            1,
+            clone!(
+                #[strong]
+                ctx,
+                move || {
+                    ctx.with_model_mut(|ws| ws.tick());
+                    glib::ControlFlow::Continue
+                }
+            ),
+        );
+        window.connect_is_active_notify(clone!(
+            #[strong]
+            ctx,
+            move |window| {
+                if !window.is_active() {
+                    ctx.with_model_mut(|ws| ws.focus_lost());
+                }
+            }
+        ));
+
         // Present window
         window.present();
 
+        for file in ctx.with_model(|ws| ws.recoverable_files()) {
+            prompt_recovery(&ctx, &window, file);
+        }
+
         Self {
             window,
             action_new,
@@ -201,10 +330,13 @@ impl Component for WindowComponent {
             action_save_as,
             dir_bar,
             dir_bar2,
+            outline,
             code_views,
             tab_labels,
             notebook,
+            page_views,
             last_views: HashSet::new(),
+            prompted_views: HashSet::new(),
         }
     }
 
@@ -220,16 +352,20 @@ impl Component for WindowComponent {
 
         // Remove old views
         for view_id in last_views.difference(&views) {
-            let page_num = self
-                .notebook
-                .page_num(&self.code_views.get(view_id).unwrap().widget());
+            let page = self.code_views.get(view_id).unwrap().widget();
+            self.page_views
+                .borrow_mut()
+                .remove(page.upcast_ref::<gtk::Widget>());
+            let page_num = self.notebook.page_num(&page);
             self.notebook.remove_page(page_num);
             self.code_views.remove(view_id);
             self.tab_labels.remove(view_id);
         }
 
-        // Add new views
-        for view_id in views.difference(&last_views).copied() {
+        // Add new views, in the order they were opened
+        let mut new_views: Vec<ViewId> = views.difference(&last_views).copied().collect();
+        new_views.sort_unstable();
+        for view_id in new_views {
             let cv_comp: ComponentHandle<CodeViewComponent> =
                 ctx.create_child(|ws| ws, |ws| ws, view_id);
             let tl_comp: ComponentHandle<TabLabelComponent> =
@@ -237,7 +373,12 @@ impl Component for WindowComponent {
             let page_num = dbg!(self
                 .notebook
                 .append_page(&cv_comp.widget(), Some(&tl_comp.widget())));
-            self.notebook.set_current_page(Some(page_num as u32));
+            self.page_views
+                .borrow_mut()
+                .insert(cv_comp.widget().upcast(), view_id);
+            if focused_view == Some(view_id) {
+                self.notebook.set_current_page(Some(page_num as u32));
+            }
 
             self.code_views.insert(view_id, cv_comp);
             self.tab_labels.insert(view_id, tl_comp);
@@ -245,10 +386,181 @@ impl Component for WindowComponent {
 
         self.last_views = views;
 
+        // Follow the focus to views opened earlier, like when going to a
+        // bookmark in another tab
+        let focused_page = focused_view
+            .and_then(|v| self.code_views.get(&v))
+            .and_then(|cv| self.notebook.page_num(&cv.widget()));
+        if focused_page.is_some() && focused_page != self.notebook.current_page() {
+            self.notebook.set_current_page(focused_page);
+        }
+
+        // Ask what to do about files changed on disk under unsaved edits
+        let pending: HashSet<ViewId> =
+            ctx.with_model(|ws| ws.pending_external_changes().into_iter().collect());
+        self.prompted_views.retain(|v| pending.contains(v));
+        for view_id in pending {
+            if self.prompted_views.insert(view_id) {
+                self.prompt_external_change(&ctx, view_id);
+            }
+        }
+
         ctx.rebuild_children();
     }
 }
 
+impl WindowComponent {
+    fn prompt_external_change(&self, ctx: &ComponentCtx<Self>, view_id: ViewId) {
+        let (name, deleted) = ctx.with_model(|ws| {
+            (
+                ws.display_name(view_id),
+                matches!(
+                    ws.buffer(view_id).external_change,
+                    Some(ExternalChange::Deleted)
+                ),
+            )
+        });
+        let dialog = MessageDialog::builder()
+            .transient_for(&self.window)
+            .destroy_with_parent(true)
+            .modal(true)
+            .message_type(MessageType::Warning)
+            .buttons(ButtonsType::None)
+            .build();
+        if deleted {
+            dialog.set_text(Some(&format!("{name} was deleted from disk")));
+            dialog.add_button("Keep Mine", ResponseType::Other(0));
+            dialog.add_button("Close", ResponseType::Other(1));
+        } else {
+            dialog.set_text(Some(&format!("{name} was changed on disk")));
+            dialog.add_button("Keep Mine", ResponseType::Other(0));
+            dialog.add_button("Take Theirs", ResponseType::Other(1));
+            dialog.add_button("Merge", ResponseType::Other(2));
+        }
+        dialog.connect_response(clone!(
+            #[strong]
+            ctx,
+            #[weak(rename_to = window)]
+            self.window,
+            move |dialog, response| {
+                dialog.destroy();
+                let resolution = match response {
+                    ResponseType::Other(1) => ConflictResolution::TakeTheirs,
+                    ResponseType::Other(2) => ConflictResolution::Merge,
+                    _ => ConflictResolution::KeepMine,
+                };
+                let conflicts =
+                    ctx.with_model_mut(|ws| ws.resolve_external_change(view_id, resolution));
+                if conflicts > 0 {
+                    show_err(
+                        &window,
+                        anyhow::anyhow!("merge left {conflicts} conflict(s) to resolve"),
+                    );
+                }
+            }
+        ));
+        dialog.present();
+    }
+}
+
+fn prompt_recovery(
+    ctx: &ComponentCtx<WindowComponent>,
+    window: &ApplicationWindow,
+    file: RecoveryFile,
+) {
+    let name = match &file.key {
+        RecoveryKey::Path(p) => p.display().to_string(),
+        RecoveryKey::Untitled(_) => "an untitled buffer".to_string(),
+    };
+    let dialog = MessageDialog::builder()
+        .transient_for(window)
+        .destroy_with_parent(true)
+        .modal(true)
+        .message_type(MessageType::Question)
+        .buttons(ButtonsType::None)
+        .text(format!("Recover unsaved changes to {name}?"))
+        .build();
+
+    let diff_view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .build();
+    diff_view.buffer().set_text(&file.diff_against_disk());
+    let scrolled = gtk::ScrolledWindow::builder()
+        .child(&diff_view)
+        .min_content_width(600)
+        .min_content_height(300)
+        .build();
+    dialog.message_area().append(&scrolled);
+
+    dialog.add_button("Discard", ResponseType::Reject);
+    dialog.add_button("Later", ResponseType::Cancel);
+    dialog.add_button("Recover", ResponseType::Accept);
+    dialog.connect_response(clone!(
+        #[strong]
+        ctx,
+        #[weak]
+        window,
+        move |dialog, response| {
+            dialog.destroy();
+            match response {
+                ResponseType::Accept => {
+                    let res = ctx.with_model_mut(|ws| ws.restore_recovery(&file));
+                    show_res(&window, res);
+                }
+                ResponseType::Reject => ctx.with_model_mut(|ws| ws.discard_recovery(&file)),
+                _ => {}
+            }
+        }
+    ));
+    dialog.present();
+}
+
+/// Lists the bindings in effect for the focused view, along with any
+/// problems with the keymap
+fn show_key_bindings(ctx: &ComponentCtx<WindowComponent>, window: &ApplicationWindow) {
+    let text = ctx.with_model(|ws| {
+        let mut text = String::new();
+        if let Some(e) = &ws.keymap_error {
+            text.push_str(&format!("{e}\n\n"));
+        }
+        let conflicts = ws.keymap.conflicts();
+        for conflict in &conflicts {
+            text.push_str(&format!("{conflict}\n"));
+        }
+        if !conflicts.is_empty() {
+            text.push('\n');
+        }
+        let key_ctx = ws.focused_view.map(|view_id| ws.key_context(view_id));
+        for binding in ws.keymap.active_bindings(key_ctx.as_ref()) {
+            text.push_str(&format!("{binding}\n"));
+        }
+        text
+    });
+    let dialog = MessageDialog::builder()
+        .transient_for(window)
+        .destroy_with_parent(true)
+        .modal(true)
+        .message_type(MessageType::Info)
+        .buttons(ButtonsType::Close)
+        .text("Key Bindings")
+        .build();
+
+    let list_view = gtk::TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .build();
+    list_view.buffer().set_text(&text);
+    let scrolled = gtk::ScrolledWindow::builder()
+        .child(&list_view)
+        .min_content_width(600)
+        .min_content_height(400)
+        .build();
+    dialog.message_area().append(&scrolled);
+    dialog.connect_response(|dialog, _| dialog.destroy());
+    dialog.present();
+}
+
 fn show_res<R>(window: &ApplicationWindow, res: Result<R, anyhow::Error>) {
     dbg!("show_res");
     if let Err(e) = res {
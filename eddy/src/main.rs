@@ -15,14 +15,39 @@ mod objects;
 mod theme;
 mod widgets;
 
+thread_local! {
+    /// The application state, for wakeups to reach from other threads
+    static MODEL: RefCell<Option<Rc<RefCell<Obs<Model>>>>> = const { RefCell::new(None) };
+}
+
+/// Handles the model's events, or tries again once the main loop is idle if
+/// the model is busy, so results from other threads aren't left waiting
+fn handle_model_events() {
+    MODEL.with(|m| {
+        let Some(model) = m.borrow().clone() else {
+            return;
+        };
+        let Ok(mut model) = model.try_borrow_mut() else {
+            glib::source::idle_add_local_once(handle_model_events);
+            return;
+        };
+        if model.get().has_events() {
+            model.get_mut().handle_events();
+        }
+    })
+}
+
 fn main() -> ExitCode {
     env_logger::init();
     gtk::init().expect("gtk init");
 
-    // Create the global application state
+    // Create the global application state.  Waking up from another thread
+    // handles the model's events back on this one, which marks it changed
+    // so the observer below redraws.
     let model = Rc::new(RefCell::new(Obs::new(Model::new(Arc::new(|| {
-        // This wakeup function is now handled by the observer below
+        glib::MainContext::default().invoke(handle_model_events);
     })))));
+    MODEL.with(|m| *m.borrow_mut() = Some(model.clone()));
 
     // Create the root of the component tree
     let mut ctree = ComponentTree::new(model.clone());
@@ -2,9 +2,15 @@ use crate::color::{pango_to_gdk, text_theme_to_gdk};
 use crate::components::code_view_text::CodeViewTextComponent;
 use crate::theme::Theme;
 use crate::widgets::layout::{LayoutItem, LayoutLine};
+use crate::widgets::picker::{picker_row, Picker};
 use cairo::glib::{ParamSpecEnum, ParamSpecObject};
-use eddy_model::style::{Attr, AttrSpan, Color};
-use eddy_model::{Buffer, Selection};
+use eddy_model::keymap::{format_key_sequence, normalize_key, KeyChord, KeyResult};
+use eddy_model::palette::{PaletteAction, PaletteEntry, Prompt};
+use eddy_model::quick_open::{parse_position, QuickOpenEntry};
+use eddy_model::style::{Attr, AttrSpan, Color, UnderlineStyle, Weight};
+use eddy_model::{
+    BookmarkEntry, Buffer, Command, CommandOutput, Selection, SymbolEntry, UiRequest,
+};
 use gdk::{Key, ModifierType};
 use gflux::ComponentCtx;
 use gio::Cancellable;
@@ -17,12 +23,13 @@ use log::*;
 
 use once_cell::sync::Lazy;
 use once_cell::unsync::OnceCell;
-use pango::{AttrColor, AttrList};
+use pango::{AttrColor, AttrInt, AttrList};
 use ropey::RopeSlice;
 use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
 use std::cmp::min;
 use std::collections::HashSet;
+use std::rc::Rc;
 
 use std::time::Instant;
 
@@ -154,6 +161,8 @@ impl ObjectImpl for CodeViewTextPrivate {
         obj.set_hexpand(true);
 
         let gesture_click = gtk::GestureClick::new();
+        // Every button, for the mouse's back and forward buttons
+        gesture_click.set_button(0);
         gesture_click.connect_pressed(clone!(
             #[strong(rename_to = this)]
             obj,
@@ -489,10 +498,15 @@ impl CodeViewTextPrivate {
         x: f64,
         y: f64,
     ) {
-        let view_id = self.view_id.get();
         // dbg!(n_press);
         let sequence = gc.current_sequence(); // Can be None
-        let _button = gc.current_button();
+        let button = gc.current_button();
+        match button {
+            MOUSE_BACK => return self.execute(Command::JumpBack),
+            MOUSE_FORWARD => return self.execute(Command::JumpForward),
+            gdk::BUTTON_PRIMARY => {}
+            _ => return,
+        }
         let _event = gc.last_event(sequence.as_ref()).unwrap();
 
         let _shift = gc
@@ -516,35 +530,34 @@ impl CodeViewTextPrivate {
         match n_press {
             1 => {
                 if ctrl {
-                    self.with_buffer_mut(|b| b.gesture_toggle_sel(view_id, line, idx));
+                    self.execute(Command::ToggleSelect { line, byte: idx });
                 } else {
-                    self.with_buffer_mut(|b| b.gesture_point_select(view_id, line, idx));
+                    self.execute(Command::PointSelect { line, byte: idx });
                 }
             }
 
             2 => {
-                self.with_buffer_mut(|b| b.gesture_word_select(view_id, line, idx));
+                self.execute(Command::WordSelect { line, byte: idx });
             }
             3 => {
-                self.with_buffer_mut(|b| b.gesture_line_select(view_id, line));
+                self.execute(Command::LineSelect { line });
             }
             _ => {}
         };
     }
 
     fn gesture_toggle_sel(&self, cvt: &CodeViewText, x: f64, y: f64) {
-        let (line, byte_idx) = self.xy_to_line_idx(cvt, x, y);
-        self.with_buffer_mut(|b| b.gesture_toggle_sel(self.view_id.get(), line, byte_idx));
+        let (line, byte) = self.xy_to_line_idx(cvt, x, y);
+        self.execute(Command::ToggleSelect { line, byte });
     }
 
     fn gesture_drag(&self, cvt: &CodeViewText, x: f64, y: f64) {
-        let (line, idx) = self.xy_to_line_idx(cvt, x, y);
-        self.with_buffer_mut(|b| b.drag_update(self.view_id.get(), line, idx));
-        self.scroll_to_carets(&self.obj());
+        let (line, byte) = self.xy_to_line_idx(cvt, x, y);
+        self.execute(Command::DragUpdate { line, byte });
     }
 
     fn drag_end(&self, _cvt: &CodeViewText) {
-        self.with_buffer_mut(|b| b.drag_end(self.view_id.get()));
+        self.execute(Command::DragEnd);
         self.do_copy_primary();
     }
 
@@ -698,6 +711,13 @@ impl CodeViewTextPrivate {
 
             // Draw the cursors on the line
             let selections = self.with_buffer(|b| b.selections(view_id).to_vec());
+            let block_caret = self
+                .ctx
+                .get()
+                .unwrap()
+                .with_model(|ws| ws.vim_mode(view_id))
+                .map(|mode| mode.block_caret())
+                .unwrap_or_default();
             for sel in selections {
                 if self.with_buffer(|b| b.char_to_line(sel.cursor())) != line_num {
                     continue;
@@ -706,14 +726,33 @@ impl CodeViewTextPrivate {
                     - self.with_buffer(|b| b.line_to_byte(line_num));
                 let x = layout_line.index_to_x(line_byte) as f32 / pango::SCALE as f32;
 
-                let color = text_theme_to_gdk(text_theme.fg);
+                let mut color = text_theme_to_gdk(text_theme.fg);
+                let mut width = CURSOR_WIDTH as f32;
+                if block_caret {
+                    // A block over the character the caret is on, or half a
+                    // line's height wide at the end of the line
+                    let next = self.with_buffer(|b| {
+                        let pos = sel.cursor();
+                        (pos < b.len_chars() && b.rope().char(pos) != '\n')
+                            .then(|| b.rope().char(pos).len_utf8())
+                    });
+                    width = match next {
+                        Some(len) => {
+                            let next_x = layout_line.index_to_x(line_byte + len) as f32
+                                / pango::SCALE as f32;
+                            (next_x - x).abs().max(CURSOR_WIDTH as f32)
+                        }
+                        None => font_height as f32 / 2.0,
+                    };
+                    color.set_alpha(0.5);
+                }
 
                 let rect_node = gtk::gsk::ColorNode::new(
                     &color,
                     &graphene::Rect::new(
                         x - hadj_value as f32,
                         line_y - font_ascent as f32,
-                        CURSOR_WIDTH as f32,
+                        width,
                         font_height as f32,
                     ),
                 );
@@ -751,17 +790,11 @@ impl CodeViewTextPrivate {
     fn create_pango_attr_list(&self, attr_spans: &[AttrSpan]) -> pango::AttrList {
         let attr_list = pango::AttrList::new();
         for aspan in attr_spans {
-            let mut pattr = match aspan.attr {
-                Attr::ForegroundColor(color) => {
-                    AttrColor::new_foreground(color.r_u16(), color.g_u16(), color.b_u16())
-                }
-                Attr::BackgroundColor(color) => {
-                    AttrColor::new_background(color.r_u16(), color.g_u16(), color.b_u16())
-                }
-            };
-            pattr.set_start_index(aspan.start_idx as u32);
-            pattr.set_end_index(aspan.end_idx as u32);
-            attr_list.insert(pattr);
+            for mut pattr in pango_attrs(aspan.attr) {
+                pattr.set_start_index(aspan.start_idx as u32);
+                pattr.set_end_index(aspan.end_idx as u32);
+                attr_list.insert(pattr);
+            }
         }
 
         // let font_desc_attr = Attribute::new_font_desc(&self.font_desc);
@@ -781,21 +814,7 @@ impl CodeViewTextPrivate {
         let text: Cow<str> = (*line).into();
         layout.set_text(&text);
 
-        let attr_list = pango::AttrList::new();
-        for aspan in attr_spans {
-            let mut pattr = match aspan.attr {
-                Attr::ForegroundColor(color) => {
-                    AttrColor::new_foreground(color.r_u16(), color.g_u16(), color.b_u16())
-                }
-                Attr::BackgroundColor(color) => {
-                    AttrColor::new_background(color.r_u16(), color.g_u16(), color.b_u16())
-                }
-            };
-            pattr.set_start_index(aspan.start_idx as u32);
-            pattr.set_end_index(aspan.end_idx as u32);
-            attr_list.insert(pattr);
-        }
-
+        let attr_list = self.create_pango_attr_list(attr_spans);
         layout.set_attributes(Some(&attr_list));
         layout
     }
@@ -818,65 +837,416 @@ impl CodeViewTextPrivate {
         }
     }
 
-    fn do_cut(&self) {
+    fn do_copy_primary(&self) {
         let Some(display) = gdk::Display::default() else {
             return;
         };
-        let clipboard = gdk::Display::clipboard(&display);
+        let clipboard = gdk::Display::primary_clipboard(&display);
 
         let view_id = self.view_id.get();
 
-        if let Some(text) = self.with_buffer_mut(|b| b.cut(view_id)) {
+        if let Some(text) = self.with_buffer(|b| b.selected_text(view_id)) {
             clipboard.set_text(&text);
         }
     }
 
-    fn do_copy(&self) {
+    fn do_paste(&self) {
         let Some(display) = gdk::Display::default() else {
             return;
         };
         let clipboard = gdk::Display::clipboard(&display);
 
         let view_id = self.view_id.get();
+        let ctx = self.ctx.get().unwrap().clone();
+        clipboard.read_text_async(
+            Cancellable::NONE,
+            clone!(
+                #[strong]
+                ctx,
+                move |res| {
+                    if let Ok(Some(s)) = res {
+                        let cmd = Command::Paste(s.to_string());
+                        if let Err(e) = ctx.with_model_mut(|ws| ws.execute(view_id, cmd.clone())) {
+                            error!("paste failed: {e}");
+                        }
+                    }
+                }
+            ),
+        );
+    }
 
-        if let Some(text) = self.with_buffer(|b| b.copy(view_id)) {
-            clipboard.set_text(&text);
+    /// Runs a command on this view.  Text that it cuts or copies goes on the
+    /// clipboard.
+    fn execute(&self, cmd: Command) {
+        let view_id = self.view_id.get();
+        let ctx = self.ctx.get().unwrap();
+        let page_lines = self.page_lines(&self.obj());
+        match ctx.with_model_mut(|ws| {
+            ws.page_lines.insert(view_id, page_lines);
+            ws.execute(view_id, cmd.clone())
+        }) {
+            Ok(CommandOutput::Clipboard(text)) => {
+                if let Some(display) = gdk::Display::default() {
+                    display.clipboard().set_text(&text);
+                }
+            }
+            Ok(CommandOutput::Ui(UiRequest::ReadClipboard)) => self.do_paste(),
+            Ok(CommandOutput::Ui(UiRequest::ClipboardHistory)) => self.do_paste_from_history(),
+            Ok(CommandOutput::Ui(UiRequest::OpenFile)) => self.do_open_file(),
+            Ok(CommandOutput::Ui(UiRequest::CommandPalette)) => self.do_command_palette(),
+            Ok(CommandOutput::Ui(UiRequest::QuickOpen)) => self.do_quick_open(),
+            Ok(CommandOutput::Ui(UiRequest::Bookmarks)) => self.do_bookmarks(),
+            Ok(CommandOutput::Ui(UiRequest::Symbols)) => self.do_symbols(),
+            Ok(_) => {}
+            Err(e) => error!("{cmd:?} failed: {e}"),
         }
+        self.scroll_to_carets(&self.obj());
     }
 
-    fn do_copy_primary(&self) {
-        let Some(display) = gdk::Display::default() else {
-            return;
-        };
-        let clipboard = gdk::Display::primary_clipboard(&display);
+    /// Shows the command palette, and runs the command picked from it on
+    /// this view.  Commands that need an argument ask for it in the same
+    /// entry.
+    fn do_command_palette(&self) {
+        let view_id = self.view_id.get();
+        let ctx = self.ctx.get().unwrap().clone();
+        let obj = self.obj().clone();
+        let prompt: Rc<Cell<Option<Prompt>>> = Rc::default();
+
+        let picker = Picker::new(
+            &*self.obj(),
+            "Command Palette",
+            Some("Type a command"),
+            (500, 360),
+            clone!(
+                #[strong]
+                ctx,
+                #[strong]
+                prompt,
+                move |query: &str| match prompt.get() {
+                    Some(_) => Vec::new(),
+                    None => ctx.with_model(|ws| ws.palette_entries(view_id, query)),
+                }
+            ),
+            |e: &PaletteEntry| {
+                let title = gtk::Label::builder()
+                    .use_markup(true)
+                    .label(fuzzy_markup(&e.title, &e.fuzzy.indices))
+                    .xalign(0.0)
+                    .hexpand(true)
+                    .build();
+                let keys = gtk::Label::builder()
+                    .label(e.keys.as_deref().unwrap_or_default())
+                    .css_classes(["dim-label", "monospace"])
+                    .build();
+                let row = picker_row();
+                row.append(&title);
+                row.append(&keys);
+                row.upcast()
+            },
+        );
+        let entry = picker.entry.clone().unwrap();
+        let error = gtk::Label::builder()
+            .xalign(0.0)
+            .margin_start(6)
+            .visible(false)
+            .css_classes(["error"])
+            .build();
+        picker.vbox.insert_child_after(&error, Some(&entry));
+        entry.connect_changed(clone!(
+            #[weak]
+            error,
+            move |_| error.set_visible(false)
+        ));
 
+        let list = picker.list.clone();
+        picker.connect_pick(clone!(
+            #[weak]
+            entry,
+            #[weak]
+            list,
+            #[weak]
+            error,
+            move |palette: &gtk::Window, picked: Option<PaletteEntry>| {
+                if let Some(p) = prompt.get() {
+                    match p.command(&entry.text()) {
+                        Ok(cmd) => {
+                            palette.close();
+                            obj.imp().execute(cmd);
+                        }
+                        Err(e) => {
+                            error.set_text(&e);
+                            error.set_visible(true);
+                        }
+                    }
+                    return;
+                }
+                let Some(picked) = picked else {
+                    return;
+                };
+                ctx.with_model_mut(|ws| ws.palette.record(&picked.title));
+                match picked.action {
+                    PaletteAction::Run(cmd) => {
+                        palette.close();
+                        obj.imp().execute(cmd);
+                    }
+                    PaletteAction::Prompt(p) => {
+                        prompt.set(Some(p));
+                        list.remove_all();
+                        palette.set_title(Some(p.title()));
+                        entry.set_placeholder_text(Some(p.placeholder()));
+                        entry.set_text("");
+                    }
+                }
+            }
+        ));
+        picker.present();
+    }
+
+    /// Shows quick open, and opens the file picked from it in a new view.
+    /// A `:line:column` typed after the name goes to that position.
+    fn do_quick_open(&self) {
+        let ctx = self.ctx.get().unwrap().clone();
+
+        let picker = Picker::new(
+            &*self.obj(),
+            "Quick Open",
+            Some("Type part of a file's path"),
+            (600, 400),
+            clone!(
+                #[strong]
+                ctx,
+                move |query: &str| {
+                    let (found, names) = ctx.with_model(|ws| {
+                        let found = ws.quick_open_entries(query);
+                        let names: Vec<String> = found
+                            .iter()
+                            .map(|e| ws.projects[&e.project].name.clone())
+                            .collect();
+                        (found, names)
+                    });
+                    // Projects are only named when there's more than one
+                    let many_projects = names.iter().any(|n| *n != names[0]);
+                    found
+                        .into_iter()
+                        .zip(names)
+                        .map(|(e, name)| (e, many_projects.then_some(name)))
+                        .collect()
+                }
+            ),
+            |(e, name): &(QuickOpenEntry, Option<String>)| {
+                let label = gtk::Label::builder()
+                    .use_markup(true)
+                    .label(fuzzy_markup(&e.label, &e.fuzzy.indices))
+                    .xalign(0.0)
+                    .hexpand(true)
+                    .build();
+                let project = gtk::Label::builder()
+                    .label(name.as_deref().unwrap_or_default())
+                    .visible(name.is_some())
+                    .css_classes(["dim-label"])
+                    .build();
+                let row = picker_row();
+                row.append(&label);
+                row.append(&project);
+                row.upcast()
+            },
+        );
+        let entry = picker.entry.clone().unwrap();
+        picker.connect_pick(clone!(
+            #[weak]
+            entry,
+            move |picker: &gtk::Window, picked: Option<(QuickOpenEntry, Option<String>)>| {
+                let Some((picked, _)) = picked else {
+                    return;
+                };
+                let (_, position) = parse_position(&entry.text());
+                picker.close();
+                if let Err(e) = ctx.with_model_mut(|ws| ws.quick_open_file(&picked.path, position))
+                {
+                    error!("open failed: {e}");
+                }
+            }
+        ));
+        picker.present();
+    }
+
+    /// Asks for a file, and opens it in a new view
+    fn do_open_file(&self) {
         let view_id = self.view_id.get();
+        let ctx = self.ctx.get().unwrap().clone();
+        let root = self.obj().root().and_downcast::<gtk::Window>();
+        let fcd = gtk::FileChooserDialog::new(
+            Some("Open File"),
+            root.as_ref(),
+            gtk::FileChooserAction::Open,
+            &[
+                ("_Cancel", gtk::ResponseType::Cancel),
+                ("_Open", gtk::ResponseType::Accept),
+            ],
+        );
+        fcd.set_modal(true);
+        fcd.connect_response(move |chooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = chooser.file().and_then(|f| f.path()) {
+                    let cmd = Command::NewView(Some(path));
+                    if let Err(e) = ctx.with_model_mut(|ws| ws.execute(view_id, cmd.clone())) {
+                        error!("open failed: {e}");
+                    }
+                }
+            }
+            chooser.close();
+        });
+        fcd.present();
+    }
 
-        if let Some(text) = self.with_buffer(|b| b.copy(view_id)) {
-            clipboard.set_text(&text);
+    /// Shows the clipboard history, and pastes the entry picked from it
+    fn do_paste_from_history(&self) {
+        let view_id = self.view_id.get();
+        let ctx = self.ctx.get().unwrap().clone();
+        let labels: Vec<(usize, String)> = ctx.with_model(|ws| {
+            ws.clipboard_history
+                .iter()
+                .map(|e| {
+                    let text = e.text();
+                    let first = text.lines().next().unwrap_or_default().trim();
+                    let mut label: String = first.chars().take(80).collect();
+                    if e.chunks.len() > 1 {
+                        label.push_str(&format!("  ({} selections)", e.chunks.len()));
+                    } else if text.lines().count() > 1 {
+                        label.push_str(&format!("  ({} lines)", text.lines().count()));
+                    }
+                    label
+                })
+                .enumerate()
+                .collect()
+        });
+        if labels.is_empty() {
+            return;
         }
+
+        let picker = Picker::new(
+            &*self.obj(),
+            "Paste from History",
+            None,
+            (500, 300),
+            move |_: &str| labels.clone(),
+            |(_, label): &(usize, String)| {
+                let row = picker_row();
+                row.append(&gtk::Label::builder().label(label).xalign(0.0).build());
+                row.upcast()
+            },
+        );
+        picker.connect_pick(
+            move |picker: &gtk::Window, picked: Option<(usize, String)>| {
+                if let Some((index, _)) = picked {
+                    let _ = ctx
+                        .with_model_mut(|ws| ws.execute(view_id, Command::PasteFromHistory(index)));
+                }
+                picker.close();
+            },
+        );
+        picker.present();
     }
 
-    fn do_paste(&self) {
-        let Some(display) = gdk::Display::default() else {
+    /// Lists the bookmarks in every buffer, and goes to the one picked
+    fn do_bookmarks(&self) {
+        let ctx = self.ctx.get().unwrap().clone();
+        let entries: Vec<BookmarkEntry> = ctx.with_model(|ws| ws.bookmark_entries());
+        if entries.is_empty() {
             return;
-        };
-        let clipboard = gdk::Display::clipboard(&display);
+        }
 
-        let view_id = self.view_id.get();
+        let picker = Picker::new(
+            &*self.obj(),
+            "Bookmarks",
+            None,
+            (600, 300),
+            move |_: &str| entries.clone(),
+            |e: &BookmarkEntry| {
+                let place = gtk::Label::builder()
+                    .label(format!("{}:{}", e.file, e.line + 1))
+                    .xalign(0.0)
+                    .build();
+                let name = gtk::Label::builder()
+                    .label(e.name.as_deref().unwrap_or_default())
+                    .visible(e.name.is_some())
+                    .css_classes(["heading"])
+                    .build();
+                let preview = gtk::Label::builder()
+                    .label(e.preview.chars().take(80).collect::<String>())
+                    .xalign(0.0)
+                    .hexpand(true)
+                    .css_classes(["dim-label"])
+                    .build();
+                let row = picker_row();
+                row.append(&place);
+                row.append(&name);
+                row.append(&preview);
+                row.upcast()
+            },
+        );
+        picker.connect_pick(move |picker: &gtk::Window, picked: Option<BookmarkEntry>| {
+            if let Some(entry) = picked {
+                ctx.with_model_mut(|ws| ws.go_to_bookmark(&entry));
+            }
+            picker.close();
+        });
+        picker.present();
+    }
+
+    /// Shows the symbols in the buffer, filtered by what's typed, and moves
+    /// the caret to the one picked
+    fn do_symbols(&self) {
         let ctx = self.ctx.get().unwrap().clone();
-        clipboard.read_text_async(
-            Cancellable::NONE,
+        let view_id = self.view_id.get();
+
+        let picker = Picker::new(
+            &*self.obj(),
+            "Go to Symbol",
+            Some("Go to symbol"),
+            (500, 400),
             clone!(
                 #[strong]
                 ctx,
-                move |res| {
-                    if let Ok(Some(s)) = res {
-                        ctx.with_model_mut(|ws| ws.buffer_mut(view_id).insert(view_id, s.as_str()))
+                move |query: &str| {
+                    let mut found = ctx.with_model(|ws| ws.symbol_entries(view_id, query));
+                    // Nesting only means something in outline order
+                    if !query.trim().is_empty() {
+                        found.iter_mut().for_each(|e| e.depth = 0);
                     }
+                    found
                 }
             ),
+            |e: &SymbolEntry| {
+                let kind = gtk::Label::builder()
+                    .label(e.symbol.kind.to_string())
+                    .css_classes(["dim-label"])
+                    .build();
+                let name = gtk::Label::builder()
+                    .use_markup(true)
+                    .label(fuzzy_markup(&e.symbol.name, &e.fuzzy.indices))
+                    .xalign(0.0)
+                    .hexpand(true)
+                    .build();
+                let line = gtk::Label::builder()
+                    .label((e.symbol.line + 1).to_string())
+                    .css_classes(["dim-label"])
+                    .build();
+                let row = picker_row();
+                row.set_margin_start(6 + 18 * e.depth as i32);
+                row.append(&kind);
+                row.append(&name);
+                row.append(&line);
+                row.upcast()
+            },
         );
+        picker.connect_pick(move |picker: &gtk::Window, picked: Option<SymbolEntry>| {
+            if let Some(picked) = picked {
+                ctx.with_model_mut(|ws| ws.go_to_symbol(view_id, &picked.symbol));
+                picker.close();
+            }
+        });
+        picker.present();
     }
 
     fn key_pressed(&self, key: Key, _keycode: u32, state: ModifierType) {
@@ -887,175 +1257,102 @@ impl CodeViewTextPrivate {
             key.to_unicode(),
         );
 
-        let view_id = self.view_id.get();
-        let ch = key.to_unicode();
-
-        let alt = state.contains(ModifierType::ALT_MASK);
-        let ctrl = state.contains(ModifierType::CONTROL_MASK);
-        let meta = state.contains(ModifierType::META_MASK);
-        let shift = state.contains(ModifierType::SHIFT_MASK);
-        let norm = !alt && !ctrl && !meta;
-
-        match key {
-            Key::Delete if norm => {
-                self.with_buffer_mut(|b| b.delete_forward(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
+        let chord = KeyChord {
+            ctrl: state.contains(ModifierType::CONTROL_MASK),
+            alt: state.contains(ModifierType::ALT_MASK),
+            shift: state.contains(ModifierType::SHIFT_MASK),
+            meta: state.contains(ModifierType::META_MASK),
+            key: key_name(key),
+        };
+        let plain = chord.is_plain();
 
-            Key::BackSpace if norm => {
-                self.with_buffer_mut(|b| b.delete_backward(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Return | Key::KP_Enter => {
-                self.with_buffer_mut(|b| b.insert_newline(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Tab if norm && !shift => {
-                self.with_buffer_mut(|b| b.insert_tab(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Up if norm && !shift => {
-                self.with_buffer_mut(|b| b.move_up(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Down if norm && !shift => {
-                self.with_buffer_mut(|b| b.move_down(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Left if norm && !shift => {
-                self.with_buffer_mut(|b| b.move_left(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Right if norm && !shift => {
-                self.with_buffer_mut(|b| b.move_right(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Up if norm && shift => {
-                self.with_buffer_mut(|b| b.move_up_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Down if norm && shift => {
-                self.with_buffer_mut(|b| b.move_down_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Left if norm && shift => {
-                self.with_buffer_mut(|b| b.move_left_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Right if norm && shift => {
-                self.with_buffer_mut(|b| b.move_right_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Left if ctrl && !shift => {
-                self.with_buffer_mut(|b| b.move_word_left(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Right if ctrl && !shift => {
-                self.with_buffer_mut(|b| b.move_word_right(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Left if ctrl && shift => {
-                self.with_buffer_mut(|b| b.move_word_left_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Right if ctrl && shift => {
-                self.with_buffer_mut(|b| b.move_word_right_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Home if norm && !shift => {
-                self.with_buffer_mut(|b| b.move_to_left_end_of_line(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::End if norm && !shift => {
-                self.with_buffer_mut(|b| b.move_to_right_end_of_line(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Home if norm && shift => {
-                self.with_buffer_mut(|b| b.move_to_left_end_of_line_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::End if norm && shift => {
-                self.with_buffer_mut(|b| b.move_to_right_end_of_line_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Home if ctrl && !shift => {
-                self.with_buffer_mut(|b| b.move_to_beginning_of_document(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::End if ctrl && !shift => {
-                self.with_buffer_mut(|b| b.move_to_end_of_document(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Home if ctrl && shift => {
-                self.with_buffer_mut(|b| {
-                    b.move_to_beginning_of_document_and_modify_selection(view_id)
-                });
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::End if ctrl && shift => {
-                self.with_buffer_mut(|b| b.move_to_end_of_document_and_modify_selection(view_id));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Page_Up if norm && !shift => {
-                self.with_buffer_mut(|b| b.page_up(view_id, self.page_lines(&self.obj())));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Page_Down if norm && !shift => {
-                self.with_buffer_mut(|b| b.page_down(view_id, self.page_lines(&self.obj())));
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Page_Up if norm && shift => {
-                self.with_buffer_mut(|b| {
-                    b.page_up_and_modify_selection(view_id, self.page_lines(&self.obj()))
-                });
-                self.scroll_to_carets(&self.obj());
-            }
-            Key::Page_Down if norm && shift => {
-                self.with_buffer_mut(|b| {
-                    b.page_down_and_modify_selection(view_id, self.page_lines(&self.obj()))
-                });
-                self.scroll_to_carets(&self.obj());
+        let view_id = self.view_id.get();
+        let ctx = self.ctx.get().unwrap();
+        let result =
+            ctx.with_model_mut(|ws| ws.press_key(view_id, chord.clone(), &["editor_focused"]));
+        match result {
+            KeyResult::Command(cmd) => self.execute(cmd),
+            KeyResult::Repeat(cmd, count) => {
+                for _ in 0..count {
+                    self.execute(cmd.clone());
+                }
             }
-            _ => {
-                if let Some(ch) = ch {
-                    match ch {
-                        'a' if ctrl => {
-                            self.with_buffer_mut(|b| b.select_all(view_id));
-                        }
-                        'c' if ctrl => {
-                            self.do_copy();
-                        }
-                        'f' if ctrl => {
-                            // self.start_search(state);
-                        }
-                        'v' if ctrl => {
-                            self.do_paste();
-                        }
-                        's' if ctrl => {
-                            self.with_buffer_mut(|b| b.save());
-                        }
-                        't' if ctrl => {
-                            // TODO new tab
-                        }
-                        'x' if ctrl => {
-                            self.do_cut();
-                        }
-                        'z' if ctrl => {
-                            self.with_buffer_mut(|b| b.undo(view_id));
-                        }
-                        'Z' if ctrl && shift => {
-                            self.with_buffer_mut(|b| b.redo(view_id));
-                        }
-                        c if (norm) && c >= '\u{0020}' => {
-                            self.with_buffer_mut(|b| b.insert(view_id, &c.to_string()));
-                        }
-                        _ => {
-                            debug!("unhandled key: {ch:?}");
-                        }
-                    }
+            KeyResult::Pending | KeyResult::Handled => {}
+            KeyResult::Unbound(keys) => match key.to_unicode() {
+                Some(ch) if keys.len() == 1 && plain && ch >= '\u{0020}' => {
+                    self.execute(Command::Insert(ch.to_string()));
                 }
+                _ => debug!("unbound keys: {}", format_key_sequence(&keys)),
+            },
+        }
+    }
+}
+
+/// The pango attributes an eddy attribute is made of
+fn pango_attrs(attr: Attr) -> Vec<pango::Attribute> {
+    match attr {
+        Attr::ForegroundColor(color) => {
+            vec![AttrColor::new_foreground(color.r_u16(), color.g_u16(), color.b_u16()).into()]
+        }
+        Attr::BackgroundColor(color) => {
+            vec![AttrColor::new_background(color.r_u16(), color.g_u16(), color.b_u16()).into()]
+        }
+        Attr::Weight(weight) => {
+            let weight = match weight {
+                Weight::Normal => pango::Weight::Normal,
+                Weight::Bold => pango::Weight::Bold,
+            };
+            vec![AttrInt::new_weight(weight).into()]
+        }
+        Attr::Italic => vec![AttrInt::new_style(pango::Style::Italic).into()],
+        Attr::Underline { style, color } => {
+            let underline = match style {
+                UnderlineStyle::Single => pango::Underline::Single,
+                UnderlineStyle::Double => pango::Underline::Double,
+                // Pango draws its error underline as a squiggle
+                UnderlineStyle::Curly => pango::Underline::Error,
+            };
+            let mut attrs = vec![AttrInt::new_underline(underline).into()];
+            if let Some(color) = color {
+                attrs.push(
+                    AttrColor::new_underline_color(color.r_u16(), color.g_u16(), color.b_u16())
+                        .into(),
+                );
             }
-        };
+            attrs
+        }
+        Attr::Strikethrough => vec![AttrInt::new_strikethrough(true).into()],
+    }
+}
+
+/// Pango markup for a fuzzy matched name, with the matched characters in
+/// bold
+fn fuzzy_markup(text: &str, indices: &[usize]) -> String {
+    let mut markup = String::new();
+    for (i, ch) in text.chars().enumerate() {
+        let ch = glib::markup_escape_text(&ch.to_string());
+        if indices.contains(&i) {
+            markup.push_str(&format!("<b>{ch}</b>"));
+        } else {
+            markup.push_str(&ch);
+        }
+    }
+    markup
+}
+
+/// The mouse buttons that go back and forward, as numbered by X11 and
+/// Wayland
+const MOUSE_BACK: u32 = 8;
+const MOUSE_FORWARD: u32 = 9;
+
+/// The name a gdk key goes by in keymaps
+fn key_name(key: Key) -> String {
+    if key == Key::ISO_Left_Tab {
+        return "tab".to_string();
+    }
+    match key.to_lower().to_unicode() {
+        Some(ch) if ch > '\u{0020}' && ch != '\u{007f}' => ch.to_string(),
+        _ => normalize_key(&key.name().map(|n| n.to_string()).unwrap_or_default()),
     }
 }
 
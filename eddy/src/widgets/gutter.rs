@@ -15,7 +15,7 @@ use once_cell::unsync::OnceCell;
 
 use std::cell::{Cell, RefCell};
 use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::time::Instant;
 
 pub struct GutterPrivate {
@@ -211,6 +211,27 @@ impl GutterPrivate {
         // Calculate ordinal or max line length
         let nchars: usize = std::cmp::max(format!("{num_lines}").len(), 2);
 
+        // Mark bookmarked lines in the column left of the line numbers, with
+        // the first letter of the name if the bookmark has one
+        let bookmarks: HashMap<usize, char> = self.with_buffer(|b| {
+            let mut marks = HashMap::new();
+            for mark in b.bookmarks().iter() {
+                let line = b.char_to_line(mark.pos);
+                let name = mark.name.as_ref().and_then(|n| n.chars().next());
+                match name {
+                    Some(c) => {
+                        marks.insert(line, c);
+                    }
+                    None => {
+                        marks.entry(line).or_insert('●');
+                    }
+                }
+            }
+            marks
+        });
+        let mut bookmark_color = gdk::RGBA::BLACK;
+        change_to_color(&mut bookmark_color, Some(text_theme.cursor));
+
         for line in visible_lines {
             let mut fg_color = gdk::RGBA::BLACK;
             change_to_color(&mut fg_color, text_theme.gutter.fg);
@@ -219,6 +240,18 @@ impl GutterPrivate {
                 change_to_color(&mut fg_color, text_theme.gutter_line_highlight.fg);
             }
 
+            if let Some(marker) = bookmarks.get(&line) {
+                self.append_text_to_snapshot(
+                    cv,
+                    bookmark_color,
+                    snapshot,
+                    &marker.to_string(),
+                    pango::AttrList::new(),
+                    0.0,
+                    font_ascent as f32 + font_height as f32 * (line as f32) - vadj_value as f32,
+                );
+            }
+
             self.append_text_to_snapshot(
                 cv,
                 fg_color,
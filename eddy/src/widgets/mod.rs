@@ -1,3 +1,4 @@
 pub mod code_view_text;
 pub mod gutter;
 pub mod layout;
+pub mod picker;
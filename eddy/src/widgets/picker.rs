@@ -0,0 +1,170 @@
+use gdk::Key;
+use glib::{clone, Propagation};
+use gtk::prelude::*;
+use gtk::{gdk, glib};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A modal window with a list of items to pick one from.  With an entry
+/// above the list, the items are looked up again as it's typed in, and the
+/// arrow keys move through the list while typing.
+pub struct Picker<T> {
+    pub window: gtk::Window,
+    pub vbox: gtk::Box,
+    pub entry: Option<gtk::Entry>,
+    pub list: gtk::ListBox,
+    items: Rc<RefCell<Vec<T>>>,
+}
+
+/// A row for a picker's list, with the usual spacing and margins
+pub fn picker_row() -> gtk::Box {
+    gtk::Box::builder()
+        .spacing(12)
+        .margin_start(6)
+        .margin_end(6)
+        .margin_top(3)
+        .margin_bottom(3)
+        .build()
+}
+
+impl<T: Clone + 'static> Picker<T> {
+    /// Builds a picker over the window `parent` is in.  `items` gives the
+    /// items for what's typed, or for `""` when there's no entry, and `row`
+    /// makes the row an item is shown in.
+    pub fn new(
+        parent: &impl IsA<gtk::Widget>,
+        title: &str,
+        placeholder: Option<&str>,
+        (width, height): (i32, i32),
+        items: impl Fn(&str) -> Vec<T> + 'static,
+        row: impl Fn(&T) -> gtk::Widget + 'static,
+    ) -> Self {
+        let entry = placeholder.map(|text| {
+            gtk::Entry::builder()
+                .placeholder_text(text)
+                .margin_start(6)
+                .margin_end(6)
+                .margin_top(6)
+                .margin_bottom(6)
+                .build()
+        });
+        let list = gtk::ListBox::new();
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        if let Some(entry) = &entry {
+            vbox.append(entry);
+        }
+        vbox.append(
+            &gtk::ScrolledWindow::builder()
+                .child(&list)
+                .vexpand(true)
+                .build(),
+        );
+        let window = gtk::Window::builder()
+            .title(title)
+            .modal(true)
+            .default_width(width)
+            .default_height(height)
+            .child(&vbox)
+            .build();
+        if let Some(root) = parent.root().and_downcast::<gtk::Window>() {
+            window.set_transient_for(Some(&root));
+        }
+
+        let found: Rc<RefCell<Vec<T>>> = Rc::default();
+        let refresh = clone!(
+            #[strong]
+            found,
+            #[weak]
+            list,
+            move |query: &str| {
+                let shown = items(query);
+                list.remove_all();
+                for item in &shown {
+                    list.append(&row(item));
+                }
+                list.select_row(list.row_at_index(0).as_ref());
+                *found.borrow_mut() = shown;
+            }
+        );
+        refresh("");
+        if let Some(entry) = &entry {
+            entry.connect_changed(move |entry| refresh(&entry.text()));
+        }
+
+        let arrows = entry.is_some();
+        let keys = gtk::EventControllerKey::new();
+        keys.connect_key_pressed(clone!(
+            #[weak]
+            window,
+            #[weak]
+            list,
+            #[upgrade_or]
+            Propagation::Proceed,
+            move |_, key, _, _| {
+                let step = match key {
+                    Key::Escape => {
+                        window.close();
+                        return Propagation::Stop;
+                    }
+                    Key::Up if arrows => -1,
+                    Key::Down if arrows => 1,
+                    _ => return Propagation::Proceed,
+                };
+                let index = list.selected_row().map(|r| r.index()).unwrap_or(0) + step;
+                if let Some(row) = list.row_at_index(index) {
+                    list.select_row(Some(&row));
+                }
+                Propagation::Stop
+            }
+        ));
+        // Catch the arrow keys before the entry does, so they move through
+        // the list while typing
+        keys.set_propagation_phase(gtk::PropagationPhase::Capture);
+        window.add_controller(keys);
+
+        Self {
+            window,
+            vbox,
+            entry,
+            list,
+            items: found,
+        }
+    }
+
+    /// Calls `pick` with the item whose row is activated, or the selected
+    /// one when Enter is pressed in the entry.  It's given `None` when the
+    /// list is empty, and the picker's window, to close when it's done.
+    pub fn connect_pick(&self, pick: impl Fn(&gtk::Window, Option<T>) + 'static) {
+        let items = self.items.clone();
+        let window = self.window.clone();
+        let list = self.list.clone();
+        let pick = Rc::new(clone!(
+            #[strong]
+            items,
+            #[weak]
+            window,
+            move |index: Option<usize>| {
+                let item = index.and_then(|i| items.borrow().get(i).cloned());
+                pick(&window, item);
+            }
+        ));
+        if let Some(entry) = &self.entry {
+            entry.connect_activate(clone!(
+                #[strong]
+                pick,
+                #[weak]
+                list,
+                move |_| pick(list.selected_row().map(|r| r.index() as usize))
+            ));
+        }
+        self.list
+            .connect_row_activated(move |_, row| pick(Some(row.index() as usize)));
+    }
+
+    pub fn present(&self) {
+        self.window.present();
+        if let Some(entry) = &self.entry {
+            entry.grab_focus();
+        }
+    }
+}